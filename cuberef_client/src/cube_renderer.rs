@@ -26,7 +26,7 @@ use cuberef_core::protocol::blocks::block_type_def::RenderInfo;
 use cuberef_core::protocol::blocks::{
     self as blocks_proto, BlockTypeDef, CubeRenderInfo, CubeRenderMode,
 };
-use cuberef_core::protocol::render::TextureReference;
+use cuberef_core::protocol::render::{TextureReference, TextureRotation};
 use cuberef_core::{block_id::BlockId, coordinates::ChunkOffset};
 
 use anyhow::{ensure, Context, Error, Result};
@@ -43,6 +43,7 @@ use vulkano::memory::allocator::{
 };
 
 use crate::game_state::chunk::ClientChunk;
+use crate::game_state::visibility::ChunkConnectivity;
 use crate::game_state::{ChunkManagerView, make_fallback_blockdef};
 use crate::vulkan::shaders::cube_geometry::{CubeGeometryDrawCall, CubeGeometryVertex};
 use crate::vulkan::{Texture2DHolder, VulkanContext};
@@ -59,6 +60,57 @@ pub(crate) enum CubeFace {
     ZPlus,
     ZMinus,
 }
+impl CubeFace {
+    pub(crate) const ALL: [CubeFace; 6] = [
+        CubeFace::XMinus,
+        CubeFace::XPlus,
+        CubeFace::YMinus,
+        CubeFace::YPlus,
+        CubeFace::ZMinus,
+        CubeFace::ZPlus,
+    ];
+
+    // The direction the face points in, towards the neighbor that can hide it
+    pub(crate) fn normal(self) -> (i32, i32, i32) {
+        match self {
+            CubeFace::XPlus => (1, 0, 0),
+            CubeFace::XMinus => (-1, 0, 0),
+            CubeFace::YPlus => (0, 1, 0),
+            CubeFace::YMinus => (0, -1, 0),
+            CubeFace::ZPlus => (0, 0, 1),
+            CubeFace::ZMinus => (0, 0, -1),
+        }
+    }
+
+    pub(crate) fn opposite(self) -> CubeFace {
+        match self {
+            CubeFace::XPlus => CubeFace::XMinus,
+            CubeFace::XMinus => CubeFace::XPlus,
+            CubeFace::YPlus => CubeFace::YMinus,
+            CubeFace::YMinus => CubeFace::YPlus,
+            CubeFace::ZPlus => CubeFace::ZMinus,
+            CubeFace::ZMinus => CubeFace::ZPlus,
+        }
+    }
+
+    // The axis perpendicular to the face, numbered x = 0, y = 1, z = 2
+    fn axis(self) -> usize {
+        match self {
+            CubeFace::XPlus | CubeFace::XMinus => 0,
+            CubeFace::YPlus | CubeFace::YMinus => 1,
+            CubeFace::ZPlus | CubeFace::ZMinus => 2,
+        }
+    }
+
+    // The two axes along the face, in increasing order
+    fn tangent_axes(self) -> (usize, usize) {
+        match self.axis() {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+}
 
 pub(crate) struct ClientBlockTypeManager {
     block_defs: Vec<Option<blocks_proto::BlockTypeDef>>,
@@ -115,6 +167,16 @@ pub(crate) struct CubeExtents {
     z: (f32, f32),
 }
 
+const FULL_CUBE_EXTENTS: CubeExtents = CubeExtents {
+    x: (-0.5, 0.5),
+    y: (-0.5, 0.5),
+    z: (-0.5, 0.5),
+};
+
+// Brightness of a face's corner, indexed by how unoccluded it is (see
+// ChunkMesher::corner_occlusion)
+const AO_BRIGHTNESS: [f32; 4] = [0.5, 0.7, 0.85, 1.0];
+
 #[derive(Clone)]
 pub(crate) struct VkChunkPass {
     pub(crate) vtx: Subbuffer<[CubeGeometryVertex]>,
@@ -175,25 +237,39 @@ impl VkChunkVertexData {
     }
 }
 
+/// Vertices and indices for one pass of a chunk, before they're uploaded to the GPU.
+#[derive(Clone, Default)]
+pub(crate) struct CpuChunkPass {
+    pub(crate) vtx: Vec<CubeGeometryVertex>,
+    pub(crate) idx: Vec<u32>,
+}
+impl CpuChunkPass {
+    fn upload(self, allocator: &StandardMemoryAllocator) -> Result<Option<VkChunkPass>> {
+        VkChunkPass::from_buffers(self.vtx, self.idx, allocator)
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct CpuChunkVertexData {
+    pub(crate) solid_opaque: CpuChunkPass,
+    pub(crate) transparent: CpuChunkPass,
+    pub(crate) translucent: CpuChunkPass,
+}
+
 /// Manages the block type definitions, and their underlying textures,
 /// for the game.
 pub(crate) struct BlockRenderer {
-    block_defs: Arc<ClientBlockTypeManager>,
-    texture_coords: HashMap<String, Rect>,
+    mesher: ChunkMesher,
     texture_atlas: Arc<Texture2DHolder>,
     allocator: Arc<GenericMemoryAllocator<Arc<FreeListAllocator>>>,
 }
 impl BlockRenderer {
-    fn get_texture(&self, id: &str) -> Rect {
-        self.texture_coords
-            .get(&id.to_string())
-            .copied()
-            .unwrap_or_else(|| *self.texture_coords.get(FALLBACK_UNKNOWN_TEXTURE).unwrap())
-    }
     pub(crate) async fn new<T>(
         block_defs: Arc<ClientBlockTypeManager>,
         texture_loader: T,
         ctx: &VulkanContext,
+        greedy_meshing: bool,
+        ambient_occlusion: bool,
     ) -> Result<BlockRenderer>
     where
         T: AsyncTextureLoader,
@@ -202,8 +278,13 @@ impl BlockRenderer {
             build_texture_atlas(&block_defs, texture_loader).await?;
         let texture_atlas = Arc::new(Texture2DHolder::create(ctx, &texture_atlas)?);
         Ok(BlockRenderer {
-            block_defs,
-            texture_coords,
+            mesher: ChunkMesher {
+                block_defs,
+                texture_coords,
+                atlas_dimensions: texture_atlas.dimensions(),
+                greedy: greedy_meshing,
+                ambient_occlusion,
+            },
             texture_atlas,
             allocator: ctx.allocator(),
         })
@@ -222,264 +303,21 @@ impl BlockRenderer {
         chunk_data: &ChunkManagerView,
         current_chunk: &ClientChunk,
     ) -> Result<VkChunkVertexData> {
+        let mesh = self.mesher.mesh_chunk(chunk_data, current_chunk);
         Ok(VkChunkVertexData {
-            solid_opaque: self.mesh_chunk_subpass(
-                chunk_data,
-                current_chunk,
-                |block| match block.render_info {
-                    Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
-                        x == CubeRenderMode::SolidOpaque.into()
-                    }
-                    Some(_) | None => false,
-                },
-                |_block, neighbor| {
-                    neighbor.is_some_and(|neighbor| match neighbor.render_info {
-                        Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
-                            x == CubeRenderMode::SolidOpaque.into()
-                        }
-                        Some(_) | None => false,
-                    })
-                },
-            )?,
-            transparent: self.mesh_chunk_subpass(
-                chunk_data,
-                current_chunk,
-                |block| match block.render_info {
-                    Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
-                        x == CubeRenderMode::Transparent.into()
-                    }
-                    Some(_) | None => false,
-                },
-                |block, neighbor| {
-                    neighbor.is_some_and(|neighbor| {
-                        BlockId(block.id).equals_ignore_variant(BlockId(neighbor.id))
-                    })
-                },
-            )?,
-            translucent: self.mesh_chunk_subpass(
-                chunk_data,
-                current_chunk,
-                |block| match block.render_info {
-                    Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
-                        x == CubeRenderMode::Translucent.into()
-                    }
-                    Some(_) | None => false,
-                },
-                |block, neighbor| {
-                    neighbor.is_some_and(|neighbor| {
-                        if BlockId(block.id).equals_ignore_variant(BlockId(neighbor.id)) {
-                            return true;
-                        }
-                        match &neighbor.render_info {
-                            Some(RenderInfo::Cube(x)) => {
-                                x.render_mode() == CubeRenderMode::SolidOpaque
-                            }
-                            Some(_) => false,
-                            None => false,
-                        }
-                    })
-                },
-            )?,
+            solid_opaque: mesh.solid_opaque.upload(self.allocator())?,
+            transparent: mesh.transparent.upload(self.allocator())?,
+            translucent: mesh.translucent.upload(self.allocator())?,
         })
     }
 
-    pub(crate) fn mesh_chunk_subpass<F, G>(
-        &self,
-        chunk_data: &ChunkManagerView,
-        current_chunk: &ClientChunk,
-        // closure taking a block and returning whether this subpass should render it
-        include_block_when: F,
-        // closure taking a block and its neighbor, and returning whether we should render the face of our block that faces the given neighbor
-        suppress_face_when: G,
-    ) -> Result<Option<VkChunkPass>>
-    where
-        F: Fn(&BlockTypeDef) -> bool,
-        G: Fn(&BlockTypeDef, Option<&BlockTypeDef>) -> bool,
-    {
-        let _span = span!("mesh subpass");
-        let mut vtx = Vec::new();
-        let mut idx = Vec::new();
-        for x in 0..16 {
-            for y in 0..16 {
-                for z in 0..16 {
-                    let offset = ChunkOffset { x, y, z };
-
-                    let block_ids = current_chunk.block_ids();
-                    let block = self.get_block(&block_ids, offset);
-                    if let Some(RenderInfo::Cube(cube_render_info)) = &block.render_info {
-                        if include_block_when(block) {
-                            self.emit_full_cube(
-                                block,
-                                chunk_data,
-                                current_chunk.coord().with_offset(offset),
-                                &current_chunk.block_ids(),
-                                &mut vtx,
-                                &mut idx,
-                                cube_render_info,
-                                &suppress_face_when,
-                            );
-                        }
-                    }
-                }
-            }
-        }
-        VkChunkPass::from_buffers(vtx, idx, self.allocator())
-    }
-
-    fn emit_full_cube<F>(
-        &self,
-        block: &BlockTypeDef,
-        chunk_data: &ChunkManagerView,
-        coord: BlockCoordinate,
-        own_ids: &[BlockId; 4096],
-        vtx: &mut Vec<CubeGeometryVertex>,
-        idx: &mut Vec<u32>,
-        render_info: &CubeRenderInfo,
-        suppress_face_when: F,
-    ) where
-        F: Fn(&BlockTypeDef, Option<&BlockTypeDef>) -> bool,
-    {
-        const FULL_CUBE_EXTENTS: CubeExtents = CubeExtents {
-            x: (-0.5, 0.5),
-            y: (-0.5, 0.5),
-            z: (-0.5, 0.5),
-        };
-        let e = FULL_CUBE_EXTENTS;
-
-        let chunk = coord.chunk();
-        let offset = coord.offset();
-        let pos = vec3(offset.x.into(), offset.y.into(), offset.z.into());
-
-        if !suppress_face_when(
-            block,
-            coord.try_delta(-1, 0, 0).and_then(|neighbor| {
-                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
-            }),
-        ) {
-            let frame = self.get_texture(render_info.tex_left.tex_name());
-            emit_cube_face_vk(
-                pos,
-                frame,
-                self.texture_atlas.dimensions(),
-                CubeFace::XMinus,
-                vtx,
-                idx,
-                e,
-            );
-        }
-
-        if !suppress_face_when(
-            block,
-            coord.try_delta(1, 0, 0).and_then(|neighbor| {
-                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
-            }),
-        ) {
-            let frame = self.get_texture(render_info.tex_left.tex_name());
-            emit_cube_face_vk(
-                pos,
-                frame,
-                self.texture_atlas.dimensions(),
-                CubeFace::XPlus,
-                vtx,
-                idx,
-                e,
-            );
-        }
-        if !suppress_face_when(
-            block,
-            coord.try_delta(0, -1, 0).and_then(|neighbor| {
-                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
-            }),
-        ) {
-            let frame = self.get_texture(render_info.tex_bottom.tex_name());
-            emit_cube_face_vk(
-                pos,
-                frame,
-                self.texture_atlas.dimensions(),
-                CubeFace::YMinus,
-                vtx,
-                idx,
-                e,
-            );
-        }
-        if !suppress_face_when(
-            block,
-            coord.try_delta(0, 1, 0).and_then(|neighbor| {
-                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
-            }),
-        ) {
-            let frame = self.get_texture(render_info.tex_top.tex_name());
-            emit_cube_face_vk(
-                pos,
-                frame,
-                self.texture_atlas.dimensions(),
-                CubeFace::YPlus,
-                vtx,
-                idx,
-                e,
-            );
-        }
-        if !suppress_face_when(
-            block,
-            coord.try_delta(0, 0, -1).and_then(|neighbor| {
-                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
-            }),
-        ) {
-            let frame = self.get_texture(render_info.tex_front.tex_name());
-            emit_cube_face_vk(
-                pos,
-                frame,
-                self.texture_atlas.dimensions(),
-                CubeFace::ZMinus,
-                vtx,
-                idx,
-                e,
-            );
-        }
-        if !suppress_face_when(
-            block,
-            coord.try_delta(0, 0, 1).and_then(|neighbor| {
-                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
-            }),
-        ) {
-            let frame = self.get_texture(render_info.tex_back.tex_name());
-            emit_cube_face_vk(
-                pos,
-                frame,
-                self.texture_atlas.dimensions(),
-                CubeFace::ZPlus,
-                vtx,
-                idx,
-                e,
-            );
-        }
-    }
-
-    fn get_block(&self, ids: &[BlockId; 4096], coord: ChunkOffset) -> &BlockTypeDef {
-        let block_id = ids[coord.as_index()];
-
-        let def = self
-            .block_defs
-            .get_blockdef(block_id)
-            .unwrap_or_else(|| self.block_defs.get_fallback_blockdef());
-        def
-    }
-
-    fn get_block_maybe_neighbor(
-        &self,
-        all_chunks: &ChunkManagerView,
-        own_ids: &[BlockId; 4096],
-        own_chunk: ChunkCoordinate,
-        target: BlockCoordinate,
-    ) -> Option<&BlockTypeDef> {
-        let target_chunk = target.chunk();
-        if target_chunk == own_chunk {
-            Some(self.get_block(own_ids, target.offset()))
-        } else {
-            all_chunks
-                .get(&target_chunk)
-                .map(|x| self.get_block(&x.block_ids(), target.offset()))
-        }
+    /// Works out which faces of the chunk can be seen from each other, through the blocks
+    /// that aren't solid and opaque.
+    pub(crate) fn chunk_connectivity(&self, chunk: &ClientChunk) -> ChunkConnectivity {
+        let block_ids = chunk.block_ids();
+        ChunkConnectivity::compute(|offset| {
+            !is_solid_opaque(self.mesher.get_block(&block_ids, offset))
+        })
     }
 
     pub(crate) fn make_pointee_cube(
@@ -489,7 +327,8 @@ impl BlockRenderer {
     ) -> Result<CubeGeometryDrawCall> {
         let mut vtx = vec![];
         let mut idx = vec![];
-        let frame = *self.texture_coords.get(SELECTION_RECTANGLE).unwrap();
+        let frame =
+            FaceTexture::plain(*self.mesher.texture_coords.get(SELECTION_RECTANGLE).unwrap());
         const POINTEE_SELECTION_EXTENTS: CubeExtents = CubeExtents {
             x: (-0.51, 0.51),
             y: (-0.51, 0.51),
@@ -606,6 +445,392 @@ impl BlockRenderer {
     }
 }
 
+/// Turns chunks into vertex data on the CPU. This is kept apart from the GPU resources in
+/// [BlockRenderer] so that meshing can be tested and benchmarked without a Vulkan device.
+pub(crate) struct ChunkMesher {
+    block_defs: Arc<ClientBlockTypeManager>,
+    texture_coords: HashMap<String, Rect>,
+    atlas_dimensions: (u32, u32),
+    // Whether to merge adjacent faces with the same texture into larger quads
+    greedy: bool,
+    // Whether to darken the corners of faces that are surrounded by solid blocks
+    ambient_occlusion: bool,
+}
+impl ChunkMesher {
+    fn get_texture(&self, tex: &Option<TextureReference>) -> FaceTexture {
+        match tex
+            .as_ref()
+            .and_then(|x| Some((x, *self.texture_coords.get(&x.texture_name)?)))
+        {
+            Some((tex, rect)) => FaceTexture::new(rect, tex),
+            None => FaceTexture::plain(*self.texture_coords.get(FALLBACK_UNKNOWN_TEXTURE).unwrap()),
+        }
+    }
+
+    pub(crate) fn mesh_chunk(
+        &self,
+        chunk_data: &ChunkManagerView,
+        current_chunk: &ClientChunk,
+    ) -> CpuChunkVertexData {
+        CpuChunkVertexData {
+            solid_opaque: self.mesh_chunk_subpass(
+                chunk_data,
+                current_chunk,
+                |block| match block.render_info {
+                    Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
+                        x == CubeRenderMode::SolidOpaque.into()
+                    }
+                    Some(_) | None => false,
+                },
+                |_block, neighbor| {
+                    neighbor.is_some_and(|neighbor| match neighbor.render_info {
+                        Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
+                            x == CubeRenderMode::SolidOpaque.into()
+                        }
+                        Some(_) | None => false,
+                    })
+                },
+            ),
+            transparent: self.mesh_chunk_subpass(
+                chunk_data,
+                current_chunk,
+                |block| match block.render_info {
+                    Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
+                        x == CubeRenderMode::Transparent.into()
+                    }
+                    Some(_) | None => false,
+                },
+                |block, neighbor| {
+                    neighbor.is_some_and(|neighbor| {
+                        BlockId(block.id).equals_ignore_variant(BlockId(neighbor.id))
+                    })
+                },
+            ),
+            translucent: self.mesh_chunk_subpass(
+                chunk_data,
+                current_chunk,
+                |block| match block.render_info {
+                    Some(RenderInfo::Cube(CubeRenderInfo { render_mode: x, .. })) => {
+                        x == CubeRenderMode::Translucent.into()
+                    }
+                    Some(_) | None => false,
+                },
+                |block, neighbor| {
+                    neighbor.is_some_and(|neighbor| {
+                        if BlockId(block.id).equals_ignore_variant(BlockId(neighbor.id)) {
+                            return true;
+                        }
+                        match &neighbor.render_info {
+                            Some(RenderInfo::Cube(x)) => {
+                                x.render_mode() == CubeRenderMode::SolidOpaque
+                            }
+                            Some(_) => false,
+                            None => false,
+                        }
+                    })
+                },
+            ),
+        }
+    }
+
+    pub(crate) fn mesh_chunk_subpass<F, G>(
+        &self,
+        chunk_data: &ChunkManagerView,
+        current_chunk: &ClientChunk,
+        // closure taking a block and returning whether this subpass should render it
+        include_block_when: F,
+        // closure taking a block and its neighbor, and returning whether we should render the face of our block that faces the given neighbor
+        suppress_face_when: G,
+    ) -> CpuChunkPass
+    where
+        F: Fn(&BlockTypeDef) -> bool,
+        G: Fn(&BlockTypeDef, Option<&BlockTypeDef>) -> bool,
+    {
+        let _span = span!("mesh subpass");
+        let block_ids = current_chunk.block_ids();
+        let mut pass = CpuChunkPass::default();
+        let visible_face = |offset, face| {
+            let tex = self.visible_face(
+                chunk_data,
+                current_chunk.coord(),
+                &block_ids,
+                offset,
+                face,
+                &include_block_when,
+                &suppress_face_when,
+            )?;
+            let occlusion =
+                self.corner_occlusion(chunk_data, current_chunk.coord(), &block_ids, offset, face);
+            Some((tex, occlusion))
+        };
+        if self.greedy {
+            for face in CubeFace::ALL {
+                let faces: Vec<_> = (0..4096)
+                    .map(|i| visible_face(ChunkOffset::from_index(i), face))
+                    .collect();
+                self.emit_merged_faces(face, &faces, &mut pass);
+            }
+        } else {
+            for x in 0..16 {
+                for y in 0..16 {
+                    for z in 0..16 {
+                        let offset = ChunkOffset { x, y, z };
+                        let pos = vec3(x.into(), y.into(), z.into());
+                        for face in CubeFace::ALL {
+                            if let Some((tex, occlusion)) = visible_face(offset, face) {
+                                self.emit_shaded_face(
+                                    pos,
+                                    tex,
+                                    occlusion,
+                                    face,
+                                    FULL_CUBE_EXTENTS,
+                                    &mut pass,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        pass
+    }
+
+    // Returns the texture of the given face of the block at offset, or None if the face
+    // shouldn't be drawn in this subpass (including when it's hidden by its neighbor)
+    fn visible_face<F, G>(
+        &self,
+        chunk_data: &ChunkManagerView,
+        chunk: ChunkCoordinate,
+        own_ids: &[BlockId; 4096],
+        offset: ChunkOffset,
+        face: CubeFace,
+        include_block_when: F,
+        suppress_face_when: G,
+    ) -> Option<FaceTexture>
+    where
+        F: Fn(&BlockTypeDef) -> bool,
+        G: Fn(&BlockTypeDef, Option<&BlockTypeDef>) -> bool,
+    {
+        let block = self.get_block(own_ids, offset);
+        let render_info = match &block.render_info {
+            Some(RenderInfo::Cube(x)) if include_block_when(block) => x,
+            _ => return None,
+        };
+        let (dx, dy, dz) = face.normal();
+        let neighbor = chunk
+            .with_offset(offset)
+            .try_delta(dx, dy, dz)
+            .and_then(|neighbor| {
+                self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, neighbor)
+            });
+        if suppress_face_when(block, neighbor) {
+            return None;
+        }
+        let tex = match face {
+            CubeFace::XMinus | CubeFace::XPlus => &render_info.tex_left,
+            CubeFace::YMinus => &render_info.tex_bottom,
+            CubeFace::YPlus => &render_info.tex_top,
+            CubeFace::ZMinus => &render_info.tex_front,
+            CubeFace::ZPlus => &render_info.tex_back,
+        };
+        Some(self.get_texture(tex))
+    }
+
+    // How much each corner of a face is lit, from 0 (fully occluded) to 3 (not occluded at all).
+    // This is the usual voxel ambient occlusion: a corner is shaded by the two solid blocks beside
+    // it and the one diagonal to it, in the layer that the face looks into. Corners are indexed as
+    // 2 * a + b, where a and b are whether the corner is on the positive side of the face's
+    // tangent axes.
+    fn corner_occlusion(
+        &self,
+        chunk_data: &ChunkManagerView,
+        chunk: ChunkCoordinate,
+        own_ids: &[BlockId; 4096],
+        offset: ChunkOffset,
+        face: CubeFace,
+    ) -> [u8; 4] {
+        if !self.ambient_occlusion {
+            return [3; 4];
+        }
+        let (a_axis, b_axis) = face.tangent_axes();
+        let (nx, ny, nz) = face.normal();
+        let coord = chunk.with_offset(offset);
+        let occludes = |delta: [i32; 3]| {
+            coord
+                .try_delta(nx + delta[0], ny + delta[1], nz + delta[2])
+                .and_then(|target| {
+                    self.get_block_maybe_neighbor(chunk_data, own_ids, chunk, target)
+                })
+                .is_some_and(is_solid_opaque)
+        };
+        std::array::from_fn(|corner| {
+            let mut side_a = [0; 3];
+            side_a[a_axis] = if corner & 2 == 0 { -1 } else { 1 };
+            let mut side_b = [0; 3];
+            side_b[b_axis] = if corner & 1 == 0 { -1 } else { 1 };
+            match (occludes(side_a), occludes(side_b)) {
+                // The diagonal block can't make the corner any darker
+                (true, true) => 0,
+                (a, b) => {
+                    let diagonal = [0, 1, 2].map(|i| side_a[i] + side_b[i]);
+                    3 - u8::from(a) - u8::from(b) - u8::from(occludes(diagonal))
+                }
+            }
+        })
+    }
+
+    // Emits a face and shades each of its vertices by the occlusion of its corner
+    fn emit_shaded_face(
+        &self,
+        pos: Vector3<f32>,
+        tex: FaceTexture,
+        occlusion: [u8; 4],
+        face: CubeFace,
+        e: CubeExtents,
+        pass: &mut CpuChunkPass,
+    ) {
+        let first_vtx = pass.vtx.len();
+        emit_cube_face_vk(
+            pos,
+            tex,
+            self.atlas_dimensions,
+            face,
+            &mut pass.vtx,
+            &mut pass.idx,
+            e,
+        );
+        let (a_axis, b_axis) = face.tangent_axes();
+        let mut levels = [0; 4];
+        for (level, vertex) in levels.iter_mut().zip(&mut pass.vtx[first_vtx..]) {
+            // Back to game coordinates, relative to pos; +Y is down in Vulkan's coordinates
+            let [x, y, z] = vertex.position;
+            let offset = [x - pos.x, -(y + pos.y), z - pos.z];
+            let corner = 2 * usize::from(offset[a_axis] > 0.0) + usize::from(offset[b_axis] > 0.0);
+            *level = occlusion[corner];
+            vertex.brightness = AO_BRIGHTNESS[usize::from(*level)];
+        }
+        // Quads are split along the diagonal from their first vertex to their third. Split along
+        // the other one if it's brighter, so that a single dark corner doesn't spread across the
+        // whole face.
+        if levels[0] + levels[2] < levels[1] + levels[3] {
+            let first_idx = pass.idx.len() - 6;
+            let v: u32 = first_vtx.try_into().unwrap();
+            pass.idx[first_idx..].copy_from_slice(&[v + 1, v + 2, v + 3, v + 1, v + 3, v]);
+        }
+    }
+
+    // Greedily merges the faces pointing in one direction, one slice of the chunk at a time:
+    // each face that isn't merged yet grows along one axis of the slice as far as the faces
+    // match, then along the other axis as long as the whole row matches.
+    fn emit_merged_faces(
+        &self,
+        face: CubeFace,
+        faces: &[Option<(FaceTexture, [u8; 4])>],
+        pass: &mut CpuChunkPass,
+    ) {
+        // Axes are numbered x = 0, y = 1, z = 2. The slice is perpendicular to the face's normal.
+        let normal_axis = face.axis();
+        let (a_axis, b_axis) = face.tangent_axes();
+        for slice in 0..16 {
+            let position = |a: usize, b: usize| {
+                let mut position = [0; 3];
+                position[normal_axis] = slice;
+                position[a_axis] = a;
+                position[b_axis] = b;
+                position
+            };
+            let face_at = |a: usize, b: usize| {
+                let [x, y, z] = position(a, b);
+                faces[256 * z + 16 * y + x]
+            };
+            let mut merged = [[false; 16]; 16];
+            for a in 0..16 {
+                for b in 0..16 {
+                    if merged[a][b] {
+                        continue;
+                    }
+                    let (tex, occlusion) = match face_at(a, b) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    // A merged quad is only shaded at its own corners, so only faces that are lit
+                    // evenly can be merged
+                    let evenly_lit = occlusion.iter().all(|&x| x == occlusion[0]);
+                    let matches = |a: usize, b: usize| {
+                        evenly_lit && !merged[a][b] && face_at(a, b) == Some((tex, occlusion))
+                    };
+                    let mut len_b = 1;
+                    while b + len_b < 16 && matches(a, b + len_b) {
+                        len_b += 1;
+                    }
+                    let mut len_a = 1;
+                    while a + len_a < 16 && (b..b + len_b).all(|b| matches(a + len_a, b)) {
+                        len_a += 1;
+                    }
+                    for row in &mut merged[a..a + len_a] {
+                        row[b..b + len_b].fill(true);
+                    }
+
+                    let mut counts = [1.0; 3];
+                    counts[a_axis] = len_a as f32;
+                    counts[b_axis] = len_b as f32;
+                    let [nx, ny, nz] = counts;
+                    // Relative to the block with the smallest coordinates; +Y is down in
+                    // Vulkan's coordinates
+                    let e = CubeExtents {
+                        x: (-0.5, nx - 0.5),
+                        y: (0.5 - ny, 0.5),
+                        z: (-0.5, nz - 0.5),
+                    };
+                    let [x, y, z] = position(a, b);
+                    self.emit_shaded_face(
+                        vec3(x as f32, y as f32, z as f32),
+                        tex,
+                        occlusion,
+                        face,
+                        e,
+                        pass,
+                    );
+                }
+            }
+        }
+    }
+
+    fn get_block(&self, ids: &[BlockId; 4096], coord: ChunkOffset) -> &BlockTypeDef {
+        let block_id = ids[coord.as_index()];
+
+        let def = self
+            .block_defs
+            .get_blockdef(block_id)
+            .unwrap_or_else(|| self.block_defs.get_fallback_blockdef());
+        def
+    }
+
+    fn get_block_maybe_neighbor(
+        &self,
+        all_chunks: &ChunkManagerView,
+        own_ids: &[BlockId; 4096],
+        own_chunk: ChunkCoordinate,
+        target: BlockCoordinate,
+    ) -> Option<&BlockTypeDef> {
+        let target_chunk = target.chunk();
+        if target_chunk == own_chunk {
+            Some(self.get_block(own_ids, target.offset()))
+        } else {
+            all_chunks
+                .get(&target_chunk)
+                .map(|x| self.get_block(&x.block_ids(), target.offset()))
+        }
+    }
+}
+
+fn is_solid_opaque(block: &BlockTypeDef) -> bool {
+    match &block.render_info {
+        Some(RenderInfo::Cube(x)) => x.render_mode() == CubeRenderMode::SolidOpaque,
+        Some(_) | None => false,
+    }
+}
+
 async fn build_texture_atlas<T: AsyncTextureLoader>(
     block_defs: &ClientBlockTypeManager,
     mut texture_loader: T,
@@ -695,38 +920,104 @@ async fn build_texture_atlas<T: AsyncTextureLoader>(
     Ok((texture_atlas, texture_coords))
 }
 
-pub(crate) fn fallback_texture() -> Option<TextureReference> {
-    Some(TextureReference {
-        texture_name: FALLBACK_UNKNOWN_TEXTURE.to_string(),
-    })
+/// Returns the part of the atlas that shows the texture when it isn't animated, i.e. its first
+/// animation frame, cropped as the texture reference requests.
+pub(crate) fn static_texture_rect(rect: Rect, tex: &TextureReference) -> Rect {
+    let frame_count = match &tex.animation {
+        Some(animation) => animation.frame_count.clamp(1, rect.h),
+        None => 1,
+    };
+    let frame = Rect::new(rect.x, rect.y, rect.w, rect.h / frame_count);
+    match &tex.crop {
+        Some(crop) if crop.left < frame.w && crop.top < frame.h => Rect::new(
+            frame.x + crop.left,
+            frame.y + crop.top,
+            crop.width.clamp(1, frame.w - crop.left),
+            crop.height.clamp(1, frame.h - crop.top),
+        ),
+        Some(_) => {
+            log::warn!("Crop for {} is outside the texture", tex.texture_name);
+            frame
+        }
+        None => frame,
+    }
 }
 
-
-enum TextureRotation {
-    Zero,
-    Ccw90,
-    Flip180,
-    Cw90,
+/// A face's texture, resolved to its location in the atlas along with how it should be drawn.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FaceTexture {
+    // The part of the atlas to draw, for the first animation frame
+    rect: Rect,
+    rotation: TextureRotation,
+    flip_horizontal: bool,
+    tint: [u8; 4],
+    anim_frames: u32,
+    anim_frame_millis: u32,
+    // Distance between the tops of consecutive frames, in pixels
+    anim_frame_stride: u32,
 }
+impl FaceTexture {
+    fn new(rect: Rect, tex: &TextureReference) -> FaceTexture {
+        let (anim_frames, anim_frame_millis) = match &tex.animation {
+            Some(animation) if animation.frame_count > 1 => (
+                animation.frame_count.min(rect.h),
+                animation.frame_duration_millis.max(1),
+            ),
+            _ => (1, 0),
+        };
+        let tint = if tex.has_tint {
+            let [_, r, g, b] = tex.tint.to_be_bytes();
+            [r, g, b, 255]
+        } else {
+            [255; 4]
+        };
+        FaceTexture {
+            rect: static_texture_rect(rect, tex),
+            rotation: tex.rotation(),
+            flip_horizontal: tex.flip_horizontal,
+            tint,
+            anim_frames,
+            anim_frame_millis,
+            anim_frame_stride: rect.h / anim_frames,
+        }
+    }
 
-#[inline]
-fn make_cgv(
-    coord: Vector3<f32>,
-    x: f32,
-    y: f32,
-    z: f32,
-    tex_uv: cgmath::Vector2<f32>,
-) -> CubeGeometryVertex {
-    CubeGeometryVertex {
-        position: [coord.x + x, -(coord.y) + y, coord.z + z],
-        uv_texcoord: tex_uv.into(),
-        brightness: 1.0,
+    fn plain(rect: Rect) -> FaceTexture {
+        FaceTexture {
+            rect,
+            rotation: TextureRotation::Zero,
+            flip_horizontal: false,
+            tint: [255; 4],
+            anim_frames: 1,
+            anim_frame_millis: 0,
+            anim_frame_stride: 0,
+        }
     }
 }
+// Rect doesn't implement PartialEq. Faces are only merged if they compare equal.
+impl PartialEq for FaceTexture {
+    fn eq(&self, other: &Self) -> bool {
+        (self.rect.x, self.rect.y, self.rect.w, self.rect.h)
+            == (other.rect.x, other.rect.y, other.rect.w, other.rect.h)
+            && self.rotation == other.rotation
+            && self.flip_horizontal == other.flip_horizontal
+            && self.tint == other.tint
+            && self.anim_frames == other.anim_frames
+            && self.anim_frame_millis == other.anim_frame_millis
+            && self.anim_frame_stride == other.anim_frame_stride
+    }
+}
+
+pub(crate) fn fallback_texture() -> Option<TextureReference> {
+    Some(TextureReference {
+        texture_name: FALLBACK_UNKNOWN_TEXTURE.to_string(),
+        ..Default::default()
+    })
+}
 
 pub(crate) fn emit_cube_face_vk(
     coord: Vector3<f32>,
-    frame: Rect,
+    tex: FaceTexture,
     tex_dimension: (u32, u32),
     face: CubeFace,
     vert_buf: &mut Vec<CubeGeometryVertex>,
@@ -735,53 +1026,97 @@ pub(crate) fn emit_cube_face_vk(
 ) {
     let width = (tex_dimension.0) as f32;
     let height = (tex_dimension.1) as f32;
-    let l = frame.left() as f32 / width;
-    let r = (frame.right() + 1) as f32 / width;
-    let t = frame.top() as f32 / height;
-    let b = (frame.bottom() + 1) as f32 / height;
-    // todo handle rotating textures (both in the texture ref and here)
-    // Possibly at the caller?
-    let tl = Vector2::new(l, t);
-    let bl = Vector2::new(l, b);
-    let tr = Vector2::new(r, t);
-    let br = Vector2::new(r, b);
+    let tex_rect = [
+        tex.rect.left() as f32 / width,
+        tex.rect.top() as f32 / height,
+        tex.rect.w as f32 / width,
+        tex.rect.h as f32 / height,
+    ];
+    let quarter_turns = match tex.rotation {
+        TextureRotation::Zero => 0,
+        TextureRotation::Cw90 => 1,
+        TextureRotation::Cw180 => 2,
+        TextureRotation::Cw270 => 3,
+    };
+    // The texture repeats once for each whole block the face spans, along its top edge (from the
+    // first vertex to the last) and its left edge (from the first vertex to the second).
+    let blocks = |(lo, hi): (f32, f32)| ((hi - lo).round()).max(1.0);
+    let (across, down) = match face {
+        CubeFace::ZMinus | CubeFace::ZPlus => (blocks(e.x), blocks(e.y)),
+        CubeFace::XMinus | CubeFace::XPlus => (blocks(e.z), blocks(e.y)),
+        CubeFace::YPlus => (blocks(e.x), blocks(e.z)),
+        CubeFace::YMinus => (blocks(e.z), blocks(e.x)),
+    };
+    // Texture coordinates are in tile space, where each whole unit is one repetition. A rotated
+    // texture's own width runs down the face after an odd number of quarter turns.
+    let (u_tiles, v_tiles) = if quarter_turns % 2 == 1 {
+        (down, across)
+    } else {
+        (across, down)
+    };
+    let (mut l, mut r) = (0.0, u_tiles);
+    let (t, b) = (0.0, v_tiles);
+    if tex.flip_horizontal {
+        std::mem::swap(&mut l, &mut r);
+    }
+    // Corners in the order the vertices of each face are emitted. Rotating the texture clockwise
+    // by a quarter turn moves each corner to the vertex before it.
+    let corners = [
+        Vector2::new(l, t),
+        Vector2::new(l, b),
+        Vector2::new(r, b),
+        Vector2::new(r, t),
+    ];
+    let [tl, bl, br, tr] = std::array::from_fn(|i| corners[(i + quarter_turns) % 4]);
+    let stride = tex.anim_frame_stride as f32 / height;
+
+    let make_cgv = |x: f32, y: f32, z: f32, tex_uv: Vector2<f32>| CubeGeometryVertex {
+        position: [coord.x + x, -(coord.y) + y, coord.z + z],
+        uv_texcoord: tex_uv.into(),
+        brightness: 1.0,
+        tint: tex.tint,
+        anim_frames: tex.anim_frames,
+        anim_frame_millis: tex.anim_frame_millis,
+        anim_frame_stride: stride,
+        tex_rect,
+    };
 
     let mut vertices = match face {
         CubeFace::ZMinus => vec![
-            make_cgv(coord, e.x.1, e.y.0, e.z.0, tl),
-            make_cgv(coord, e.x.1, e.y.1, e.z.0, bl),
-            make_cgv(coord, e.x.0, e.y.1, -e.z.1, br),
-            make_cgv(coord, e.x.0, e.y.0, -e.z.1, tr),
+            make_cgv(e.x.1, e.y.0, e.z.0, tl),
+            make_cgv(e.x.1, e.y.1, e.z.0, bl),
+            make_cgv(e.x.0, e.y.1, e.z.0, br),
+            make_cgv(e.x.0, e.y.0, e.z.0, tr),
         ],
         CubeFace::ZPlus => vec![
-            make_cgv(coord, e.x.0, e.y.0, e.z.1, tl),
-            make_cgv(coord, e.x.0, e.y.1, e.z.1, bl),
-            make_cgv(coord, e.x.1, e.y.1, e.z.1, br),
-            make_cgv(coord, e.x.1, e.y.0, e.z.1, tr),
+            make_cgv(e.x.0, e.y.0, e.z.1, tl),
+            make_cgv(e.x.0, e.y.1, e.z.1, bl),
+            make_cgv(e.x.1, e.y.1, e.z.1, br),
+            make_cgv(e.x.1, e.y.0, e.z.1, tr),
         ],
         CubeFace::XPlus => vec![
-            make_cgv(coord, e.x.1, e.y.0, e.z.1, tl),
-            make_cgv(coord, e.x.1, e.y.1, e.z.1, bl),
-            make_cgv(coord, e.x.1, e.y.1, -e.z.1, br),
-            make_cgv(coord, e.x.1, e.y.0, -e.z.1, tr),
+            make_cgv(e.x.1, e.y.0, e.z.1, tl),
+            make_cgv(e.x.1, e.y.1, e.z.1, bl),
+            make_cgv(e.x.1, e.y.1, e.z.0, br),
+            make_cgv(e.x.1, e.y.0, e.z.0, tr),
         ],
         CubeFace::XMinus => vec![
-            make_cgv(coord, e.x.0, e.y.0, e.z.0, tl),
-            make_cgv(coord, e.x.0, e.y.1, e.z.0, bl),
-            make_cgv(coord, e.x.0, e.y.1, e.z.1, br),
-            make_cgv(coord, e.x.0, e.y.0, e.z.1, tr),
+            make_cgv(e.x.0, e.y.0, e.z.0, tl),
+            make_cgv(e.x.0, e.y.1, e.z.0, bl),
+            make_cgv(e.x.0, e.y.1, e.z.1, br),
+            make_cgv(e.x.0, e.y.0, e.z.1, tr),
         ],
         CubeFace::YPlus => vec![
-            make_cgv(coord, e.x.0, e.y.0, e.z.0, tl),
-            make_cgv(coord, e.x.0, e.y.0, e.z.1, bl),
-            make_cgv(coord, e.x.1, e.y.0, e.z.1, br),
-            make_cgv(coord, e.x.1, e.y.0, -e.z.1, tr),
+            make_cgv(e.x.0, e.y.0, e.z.0, tl),
+            make_cgv(e.x.0, e.y.0, e.z.1, bl),
+            make_cgv(e.x.1, e.y.0, e.z.1, br),
+            make_cgv(e.x.1, e.y.0, e.z.0, tr),
         ],
         CubeFace::YMinus => vec![
-            make_cgv(coord, e.x.0, e.y.1, e.z.0, tl),
-            make_cgv(coord, e.x.1, e.y.1, e.z.0, bl),
-            make_cgv(coord, e.x.1, e.y.1, e.z.1, br),
-            make_cgv(coord, e.x.0, e.y.1, e.z.1, tr),
+            make_cgv(e.x.0, e.y.1, e.z.0, tl),
+            make_cgv(e.x.1, e.y.1, e.z.0, bl),
+            make_cgv(e.x.1, e.y.1, e.z.1, br),
+            make_cgv(e.x.0, e.y.1, e.z.1, tr),
         ],
     };
     let si: u32 = vert_buf.len().try_into().unwrap();
@@ -793,19 +1128,542 @@ pub(crate) fn emit_cube_face_vk(
     idx_buf.append(&mut indices);
 }
 
-trait TexRefHelper {
-    fn tex_name(&self) -> &str;
+#[async_trait]
+pub(crate) trait AsyncTextureLoader {
+    async fn load_texture(&mut self, tex_name: &str) -> Result<DynamicImage>;
 }
-impl TexRefHelper for Option<TextureReference> {
-    fn tex_name(&self) -> &str {
-        match self {
-            Some(x) => &x.texture_name,
-            None => FALLBACK_UNKNOWN_TEXTURE,
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use cuberef_core::protocol::blocks::Empty;
+    use cuberef_core::protocol::game_rpc::MapChunk;
+    use cuberef_core::protocol::map::{stored_chunk::ChunkData, ChunkV1, StoredChunk};
+    use cuberef_core::protocol::render::{TextureAnimation, TextureCrop};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::game_state::ChunkManager;
+
+    use super::*;
+
+    const AIR: u32 = 0;
+    const STONE: u32 = 1 << 12;
+    const DIRT: u32 = 2 << 12;
+    const GLASS: u32 = 3 << 12;
+    const WATER: u32 = 4 << 12;
+    const LOG: u32 = 5 << 12;
+
+    fn tex(name: &str) -> Option<TextureReference> {
+        Some(TextureReference {
+            texture_name: name.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn cube(
+        id: u32,
+        render_mode: CubeRenderMode,
+        textures: [Option<TextureReference>; 3],
+    ) -> BlockTypeDef {
+        let [top, bottom, side] = textures;
+        BlockTypeDef {
+            id,
+            short_name: format!("test:{id}"),
+            render_info: Some(RenderInfo::Cube(CubeRenderInfo {
+                tex_left: side.clone(),
+                tex_right: side.clone(),
+                tex_top: top,
+                tex_bottom: bottom,
+                tex_front: side.clone(),
+                tex_back: side,
+                render_mode: render_mode.into(),
+            })),
+            ..Default::default()
         }
     }
-}
 
-#[async_trait]
-pub(crate) trait AsyncTextureLoader {
-    async fn load_texture(&mut self, tex_name: &str) -> Result<DynamicImage>;
+    fn make_mesher(greedy: bool, ambient_occlusion: bool) -> ChunkMesher {
+        let water = TextureReference {
+            texture_name: "test:water".to_string(),
+            animation: Some(TextureAnimation {
+                frame_count: 4,
+                frame_duration_millis: 250,
+            }),
+            has_tint: true,
+            tint: 0x3070ff,
+            ..Default::default()
+        };
+        let log_side = TextureReference {
+            texture_name: "test:log_side".to_string(),
+            rotation: TextureRotation::Cw90.into(),
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        let defs = vec![
+            BlockTypeDef {
+                id: AIR,
+                short_name: "test:air".to_string(),
+                render_info: Some(RenderInfo::Empty(Empty {})),
+                ..Default::default()
+            },
+            cube(
+                STONE,
+                CubeRenderMode::SolidOpaque,
+                [tex("test:stone"), tex("test:stone"), tex("test:stone")],
+            ),
+            cube(
+                DIRT,
+                CubeRenderMode::SolidOpaque,
+                [tex("test:grass"), tex("test:dirt"), tex("test:dirt")],
+            ),
+            cube(
+                GLASS,
+                CubeRenderMode::Transparent,
+                [tex("test:glass"), tex("test:glass"), tex("test:glass")],
+            ),
+            cube(
+                WATER,
+                CubeRenderMode::Translucent,
+                [Some(water.clone()), Some(water.clone()), Some(water)],
+            ),
+            cube(
+                LOG,
+                CubeRenderMode::SolidOpaque,
+                [tex("test:log_top"), tex("test:log_top"), Some(log_side)],
+            ),
+        ];
+        let texture_coords = [
+            (FALLBACK_UNKNOWN_TEXTURE, Rect::new(0, 0, 16, 16)),
+            (SELECTION_RECTANGLE, Rect::new(16, 0, 16, 16)),
+            ("test:stone", Rect::new(32, 0, 16, 16)),
+            ("test:grass", Rect::new(48, 0, 16, 16)),
+            ("test:dirt", Rect::new(64, 0, 16, 16)),
+            ("test:glass", Rect::new(80, 0, 16, 16)),
+            ("test:log_top", Rect::new(96, 0, 16, 16)),
+            ("test:log_side", Rect::new(112, 0, 16, 16)),
+            ("test:water", Rect::new(0, 16, 16, 64)),
+        ]
+        .into_iter()
+        .map(|(name, rect)| (name.to_string(), rect))
+        .collect();
+        ChunkMesher {
+            block_defs: Arc::new(ClientBlockTypeManager::new(defs).unwrap()),
+            texture_coords,
+            atlas_dimensions: (128, 128),
+            greedy,
+            ambient_occlusion,
+        }
+    }
+
+    fn make_chunk(coord: ChunkCoordinate, block_at: impl FnMut(ChunkOffset) -> u32) -> ClientChunk {
+        ClientChunk::from_proto(MapChunk {
+            chunk_coord: Some(coord.into()),
+            chunk_data: Some(StoredChunk {
+                chunk_data: Some(ChunkData::V1(ChunkV1 {
+                    block_ids: (0..4096)
+                        .map(ChunkOffset::from_index)
+                        .map(block_at)
+                        .collect(),
+                    extended_data: vec![],
+                })),
+            }),
+        })
+        .unwrap()
+    }
+
+    fn random_chunk(coord: ChunkCoordinate, seed: u64) -> ClientChunk {
+        const BLOCKS: [u32; 6] = [AIR, STONE, DIRT, GLASS, WATER, LOG];
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Skew towards air and stone so that there are larger runs to merge
+        make_chunk(coord, |_| match rng.gen_range(0..10) {
+            0..=3 => AIR,
+            4..=5 => STONE,
+            _ => BLOCKS[rng.gen_range(0..BLOCKS.len())],
+        })
+    }
+
+    fn terrain_chunk(coord: ChunkCoordinate) -> ClientChunk {
+        make_chunk(coord, |offset| {
+            let height = 6 + (offset.x / 4 + offset.z / 5) % 4;
+            match offset.y {
+                y if y < height - 2 => STONE,
+                y if y < height => DIRT,
+                y if y < 8 => WATER,
+                y if y < height + 4 && offset.x == 12 && offset.z == 3 => LOG,
+                y if y == 12 && offset.x > 10 && offset.z < 6 => GLASS,
+                _ => AIR,
+            }
+        })
+    }
+
+    // Meshes the chunk at coord, with the given chunks loaded around it
+    fn mesh(
+        mesher: &ChunkMesher,
+        coord: ChunkCoordinate,
+        chunks: impl IntoIterator<Item = ClientChunk>,
+    ) -> CpuChunkVertexData {
+        let manager = ChunkManager::new();
+        for chunk in chunks {
+            manager.insert(chunk.coord(), chunk);
+        }
+        let view = manager.read_lock();
+        mesher.mesh_chunk(&view, view.get(&coord).unwrap())
+    }
+
+    // Where a block-sized square of surface is, as its center (doubled, so that it's integral)
+    // and the direction it faces
+    type SquareKey = ([i32; 3], [i32; 3]);
+    // How a block-sized square is drawn: the vertex data shared by its quad, the texture
+    // coordinates at three of its corners, moved into the first tile, and the brightness at all
+    // four corners, in hundredths
+    type SquareLook = ([u32; 4], [u8; 4], u32, u32, [[i32; 2]; 3], [i32; 4]);
+
+    // Splits every quad in the pass into block-sized squares
+    fn coverage(pass: &CpuChunkPass) -> HashMap<SquareKey, SquareLook> {
+        let mut squares = HashMap::new();
+        for quad in pass.idx.chunks(6) {
+            // The quad may be split along either diagonal, but its vertices are in order
+            let si = *quad.iter().min().unwrap();
+            let [v0, v1, v2, v3] = [si, si + 1, si + 2, si + 3].map(|i| pass.vtx[i as usize]);
+            let [p0, p1, p3] = [v0, v1, v3].map(|v| Vector3::from(v.position));
+            let [t0, t1, t3] = [v0, v1, v3].map(|v| Vector2::from(v.uv_texcoord));
+            let (across, down) = (p3 - p0, p1 - p0);
+            let normal = across.cross(down).normalize();
+            let normal = [normal.x, normal.y, normal.z].map(|x| x.round() as i32);
+            let w = across.magnitude().round() as usize;
+            let h = down.magnitude().round() as usize;
+            let uv = |s: f32, t: f32| t0 + (t3 - t0) * s + (t1 - t0) * t;
+            let brightness = |s: f32, t: f32| {
+                let b = v0.brightness * (1.0 - s) * (1.0 - t)
+                    + v3.brightness * s * (1.0 - t)
+                    + v1.brightness * (1.0 - s) * t
+                    + v2.brightness * s * t;
+                (b * 100.0).round() as i32
+            };
+            for i in 0..w {
+                for j in 0..h {
+                    let (s0, s1) = (i as f32 / w as f32, (i + 1) as f32 / w as f32);
+                    let (u0, u1) = (j as f32 / h as f32, (j + 1) as f32 / h as f32);
+                    let center = p0
+                        + across * ((i as f32 + 0.5) / w as f32)
+                        + down * ((j as f32 + 0.5) / h as f32);
+                    let corners = [uv(s0, u0), uv(s1, u0), uv(s0, u1), uv(s1, u1)];
+                    let tile = Vector2::new(
+                        corners
+                            .iter()
+                            .map(|c| c.x)
+                            .fold(f32::INFINITY, f32::min)
+                            .floor(),
+                        corners
+                            .iter()
+                            .map(|c| c.y)
+                            .fold(f32::INFINITY, f32::min)
+                            .floor(),
+                    );
+                    let uvs = [corners[0], corners[1], corners[2]]
+                        .map(|c| [(c.x - tile.x).round() as i32, (c.y - tile.y).round() as i32]);
+                    let key = (
+                        [center.x, center.y, center.z].map(|x| (x * 2.0).round() as i32),
+                        normal,
+                    );
+                    let look = (
+                        v0.tex_rect.map(f32::to_bits),
+                        v0.tint,
+                        v0.anim_frames,
+                        v0.anim_frame_stride.to_bits(),
+                        uvs,
+                        [(s0, u0), (s1, u0), (s0, u1), (s1, u1)].map(|(s, t)| brightness(s, t)),
+                    );
+                    assert!(
+                        squares.insert(key, look).is_none(),
+                        "square {key:?} drawn twice"
+                    );
+                }
+            }
+        }
+        squares
+    }
+
+    fn assert_same_coverage(naive: &CpuChunkVertexData, greedy: &CpuChunkVertexData) {
+        for (name, naive, greedy) in [
+            ("solid_opaque", &naive.solid_opaque, &greedy.solid_opaque),
+            ("transparent", &naive.transparent, &greedy.transparent),
+            ("translucent", &naive.translucent, &greedy.translucent),
+        ] {
+            assert!(
+                greedy.idx.len() <= naive.idx.len(),
+                "greedy {name} pass has more quads than the naive one"
+            );
+            assert!(
+                coverage(naive) == coverage(greedy),
+                "greedy {name} pass covers a different surface than the naive one"
+            );
+        }
+    }
+
+    #[test]
+    fn greedy_merges_solid_chunk() {
+        let coord = ChunkCoordinate::new(0, 0, 0);
+        let naive = mesh(
+            &make_mesher(false, true),
+            coord,
+            [make_chunk(coord, |_| STONE)],
+        );
+        let greedy = mesh(
+            &make_mesher(true, true),
+            coord,
+            [make_chunk(coord, |_| STONE)],
+        );
+        assert_eq!(naive.solid_opaque.idx.len(), 6 * 256 * 6);
+        // One quad per side of the chunk
+        assert_eq!(greedy.solid_opaque.idx.len(), 6 * 6);
+        assert_same_coverage(&naive, &greedy);
+    }
+
+    #[test]
+    fn greedy_matches_naive_on_terrain() {
+        let coord = ChunkCoordinate::new(0, 0, 0);
+        for ambient_occlusion in [false, true] {
+            let naive = mesh(
+                &make_mesher(false, ambient_occlusion),
+                coord,
+                [terrain_chunk(coord)],
+            );
+            let greedy = mesh(
+                &make_mesher(true, ambient_occlusion),
+                coord,
+                [terrain_chunk(coord)],
+            );
+            // Unevenly lit faces can't be merged, so there are fewer merges with shading
+            if !ambient_occlusion {
+                assert!(greedy.solid_opaque.idx.len() * 4 < naive.solid_opaque.idx.len());
+            }
+            assert!(greedy.solid_opaque.idx.len() < naive.solid_opaque.idx.len());
+            assert_same_coverage(&naive, &greedy);
+        }
+    }
+
+    #[test]
+    fn greedy_matches_naive_on_random_chunks() {
+        let coord = ChunkCoordinate::new(2, -1, 3);
+        for seed in 0..16 {
+            // Neighbors on some sides hide faces across the chunk boundary
+            let chunks = || {
+                [
+                    random_chunk(coord, seed),
+                    random_chunk(coord.try_delta(1, 0, 0).unwrap(), seed + 1000),
+                    random_chunk(coord.try_delta(0, -1, 0).unwrap(), seed + 2000),
+                ]
+            };
+            let naive = mesh(&make_mesher(false, true), coord, chunks());
+            let greedy = mesh(&make_mesher(true, true), coord, chunks());
+            assert_same_coverage(&naive, &greedy);
+        }
+    }
+
+    #[test]
+    fn ambient_occlusion_darkens_corners() {
+        let coord = ChunkCoordinate::new(0, 0, 0);
+        // A stone floor, with a single block standing on it
+        let chunk = || {
+            make_chunk(coord, |offset| match (offset.x, offset.y, offset.z) {
+                (_, 0, _) | (5, 1, 5) => STONE,
+                _ => AIR,
+            })
+        };
+        // Brightness of the top of the floor block at (6, 0, 5), next to the standing block,
+        // keyed by the x and z of each vertex
+        let floor_next_to_block = |data: CpuChunkVertexData| {
+            let face = data
+                .solid_opaque
+                .vtx
+                .chunks(4)
+                .find(|face| {
+                    face.iter().all(|v| {
+                        let [x, y, z] = v.position;
+                        // +Y is down in Vulkan's coordinates
+                        y == -0.5 && (5.5..=6.5).contains(&x) && (4.5..=5.5).contains(&z)
+                    })
+                })
+                .unwrap();
+            face.iter()
+                .map(|v| ((v.position[0], v.position[2]), v.brightness))
+                .collect::<Vec<_>>()
+        };
+
+        let shaded = floor_next_to_block(mesh(&make_mesher(false, true), coord, [chunk()]));
+        assert_eq!(shaded.len(), 4);
+        for ((x, _), brightness) in shaded {
+            // Only the corners touching the standing block are darkened
+            if x == 5.5 {
+                assert_eq!(brightness, AO_BRIGHTNESS[2]);
+            } else {
+                assert_eq!(brightness, 1.0);
+            }
+        }
+
+        let unshaded = floor_next_to_block(mesh(&make_mesher(false, false), coord, [chunk()]));
+        assert!(unshaded.iter().all(|&(_, brightness)| brightness == 1.0));
+    }
+
+    fn rect_tuple(rect: Rect) -> (u32, u32, u32, u32) {
+        (rect.x, rect.y, rect.w, rect.h)
+    }
+
+    fn animated(frame_count: u32, frame_duration_millis: u32) -> TextureReference {
+        TextureReference {
+            texture_name: "animated".to_string(),
+            animation: Some(TextureAnimation {
+                frame_count,
+                frame_duration_millis,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn static_texture_rect_uses_first_frame() {
+        let rect = Rect::new(10, 20, 32, 256);
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &tex("plain").unwrap())),
+            (10, 20, 32, 256)
+        );
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &animated(8, 100))),
+            (10, 20, 32, 32)
+        );
+        // More frames than rows of pixels, and no frames at all
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &animated(1000, 100))),
+            (10, 20, 32, 1)
+        );
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &animated(0, 100))),
+            (10, 20, 32, 256)
+        );
+    }
+
+    #[test]
+    fn static_texture_rect_crops_first_frame() {
+        let rect = Rect::new(10, 20, 32, 256);
+        let cropped = |left, top, width, height| TextureReference {
+            crop: Some(TextureCrop {
+                left,
+                top,
+                width,
+                height,
+            }),
+            ..animated(8, 100)
+        };
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &cropped(4, 8, 16, 16))),
+            (14, 28, 16, 16)
+        );
+        // Crops that overhang the frame are cut off at its edge
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &cropped(24, 24, 16, 16))),
+            (34, 44, 8, 8)
+        );
+        // Crops that start outside the frame are ignored
+        assert_eq!(
+            rect_tuple(static_texture_rect(rect, &cropped(4, 32, 16, 16))),
+            (10, 20, 32, 32)
+        );
+    }
+
+    #[test]
+    fn face_texture_animation() {
+        let rect = Rect::new(0, 64, 32, 256);
+        let face = FaceTexture::new(rect, &animated(8, 150));
+        assert_eq!(rect_tuple(face.rect), (0, 64, 32, 32));
+        assert_eq!(face.anim_frames, 8);
+        assert_eq!(face.anim_frame_millis, 150);
+        assert_eq!(face.anim_frame_stride, 32);
+
+        // Frames that don't evenly divide the texture leave the remainder unused
+        let face = FaceTexture::new(rect, &animated(5, 0));
+        assert_eq!(face.anim_frames, 5);
+        assert_eq!(face.anim_frame_millis, 1);
+        assert_eq!(face.anim_frame_stride, 51);
+        assert_eq!(face.rect.h, 51);
+
+        let face = FaceTexture::new(rect, &tex("plain").unwrap());
+        assert_eq!(face.anim_frames, 1);
+        assert_eq!(face.anim_frame_millis, 0);
+        assert_eq!(rect_tuple(face.rect), (0, 64, 32, 256));
+    }
+
+    #[test]
+    fn animation_stride_in_tex_space() {
+        let face = FaceTexture::new(Rect::new(0, 0, 32, 256), &animated(8, 150));
+        let (mut vtx, mut idx) = (vec![], vec![]);
+        emit_cube_face_vk(
+            vec3(0.0, 0.0, 0.0),
+            face,
+            (64, 512),
+            CubeFace::ZPlus,
+            &mut vtx,
+            &mut idx,
+            FULL_CUBE_EXTENTS,
+        );
+        assert_eq!(vtx.len(), 4);
+        for v in vtx {
+            assert_eq!(v.anim_frames, 8);
+            assert_eq!(v.anim_frame_stride, 32.0 / 512.0);
+            assert_eq!(v.tex_rect, [0.0f32, 0.0, 0.5, 32.0 / 512.0]);
+        }
+    }
+
+    #[test]
+    fn rotation_moves_texture_corners() {
+        let uvs = |rotation: TextureRotation, flip_horizontal: bool| {
+            let mut reference = tex("plain").unwrap();
+            reference.set_rotation(rotation);
+            reference.flip_horizontal = flip_horizontal;
+            let (mut vtx, mut idx) = (vec![], vec![]);
+            emit_cube_face_vk(
+                vec3(0.0, 0.0, 0.0),
+                FaceTexture::new(Rect::new(0, 0, 16, 16), &reference),
+                (16, 16),
+                CubeFace::ZPlus,
+                &mut vtx,
+                &mut idx,
+                FULL_CUBE_EXTENTS,
+            );
+            vtx.iter().map(|v| v.uv_texcoord).collect::<Vec<_>>()
+        };
+        // In the order the face's vertices are emitted
+        let [tl, bl, br, tr]: [[f32; 2]; 4] = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
+        assert_eq!(uvs(TextureRotation::Zero, false), [tl, bl, br, tr]);
+        assert_eq!(uvs(TextureRotation::Cw90, false), [bl, br, tr, tl]);
+        assert_eq!(uvs(TextureRotation::Cw180, false), [br, tr, tl, bl]);
+        assert_eq!(uvs(TextureRotation::Cw270, false), [tr, tl, bl, br]);
+        assert_eq!(uvs(TextureRotation::Zero, true), [tr, br, bl, tl]);
+        // Flipping happens before rotating
+        assert_eq!(uvs(TextureRotation::Cw90, true), [br, bl, tl, tr]);
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with `cargo test -p cuberef_client -- --ignored benchmarks`"]
+    fn benchmarks() {
+        let coord = ChunkCoordinate::new(0, 0, 0);
+        let options = microbench::Options::default();
+        for (name, chunk) in [
+            ("terrain", terrain_chunk(coord)),
+            ("random", random_chunk(coord, 0)),
+        ] {
+            let manager = ChunkManager::new();
+            manager.insert(coord, chunk);
+            let view = manager.read_lock();
+            let chunk = view.get(&coord).unwrap();
+            let naive = make_mesher(false, true);
+            microbench::bench(&options, &format!("mesh_naive_{name}"), || {
+                naive.mesh_chunk(&view, chunk)
+            });
+            let greedy = make_mesher(true, true);
+            microbench::bench(&options, &format!("mesh_greedy_{name}"), || {
+                greedy.mesh_chunk(&view, chunk)
+            });
+        }
+    }
 }
@@ -14,6 +14,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
 use std::ops::Deref;
 
 use cgmath::{ElementWise, Matrix4, Vector3};
@@ -24,11 +25,13 @@ use cuberef_core::{block_id::BlockId, coordinates::ChunkCoordinate};
 use anyhow::{ensure, Context, Result};
 
 use parking_lot::{RwLock, Mutex, RwLockReadGuard};
+use rustc_hash::FxHashMap;
 use tracy_client::span;
 
 use crate::cube_renderer::{BlockRenderer, VkChunkVertexData};
 use crate::vulkan::shaders::cube_geometry::CubeGeometryDrawCall;
 
+use super::visibility::ChunkConnectivity;
 use super::ChunkManagerView;
 
 pub(crate) struct BlockIdView<'a>(RwLockReadGuard<'a, Vec<BlockId>>);
@@ -45,6 +48,8 @@ pub(crate) struct ClientChunk {
     coord: ChunkCoordinate,
     block_ids: RwLock<Vec<BlockId>>,
     cached_vertex_data: Mutex<Option<VkChunkVertexData>>,
+    // Updated whenever the chunk is meshed; until then, assume it hides nothing
+    connectivity: Mutex<ChunkConnectivity>,
 }
 impl ClientChunk {
     pub(crate) fn from_proto(proto: rpc_proto::MapChunk) -> Result<ClientChunk> {
@@ -71,6 +76,7 @@ impl ClientChunk {
             coord,
             block_ids: RwLock::new(block_ids),
             cached_vertex_data: Mutex::new(None),
+            connectivity: Mutex::new(ChunkConnectivity::ALL),
         })
     }
 
@@ -158,6 +164,10 @@ impl ClientChunk {
     pub(crate) fn coord(&self) -> ChunkCoordinate {
         self.coord
     }
+
+    pub(crate) fn connectivity(&self) -> ChunkConnectivity {
+        *self.connectivity.lock()
+    }
 }
 
 pub(crate) fn maybe_mesh_chunk(
@@ -181,6 +191,170 @@ pub(crate) fn mesh_chunk(
     if let Some(current_chunk) = chunk_data.get(&chunk_coord) {
         let vertex_data = cube_renderer.mesh_chunk(chunk_data, current_chunk.deref())?;
         *current_chunk.cached_vertex_data.lock() = Some(vertex_data);
+        *current_chunk.connectivity.lock() = cube_renderer.chunk_connectivity(current_chunk);
     }
     Ok(())
 }
+
+/// Chunks that the server unsubscribed us from, kept (up to a fixed number of them) in case
+/// they come back into view. They aren't rendered, but the server keeps sending their updates
+/// until it evicts them.
+///
+/// If there are too many, the least recently unsubscribed chunks are evicted first.
+pub(crate) struct ChunkLru<T> {
+    // Each entry is tagged with a sequence number, which is its key in `order`
+    entries: FxHashMap<ChunkCoordinate, (u64, T)>,
+    order: BTreeMap<u64, ChunkCoordinate>,
+    next_seq: u64,
+    capacity: usize,
+}
+impl<T> ChunkLru<T> {
+    pub(crate) fn new(capacity: usize) -> ChunkLru<T> {
+        ChunkLru {
+            entries: FxHashMap::default(),
+            order: BTreeMap::new(),
+            next_seq: 0,
+            capacity,
+        }
+    }
+    /// Adds a chunk (replacing any existing entry for the same coordinate), evicting the
+    /// oldest chunks if the cache is over capacity.
+    pub(crate) fn insert(&mut self, coord: ChunkCoordinate, value: T) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some((old_seq, _)) = self.entries.insert(coord, (seq, value)) {
+            self.order.remove(&old_seq);
+        }
+        self.order.insert(seq, coord);
+        self.evict();
+    }
+    /// Returns a chunk without making it the newest entry.
+    pub(crate) fn get(&self, coord: &ChunkCoordinate) -> Option<&T> {
+        self.entries.get(coord).map(|(_, value)| value)
+    }
+    pub(crate) fn remove(&mut self, coord: &ChunkCoordinate) -> Option<T> {
+        let (seq, value) = self.entries.remove(coord)?;
+        self.order.remove(&seq);
+        Some(value)
+    }
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let (_, coord) = self.order.pop_first().unwrap();
+            self.entries.remove(&coord);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn coord(x: i32) -> ChunkCoordinate {
+        ChunkCoordinate::new(x, 0, 0)
+    }
+
+    // The x coordinates of the cached chunks, oldest first
+    fn contents<T>(lru: &ChunkLru<T>) -> Vec<i32> {
+        assert_eq!(lru.order.len(), lru.entries.len());
+        lru.order.values().map(|c| c.x).collect()
+    }
+
+    #[test]
+    fn test_chunk_lru_evicts_oldest() {
+        let mut lru = ChunkLru::new(2);
+        lru.insert(coord(1), 1);
+        lru.insert(coord(2), 2);
+        lru.insert(coord(3), 3);
+        assert_eq!(contents(&lru), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_chunk_lru_reinsert_refreshes() {
+        let mut lru = ChunkLru::new(2);
+        lru.insert(coord(1), 1);
+        lru.insert(coord(2), 2);
+        // Replaces the old value, and makes it the newest entry
+        lru.insert(coord(1), 10);
+        assert_eq!(contents(&lru), vec![2, 1]);
+        lru.insert(coord(3), 3);
+        assert_eq!(contents(&lru), vec![1, 3]);
+        assert_eq!(lru.remove(&coord(1)), Some(10));
+    }
+
+    #[test]
+    fn test_chunk_lru_remove() {
+        let mut lru = ChunkLru::new(3);
+        lru.insert(coord(1), 1);
+        lru.insert(coord(2), 2);
+        assert_eq!(lru.remove(&coord(1)), Some(1));
+        assert_eq!(lru.remove(&coord(1)), None);
+        assert_eq!(lru.remove(&coord(5)), None);
+        // The removed entry doesn't take up space
+        lru.insert(coord(3), 3);
+        lru.insert(coord(4), 4);
+        assert_eq!(contents(&lru), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_chunk_lru_get() {
+        let mut lru = ChunkLru::new(2);
+        lru.insert(coord(1), 1);
+        lru.insert(coord(2), 2);
+        assert_eq!(lru.get(&coord(1)), Some(&1));
+        assert_eq!(lru.get(&coord(3)), None);
+        // Getting an entry doesn't make it the newest
+        lru.insert(coord(3), 3);
+        assert_eq!(contents(&lru), vec![2, 3]);
+    }
+
+    fn make_chunk(coord: ChunkCoordinate) -> rpc_proto::MapChunk {
+        use cuberef_core::protocol::map::{stored_chunk::ChunkData, ChunkV1, StoredChunk};
+        rpc_proto::MapChunk {
+            chunk_coord: Some(coord.into()),
+            chunk_data: Some(StoredChunk {
+                chunk_data: Some(ChunkData::V1(ChunkV1 {
+                    block_ids: vec![0; 4096],
+                    extended_data: vec![],
+                })),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_chunk_manager_cache() {
+        let chunks = crate::game_state::ChunkManager::new();
+        chunks
+            .insert_or_update(coord(1), make_chunk(coord(1)))
+            .unwrap();
+        assert!(!chunks.unsubscribe(&coord(2)));
+
+        // Unsubscribed chunks aren't rendered, but are kept up to date
+        assert!(chunks.unsubscribe(&coord(1)));
+        assert!(!chunks.read_lock().contains_key(&coord(1)));
+        let cached = chunks.get_cached(&coord(1)).unwrap();
+        let offset = ChunkOffset { x: 1, y: 2, z: 3 };
+        let update = rpc_proto::MapDeltaUpdate {
+            block_coord: Some(coord(1).with_offset(offset).into()),
+            new_id: 5,
+        };
+        assert!(cached.apply_delta(&update).unwrap());
+
+        // Resubscribing shows the same, updated chunk again
+        assert!(chunks.resubscribe(&coord(1)));
+        assert!(!chunks.resubscribe(&coord(1)));
+        let lock = chunks.read_lock();
+        let chunk = lock.get(&coord(1)).unwrap();
+        assert!(Arc::ptr_eq(chunk, &cached));
+        assert_eq!(chunk.block_ids()[offset.as_index()].0, 5);
+        drop(lock);
+
+        // Evicted chunks are gone for good
+        assert!(chunks.unsubscribe(&coord(1)));
+        assert!(chunks.evict_cached(&coord(1)));
+        assert!(!chunks.evict_cached(&coord(1)));
+        assert!(chunks.get_cached(&coord(1)).is_none());
+        assert!(!chunks.resubscribe(&coord(1)));
+    }
+}
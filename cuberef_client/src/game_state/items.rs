@@ -55,6 +55,7 @@ pub(crate) struct ClientInventory {
     pub(crate) can_place: bool,
     pub(crate) can_take: bool,
     pub(crate) take_exact: bool,
+    slot_filters: Vec<items_proto::SlotFilter>,
 }
 impl ClientInventory {
     pub(crate) fn from_proto(
@@ -78,6 +79,7 @@ impl ClientInventory {
             can_place: proto.can_place,
             can_take: proto.can_take,
             take_exact: proto.take_exact,
+            slot_filters: proto.slot_filters.clone(),
         }
     }
     pub(crate) fn contents(&self) -> &[Option<items_proto::ItemStack>] {
@@ -86,6 +88,31 @@ impl ClientInventory {
     pub(crate) fn contents_mut(&mut self) -> &mut [Option<items_proto::ItemStack>] {
         &mut self.stacks
     }
+    /// Whether the server will accept the stack into the given slot, according to the slot's
+    /// filter. This mirrors the server's check, but the server has the final say.
+    pub(crate) fn accepts(
+        &self,
+        slot: usize,
+        stack: &items_proto::ItemStack,
+        item_defs: &ClientItemManager,
+    ) -> bool {
+        let filter = match self.slot_filters.get(slot) {
+            Some(x) => x,
+            None => return true,
+        };
+        if filter.reject_all {
+            return false;
+        }
+        if filter.accepted_groups.is_empty() && filter.accepted_items.is_empty() {
+            return true;
+        }
+        filter.accepted_items.contains(&stack.item_name)
+            || item_defs.get(&stack.item_name).is_some_and(|item| {
+                item.groups
+                    .iter()
+                    .any(|group| filter.accepted_groups.contains(group))
+            })
+    }
 }
 
 pub(crate) struct InventoryViewManager {
@@ -21,6 +21,7 @@ use std::time::Instant;
 use arc_swap::ArcSwap;
 use cgmath::{Deg, Zero};
 use cuberef_core::constants::block_groups::DEFAULT_SOLID;
+use cuberef_core::constants::chunks::MAX_CLIENT_CHUNK_CACHE_SIZE;
 use cuberef_core::coordinates::{BlockCoordinate, ChunkCoordinate, PlayerPositionUpdate};
 
 use cuberef_core::protocol;
@@ -32,7 +33,7 @@ use tracy_client::span;
 use winit::event::Event;
 
 use crate::cube_renderer::{BlockRenderer, ClientBlockTypeManager, fallback_texture};
-use crate::game_state::chunk::ClientChunk;
+use crate::game_state::chunk::{ChunkLru, ClientChunk};
 use crate::game_ui::egui_ui::EguiUi;
 use crate::game_ui::hud::GameHud;
 
@@ -47,6 +48,7 @@ pub(crate) mod items;
 pub(crate) mod physics;
 pub(crate) mod settings;
 pub(crate) mod tool_controller;
+pub(crate) mod visibility;
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct DigTapAction {
@@ -70,11 +72,13 @@ pub(crate) struct InventoryAction {
     pub(crate) destination_slot: usize,
     pub(crate) count: u32,
     pub(crate) swap: bool,
+    pub(crate) quick_move: bool,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) enum GameAction {
     Dig(DigTapAction),
+    DigStart(BlockCoordinate),
     Tap(DigTapAction),
     Place(PlaceAction),
     Inventory(InventoryAction),
@@ -85,11 +89,17 @@ pub(crate) enum GameAction {
 pub(crate) type ChunkMap = FxHashMap<ChunkCoordinate, Arc<ClientChunk>>;
 pub(crate) struct ChunkManager {
     chunks: parking_lot::RwLock<ChunkMap>,
+    // Chunks the server unsubscribed us from. Not rendered, but kept up to date until the server
+    // evicts them. Always locked after the chunks lock, if both are held.
+    unsubscribed: Mutex<ChunkLru<Arc<ClientChunk>>>,
 }
 impl ChunkManager {
     pub(crate) fn new() -> ChunkManager {
         ChunkManager {
             chunks: parking_lot::RwLock::new(FxHashMap::default()),
+            // The server evicts chunks long before this; it only guards against a server that
+            // doesn't
+            unsubscribed: Mutex::new(ChunkLru::new(MAX_CLIENT_CHUNK_CACHE_SIZE as usize)),
         }
     }
     /// Locks the chunk manager and returns a struct that can be used to access chunks in a read/write manner,
@@ -122,12 +132,44 @@ impl ChunkManager {
         };
         lock.insert(coord, Arc::new(chunk))
     }
-    pub(crate) fn remove(&self, coord: &ChunkCoordinate) -> Option<Arc<ClientChunk>> {
+    /// Stops rendering a chunk that the server unsubscribed us from, and keeps it cached until
+    /// the server evicts it, in case it comes back into view. Returns false if the chunk wasn't
+    /// present.
+    pub(crate) fn unsubscribe(&self, coord: &ChunkCoordinate) -> bool {
         let mut lock = {
             let _span = span!("Acquire global chunk lock");
             self.chunks.write()
         };
-        lock.remove(coord)
+        match lock.remove(coord) {
+            Some(chunk) => {
+                self.unsubscribed.lock().insert(*coord, chunk);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Drops a chunk from the cache of unsubscribed chunks. Returns false if it wasn't cached.
+    pub(crate) fn evict_cached(&self, coord: &ChunkCoordinate) -> bool {
+        self.unsubscribed.lock().remove(coord).is_some()
+    }
+    /// Moves a cached chunk that came back into view back into the rendered chunks. Returns
+    /// false if it wasn't cached.
+    pub(crate) fn resubscribe(&self, coord: &ChunkCoordinate) -> bool {
+        let mut lock = {
+            let _span = span!("Acquire global chunk lock");
+            self.chunks.write()
+        };
+        match self.unsubscribed.lock().remove(coord) {
+            Some(chunk) => {
+                lock.insert(*coord, chunk);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns a cached (i.e. unsubscribed and not rendered) chunk, e.g. to apply updates to it.
+    pub(crate) fn get_cached(&self, coord: &ChunkCoordinate) -> Option<Arc<ClientChunk>> {
+        self.unsubscribed.lock().get(coord).cloned()
     }
 
     pub(crate) fn insert_or_update(
@@ -142,7 +184,17 @@ impl ChunkManager {
         match lock.entry(coord) {
             std::collections::hash_map::Entry::Occupied(x) => x.get().update_from(proto),
             std::collections::hash_map::Entry::Vacant(x) => {
-                x.insert(Arc::new(ClientChunk::from_proto(proto)?));
+                // If we still have the chunk from before it was unsubscribed, reuse it so that
+                // its old mesh is shown until it's remeshed.
+                let cached = self.unsubscribed.lock().remove(&coord);
+                let chunk = match cached {
+                    Some(chunk) => {
+                        chunk.update_from(proto)?;
+                        chunk
+                    }
+                    None => Arc::new(ClientChunk::from_proto(proto)?),
+                };
+                x.insert(chunk);
                 Ok(())
             }
         }
@@ -18,10 +18,18 @@ use std::{collections::HashSet, ops::RangeInclusive, time::Duration};
 
 use cgmath::{vec3, Angle, Deg, InnerSpace, Vector3};
 use cuberef_core::{
+    constants::player_physics::{
+        EYE_TO_BTM, EYE_TO_TOP, FLY_SPEED, GRAVITY_ACCEL, JUMP_VELOCITY, PLAYER_WIDTH,
+        TERMINAL_VELOCITY, TRAVERSABLE_BUMP_HEIGHT_LANDED, TRAVERSABLE_BUMP_HEIGHT_MIDAIR,
+        WALK_SPEED,
+    },
     coordinates::BlockCoordinate,
-    protocol::blocks::{
-        block_type_def::{self, PhysicsInfo},
-        BlockTypeDef,
+    protocol::{
+        blocks::{
+            block_type_def::{self, PhysicsInfo},
+            BlockTypeDef,
+        },
+        ui::{Notification, NotificationSeverity},
     },
 };
 
@@ -34,9 +42,6 @@ use super::{
     ChunkManagerView, ClientState,
 };
 
-const PLAYER_WIDTH: f64 = 0.75;
-const EYE_TO_TOP: f64 = 0.2;
-const EYE_TO_BTM: f64 = 1.5;
 // opposite corners of an axis-aligned-bounding-box
 const PLAYER_COLLISIONBOX_CORNER_POS: Vector3<f64> =
     vec3(PLAYER_WIDTH / 2., EYE_TO_TOP, PLAYER_WIDTH / 2.);
@@ -52,13 +57,6 @@ const _PLAYER_COLLISIONBOX_CORNERS: [Vector3<f64>; 8] = [
     vec3(-PLAYER_WIDTH / 2., -EYE_TO_BTM, PLAYER_WIDTH / 2.),
     vec3(-PLAYER_WIDTH / 2., -EYE_TO_BTM, -PLAYER_WIDTH / 2.),
 ];
-const TRAVERSABLE_BUMP_HEIGHT_LANDED: f64 = 0.501;
-const TRAVERSABLE_BUMP_HEIGHT_MIDAIR: f64 = 0.2;
-const WALK_SPEED: f64 = 3.0;
-const JUMP_VELOCITY: f64 = 6.0;
-// Not quite earth gravity; tuned for a natural feeling
-const GRAVITY_ACCEL: f64 = 16.;
-const TERMINAL_VELOCITY: f64 = 90.;
 const DAMPING: f64 = GRAVITY_ACCEL / (TERMINAL_VELOCITY * TERMINAL_VELOCITY);
 
 // Two levels of epsilon. physics_eps is used to break out of
@@ -110,6 +108,9 @@ pub(crate) struct PhysicsState {
     target_az: Deg<f64>,
     target_el: Deg<f64>,
     physics_mode: PhysicsMode,
+    // Which of the other physics modes the server accepts; it moves the player back otherwise
+    allow_flight: bool,
+    allow_noclip: bool,
     // Used for the standard physics mode
     y_velocity: f64,
     landed_last_frame: bool,
@@ -125,6 +126,8 @@ impl PhysicsState {
             target_az: Deg(0.),
             target_el: Deg(0.),
             physics_mode: PhysicsMode::Standard,
+            allow_flight: false,
+            allow_noclip: false,
             y_velocity: 0.,
             landed_last_frame: false,
             last_land_height: 0.,
@@ -140,7 +143,14 @@ impl PhysicsState {
     ) -> (cgmath::Vector3<f64>, (f64, f64)) {
         let mut input = client_state.input.lock();
         if input.take_just_pressed(BoundAction::TogglePhysics) {
-            self.physics_mode = self.physics_mode.next();
+            let next = self.next_allowed_mode();
+            if next == self.physics_mode {
+                client_state.egui.lock().show_notification(&Notification {
+                    severity: NotificationSeverity::Info.into(),
+                    message: "Flying isn't allowed on this server".to_string(),
+                });
+            }
+            self.physics_mode = next;
         }
         let (x, y) = input.take_mouse_delta();
         self.target_az = Deg((self.target_az.0 + (x)).rem_euclid(360.0));
@@ -185,7 +195,7 @@ impl PhysicsState {
         let block_physics = surrounding_block.and_then(|x| x.physics_info.as_ref());
         let (mut new_yv, target) = match block_physics {
             Some(PhysicsInfo::Air(_)) => {
-                self.update_target_air(input, self.pos, delta_secs * 3.0, delta)
+                self.update_target_air(input, self.pos, delta_secs * WALK_SPEED, delta)
             }
             Some(PhysicsInfo::Fluid(fluid_data)) => {
                 let surface_test_block = get_block(
@@ -207,9 +217,9 @@ impl PhysicsState {
                 // We're in a block, possibly one we just placed. This shouldn't happen, so allow
                 // the user to jump or walk out of it (unless they would run into other solid blocks
                 // in the process)
-                self.update_target_air(input, self.pos, delta_secs * 3.0, delta)
+                self.update_target_air(input, self.pos, delta_secs * WALK_SPEED, delta)
             }
-            None => self.update_target_air(input, self.pos, delta_secs * 3.0, delta),
+            None => self.update_target_air(input, self.pos, delta_secs * WALK_SPEED, delta),
         };
 
         plot!("physics_delta", (target - self.pos).magnitude());
@@ -366,7 +376,7 @@ impl PhysicsState {
         collisions: bool,
         client_state: &ClientState,
     ) {
-        let distance = delta.as_secs_f64() * WALK_SPEED * 5.0;
+        let distance = delta.as_secs_f64() * FLY_SPEED;
 
         let mut new_pos = self.pos;
 
@@ -406,6 +416,30 @@ impl PhysicsState {
     pub(crate) fn set_position(&mut self, pos: Vector3<f64>) {
         self.pos = pos;
     }
+    /// Sets which physics modes the server accepts. If the current mode isn't one of them, the
+    /// player goes back to standard physics.
+    pub(crate) fn set_allowed_modes(&mut self, allow_flight: bool, allow_noclip: bool) {
+        self.allow_flight = allow_flight;
+        self.allow_noclip = allow_noclip;
+        if !self.is_allowed(self.physics_mode) {
+            self.physics_mode = PhysicsMode::Standard;
+        }
+    }
+    fn is_allowed(&self, mode: PhysicsMode) -> bool {
+        match mode {
+            PhysicsMode::Standard => true,
+            PhysicsMode::FlyingCollisions => self.allow_flight,
+            PhysicsMode::Noclip => self.allow_noclip,
+        }
+    }
+    fn next_allowed_mode(&self) -> PhysicsMode {
+        let mut mode = self.physics_mode.next();
+        // Terminates, since standard physics is always allowed
+        while !self.is_allowed(mode) {
+            mode = mode.next();
+        }
+        mode
+    }
     pub(crate) fn angle(&self) -> (f64, f64) {
         (self.az.0, self.el.0)
     }
@@ -6,12 +6,41 @@ use serde::{Deserialize, Serialize};
 
 const SETTINGS_RON_FILE: &str = "settings.ron";
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub(crate) const MAX_VIEW_DISTANCE: u32 = 64;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(default)]
 pub(crate) struct GameSettings {
     pub(crate) input: KeybindSettings,
     pub(crate) last_hostname: String,
     pub(crate) last_username: String,
+    // Name of the texture pack to use, or empty to use only the server's textures
+    pub(crate) texture_pack: String,
+    // How far away chunks should be loaded, in chunks. The server may limit this.
+    pub(crate) view_distance: u32,
+    // How many out-of-view chunks the server should let us keep, so they needn't be sent again
+    // when they come back into view. The server may limit this.
+    pub(crate) chunk_cache_size: u32,
+    // Whether to merge adjacent block faces with the same texture into larger quads. Takes
+    // effect on the next connection.
+    pub(crate) greedy_meshing: bool,
+    // Whether to darken block corners that are surrounded by solid blocks. Takes effect on the
+    // next connection.
+    pub(crate) ambient_occlusion: bool,
+}
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            input: KeybindSettings::default(),
+            last_hostname: String::new(),
+            last_username: String::new(),
+            texture_pack: String::new(),
+            view_distance: 30,
+            chunk_cache_size: 2048,
+            greedy_meshing: false,
+            ambient_occlusion: true,
+        }
+    }
 }
 impl GameSettings {
     pub(crate) fn save_to_disk(&self) -> Result<()> {
@@ -20,6 +20,7 @@ use cgmath::{Angle, Deg};
 use cuberef_core::block_id::BlockId;
 
 use cuberef_core::constants::items::default_item_interaction_rules;
+use cuberef_core::constants::player_interaction::REACH_DISTANCE;
 use cuberef_core::coordinates::BlockCoordinate;
 
 use cuberef_core::protocol::blocks::BlockTypeDef;
@@ -40,6 +41,8 @@ struct DigState {
     item_dig_behavior: Option<DigBehavior>,
     // Current durability (of selected block) for scaled time
     base_durability: f64,
+    // Whether the server has been told that digging started
+    start_reported: bool,
 }
 
 // Computes the block that the player is pointing at, tracks the progress of digging, etc.
@@ -116,11 +119,24 @@ impl ToolController {
                 id: BlockId(block_def.id),
                 item_dig_behavior: Some(behavior),
                 base_durability: block_def.base_dig_time,
+                start_reported: false,
             });
         } else if input.is_pressed(BoundAction::Dig) {
             if let Some(dig_progress) = &mut self.dig_progress {
+                // The server checks that timed digs take long enough, so it needs to know
+                // when digging started. Progress starts counting on the next frame.
+                let starting = !dig_progress.start_reported
+                    && matches!(
+                        dig_progress.item_dig_behavior,
+                        Some(DigBehavior::ConstantTime(_)) | Some(DigBehavior::ScaledTime(_))
+                    );
+                if starting {
+                    dig_progress.start_reported = true;
+                    action = Some(GameAction::DigStart(pointee));
+                }
                 // The pointee is the same, update dig progress
                 let delta_progress = match dig_progress.item_dig_behavior {
+                    _ if starting => 0.,
                     None => 0.,
                     Some(DigBehavior::InstantDig(_)) => 0.,
                     Some(DigBehavior::InstantDigOneshot(_)) => {
@@ -270,7 +286,7 @@ pub(crate) struct ToolState {
     pub(crate) action: Option<GameAction>,
 }
 
-const POINTEE_DISTANCE: f64 = 6.;
+const POINTEE_DISTANCE: f64 = REACH_DISTANCE;
 // line_drawing seems to have problems when using Center rather than Corner
 // Fudge it manually
 // TODO file a bug for that crate
@@ -0,0 +1,347 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Works out which chunks might be visible from the camera, so that the others needn't be drawn.
+//!
+//! Chunks outside the view frustum are skipped, as are chunks that can't be seen through
+//! the see-through blocks (e.g. air) of the chunks between them and the camera.
+
+use std::collections::VecDeque;
+
+use cgmath::{vec3, Matrix, Matrix4, Vector3, Vector4};
+use cuberef_core::coordinates::{BlockCoordinate, ChunkCoordinate, ChunkOffset};
+use rustc_hash::FxHashSet;
+
+use crate::cube_renderer::CubeFace;
+
+/// The region of space the camera can see, as six planes. Points are inside if they're on the
+/// positive side of every plane.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Frustum {
+    planes: [Vector4<f64>; 6],
+}
+impl Frustum {
+    /// Extracts the frustum from a view-projection matrix, for Vulkan's clip space (where depth
+    /// ranges from 0 to 1).
+    pub(crate) fn from_view_proj(view_proj: Matrix4<f32>) -> Frustum {
+        let m: Matrix4<f64> = view_proj.cast().unwrap();
+        let [x, y, z, w] = [m.row(0), m.row(1), m.row(2), m.row(3)];
+        Frustum {
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    /// Returns false if no part of the axis-aligned box is inside the frustum. This may return
+    /// true for some boxes that are just outside it, near its corners.
+    pub(crate) fn intersects_box(&self, min: Vector3<f64>, max: Vector3<f64>) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal
+            let corner = vec3(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.x * corner.x + plane.y * corner.y + plane.z * corner.z + plane.w >= 0.0
+        })
+    }
+}
+
+/// The box a chunk's blocks occupy, relative to the player, in Vulkan coordinates (+Y is down),
+/// matching the transform in [super::chunk::ClientChunk::make_draw_call].
+pub(crate) fn chunk_bounds(
+    coord: ChunkCoordinate,
+    player_position: Vector3<f64>,
+) -> (Vector3<f64>, Vector3<f64>) {
+    let min = vec3(
+        16. * coord.x as f64 - 0.5,
+        16. * coord.y as f64 - 0.5,
+        16. * coord.z as f64 - 0.5,
+    ) - player_position;
+    let max = min + vec3(16., 16., 16.);
+    (vec3(min.x, -max.y, min.z), vec3(max.x, -min.y, max.z))
+}
+
+/// Which faces of a chunk can be seen from which other faces, looking only through the chunk's
+/// see-through blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ChunkConnectivity(u64);
+impl ChunkConnectivity {
+    /// Every face can see every other face, e.g. for a chunk that's all air, or one that we
+    /// don't know the contents of yet.
+    pub(crate) const ALL: ChunkConnectivity = ChunkConnectivity((1 << 36) - 1);
+    /// No face can see any other face, e.g. for a chunk that's entirely solid.
+    pub(crate) const NONE: ChunkConnectivity = ChunkConnectivity(0);
+
+    /// Flood-fills the see-through blocks of a chunk, connecting every pair of faces that the
+    /// same region of see-through blocks touches.
+    pub(crate) fn compute(see_through: impl Fn(ChunkOffset) -> bool) -> ChunkConnectivity {
+        let open: Vec<bool> = (0..4096)
+            .map(|i| see_through(ChunkOffset::from_index(i)))
+            .collect();
+        let mut filled = vec![false; 4096];
+        let mut stack = Vec::new();
+        let mut result = ChunkConnectivity::NONE;
+        for start in 0..4096 {
+            if filled[start] || !open[start] {
+                continue;
+            }
+            filled[start] = true;
+            stack.push(start);
+            // Bitmask of the faces that this region touches
+            let mut touched = 0u8;
+            while let Some(index) = stack.pop() {
+                let offset = ChunkOffset::from_index(index);
+                for face in CubeFace::ALL {
+                    let (dx, dy, dz) = face.normal();
+                    match offset.try_delta(dx as i8, dy as i8, dz as i8) {
+                        Some(neighbor) => {
+                            let neighbor = neighbor.as_index();
+                            if open[neighbor] && !filled[neighbor] {
+                                filled[neighbor] = true;
+                                stack.push(neighbor);
+                            }
+                        }
+                        // This block is on the edge of the chunk
+                        None => touched |= 1 << face as u8,
+                    }
+                }
+            }
+            for a in CubeFace::ALL {
+                for b in CubeFace::ALL {
+                    if touched & (1 << a as u8) != 0 && touched & (1 << b as u8) != 0 {
+                        result.0 |= Self::bit(a, b);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Whether something entering the chunk through one face can leave through the other.
+    pub(crate) fn connects(self, a: CubeFace, b: CubeFace) -> bool {
+        self.0 & Self::bit(a, b) != 0
+    }
+
+    fn bit(a: CubeFace, b: CubeFace) -> u64 {
+        1 << (6 * a as u64 + b as u64)
+    }
+}
+
+/// The smallest box (with inclusive corners) containing all of the given chunks.
+pub(crate) fn chunk_extent(
+    coords: impl IntoIterator<Item = ChunkCoordinate>,
+) -> Option<(ChunkCoordinate, ChunkCoordinate)> {
+    coords.into_iter().fold(None, |extent, coord| match extent {
+        None => Some((coord, coord)),
+        Some((min, max)) => Some((
+            ChunkCoordinate::new(min.x.min(coord.x), min.y.min(coord.y), min.z.min(coord.z)),
+            ChunkCoordinate::new(max.x.max(coord.x), max.y.max(coord.y), max.z.max(coord.z)),
+        )),
+    })
+}
+
+/// Finds the chunks that might be visible from the player's position, roughly nearest first.
+///
+/// This walks outward from the player's chunk, only crossing into chunks that are in the frustum,
+/// never heading back towards the player, and only leaving a chunk through a face that can be
+/// seen from the face it was entered through. Chunks outside `bounds` (inclusive; usually the
+/// extent of the loaded chunks) aren't visited. `connectivity` should return
+/// [ChunkConnectivity::ALL] for chunks that aren't loaded, so that they don't hide anything.
+pub(crate) fn visible_chunks(
+    frustum: &Frustum,
+    player_position: Vector3<f64>,
+    bounds: (ChunkCoordinate, ChunkCoordinate),
+    connectivity: impl Fn(ChunkCoordinate) -> ChunkConnectivity,
+) -> Vec<ChunkCoordinate> {
+    struct Step {
+        coord: ChunkCoordinate,
+        // The face of this chunk that we came in through, or None for the player's chunk
+        entered_through: Option<CubeFace>,
+        // Bitmask of the directions travelled to get here
+        directions: u8,
+    }
+    let in_bounds = |coord: ChunkCoordinate| {
+        (bounds.0.x..=bounds.1.x).contains(&coord.x)
+            && (bounds.0.y..=bounds.1.y).contains(&coord.y)
+            && (bounds.0.z..=bounds.1.z).contains(&coord.z)
+    };
+
+    let start = match BlockCoordinate::try_from(player_position) {
+        Ok(x) => x.chunk(),
+        Err(_) => return vec![],
+    };
+    let mut visible = vec![];
+    let mut visited = FxHashSet::default();
+    let mut queue = VecDeque::new();
+    visited.insert(start);
+    queue.push_back(Step {
+        coord: start,
+        entered_through: None,
+        directions: 0,
+    });
+    while let Some(step) = queue.pop_front() {
+        visible.push(step.coord);
+        let connectivity = connectivity(step.coord);
+        for face in CubeFace::ALL {
+            if step.directions & (1 << face.opposite() as u8) != 0 {
+                continue;
+            }
+            if let Some(entered_through) = step.entered_through {
+                if !connectivity.connects(entered_through, face) {
+                    continue;
+                }
+            }
+            let (dx, dy, dz) = face.normal();
+            let next = match step.coord.try_delta(dx, dy, dz) {
+                Some(x) => x,
+                None => continue,
+            };
+            if !in_bounds(next) || visited.contains(&next) {
+                continue;
+            }
+            let (min, max) = chunk_bounds(next, player_position);
+            if !frustum.intersects_box(min, max) {
+                continue;
+            }
+            visited.insert(next);
+            queue.push_back(Step {
+                coord: next,
+                entered_through: Some(face.opposite()),
+                directions: step.directions | (1 << face as u8),
+            });
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg};
+    use rustc_hash::FxHashMap;
+
+    use super::*;
+
+    // Looking towards -Z from the origin
+    fn frustum() -> Frustum {
+        Frustum::from_view_proj(perspective(Deg(45.0), 1.0, 0.01, 1000.))
+    }
+
+    #[test]
+    fn frustum_culls_boxes() {
+        let frustum = frustum();
+        let cube = |x: f64, y: f64, z: f64| (vec3(x, y, z), vec3(x + 1., y + 1., z + 1.));
+        let visible = |(min, max)| frustum.intersects_box(min, max);
+        assert!(visible(cube(-0.5, -0.5, -10.)));
+        // Containing the camera
+        assert!(visible(cube(-0.5, -0.5, -0.5)));
+        // Behind the camera
+        assert!(!visible(cube(-0.5, -0.5, 10.)));
+        // Off to the sides
+        assert!(!visible(cube(20., -0.5, -10.)));
+        assert!(!visible(cube(-0.5, -20., -10.)));
+        // Past the far plane
+        assert!(!visible(cube(-0.5, -0.5, -2000.)));
+        // A large box that only pokes into the view
+        assert!(visible((vec3(3., -50., -10.), vec3(50., 50., -9.))));
+    }
+
+    #[test]
+    fn connectivity_of_uniform_chunks() {
+        assert_eq!(ChunkConnectivity::compute(|_| true), ChunkConnectivity::ALL);
+        assert_eq!(
+            ChunkConnectivity::compute(|_| false),
+            ChunkConnectivity::NONE
+        );
+    }
+
+    #[test]
+    fn connectivity_through_walls_and_tunnels() {
+        // A solid wall at x = 8 splits the chunk in two
+        let wall = ChunkConnectivity::compute(|offset| offset.x != 8);
+        assert!(!wall.connects(CubeFace::XMinus, CubeFace::XPlus));
+        assert!(wall.connects(CubeFace::XMinus, CubeFace::YPlus));
+        assert!(wall.connects(CubeFace::XPlus, CubeFace::ZMinus));
+        assert!(wall.connects(CubeFace::YPlus, CubeFace::YMinus));
+
+        // A solid chunk with a tunnel along Z
+        let tunnel = ChunkConnectivity::compute(|offset| offset.x == 3 && offset.y == 4);
+        assert!(tunnel.connects(CubeFace::ZMinus, CubeFace::ZPlus));
+        assert!(tunnel.connects(CubeFace::ZPlus, CubeFace::ZMinus));
+        assert!(!tunnel.connects(CubeFace::ZMinus, CubeFace::XMinus));
+        assert!(!tunnel.connects(CubeFace::YPlus, CubeFace::YMinus));
+
+        // A pocket of air that doesn't reach any face
+        let pocket = ChunkConnectivity::compute(|offset| {
+            (4..8).contains(&offset.x) && (4..8).contains(&offset.y) && (4..8).contains(&offset.z)
+        });
+        assert_eq!(pocket, ChunkConnectivity::NONE);
+    }
+
+    const BOUNDS: (ChunkCoordinate, ChunkCoordinate) = (
+        ChunkCoordinate {
+            x: -4,
+            y: -4,
+            z: -4,
+        },
+        ChunkCoordinate { x: 4, y: 4, z: 4 },
+    );
+    // In the middle of chunk (0, 0, 0)
+    const PLAYER: Vector3<f64> = vec3(7.5, 7.5, 7.5);
+
+    #[test]
+    fn visible_chunks_in_open_space() {
+        let visible = visible_chunks(&frustum(), PLAYER, BOUNDS, |_| ChunkConnectivity::ALL);
+        assert_eq!(visible[0], ChunkCoordinate::new(0, 0, 0));
+        assert!(visible.contains(&ChunkCoordinate::new(0, 0, -4)));
+        assert!(visible.contains(&ChunkCoordinate::new(1, 1, -4)));
+        // Behind the camera
+        assert!(!visible.contains(&ChunkCoordinate::new(0, 0, 2)));
+        // Outside the bounds
+        assert!(!visible.contains(&ChunkCoordinate::new(0, 0, -5)));
+    }
+
+    #[test]
+    fn solid_chunks_hide_chunks_behind_them() {
+        let solid = |_| ChunkConnectivity::NONE;
+        let visible = visible_chunks(&frustum(), PLAYER, BOUNDS, solid);
+        // The neighbors' faces are visible, but nothing past them
+        assert!(visible.contains(&ChunkCoordinate::new(0, 0, -1)));
+        assert!(!visible.contains(&ChunkCoordinate::new(0, 0, -2)));
+        assert!(!visible.contains(&ChunkCoordinate::new(0, 0, -4)));
+    }
+
+    #[test]
+    fn tunnels_let_chunks_be_seen() {
+        // Solid, apart from a tunnel heading away from the camera along -Z
+        let mut chunks = FxHashMap::default();
+        let tunnel = ChunkConnectivity::compute(|offset| offset.x == 7 && offset.y == 7);
+        for z in -3..=0 {
+            chunks.insert(ChunkCoordinate::new(0, 0, z), tunnel);
+        }
+        let visible = visible_chunks(&frustum(), PLAYER, BOUNDS, |coord| {
+            chunks
+                .get(&coord)
+                .copied()
+                .unwrap_or(ChunkConnectivity::NONE)
+        });
+        for z in -4..=0 {
+            assert!(visible.contains(&ChunkCoordinate::new(0, 0, z)));
+        }
+        // Beside the tunnel, so hidden by its walls
+        assert!(!visible.contains(&ChunkCoordinate::new(1, 0, -2)));
+        assert!(!visible.contains(&ChunkCoordinate::new(0, 1, -3)));
+    }
+}
@@ -1,12 +1,12 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use cuberef_core::protocol::items::ItemStack;
-use cuberef_core::protocol::ui::{self as proto, PopupResponse};
+use cuberef_core::protocol::ui::{self as proto, PopupResponse, PopupUpdate};
 use cuberef_core::protocol::{items::item_def::QuantityType, ui::PopupDescription};
 use egui::{vec2, Button, Color32, Id, Sense, Stroke, TextEdit, TextStyle, TextureId};
-use log::warn;
 use parking_lot::MutexGuard;
 use rustc_hash::FxHashMap;
 use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, sync::Arc, usize};
 
 use crate::game_state::items::InventoryViewManager;
@@ -19,12 +19,22 @@ use crate::{
 };
 
 use super::hud::render_number;
-use super::{get_texture, FRAME_UNSELECTED};
+use super::{get_texture, wear_bar_color, wear_fraction, FRAME_UNSELECTED, UNKNOWN_TEXTURE};
+
+// How long a slot is highlighted after it refuses the stack the user tried to put into it
+const REJECTED_DROP_HIGHLIGHT: Duration = Duration::from_millis(400);
+// Size of popup images that don't specify one
+const DEFAULT_IMAGE_SIZE: f32 = 32.0;
+// How long notifications from the server stay on screen, and how many can be shown at once
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+const MAX_TOASTS: usize = 5;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum InvClickType {
     LeftClick,
     RightClick,
+    // Left click with shift held, to quickly move the stack to another view
+    ShiftClick,
 }
 
 pub(crate) struct EguiUi {
@@ -39,11 +49,19 @@ pub(crate) struct EguiUi {
 
     visible_popups: Vec<PopupDescription>,
 
+    // Current values of the form elements in each popup, keyed by (popup id, element key)
     text_fields: FxHashMap<(u64, String), String>,
+    checkboxes: FxHashMap<(u64, String), bool>,
+    dropdowns: FxHashMap<(u64, String), u32>,
+    sliders: FxHashMap<(u64, String), f64>,
 
     pub(crate) inventory_manipulation_view_id: Option<u64>,
     last_mouse_position: egui::Pos2,
     stack_carried_by_mouse_offset: (f32, f32),
+    // (view id, slot, time) of the last slot whose filter refused the carried stack
+    rejected_drop: Option<(u64, usize, Instant)>,
+    // Notifications from the server, and when each was received
+    toasts: Vec<(proto::Notification, Instant)>,
 }
 impl EguiUi {
     pub(crate) fn new(
@@ -61,9 +79,14 @@ impl EguiUi {
             scale: 1.0,
             visible_popups: vec![],
             text_fields: FxHashMap::default(),
+            checkboxes: FxHashMap::default(),
+            dropdowns: FxHashMap::default(),
+            sliders: FxHashMap::default(),
             inventory_manipulation_view_id: None,
             last_mouse_position: egui::Pos2 { x: 0., y: 0. },
             stack_carried_by_mouse_offset: (0., 0.),
+            rejected_drop: None,
+            toasts: vec![],
         }
     }
     pub(crate) fn wants_draw(&self) -> bool {
@@ -136,22 +159,29 @@ impl EguiUi {
         }
     }
 
-    fn get_text_fields(&self, popup_id: u64) -> HashMap<String, String> {
-        self.text_fields
-            .iter()
-            .filter(|((popup, _), _)| popup == &popup_id)
-            .map(|((_, form_key), value)| (form_key.clone(), value.clone()))
-            .collect()
-    }
-    fn clear_text_fields(&mut self, popup: &PopupDescription) {
-        for field in popup.element.iter() {
-            if let Some(proto::ui_element::Element::TextField(text_field)) = &field.element {
-                // ugh, clone
-                self.text_fields
-                    .remove(&(popup.popup_id, text_field.key.clone()));
-            }
+    fn make_popup_response(
+        &self,
+        popup_id: u64,
+        closed: bool,
+        clicked_button: String,
+    ) -> PopupResponse {
+        PopupResponse {
+            popup_id,
+            closed,
+            clicked_button,
+            text_fields: form_values(&self.text_fields, popup_id),
+            checkboxes: form_values(&self.checkboxes, popup_id),
+            dropdowns: form_values(&self.dropdowns, popup_id),
+            sliders: form_values(&self.sliders, popup_id),
         }
     }
+    fn clear_form_values(&mut self, popup: &PopupDescription) {
+        let popup_id = popup.popup_id;
+        self.text_fields.retain(|(id, _), _| *id != popup_id);
+        self.checkboxes.retain(|(id, _), _| *id != popup_id);
+        self.dropdowns.retain(|(id, _), _| *id != popup_id);
+        self.sliders.retain(|(id, _), _| *id != popup_id);
+    }
 
     fn draw_popup(
         &mut self,
@@ -171,67 +201,35 @@ impl EguiUi {
 
                 let mut clicked_button = None;
                 for element in &popup.element {
-                    match &element.element {
-                        Some(proto::ui_element::Element::Label(label)) => {
-                            ui.label(label);
-                        }
-                        Some(proto::ui_element::Element::TextField(text_field)) => {
-                            let value = self
-                                .text_fields
-                                .entry((popup.popup_id, text_field.key.clone()))
-                                .or_insert(text_field.initial.clone());
-                            // todo support multiline, other styling
-                            let editor = egui::TextEdit::singleline(value);
-                            ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
-                                let label = ui.label(text_field.label.clone());
-                                ui.add_enabled(text_field.enabled, editor)
-                                    .labelled_by(label.id);
-                            });
-                        }
-                        Some(proto::ui_element::Element::Button(button_def)) => {
-                            let button = egui::Button::new(button_def.label.clone());
-                            if ui.add_enabled(button_def.enabled, button).clicked() {
-                                clicked_button = Some(button_def.key.clone());
-                            }
-                        }
-                        Some(proto::ui_element::Element::Inventory(inventory)) => {
-                            let mut inventory_manager = client_state.inventories.lock();
-
-                            self.draw_inventory_view(
-                                ui,
-                                inventory.inventory_key,
-                                &mut inventory_manager,
-                                atlas_texture_id,
-                                client_state,
-                            );
-                        }
-                        None => {
-                            ui.label("Invalid/missing popup item entry");
-                        }
-                    }
+                    self.draw_element(
+                        ui,
+                        popup.popup_id,
+                        element,
+                        atlas_texture_id,
+                        client_state,
+                        &mut clicked_button,
+                    );
                 }
                 if let Some(clicked_button) = clicked_button {
                     send_event(
                         client_state,
-                        GameAction::PopupResponse(PopupResponse {
-                            popup_id: popup.popup_id,
-                            closed: false,
+                        GameAction::PopupResponse(self.make_popup_response(
+                            popup.popup_id,
+                            false,
                             clicked_button,
-                            text_fields: self.get_text_fields(popup.popup_id),
-                        }),
+                        )),
                     );
                 }
                 if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
                     send_event(
                         client_state,
-                        GameAction::PopupResponse(PopupResponse {
-                            popup_id: popup.popup_id,
-                            closed: true,
-                            clicked_button: "".to_string(),
-                            text_fields: self.get_text_fields(popup.popup_id),
-                        }),
+                        GameAction::PopupResponse(self.make_popup_response(
+                            popup.popup_id,
+                            true,
+                            "".to_string(),
+                        )),
                     );
-                    self.clear_text_fields(popup);
+                    self.clear_form_values(popup);
                     ControlFlow::Break(())
                 } else {
                     ControlFlow::Continue(())
@@ -241,10 +239,248 @@ impl EguiUi {
             .unwrap_or(ControlFlow::Continue(()))
     }
 
+    fn draw_element(
+        &mut self,
+        ui: &mut egui::Ui,
+        popup_id: u64,
+        element: &proto::UiElement,
+        atlas_texture_id: TextureId,
+        client_state: &ClientState,
+        clicked_button: &mut Option<String>,
+    ) {
+        match &element.element {
+            Some(proto::ui_element::Element::Label(label)) => {
+                ui.label(label);
+            }
+            Some(proto::ui_element::Element::KeyedLabel(label)) => {
+                ui.label(&label.text);
+            }
+            Some(proto::ui_element::Element::TextField(text_field)) => {
+                let value = self
+                    .text_fields
+                    .entry((popup_id, text_field.key.clone()))
+                    .or_insert(text_field.initial.clone());
+                // todo support other styling
+                let editor = if text_field.multiline {
+                    egui::TextEdit::multiline(value)
+                } else {
+                    egui::TextEdit::singleline(value)
+                };
+                ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
+                    let label = ui.label(text_field.label.clone());
+                    ui.add_enabled(text_field.enabled, editor)
+                        .labelled_by(label.id);
+                });
+            }
+            Some(proto::ui_element::Element::Button(button_def)) => {
+                let button = egui::Button::new(button_def.label.clone());
+                if ui.add_enabled(button_def.enabled, button).clicked() {
+                    *clicked_button = Some(button_def.key.clone());
+                }
+            }
+            Some(proto::ui_element::Element::Inventory(inventory)) => {
+                let mut inventory_manager = client_state.inventories.lock();
+
+                self.draw_inventory_view(
+                    ui,
+                    inventory.inventory_key,
+                    &mut inventory_manager,
+                    atlas_texture_id,
+                    client_state,
+                );
+            }
+            Some(proto::ui_element::Element::Checkbox(checkbox)) => {
+                let value = self
+                    .checkboxes
+                    .entry((popup_id, checkbox.key.clone()))
+                    .or_insert(checkbox.initial);
+                ui.add_enabled(
+                    checkbox.enabled,
+                    egui::Checkbox::new(value, checkbox.label.clone()),
+                );
+            }
+            Some(proto::ui_element::Element::Dropdown(dropdown)) => {
+                let value = self
+                    .dropdowns
+                    .entry((popup_id, dropdown.key.clone()))
+                    .or_insert(dropdown.initial);
+                ui.add_enabled_ui(dropdown.enabled, |ui| {
+                    ui.horizontal(|ui| {
+                        if !dropdown.label.is_empty() {
+                            ui.label(dropdown.label.clone());
+                        }
+                        egui::ComboBox::from_id_source((popup_id, &dropdown.key))
+                            .selected_text(
+                                dropdown
+                                    .options
+                                    .get(*value as usize)
+                                    .cloned()
+                                    .unwrap_or_default(),
+                            )
+                            .show_ui(ui, |ui| {
+                                for (i, option) in dropdown.options.iter().enumerate() {
+                                    ui.selectable_value(value, i as u32, option);
+                                }
+                            });
+                    });
+                });
+            }
+            Some(proto::ui_element::Element::Slider(slider)) => {
+                let value = self
+                    .sliders
+                    .entry((popup_id, slider.key.clone()))
+                    .or_insert(slider.initial);
+                let mut widget = egui::Slider::new(value, slider.min..=slider.max);
+                if !slider.label.is_empty() {
+                    widget = widget.text(slider.label.clone());
+                }
+                if slider.step > 0.0 {
+                    widget = widget.step_by(slider.step);
+                }
+                ui.add_enabled(slider.enabled, widget);
+            }
+            Some(proto::ui_element::Element::ProgressBar(progress_bar)) => {
+                let mut widget = egui::ProgressBar::new(progress_bar.progress.clamp(0.0, 1.0));
+                if !progress_bar.label.is_empty() {
+                    widget = widget.text(progress_bar.label.clone());
+                }
+                ui.add(widget);
+            }
+            Some(proto::ui_element::Element::Image(image)) => {
+                let unknown = *self.atlas_coords.get(UNKNOWN_TEXTURE).unwrap();
+                let pixel_rect = match &image.source {
+                    Some(proto::image::Source::ItemName(item_name)) => get_texture(
+                        &ItemStack {
+                            item_name: item_name.clone(),
+                            ..Default::default()
+                        },
+                        &self.atlas_coords,
+                        &self.item_defs,
+                    ),
+                    Some(proto::image::Source::TextureName(texture_name)) => self
+                        .atlas_coords
+                        .get(texture_name)
+                        .copied()
+                        .unwrap_or(unknown),
+                    None => unknown,
+                };
+                let size = |x: u32| {
+                    if x == 0 {
+                        DEFAULT_IMAGE_SIZE
+                    } else {
+                        x as f32
+                    }
+                };
+                ui.add(
+                    egui::Image::new(
+                        atlas_texture_id,
+                        vec2(size(image.width), size(image.height)),
+                    )
+                    .uv(self.pixel_rect_to_uv(pixel_rect)),
+                );
+            }
+            Some(proto::ui_element::Element::Layout(layout)) => {
+                let draw_contents = |ui: &mut egui::Ui| {
+                    for element in &layout.element {
+                        self.draw_element(
+                            ui,
+                            popup_id,
+                            element,
+                            atlas_texture_id,
+                            client_state,
+                            clicked_button,
+                        );
+                    }
+                };
+                if layout.horizontal {
+                    ui.horizontal(draw_contents);
+                } else {
+                    ui.vertical(draw_contents);
+                }
+            }
+            None => {
+                ui.label("Invalid/missing popup item entry");
+            }
+        }
+    }
+
     pub(crate) fn show_popup(&mut self, desc: &PopupDescription) {
         self.visible_popups.push(desc.clone())
     }
 
+    /// Replaces a popup that's already showing (or the inventory popup) with a new description.
+    /// Text fields and other form elements keep their values, since they're keyed by popup ID.
+    pub(crate) fn update_popup(&mut self, desc: &PopupDescription) {
+        if let Some(popup) = self
+            .visible_popups
+            .iter_mut()
+            .find(|x| x.popup_id == desc.popup_id)
+        {
+            *popup = desc.clone();
+        } else if self
+            .inventory_view
+            .as_ref()
+            .is_some_and(|x| x.popup_id == desc.popup_id)
+        {
+            self.inventory_view = Some(desc.clone());
+        }
+    }
+
+    /// Changes individual elements of a popup that's already showing (or the inventory popup).
+    pub(crate) fn update_popup_elements(&mut self, update: &PopupUpdate) {
+        let popup = match self
+            .visible_popups
+            .iter_mut()
+            .chain(self.inventory_view.as_mut())
+            .find(|x| x.popup_id == update.popup_id)
+        {
+            Some(x) => x,
+            // The popup may have been closed while the update was on its way
+            None => return,
+        };
+        for element_update in &update.update {
+            if let Err(e) = apply_element_update(&mut popup.element, element_update) {
+                log::warn!("Couldn't update popup {}: {}", update.popup_id, e);
+            }
+        }
+    }
+
+    /// Shows a notification in the corner of the screen for a few seconds.
+    pub(crate) fn show_notification(&mut self, notification: &proto::Notification) {
+        if self.toasts.len() >= MAX_TOASTS {
+            self.toasts.remove(0);
+        }
+        self.toasts.push((notification.clone(), Instant::now()));
+    }
+
+    pub(crate) fn has_toasts(&self) -> bool {
+        !self.toasts.is_empty()
+    }
+
+    /// Draws notifications that are still current, and forgets the rest.
+    pub(crate) fn draw_toasts(&mut self, ctx: &egui::Context) {
+        self.toasts
+            .retain(|(_, received)| received.elapsed() < TOAST_DURATION);
+        if self.toasts.is_empty() {
+            return;
+        }
+        egui::Area::new("toasts")
+            .anchor(egui::Align2::RIGHT_TOP, vec2(-8.0, 8.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                for (notification, _) in &self.toasts {
+                    let color = match notification.severity() {
+                        proto::NotificationSeverity::Info => Color32::WHITE,
+                        proto::NotificationSeverity::Warning => Color32::YELLOW,
+                        proto::NotificationSeverity::Error => Color32::LIGHT_RED,
+                    };
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.colored_label(color, &notification.message);
+                    });
+                }
+            });
+    }
+
     pub(crate) fn get_carried_itemstack(
         &self,
         vk_ctx: &VulkanContext,
@@ -354,8 +590,24 @@ impl EguiUi {
                 );
 
                 let frame_response = ui.put(frame_rect, frame_image);
+                if self
+                    .rejected_drop
+                    .is_some_and(|(rejected_view, rejected_index, time)| {
+                        rejected_view == view_id
+                            && rejected_index == index
+                            && time.elapsed() < REJECTED_DROP_HIGHLIGHT
+                    })
+                {
+                    ui.painter().rect_filled(
+                        frame_rect,
+                        0.0,
+                        Color32::from_rgba_unmultiplied(192, 0, 0, 96),
+                    );
+                }
 
-                if frame_response.clicked() {
+                if frame_response.clicked() && ui.input(|i| i.modifiers.shift) {
+                    clicked_index = Some((index, InvClickType::ShiftClick));
+                } else if frame_response.clicked() {
                     clicked_index = Some((index, InvClickType::LeftClick));
                     self.stack_carried_by_mouse_offset = (-frame_size.x / 2., -frame_size.y / 2.)
                 } else if frame_response.clicked_by(egui::PointerButton::Secondary) {
@@ -412,7 +664,23 @@ impl EguiUi {
                             }
                         }
                         QuantityType::Wear(_) => {
-                            warn!("TODO render wear bar");
+                            if let Some(fraction) = wear_fraction(stack, &self.item_defs) {
+                                let bar_rect = egui::Rect::from_min_size(
+                                    drawing_rect.left_bottom() + vec2(4.0, -8.0),
+                                    vec2(drawing_rect.width() - 8.0, 4.0),
+                                );
+                                let filled_rect = egui::Rect::from_min_size(
+                                    bar_rect.min,
+                                    vec2(bar_rect.width() * fraction, bar_rect.height()),
+                                );
+                                let [r, g, b] = wear_bar_color(fraction);
+                                ui.painter().rect_filled(bar_rect, 0.0, Color32::BLACK);
+                                ui.painter().rect_filled(
+                                    filled_rect,
+                                    0.0,
+                                    Color32::from_rgb(r, g, b),
+                                );
+                            }
                         }
                     }
                 }
@@ -420,6 +688,17 @@ impl EguiUi {
         }
         if let Some(manipulation_view) = self.inventory_manipulation_view_id {
             if let Some((index, click_type)) = clicked_index {
+                if click_type != InvClickType::ShiftClick
+                    && rejects_carried_stack(
+                        view_id,
+                        index,
+                        inventory_manager,
+                        manipulation_view,
+                        &self.item_defs,
+                    )
+                {
+                    self.rejected_drop = Some((view_id, index, Instant::now()));
+                }
                 handle_moves(
                     view_id,
                     index,
@@ -428,6 +707,7 @@ impl EguiUi {
                     click_type,
                     view_id,
                     client_state,
+                    &self.item_defs,
                 );
             }
         }
@@ -489,8 +769,31 @@ impl EguiUi {
     }
 }
 
+/// Whether the user is carrying a stack that the clicked slot's filter won't accept.
+fn rejects_carried_stack(
+    clicked_inv_id: u64,
+    index: usize,
+    inventory_manager: &MutexGuard<InventoryViewManager>,
+    manipulation_view: u64,
+    item_defs: &ClientItemManager,
+) -> bool {
+    let inventory = match inventory_manager.inventory_views.get(&clicked_inv_id) {
+        Some(x) => x,
+        None => return false,
+    };
+    match inventory_manager
+        .inventory_views
+        .get(&manipulation_view)
+        .and_then(|x| x.contents().first())
+    {
+        Some(Some(carried)) => inventory.can_place && !inventory.accepts(index, carried, item_defs),
+        _ => false,
+    }
+}
+
 // Allow unnecessary_unwrap until https://github.com/rust-lang/rust/issues/53667 is stabilized
 #[allow(clippy::unnecessary_unwrap)]
+#[allow(clippy::too_many_arguments)]
 fn handle_moves(
     clicked_inv_id: u64,
     index: usize,
@@ -499,6 +802,7 @@ fn handle_moves(
     click_type: InvClickType,
     view_key: u64,
     client_state: &ClientState,
+    item_defs: &ClientItemManager,
 ) {
     // we have to do a bit of a song-and-dance until get_many_mut is stabilized
     // This code generates the actual request that the server will authoritatively validate.
@@ -512,10 +816,37 @@ fn handle_moves(
         .get(&clicked_inv_id)
         .unwrap();
     let clicked_stack = inventory.contents()[index].clone();
-    let can_place = inventory.can_place;
+    // The slot's filter has to accept whatever we're carrying for us to put it there
+    let can_place = inventory.can_place
+        && !rejects_carried_stack(
+            clicked_inv_id,
+            index,
+            inventory_manager,
+            manipulation_view,
+            item_defs,
+        );
     let can_take = dbg!(inventory.can_take);
     let take_exact = inventory.take_exact;
 
+    if click_type == InvClickType::ShiftClick {
+        // The server decides where the stack ends up, and sends us the updated views
+        if clicked_stack.is_some() && can_take {
+            send_event(
+                client_state,
+                GameAction::Inventory(InventoryAction {
+                    source_view: view_key,
+                    source_slot: index,
+                    destination_view: 0,
+                    destination_slot: 0,
+                    count: 0,
+                    swap: false,
+                    quick_move: true,
+                }),
+            );
+        }
+        return;
+    }
+
     let carried_stack = match inventory_manager
         .inventory_views
         .get_mut(&manipulation_view)
@@ -571,6 +902,7 @@ fn handle_moves(
                 destination_slot: index,
                 count: quantity,
                 swap: false,
+                quick_move: false,
             }
         } else if can_take
             && clicked_stack.item_name == carried_stack_ref.item_name
@@ -620,6 +952,7 @@ fn handle_moves(
                 destination_slot: 0,
                 count: quantity,
                 swap: false,
+                quick_move: false,
             }
         } else if can_place && can_take {
             // You can both give and take, and your items don't match. Swap.
@@ -639,6 +972,7 @@ fn handle_moves(
                 destination_slot: index,
                 count: 0,
                 swap: true,
+                quick_move: false,
             }
         } else {
             // Both have items, we couldn't combine stacks in either direction,
@@ -681,6 +1015,7 @@ fn handle_moves(
             destination_slot: index,
             count: quantity,
             swap: false,
+            quick_move: false,
         }
     } else if clicked_stack.is_some() && can_take {
         let source_quantity = clicked_stack.as_ref().unwrap().quantity;
@@ -718,6 +1053,7 @@ fn handle_moves(
             destination_slot: 0,
             count: quantity,
             swap: false,
+            quick_move: false,
         }
     } else {
         // nothing to move, return
@@ -727,8 +1063,209 @@ fn handle_moves(
     send_event(client_state, GameAction::Inventory(action));
 }
 
+/// Collects the values of one popup's form elements of a given kind, by element key.
+fn form_values<T: Clone>(
+    values: &FxHashMap<(u64, String), T>,
+    popup_id: u64,
+) -> HashMap<String, T> {
+    values
+        .iter()
+        .filter(|((popup, _), _)| popup == &popup_id)
+        .map(|((_, form_key), value)| (form_key.clone(), value.clone()))
+        .collect()
+}
+
+fn apply_element_update(
+    elements: &mut Vec<proto::UiElement>,
+    update: &proto::ElementUpdate,
+) -> Result<()> {
+    use proto::element_update::Update;
+    use proto::ui_element::Element;
+    if let Some(Update::Append(element)) = &update.update {
+        let elements = if update.key.is_empty() {
+            elements
+        } else {
+            match find_element(elements, &update.key) {
+                Some(Element::Layout(layout)) => &mut layout.element,
+                Some(_) => bail!("{} isn't a layout", update.key),
+                None => bail!("No element with key {}", update.key),
+            }
+        };
+        elements.push(element.clone());
+        return Ok(());
+    }
+    let element = find_element(elements, &update.key)
+        .with_context(|| format!("No element with key {}", update.key))?;
+    match &update.update {
+        Some(Update::Text(text)) => match element {
+            Element::KeyedLabel(proto::KeyedLabel { text: x, .. })
+            | Element::Button(proto::Button { label: x, .. })
+            | Element::ProgressBar(proto::ProgressBar { label: x, .. }) => *x = text.clone(),
+            _ => bail!("{} doesn't have text that can be set", update.key),
+        },
+        Some(Update::Progress(progress)) => match element {
+            Element::ProgressBar(progress_bar) => progress_bar.progress = *progress,
+            _ => bail!("{} isn't a progress bar", update.key),
+        },
+        Some(Update::Enabled(enabled)) => match element {
+            Element::TextField(proto::TextField { enabled: x, .. })
+            | Element::Button(proto::Button { enabled: x, .. })
+            | Element::Checkbox(proto::Checkbox { enabled: x, .. })
+            | Element::Dropdown(proto::Dropdown { enabled: x, .. })
+            | Element::Slider(proto::Slider { enabled: x, .. }) => *x = *enabled,
+            _ => bail!("{} can't be enabled or disabled", update.key),
+        },
+        Some(Update::Append(_)) => unreachable!("appends are handled above"),
+        None => bail!("Missing update for {}", update.key),
+    }
+    Ok(())
+}
+
+// Finds the element with the given (non-empty) key, searching inside layouts
+fn find_element<'a>(
+    elements: &'a mut [proto::UiElement],
+    key: &str,
+) -> Option<&'a mut proto::ui_element::Element> {
+    use proto::ui_element::Element;
+    if key.is_empty() {
+        return None;
+    }
+    for element in elements.iter_mut().filter_map(|x| x.element.as_mut()) {
+        let element_key = match element {
+            Element::KeyedLabel(x) => &x.key,
+            Element::TextField(x) => &x.key,
+            Element::Button(x) => &x.key,
+            Element::Checkbox(x) => &x.key,
+            Element::Dropdown(x) => &x.key,
+            Element::Slider(x) => &x.key,
+            Element::ProgressBar(x) => &x.key,
+            Element::Layout(x) => &x.key,
+            _ => continue,
+        };
+        if element_key == key {
+            return Some(element);
+        }
+        if let Element::Layout(layout) = element {
+            if let Some(found) = find_element(&mut layout.element, key) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
 fn send_event(client_state: &ClientState, action: GameAction) {
     if client_state.actions.try_send(action).is_err() {
         log::info!("Sending action failed; server disconnected or lagging badly");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::element_update::Update;
+    use proto::ui_element::Element;
+
+    fn element(element: Element) -> proto::UiElement {
+        proto::UiElement {
+            element: Some(element),
+        }
+    }
+
+    fn nested_elements() -> Vec<proto::UiElement> {
+        vec![
+            element(Element::Label("Status".to_string())),
+            element(Element::Layout(proto::Layout {
+                horizontal: true,
+                key: "outer".to_string(),
+                element: vec![
+                    element(Element::Button(proto::Button {
+                        key: "go".to_string(),
+                        label: "Go".to_string(),
+                        enabled: true,
+                    })),
+                    element(Element::Layout(proto::Layout {
+                        horizontal: false,
+                        key: String::new(),
+                        element: vec![element(Element::ProgressBar(proto::ProgressBar {
+                            progress: 0.0,
+                            label: String::new(),
+                            key: "progress".to_string(),
+                        }))],
+                    })),
+                ],
+            })),
+        ]
+    }
+
+    fn update(key: &str, update: Update) -> proto::ElementUpdate {
+        proto::ElementUpdate {
+            key: key.to_string(),
+            update: Some(update),
+        }
+    }
+
+    #[test]
+    fn test_find_nested_element() {
+        let mut elements = nested_elements();
+        assert!(matches!(
+            find_element(&mut elements, "progress"),
+            Some(Element::ProgressBar(_))
+        ));
+        assert!(matches!(
+            find_element(&mut elements, "outer"),
+            Some(Element::Layout(_))
+        ));
+        assert!(find_element(&mut elements, "missing").is_none());
+        assert!(find_element(&mut elements, "").is_none());
+    }
+
+    #[test]
+    fn test_apply_element_update() {
+        let mut elements = nested_elements();
+        apply_element_update(&mut elements, &update("progress", Update::Progress(0.25))).unwrap();
+        apply_element_update(&mut elements, &update("go", Update::Enabled(false))).unwrap();
+        apply_element_update(
+            &mut elements,
+            &update("go", Update::Text("Stop".to_string())),
+        )
+        .unwrap();
+        match find_element(&mut elements, "progress") {
+            Some(Element::ProgressBar(bar)) => assert_eq!(bar.progress, 0.25),
+            _ => panic!("progress bar not found"),
+        }
+        match find_element(&mut elements, "go") {
+            Some(Element::Button(button)) => {
+                assert!(!button.enabled);
+                assert_eq!(button.label, "Stop");
+            }
+            _ => panic!("button not found"),
+        }
+
+        // Updates that don't fit the element are rejected without changing anything
+        let before = elements.clone();
+        assert!(apply_element_update(&mut elements, &update("go", Update::Progress(1.0))).is_err());
+        assert!(
+            apply_element_update(&mut elements, &update("missing", Update::Enabled(true))).is_err()
+        );
+        assert_eq!(elements, before);
+    }
+
+    #[test]
+    fn test_apply_append() {
+        let mut elements = nested_elements();
+        let label = element(Element::Label("Appended".to_string()));
+        apply_element_update(
+            &mut elements,
+            &update("outer", Update::Append(label.clone())),
+        )
+        .unwrap();
+        match find_element(&mut elements, "outer") {
+            Some(Element::Layout(layout)) => assert_eq!(layout.element.last(), Some(&label)),
+            _ => panic!("layout not found"),
+        }
+        apply_element_update(&mut elements, &update("", Update::Append(label.clone()))).unwrap();
+        assert_eq!(elements.last(), Some(&label));
+        assert!(apply_element_update(&mut elements, &update("go", Update::Append(label))).is_err());
+    }
+}
@@ -14,9 +14,14 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use anyhow::{Context, Result};
+use cuberef_core::protocol::ui as proto;
+use egui::{pos2, vec2, Align2, Color32, FontId, LayerId, TextureId};
 use texture_packer::Rect;
 
 
@@ -31,7 +36,10 @@ use crate::{
     },
 };
 
-use super::{get_texture, CROSSHAIR, DIGIT_ATLAS, FRAME_SELECTED, FRAME_UNSELECTED};
+use super::{
+    get_texture, wear_bar_step, wear_fraction, CROSSHAIR, DIGIT_ATLAS, FRAME_SELECTED,
+    FRAME_UNSELECTED, UNKNOWN_TEXTURE, WEAR_BAR, WEAR_BAR_BACKGROUND,
+};
 
 pub(crate) struct GameHud {
     pub(crate) texture_coords: HashMap<String, Rect>,
@@ -46,6 +54,10 @@ pub(crate) struct GameHud {
     pub(crate) hotbar_draw_call: Option<FlatTextureDrawCall>,
 
     pub(crate) fps_counter: fps_counter::FPSCounter,
+
+    // Elements added to the HUD by the server, by ID. Drawn in ID order, so newer elements are
+    // drawn on top.
+    pub(crate) server_elements: BTreeMap<u64, proto::HudElement>,
 }
 impl GameHud {
     pub(crate) fn hotbar_slot(&self) -> u32 {
@@ -176,8 +188,9 @@ impl GameHud {
                 builder.rect(item_rect, tex_coord, self.clone_atlas().dimensions());
 
                 let frame_topright = (frame0_corner.0 + offset + w - 2, frame0_corner.1 + 2);
-                // todo handle items that have a wear bar
-                if stack.max_stack > 1 && stack.quantity != 1 {
+                if let Some(fraction) = wear_fraction(stack, &self.item_defs) {
+                    self.render_wear_bar(item_rect, fraction, &mut builder);
+                } else if stack.max_stack > 1 && stack.quantity != 1 {
                     render_number(
                         frame_topright,
                         stack.quantity,
@@ -207,6 +220,161 @@ impl GameHud {
         Ok(Some(builder.build(ctx)?))
     }
 
+    // Draws a wear bar along the bottom of the given item rect
+    fn render_wear_bar(
+        &self,
+        item_rect: Rect,
+        fraction: f32,
+        builder: &mut FlatTextureDrawBuilder,
+    ) {
+        let background = *self.texture_coords.get(WEAR_BAR_BACKGROUND).unwrap();
+        let gradient = *self.texture_coords.get(WEAR_BAR).unwrap();
+        let bar_width = item_rect.w.saturating_sub(2 * WEAR_BAR_INSET);
+        let bar_y = (item_rect.y + item_rect.h).saturating_sub(WEAR_BAR_INSET + WEAR_BAR_HEIGHT);
+        builder.rect(
+            Rect::new(
+                item_rect.x + WEAR_BAR_INSET,
+                bar_y,
+                bar_width,
+                WEAR_BAR_HEIGHT,
+            ),
+            background,
+            self.texture_atlas.dimensions(),
+        );
+        let filled_width = ((bar_width as f32 * fraction).round() as u32).max(1);
+        // Sample a single column of the gradient, so the whole bar has the same color
+        let column = wear_bar_step(fraction);
+        builder.rect(
+            Rect::new(
+                item_rect.x + WEAR_BAR_INSET,
+                bar_y,
+                filled_width,
+                WEAR_BAR_HEIGHT,
+            ),
+            Rect::new(gradient.x + column, gradient.y, 1, gradient.h),
+            self.texture_atlas.dimensions(),
+        );
+    }
+
+    pub(crate) fn set_server_element(&mut self, update: &proto::SetHudElement) {
+        match &update.element {
+            Some(element) => {
+                self.server_elements.insert(update.id, element.clone());
+            }
+            None => log::warn!("Missing element in HUD update {}", update.id),
+        }
+    }
+
+    pub(crate) fn remove_server_element(&mut self, id: u64) {
+        self.server_elements.remove(&id);
+    }
+
+    pub(crate) fn has_server_elements(&self) -> bool {
+        !self.server_elements.is_empty()
+    }
+
+    /// Draws the elements added by the server behind any egui windows. Sizes and offsets are
+    /// given by the server in pixels, and are converted to egui's points here.
+    pub(crate) fn draw_server_elements(&self, ctx: &egui::Context, atlas_texture_id: TextureId) {
+        let painter = ctx.layer_painter(LayerId::background());
+        let screen = ctx.screen_rect();
+        let scale = ctx.pixels_per_point();
+        for element in self.server_elements.values() {
+            let align = hud_anchor_align(element.anchor());
+            let anchor_pos = align.pos_in_rect(&screen)
+                + vec2(element.offset_x as f32, element.offset_y as f32) / scale;
+            match &element.content {
+                Some(proto::hud_element::Content::Text(text)) => {
+                    let size = if text.size > 0.0 {
+                        text.size
+                    } else {
+                        DEFAULT_HUD_TEXT_SIZE
+                    };
+                    painter.text(
+                        anchor_pos,
+                        align,
+                        &text.text,
+                        FontId::proportional(size / scale),
+                        rgb_color(text.color),
+                    );
+                }
+                Some(proto::hud_element::Content::Image(image)) => {
+                    let texture = self
+                        .texture_coords
+                        .get(&image.texture_name)
+                        .or_else(|| self.texture_coords.get(UNKNOWN_TEXTURE))
+                        .copied()
+                        .unwrap();
+                    let rect = align.anchor_rect(egui::Rect::from_min_size(
+                        anchor_pos,
+                        vec2(image.width as f32, image.height as f32) / scale,
+                    ));
+                    painter.image(
+                        atlas_texture_id,
+                        rect,
+                        self.pixel_rect_to_uv(texture),
+                        Color32::WHITE,
+                    );
+                }
+                Some(proto::hud_element::Content::Bar(bar)) => {
+                    let rect = align.anchor_rect(egui::Rect::from_min_size(
+                        anchor_pos,
+                        vec2(bar.width as f32, bar.height as f32) / scale,
+                    ));
+                    painter.rect_filled(rect, 0.0, Color32::BLACK);
+                    let mut filled = rect;
+                    filled.set_width(rect.width() * bar.progress.clamp(0.0, 1.0));
+                    painter.rect_filled(filled, 0.0, rgb_color(bar.color));
+                }
+                Some(proto::hud_element::Content::ItemIcon(icon)) => {
+                    let size = if icon.size > 0 {
+                        icon.size as f32
+                    } else {
+                        DEFAULT_HUD_ICON_SIZE
+                    };
+                    let rect = align.anchor_rect(egui::Rect::from_min_size(
+                        anchor_pos,
+                        vec2(size, size) / scale,
+                    ));
+                    let texture = self.get_texture(&cuberef_core::protocol::items::ItemStack {
+                        item_name: icon.item_name.clone(),
+                        ..Default::default()
+                    });
+                    painter.image(
+                        atlas_texture_id,
+                        rect,
+                        self.pixel_rect_to_uv(texture),
+                        Color32::WHITE,
+                    );
+                    if icon.quantity > 1 {
+                        painter.text(
+                            rect.right_bottom(),
+                            Align2::RIGHT_BOTTOM,
+                            icon.quantity.to_string(),
+                            FontId::proportional(DEFAULT_HUD_TEXT_SIZE / scale),
+                            Color32::WHITE,
+                        );
+                    }
+                }
+                None => log::warn!("HUD element without content"),
+            }
+        }
+    }
+
+    fn pixel_rect_to_uv(&self, pixel_rect: Rect) -> egui::Rect {
+        let (width, height) = self.texture_atlas.dimensions();
+        egui::Rect::from_min_max(
+            pos2(
+                pixel_rect.left() as f32 / width as f32,
+                pixel_rect.top() as f32 / height as f32,
+            ),
+            pos2(
+                (pixel_rect.right() + 1) as f32 / width as f32,
+                (pixel_rect.bottom() + 1) as f32 / height as f32,
+            ),
+        )
+    }
+
     pub(crate) fn invalidate_hotbar(&mut self) {
         self.hotbar_draw_call = None;
     }
@@ -273,4 +441,31 @@ pub(crate) fn render_number(
     }
 }
 
+fn hud_anchor_align(anchor: proto::HudAnchor) -> Align2 {
+    match anchor {
+        proto::HudAnchor::TopLeft => Align2::LEFT_TOP,
+        proto::HudAnchor::TopCenter => Align2::CENTER_TOP,
+        proto::HudAnchor::TopRight => Align2::RIGHT_TOP,
+        proto::HudAnchor::CenterLeft => Align2::LEFT_CENTER,
+        proto::HudAnchor::Center => Align2::CENTER_CENTER,
+        proto::HudAnchor::CenterRight => Align2::RIGHT_CENTER,
+        proto::HudAnchor::BottomLeft => Align2::LEFT_BOTTOM,
+        proto::HudAnchor::BottomCenter => Align2::CENTER_BOTTOM,
+        proto::HudAnchor::BottomRight => Align2::RIGHT_BOTTOM,
+    }
+}
+
+// Colors are sent as 0xRRGGBB
+fn rgb_color(color: u32) -> Color32 {
+    let [_, r, g, b] = color.to_be_bytes();
+    Color32::from_rgb(r, g, b)
+}
+
 const DIGIT_WIDTH: u32 = 13;
+
+// Sizes, in pixels, of server HUD elements that don't specify one
+const DEFAULT_HUD_TEXT_SIZE: f32 = 18.0;
+const DEFAULT_HUD_ICON_SIZE: f32 = 32.0;
+
+const WEAR_BAR_HEIGHT: u32 = 3;
+const WEAR_BAR_INSET: u32 = 4;
@@ -1,14 +1,15 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
 };
 
 use texture_packer::{importer::ImageImporter, Rect};
 
 use anyhow::{Error, Result};
+use cuberef_core::protocol::items::item_def::QuantityType;
 
 use crate::{
-    cube_renderer::AsyncTextureLoader,
+    cube_renderer::{static_texture_rect, AsyncTextureLoader},
     game_state::items::ClientItemManager,
     vulkan::{Texture2DHolder, VulkanContext},
 };
@@ -39,6 +40,7 @@ where
         hotbar_draw_call: None,
         hotbar_view_id: None,
         fps_counter: fps_counter::FPSCounter::new(),
+        server_elements: BTreeMap::new(),
     };
 
     let egui_ui = EguiUi::new(texture_atlas, texture_coords, item_defs.clone());
@@ -109,6 +111,19 @@ where
             ImageImporter::import_from_memory(include_bytes!("digit_atlas.png")).unwrap(),
         )
         .map_err(|x| Error::msg(format!("Texture pack failed: {:?}", x)))?;
+    texture_packer
+        .pack_own(String::from(WEAR_BAR), make_wear_bar_gradient())
+        .map_err(|x| Error::msg(format!("Texture pack failed: {:?}", x)))?;
+    texture_packer
+        .pack_own(
+            String::from(WEAR_BAR_BACKGROUND),
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba([0, 0, 0, 255]),
+            )),
+        )
+        .map_err(|x| Error::msg(format!("Texture pack failed: {:?}", x)))?;
     texture_packer
         .pack_own(
             String::from(TEST_ITEM),
@@ -141,14 +156,90 @@ fn get_texture(
     item_defs
         .get(&item.item_name)
         .and_then(|x| x.inventory_texture.as_ref())
-        .map(|x| x.texture_name.as_ref())
-        .and_then(|x: &str| atlas_coords.get(x).copied())
+        .and_then(|x| Some(static_texture_rect(*atlas_coords.get(&x.texture_name)?, x)))
         .unwrap_or(*atlas_coords.get(UNKNOWN_TEXTURE).unwrap())
 }
 
+/// For items that have wear, returns the fraction of the item's wear that remains, from 0 to 1.
+fn wear_fraction(
+    item: &cuberef_core::protocol::items::ItemStack,
+    item_defs: &ClientItemManager,
+) -> Option<f32> {
+    match item_defs.get(&item.item_name)?.quantity_type {
+        Some(QuantityType::Wear(max_wear)) if max_wear > 0 => {
+            Some((item.quantity as f32 / max_wear as f32).clamp(0., 1.))
+        }
+        _ => None,
+    }
+}
+
+/// Which of the [WEAR_BAR_STEPS] colors a wear bar with the given fraction of wear remaining
+/// uses. The HUD draws that column of the wear bar texture, and egui draws the same color.
+fn wear_bar_step(fraction: f32) -> u32 {
+    (fraction.clamp(0., 1.) * (WEAR_BAR_STEPS - 1) as f32).round() as u32
+}
+
+/// The color of a wear bar: red when the item is almost broken, through yellow, to green when
+/// it's unused.
+fn wear_bar_color(fraction: f32) -> [u8; 3] {
+    wear_bar_step_color(wear_bar_step(fraction))
+}
+
+fn wear_bar_step_color(step: u32) -> [u8; 3] {
+    let fraction = step as f32 / (WEAR_BAR_STEPS - 1) as f32;
+    let red = (2. * (1. - fraction)).min(1.);
+    let green = (2. * fraction).min(1.);
+    [(red * 255.) as u8, (green * 255.) as u8, 0]
+}
+
+// A strip of every wear bar color, one column per step
+fn make_wear_bar_gradient() -> image::DynamicImage {
+    let image = image::RgbaImage::from_fn(WEAR_BAR_STEPS, 1, |x, _| {
+        let [r, g, b] = wear_bar_step_color(x);
+        image::Rgba([r, g, b, 255])
+    });
+    image::DynamicImage::ImageRgba8(image)
+}
+
+const WEAR_BAR_STEPS: u32 = 64;
+
 const CROSSHAIR: &str = "builtin:crosshair";
 const DIGIT_ATLAS: &str = "builtin:digit_atlas";
 const FRAME_SELECTED: &str = "builtin:frame_selected";
 const FRAME_UNSELECTED: &str = "builtin:frame_unselected";
 const TEST_ITEM: &str = "builtin:test_item";
 const UNKNOWN_TEXTURE: &str = "builtin:unknown";
+const WEAR_BAR: &str = "builtin:wear_bar";
+const WEAR_BAR_BACKGROUND: &str = "builtin:wear_bar_background";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wear_bar_colors() {
+        assert_eq!(wear_bar_color(0.), [255, 0, 0]);
+        // Yellow in the middle
+        let [r, g, b] = wear_bar_color(0.5);
+        assert!(r > 240 && g > 240 && b == 0);
+        assert_eq!(wear_bar_color(1.), [0, 255, 0]);
+        // Out of range fractions are clamped
+        assert_eq!(wear_bar_color(-1.), wear_bar_color(0.));
+        assert_eq!(wear_bar_color(2.), wear_bar_color(1.));
+    }
+
+    #[test]
+    fn test_wear_bar_gradient_matches() {
+        // The HUD's texture and egui's colors must agree for every fraction
+        let gradient = make_wear_bar_gradient().to_rgba8();
+        assert_eq!(gradient.width(), WEAR_BAR_STEPS);
+        for i in 0..=100 {
+            let fraction = i as f32 / 100.;
+            let [r, g, b] = wear_bar_color(fraction);
+            assert_eq!(
+                *gradient.get_pixel(wear_bar_step(fraction), 0),
+                image::Rgba([r, g, b, 255])
+            );
+        }
+    }
+}
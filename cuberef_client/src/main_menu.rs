@@ -8,7 +8,8 @@ use vulkano::{image::SampleCount, render_pass::Subpass};
 use winit::{event::WindowEvent, event_loop::EventLoop};
 
 use crate::{
-    game_state::settings::GameSettings,
+    game_state::settings::{GameSettings, MAX_VIEW_DISTANCE},
+    net_client::texture_pack,
     vulkan::{
         game_renderer::{ConnectionSettings, ConnectionState, GameState},
         VulkanContext,
@@ -22,6 +23,12 @@ pub(crate) struct MainMenu {
     pass_field: String,
     confirm_pass_field: String,
     show_register_popup: bool,
+    // Installed texture packs, and the one selected (empty for none)
+    texture_packs: Vec<String>,
+    texture_pack: String,
+    view_distance: u32,
+    greedy_meshing: bool,
+    ambient_occlusion: bool,
     settings: Arc<ArcSwap<GameSettings>>,
 }
 impl MainMenu {
@@ -51,6 +58,11 @@ impl MainMenu {
             pass_field: "".to_string(),
             confirm_pass_field: "".to_string(),
             show_register_popup: false,
+            texture_packs: texture_pack::list_texture_packs(),
+            texture_pack: settings.load().texture_pack.clone(),
+            view_distance: settings.load().view_distance,
+            greedy_meshing: settings.load().greedy_meshing,
+            ambient_occlusion: settings.load().ambient_occlusion,
             settings,
         }
     }
@@ -76,6 +88,69 @@ impl MainMenu {
                 let editor = TextEdit::singleline(&mut self.pass_field).password(true);
                 ui.add(editor).labelled_by(label.id);
             });
+            ui.with_layout(Layout::left_to_right(egui::Align::Min), |ui| {
+                let label = ui.label("Texture pack: ");
+                let previous = self.texture_pack.clone();
+                let selected = if self.texture_pack.is_empty() {
+                    "None"
+                } else {
+                    &self.texture_pack
+                };
+                let combo = egui::ComboBox::from_id_source("texture_pack")
+                    .selected_text(selected.to_string())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.texture_pack, String::new(), "None");
+                        for pack in &self.texture_packs {
+                            ui.selectable_value(&mut self.texture_pack, pack.clone(), pack);
+                        }
+                    });
+                combo.response.labelled_by(label.id);
+                if self.texture_pack != previous {
+                    self.update_settings(|x| GameSettings {
+                        texture_pack: self.texture_pack.clone(),
+                        ..x.clone()
+                    });
+                }
+                if ui.button("Refresh").clicked() {
+                    self.texture_packs = texture_pack::list_texture_packs();
+                }
+            });
+            if let Ok(dir) = texture_pack::texture_packs_dir() {
+                ui.small(format!("Texture packs are installed in {}", dir.display()));
+            }
+            ui.with_layout(Layout::left_to_right(egui::Align::Min), |ui| {
+                let label = ui.label("Render distance: ");
+                let slider = egui::Slider::new(&mut self.view_distance, 2..=MAX_VIEW_DISTANCE)
+                    .suffix(" chunks");
+                let response = ui.add(slider).labelled_by(label.id);
+                // Only save once the user lets go of the slider
+                if response.drag_released() || (response.changed() && !response.dragged()) {
+                    self.update_settings(|x| GameSettings {
+                        view_distance: self.view_distance,
+                        ..x.clone()
+                    });
+                }
+            });
+            let merge_faces = ui.checkbox(
+                &mut self.greedy_meshing,
+                "Merge block faces into larger quads (experimental, applies when connecting)",
+            );
+            if merge_faces.changed() {
+                self.update_settings(|x| GameSettings {
+                    greedy_meshing: self.greedy_meshing,
+                    ..x.clone()
+                });
+            }
+            let smooth_lighting = ui.checkbox(
+                &mut self.ambient_occlusion,
+                "Shade block corners with ambient occlusion (applies when connecting)",
+            );
+            if smooth_lighting.changed() {
+                self.update_settings(|x| GameSettings {
+                    ambient_occlusion: self.ambient_occlusion,
+                    ..x.clone()
+                });
+            }
 
             let connect_button = egui::Button::new("Connect");
             let connect_enabled = matches!(game_state, GameState::MainMenu);
@@ -191,6 +266,13 @@ impl MainMenu {
         result
     }
 
+    fn update_settings(&self, f: impl Fn(&GameSettings) -> GameSettings) {
+        self.settings.rcu(|x| f(x));
+        if let Err(e) = self.settings.load().save_to_disk() {
+            log::error!("Failure saving settings: {}", e);
+        }
+    }
+
     pub(crate) fn draw<L>(
         &mut self,
         ctx: &VulkanContext,
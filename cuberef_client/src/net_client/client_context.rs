@@ -120,6 +120,10 @@ impl OutboundContext {
 
         // If this overflows, the client is severely behind (by 4 billion chunks!) and may as well crash
         let pending_chunks = self.mesh_worker.queue.lock().len().try_into().unwrap();
+        let (view_distance, chunk_cache_size) = {
+            let settings = self.client_state.settings.load();
+            (settings.view_distance, settings.chunk_cache_size)
+        };
         let sequence = self
             .send_sequenced_message(rpc::stream_to_server::ClientMessage::PositionUpdate(
                 rpc::ClientUpdate {
@@ -132,6 +136,8 @@ impl OutboundContext {
                         }),
                     }),
                     pacing: Some(rpc::ClientPacing { pending_chunks }),
+                    view_distance,
+                    chunk_cache_size,
                 },
             ))
             .await?;
@@ -151,6 +157,14 @@ impl OutboundContext {
                 ))
                 .await?;
             }
+            GameAction::DigStart(block_coord) => {
+                self.send_sequenced_message(rpc::stream_to_server::ClientMessage::DigStart(
+                    rpc::DigStartAction {
+                        block_coord: Some(block_coord.into()),
+                    },
+                ))
+                .await?;
+            }
             GameAction::Tap(action) => {
                 self.send_sequenced_message(rpc::stream_to_server::ClientMessage::Tap(
                     rpc::TapAction {
@@ -180,6 +194,7 @@ impl OutboundContext {
                         destnation_slot: action.destination_slot.try_into()?,
                         count: action.count,
                         swap: action.swap,
+                        quick_move: action.quick_move,
                     },
                 ))
                 .await?;
@@ -322,6 +337,9 @@ impl InboundContext {
             Some(rpc::stream_to_client::ServerMessage::MapChunkUnsubscribe(unsub)) => {
                 self.handle_unsubscribe(unsub).await?;
             }
+            Some(rpc::stream_to_client::ServerMessage::MapChunkResubscribe(resub)) => {
+                self.handle_resubscribe(resub).await?;
+            }
             Some(rpc::stream_to_client::ServerMessage::MapDeltaUpdate(delta_update)) => {
                 self.handle_map_delta_update(delta_update).await?;
             }
@@ -334,6 +352,24 @@ impl InboundContext {
             Some(rpc::stream_to_client::ServerMessage::ShowPopup(popup_desc)) => {
                 self.client_state.egui.lock().show_popup(popup_desc);
             }
+            Some(rpc::stream_to_client::ServerMessage::UpdatePopup(popup_desc)) => {
+                self.client_state.egui.lock().update_popup(popup_desc);
+            }
+            Some(rpc::stream_to_client::ServerMessage::UpdatePopupElements(update)) => {
+                self.client_state.egui.lock().update_popup_elements(update);
+            }
+            Some(rpc::stream_to_client::ServerMessage::SetHudElement(update)) => {
+                self.client_state.hud.lock().set_server_element(update);
+            }
+            Some(rpc::stream_to_client::ServerMessage::RemoveHudElement(id)) => {
+                self.client_state.hud.lock().remove_server_element(*id);
+            }
+            Some(rpc::stream_to_client::ServerMessage::Notification(notification)) => {
+                self.client_state
+                    .egui
+                    .lock()
+                    .show_notification(notification);
+            }
             Some(_) => {
                 log::warn!("Unimplemented server->client message {:?}", message);
             }
@@ -347,6 +383,30 @@ impl InboundContext {
         self.mesh_worker.cond.notify_one();
     }
 
+    // Neighbors are remeshed too, since faces on their borders may now be hidden or exposed
+    fn enqueue_with_neighbors_for_meshing(&self, coord: ChunkCoordinate) {
+        self.enqueue_for_meshing(coord);
+
+        if let Some(neighbor) = coord.try_delta(-1, 0, 0) {
+            self.enqueue_for_meshing(neighbor);
+        }
+        if let Some(neighbor) = coord.try_delta(1, 0, 0) {
+            self.enqueue_for_meshing(neighbor);
+        }
+        if let Some(neighbor) = coord.try_delta(0, -1, 0) {
+            self.enqueue_for_meshing(neighbor);
+        }
+        if let Some(neighbor) = coord.try_delta(0, 1, 0) {
+            self.enqueue_for_meshing(neighbor);
+        }
+        if let Some(neighbor) = coord.try_delta(0, 0, -1) {
+            self.enqueue_for_meshing(neighbor);
+        }
+        if let Some(neighbor) = coord.try_delta(0, 0, 1) {
+            self.enqueue_for_meshing(neighbor);
+        }
+    }
+
     async fn handle_mapchunk(&mut self, chunk: &rpc::MapChunk) -> Result<()> {
         match &chunk.chunk_coord {
             Some(coord) => {
@@ -356,27 +416,7 @@ impl InboundContext {
                     self.client_state
                         .chunks
                         .insert_or_update(coord, chunk.clone())?;
-
-                    self.enqueue_for_meshing(coord);
-
-                    if let Some(neighbor) = coord.try_delta(-1, 0, 0) {
-                        self.enqueue_for_meshing(neighbor);
-                    }
-                    if let Some(neighbor) = coord.try_delta(1, 0, 0) {
-                        self.enqueue_for_meshing(neighbor);
-                    }
-                    if let Some(neighbor) = coord.try_delta(0, -1, 0) {
-                        self.enqueue_for_meshing(neighbor);
-                    }
-                    if let Some(neighbor) = coord.try_delta(0, 1, 0) {
-                        self.enqueue_for_meshing(neighbor);
-                    }
-                    if let Some(neighbor) = coord.try_delta(0, 0, -1) {
-                        self.enqueue_for_meshing(neighbor);
-                    }
-                    if let Some(neighbor) = coord.try_delta(0, 0, 1) {
-                        self.enqueue_for_meshing(neighbor);
-                    }
+                    self.enqueue_with_neighbors_for_meshing(coord);
                     Ok::<(), anyhow::Error>(())
                 })?;
             }
@@ -422,19 +462,23 @@ impl InboundContext {
     }
 
     async fn handle_unsubscribe(&mut self, unsub: &rpc::MapChunkUnsubscribe) -> Result<()> {
-        // TODO hold more old chunks (possibly LRU) to provide a higher render distance
+        // Unsubscribed chunks stay cached, and are kept up to date, until the server evicts them
+        let chunks = &self.client_state.chunks;
         let mut bad_coords = vec![];
         for coord in unsub.chunk_coord.iter() {
-            match self.client_state.chunks.remove(&coord.into()) {
-                Some(_x) => {}
-                None => {
-                    bad_coords.push(coord.clone());
-                }
+            if !chunks.unsubscribe(&coord.into()) {
+                bad_coords.push(coord.clone());
             }
             tokio::task::block_in_place(|| {
                 self.mesh_worker.queue.lock().remove(&coord.into());
             });
         }
+        let mut bad_evictions = vec![];
+        for coord in unsub.evicted_chunk_coord.iter() {
+            if !chunks.evict_cached(&coord.into()) {
+                bad_evictions.push(coord.clone());
+            }
+        }
         if !bad_coords.is_empty() {
             self.send_bugcheck(format!(
                 "Asked to unsubscribe to chunks we never subscribed to: {:?}",
@@ -442,6 +486,35 @@ impl InboundContext {
             ))
             .await?
         }
+        if !bad_evictions.is_empty() {
+            self.send_bugcheck(format!(
+                "Asked to evict chunks we don't have cached: {:?}",
+                bad_evictions
+            ))
+            .await?
+        }
+        Ok(())
+    }
+
+    async fn handle_resubscribe(&mut self, resub: &rpc::MapChunkResubscribe) -> Result<()> {
+        let mut bad_coords = vec![];
+        for coord in resub.chunk_coord.iter() {
+            tokio::task::block_in_place(|| {
+                if self.client_state.chunks.resubscribe(&coord.into()) {
+                    // Its mesh (if any) predates the updates it got while cached
+                    self.enqueue_with_neighbors_for_meshing(coord.into());
+                } else {
+                    bad_coords.push(coord.clone());
+                }
+            });
+        }
+        if !bad_coords.is_empty() {
+            self.send_bugcheck(format!(
+                "Asked to resubscribe to chunks we don't have cached: {:?}",
+                bad_coords
+            ))
+            .await?
+        }
         Ok(())
     }
 
@@ -482,7 +555,14 @@ impl InboundContext {
             let has_delta = match chunk_manager_read_lock.get(&block_coord.chunk()) {
                 Some(x) => x.apply_delta(update).unwrap(),
                 None => {
-                    unknown_coords.push(block_coord);
+                    // Cached chunks are kept up to date, but aren't rendered, so they're
+                    // remeshed once they come back into view instead of now
+                    match self.client_state.chunks.get_cached(&block_coord.chunk()) {
+                        Some(x) => {
+                            x.apply_delta(update).unwrap();
+                        }
+                        None => unknown_coords.push(block_coord),
+                    }
                     false
                 }
             };
@@ -579,10 +659,11 @@ impl InboundContext {
         let Some(pos_vector) = x else {
             return self.send_bugcheck("Missing position in ClientState update".to_string()).await;
         };
-        self.client_state
-            .physics_state
-            .lock()
-            .set_position(pos_vector.try_into()?);
+        {
+            let mut physics_state = self.client_state.physics_state.lock();
+            physics_state.set_position(pos_vector.try_into()?);
+            physics_state.set_allowed_modes(state_update.allow_flight, state_update.allow_noclip);
+        }
 
         let mut egui_lock = self.client_state.egui.lock();
         egui_lock.inventory_view = state_update.inventory_popup.clone();
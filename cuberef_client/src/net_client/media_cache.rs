@@ -0,0 +1,282 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+const CACHE_INDEX_FILE: &str = "index.ron";
+// Least recently used media is evicted once the cache grows past this size
+const MAX_CACHE_SIZE_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    size: u64,
+    // Seconds since the unix epoch
+    last_used: u64,
+}
+
+/// On-disk cache of media fetched from servers, keyed by the SHA-256 of the media's contents.
+/// Since entries are keyed by content, media shared between servers is only stored once.
+pub(crate) struct MediaCache {
+    dir: PathBuf,
+    // Keyed by the hex-encoded hash, which is also the name of the file in dir
+    index: HashMap<String, CacheEntry>,
+    max_size: u64,
+}
+impl MediaCache {
+    /// Opens the cache in the client's project cache directory, creating it if necessary.
+    pub(crate) fn open() -> Result<MediaCache> {
+        let dir = directories::ProjectDirs::from("foo", "drey7925", "cuberef")
+            .context("couldn't find cache dir")?
+            .cache_dir()
+            .join("media");
+        Self::open_in(dir, MAX_CACHE_SIZE_BYTES)
+    }
+
+    fn open_in(dir: PathBuf, max_size: u64) -> Result<MediaCache> {
+        std::fs::create_dir_all(&dir)?;
+        let index_file = dir.join(CACHE_INDEX_FILE);
+        let mut index: HashMap<String, CacheEntry> = if index_file.exists() {
+            match ron::from_str(&std::fs::read_to_string(&index_file)?) {
+                Ok(x) => x,
+                Err(e) => {
+                    log::warn!("Media cache index is corrupt, starting over: {e:?}");
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+        // Forget entries whose files were deleted out from under us
+        index.retain(|hash, _| dir.join(hash).exists());
+        Ok(MediaCache {
+            dir,
+            index,
+            max_size,
+        })
+    }
+
+    /// Returns the cached media with the given hash, if it's present and its contents still match
+    /// the hash. Corrupt entries are removed.
+    pub(crate) fn get(&mut self, sha256: &[u8]) -> Option<Vec<u8>> {
+        let key = hex::encode(sha256);
+        if !self.index.contains_key(&key) {
+            return None;
+        }
+        let data = match std::fs::read(self.dir.join(&key)) {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("Couldn't read cached media {key}: {e:?}");
+                self.remove(&key);
+                return None;
+            }
+        };
+        if !matches_hash(sha256, &data) {
+            log::warn!("Cached media {key} doesn't match its hash, discarding it");
+            self.remove(&key);
+            return None;
+        }
+        if let Some(entry) = self.index.get_mut(&key) {
+            entry.last_used = now();
+        }
+        Some(data)
+    }
+
+    /// Stores media in the cache, evicting least recently used media if the cache is too large.
+    /// Fails if the data doesn't match the given hash.
+    pub(crate) fn insert(&mut self, sha256: &[u8], data: &[u8]) -> Result<()> {
+        if !matches_hash(sha256, data) {
+            anyhow::bail!("Media data doesn't match its expected hash");
+        }
+        let key = hex::encode(sha256);
+        std::fs::write(self.dir.join(&key), data)?;
+        self.index.insert(
+            key,
+            CacheEntry {
+                size: data.len() as u64,
+                last_used: now(),
+            },
+        );
+        self.evict();
+        Ok(())
+    }
+
+    /// Writes the index to disk, so that usage times survive restarts.
+    pub(crate) fn flush(&self) -> Result<()> {
+        let index = ron::ser::to_string(&self.index)?;
+        std::fs::write(self.dir.join(CACHE_INDEX_FILE), index)?;
+        Ok(())
+    }
+
+    fn evict(&mut self) {
+        let mut total_size: u64 = self.index.values().map(|x| x.size).sum();
+        if total_size <= self.max_size {
+            return;
+        }
+        let mut entries: Vec<_> = self
+            .index
+            .iter()
+            .map(|(k, v)| (v.last_used, v.size, k.clone()))
+            .collect();
+        entries.sort();
+        for (_, size, key) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+            self.remove(&key);
+            total_size -= size;
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.index.remove(key);
+        if let Err(e) = std::fs::remove_file(self.dir.join(key)) {
+            log::warn!("Couldn't remove cached media {key}: {e:?}");
+        }
+    }
+}
+
+/// Whether the given data has the given SHA-256 hash.
+pub(crate) fn matches_hash(sha256: &[u8], data: &[u8]) -> bool {
+    sha2::Sha256::digest(data).as_slice() == sha256
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // A scratch directory that's deleted when dropped
+    struct TestDir(PathBuf);
+    impl TestDir {
+        fn new() -> TestDir {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "cuberef_media_cache_test_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            TestDir(dir)
+        }
+    }
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn hash(data: &[u8]) -> Vec<u8> {
+        sha2::Sha256::digest(data).to_vec()
+    }
+
+    fn entry<'a>(cache: &'a mut MediaCache, data: &[u8]) -> &'a mut CacheEntry {
+        cache.index.get_mut(&hex::encode(hash(data))).unwrap()
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let dir = TestDir::new();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 1024).unwrap();
+        cache.insert(&hash(b"grass"), b"grass").unwrap();
+        assert_eq!(cache.get(&hash(b"grass")).unwrap(), b"grass");
+        assert_eq!(cache.get(&hash(b"dirt")), None);
+
+        // Survives a restart once flushed
+        cache.flush().unwrap();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 1024).unwrap();
+        assert_eq!(cache.get(&hash(b"grass")).unwrap(), b"grass");
+    }
+
+    #[test]
+    fn test_hash_mismatch() {
+        let dir = TestDir::new();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 1024).unwrap();
+        // Data that doesn't match its hash is never stored
+        assert!(cache.insert(&hash(b"grass"), b"dirt").is_err());
+        assert_eq!(cache.get(&hash(b"grass")), None);
+        assert!(!dir.0.join(hex::encode(hash(b"grass"))).exists());
+
+        // A file that changed on disk is discarded when it's read
+        cache.insert(&hash(b"stone"), b"stone").unwrap();
+        let path = dir.0.join(hex::encode(hash(b"stone")));
+        std::fs::write(&path, b"corrupted").unwrap();
+        assert_eq!(cache.get(&hash(b"stone")), None);
+        assert!(!path.exists());
+        assert!(cache.index.is_empty());
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let dir = TestDir::new();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 10).unwrap();
+        cache.insert(&hash(b"aaaa"), b"aaaa").unwrap();
+        cache.insert(&hash(b"bbbb"), b"bbbb").unwrap();
+        // Timestamps only have a resolution of seconds, so set them by hand
+        entry(&mut cache, b"aaaa").last_used = 2;
+        entry(&mut cache, b"bbbb").last_used = 1;
+
+        // Over the limit; the least recently used entry goes, even though it was inserted last
+        cache.insert(&hash(b"cccc"), b"cccc").unwrap();
+        assert_eq!(cache.get(&hash(b"bbbb")), None);
+        assert!(!dir.0.join(hex::encode(hash(b"bbbb"))).exists());
+        assert_eq!(cache.get(&hash(b"aaaa")).unwrap(), b"aaaa");
+        assert_eq!(cache.get(&hash(b"cccc")).unwrap(), b"cccc");
+
+        // Media larger than the whole cache isn't kept at all
+        cache.insert(&hash(&[0; 20]), &[0; 20]).unwrap();
+        assert_eq!(cache.get(&hash(&[0; 20])), None);
+    }
+
+    #[test]
+    fn test_corrupt_index() {
+        let dir = TestDir::new();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 1024).unwrap();
+        cache.insert(&hash(b"grass"), b"grass").unwrap();
+        cache.insert(&hash(b"dirt"), b"dirt").unwrap();
+        cache.flush().unwrap();
+
+        // Entries whose files are gone are forgotten
+        std::fs::remove_file(dir.0.join(hex::encode(hash(b"dirt")))).unwrap();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 1024).unwrap();
+        assert_eq!(cache.index.len(), 1);
+        assert_eq!(cache.get(&hash(b"grass")).unwrap(), b"grass");
+
+        // An unreadable index starts the cache over, rather than failing
+        std::fs::write(dir.0.join(CACHE_INDEX_FILE), "not an index {").unwrap();
+        let mut cache = MediaCache::open_in(dir.0.clone(), 1024).unwrap();
+        assert!(cache.index.is_empty());
+        assert_eq!(cache.get(&hash(b"grass")), None);
+        // ...and is usable again afterwards
+        cache.insert(&hash(b"grass"), b"grass").unwrap();
+        assert_eq!(cache.get(&hash(b"grass")).unwrap(), b"grass");
+    }
+}
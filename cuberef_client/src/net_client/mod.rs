@@ -15,11 +15,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::{
+    collections::HashMap,
     sync::Arc,
     time::Duration,
 };
 
 use self::client_context::*;
+use self::media_cache::MediaCache;
+use self::texture_pack::TexturePackLoader;
 use anyhow::{bail, Context, Error, Result};
 
 use arc_swap::ArcSwap;
@@ -28,7 +31,7 @@ use cuberef_core::{
     protocol::game_rpc::{
         self as rpc, cuberef_game_client::CuberefGameClient, stream_to_client::ServerMessage,
         stream_to_server::ClientMessage, GetBlockDefsRequest, GetItemDefsRequest, GetMediaRequest,
-        StartAuth, StreamToClient, StreamToServer,
+        ListMediaRequest, StartAuth, StreamToClient, StreamToServer,
     },
 };
 use image::DynamicImage;
@@ -36,6 +39,7 @@ use opaque_ke::{
     ClientLoginFinishParameters, ClientRegistrationFinishParameters, CredentialResponse,
     RegistrationResponse,
 };
+use parking_lot::Mutex;
 use rand::rngs::OsRng;
 use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
@@ -53,7 +57,9 @@ use crate::{
 };
 
 mod client_context;
+mod media_cache;
 pub(crate) mod mesh_worker;
+pub(crate) mod texture_pack;
 
 async fn connect_grpc(
     server_addr: String,
@@ -97,16 +103,43 @@ pub(crate) async fn connect_game(
     );
 
     progress.send((0.4, "Loading block textures...".to_string()))?;
-    let texture_loader = GrpcTextureLoader {
-        connection: connection.clone(),
+    let media_list = connection.list_media(ListMediaRequest {}).await?;
+    let media_hashes: HashMap<_, _> = media_list
+        .into_inner()
+        .media
+        .into_iter()
+        .map(|x| (x.media_name, x.sha256))
+        .collect();
+    // A missing cache only costs us some loading time, so carry on without it
+    let media_cache = match MediaCache::open() {
+        Ok(x) => Some(Arc::new(Mutex::new(x))),
+        Err(e) => {
+            log::warn!("Couldn't open media cache: {e:?}");
+            None
+        }
     };
+    let texture_loader = TexturePackLoader::new(
+        &settings.load().texture_pack,
+        GrpcTextureLoader {
+            connection: connection.clone(),
+            media_hashes: Arc::new(media_hashes),
+            media_cache: media_cache.clone(),
+        },
+    );
 
     let block_types = Arc::new(ClientBlockTypeManager::new(
         block_defs_proto.into_inner().block_types,
     )?);
 
     progress.send((0.5, "Setting up block renderer...".to_string()))?;
-    let block_renderer = BlockRenderer::new(block_types.clone(), texture_loader.clone(), ctx).await?;
+    let block_renderer = BlockRenderer::new(
+        block_types.clone(),
+        texture_loader.clone(),
+        ctx,
+        settings.load().greedy_meshing,
+        settings.load().ambient_occlusion,
+    )
+    .await?;
 
     progress.send((0.6, "Loading item definitions...".to_string()))?;
     let item_defs_proto = connection.get_item_defs(GetItemDefsRequest {}).await?;
@@ -120,6 +153,11 @@ pub(crate) async fn connect_game(
 
     progress.send((0.7, "Loading item textures...".to_string()))?;
     let (hud, egui) = crate::game_ui::make_uis(items.clone(), texture_loader, ctx).await?;
+    if let Some(media_cache) = media_cache {
+        if let Err(e) = media_cache.lock().flush() {
+            log::warn!("Couldn't save media cache index: {e:?}");
+        }
+    }
 
     // TODO clean up this hacky cloning of the context.
     // We need to clone it to start up the game ui without running into borrow checker issues,
@@ -306,23 +344,68 @@ async fn do_login_handshake(
     }
 }
 
+// How many times media that doesn't match its hash (e.g. because it was corrupted in transit, or
+// changed on the server) is fetched before giving up
+const MAX_MEDIA_FETCH_ATTEMPTS: u32 = 3;
+
 #[derive(Clone)]
 struct GrpcTextureLoader {
     connection: CuberefGameClient<Channel>,
+    // Hashes of the server's media, from ListMedia
+    media_hashes: Arc<HashMap<String, Vec<u8>>>,
+    media_cache: Option<Arc<Mutex<MediaCache>>>,
+}
+impl GrpcTextureLoader {
+    async fn load_media(&mut self, name: &str) -> Result<Vec<u8>> {
+        let hash = self.media_hashes.get(name);
+        if let (Some(hash), Some(cache)) = (hash, &self.media_cache) {
+            if let Some(data) = cache.lock().get(hash) {
+                log::info!("Loaded resource {} from cache", name);
+                return Ok(data);
+            }
+        }
+        let mut attempts = 0;
+        let data = loop {
+            log::info!("Fetching resource {}", name);
+            let data = self
+                .connection
+                .get_media(GetMediaRequest {
+                    media_name: name.to_string(),
+                })
+                .await?
+                .into_inner()
+                .media;
+            match hash {
+                Some(hash) if !media_cache::matches_hash(hash, &data) => {
+                    attempts += 1;
+                    if attempts >= MAX_MEDIA_FETCH_ATTEMPTS {
+                        bail!(
+                            "Resource {} still didn't match its hash after {} attempts",
+                            name,
+                            attempts
+                        );
+                    }
+                    log::warn!("Resource {} didn't match its hash, fetching it again", name);
+                }
+                _ => break data,
+            }
+        };
+        match (hash, &self.media_cache) {
+            (Some(hash), Some(cache)) => {
+                if let Err(e) = cache.lock().insert(hash, &data) {
+                    log::warn!("Couldn't cache resource {}: {e:?}", name);
+                }
+            }
+            (None, _) => log::warn!("Resource {} isn't in the server's media list", name),
+            _ => {}
+        }
+        Ok(data)
+    }
 }
 #[async_trait]
 impl AsyncTextureLoader for GrpcTextureLoader {
     async fn load_texture(&mut self, tex_name: &str) -> Result<DynamicImage> {
-        // TODO caching - right now we fetch all resources every time we start the game
-        // Some resources are even fetched twice (once for blocks, once for items)
-        log::info!("Loading resource {}", tex_name);
-        let resp = self
-            .connection
-            .get_media(GetMediaRequest {
-                media_name: tex_name.to_string(),
-            })
-            .await?;
-        image::load_from_memory(&resp.into_inner().media)
-            .with_context(|| "Image was fetched, but parsing failed")
+        let data = self.load_media(tex_name).await?;
+        image::load_from_memory(&data).with_context(|| "Image was fetched, but parsing failed")
     }
 }
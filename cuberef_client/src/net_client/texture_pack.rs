@@ -0,0 +1,184 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Local texture packs, which replace the server's textures on this client only.
+//!
+//! A texture pack is a directory in [texture_packs_dir]. A texture named `namespace:name`
+//! is replaced by `namespace/name.png` within the pack, if that file exists.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use tonic::async_trait;
+
+use crate::cube_renderer::AsyncTextureLoader;
+
+/// The directory that texture packs are installed into, one subdirectory per pack.
+pub(crate) fn texture_packs_dir() -> Result<PathBuf> {
+    Ok(directories::ProjectDirs::from("foo", "drey7925", "cuberef")
+        .context("couldn't find data dir")?
+        .data_dir()
+        .join("texture_packs"))
+}
+
+/// Lists the names of the installed texture packs, sorted by name.
+pub(crate) fn list_texture_packs() -> Vec<String> {
+    let entries = match texture_packs_dir().and_then(|x| Ok(std::fs::read_dir(x)?)) {
+        Ok(x) => x,
+        // Most likely no packs were ever installed
+        Err(_) => return vec![],
+    };
+    let mut packs: Vec<String> = entries
+        .flatten()
+        .filter(|x| x.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|x| x.file_name().into_string().ok())
+        .collect();
+    packs.sort();
+    packs
+}
+
+/// Loads textures from a texture pack, falling back to another loader (usually the server) for
+/// textures the pack doesn't provide.
+#[derive(Clone)]
+pub(crate) struct TexturePackLoader<T> {
+    pack_dir: Option<PathBuf>,
+    fallback: T,
+}
+impl<T: AsyncTextureLoader> TexturePackLoader<T> {
+    /// Uses the installed pack with the given name; an empty name means no pack is used.
+    pub(crate) fn new(pack_name: &str, fallback: T) -> Self {
+        let pack_dir = if pack_name.is_empty() {
+            None
+        } else {
+            match texture_packs_dir() {
+                Ok(dir) if dir.join(pack_name).is_dir() => Some(dir.join(pack_name)),
+                Ok(_) => {
+                    log::warn!("Texture pack {} isn't installed, not using it", pack_name);
+                    None
+                }
+                Err(e) => {
+                    log::warn!("Couldn't find texture packs: {e:?}");
+                    None
+                }
+            }
+        };
+        TexturePackLoader { pack_dir, fallback }
+    }
+}
+#[async_trait]
+impl<T: AsyncTextureLoader + Send> AsyncTextureLoader for TexturePackLoader<T> {
+    async fn load_texture(&mut self, tex_name: &str) -> Result<DynamicImage> {
+        if let Some(path) = self
+            .pack_dir
+            .as_ref()
+            .and_then(|dir| texture_path(dir, tex_name))
+        {
+            if path.is_file() {
+                match image::open(&path) {
+                    Ok(image) => {
+                        log::info!("Using {} from texture pack", tex_name);
+                        return Ok(image);
+                    }
+                    Err(e) => log::warn!("Couldn't load {}: {e:?}", path.display()),
+                }
+            }
+        }
+        self.fallback.load_texture(tex_name).await
+    }
+}
+
+// Texture names come from the server, so they must not be able to point outside the pack.
+fn texture_path(pack_dir: &Path, tex_name: &str) -> Option<PathBuf> {
+    let mut path = pack_dir.to_path_buf();
+    let components: Vec<_> = tex_name.split(':').collect();
+    for (i, component) in components.iter().enumerate() {
+        let valid = !component.is_empty()
+            && !component.starts_with('.')
+            && component
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+        if !valid {
+            return None;
+        }
+        if i == components.len() - 1 {
+            path.push(format!("{component}.png"));
+        } else {
+            path.push(component);
+        }
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(tex_name: &str) -> Option<PathBuf> {
+        texture_path(Path::new("packs/mine"), tex_name)
+    }
+
+    #[test]
+    fn test_valid_names() {
+        assert_eq!(
+            path("default:dirt"),
+            Some(Path::new("packs/mine").join("default").join("dirt.png"))
+        );
+        assert_eq!(
+            path("my-mod:sub_dir:stone.v2"),
+            Some(
+                Path::new("packs/mine")
+                    .join("my-mod")
+                    .join("sub_dir")
+                    .join("stone.v2.png")
+            )
+        );
+        assert_eq!(
+            path("plain"),
+            Some(Path::new("packs/mine").join("plain.png"))
+        );
+    }
+
+    #[test]
+    fn test_parent_dirs() {
+        assert_eq!(path(".."), None);
+        assert_eq!(path("..:..:secret"), None);
+        assert_eq!(path("default:.."), None);
+        assert_eq!(path("default:.hidden"), None);
+    }
+
+    #[test]
+    fn test_absolute_paths() {
+        assert_eq!(path("/etc/passwd"), None);
+        assert_eq!(path("default:/etc/passwd"), None);
+        assert_eq!(path("C:\\Windows:system"), None);
+    }
+
+    #[test]
+    fn test_separators() {
+        assert_eq!(path("default/dirt"), None);
+        assert_eq!(path("default\\dirt"), None);
+        assert_eq!(path("default:..\\..\\dirt"), None);
+    }
+
+    #[test]
+    fn test_empty_segments() {
+        assert_eq!(path(""), None);
+        assert_eq!(path(":dirt"), None);
+        assert_eq!(path("default:"), None);
+        assert_eq!(path("default::dirt"), None);
+    }
+}
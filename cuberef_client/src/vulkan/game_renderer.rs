@@ -37,7 +37,11 @@ use winit::{
 };
 
 use crate::{
-    game_state::{settings::GameSettings, ClientState, FrameState},
+    game_state::{
+        settings::GameSettings,
+        visibility::{self, ChunkConnectivity, Frustum},
+        ClientState, FrameState,
+    },
     main_menu::MainMenu,
     net_client,
 };
@@ -105,11 +109,28 @@ impl ActiveGame {
             self.client_state.chunks.cloned_view()
         };
         plot!("total_chunks", chunk_lock.len() as f64);
-        cube_draw_calls.extend(
+        let visible_chunks = {
+            let _span = span!("find visible chunks");
+            match visibility::chunk_extent(chunk_lock.keys().copied()) {
+                Some(bounds) => visibility::visible_chunks(
+                    &Frustum::from_view_proj(view_proj_matrix),
+                    player_position,
+                    bounds,
+                    |coord| {
+                        chunk_lock
+                            .get(&coord)
+                            .map_or(ChunkConnectivity::ALL, |chunk| chunk.connectivity())
+                    },
+                ),
+                None => vec![],
+            }
+        };
+        plot!("visible_chunks", visible_chunks.len() as f64);
+        cube_draw_calls.extend(visible_chunks.iter().filter_map(|coord| {
             chunk_lock
-                .values()
-                .filter_map(|chunk| chunk.make_draw_call(player_position)),
-        );
+                .get(coord)
+                .and_then(|chunk| chunk.make_draw_call(player_position))
+        }));
         plot!(
             "chunk_rate",
             cube_draw_calls.len() as f64 / chunk_lock.len() as f64
@@ -15,8 +15,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{Context, Result};
-use cgmath::{vec3, vec4, Matrix4, Vector4, Zero};
-use std::sync::Arc;
+use cgmath::Matrix4;
+use std::{sync::Arc, time::Instant};
 use tracy_client::{plot, span};
 use vulkano::{
     buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
@@ -60,12 +60,28 @@ pub(crate) struct CubeGeometryVertex {
     /// Position, given relative to the origin of the chunk.
     /// Not transformed into camera space via a view matrix yet
     pub(crate) position: [f32; 3],
-    // Texture coordinate in tex space (0-1)
+    // Texture coordinate in tile space: the texture repeats once per unit, so that a single
+    // quad can cover several blocks
     #[format(R32G32_SFLOAT)]
     pub(crate) uv_texcoord: [f32; 2],
-    // TODO design lighting, and add it to the shader
+    // Multiplied with the texture's color; darkens corners for ambient occlusion
     #[format(R32_SFLOAT)]
     pub(crate) brightness: f32,
+    // Multiplied with the texture's color
+    #[format(R8G8B8A8_UNORM)]
+    pub(crate) tint: [u8; 4],
+    // Number of animation frames (1 if the texture isn't animated), and how long each is shown
+    #[format(R32_UINT)]
+    pub(crate) anim_frames: u32,
+    #[format(R32_UINT)]
+    pub(crate) anim_frame_millis: u32,
+    // Distance between consecutive animation frames, in tex space
+    #[format(R32_SFLOAT)]
+    pub(crate) anim_frame_stride: f32,
+    // Where the texture (its first animation frame) is in the atlas, as left, top, width, and
+    // height in tex space (0-1)
+    #[format(R32G32B32A32_SFLOAT)]
+    pub(crate) tex_rect: [f32; 4],
 }
 pub(crate) struct CubeGeometryDrawCall {
     pub(crate) models: VkChunkVertexData,
@@ -79,49 +95,8 @@ pub(crate) struct CubePipelineWrapper {
     solid_descriptor: Arc<PersistentDescriptorSet>,
     sparse_descriptor: Arc<PersistentDescriptorSet>,
     translucent_descriptor: Arc<PersistentDescriptorSet>,
-    bound_projection: Matrix4<f32>,
-}
-impl CubePipelineWrapper {
-    fn check_frustum(&self, pass_data: &CubeGeometryDrawCall) -> bool {
-        let transformation = self.bound_projection * pass_data.model_matrix;
-        const CORNERS: [Vector4<f32>; 8] = [
-            vec4(0., 0., 0., 1.),
-            vec4(16., 0., 0., 1.),
-            vec4(0., -16., 0., 1.),
-            vec4(16., -16., 0., 1.),
-            vec4(0., 0., 16., 1.),
-            vec4(16., 0., 16., 1.),
-            vec4(0., -16., 16., 1.),
-            vec4(16., -16., 16., 1.),
-        ];
-        let mut ndc_min = vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
-        let mut ndc_max = vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
-        for corner in CORNERS {
-            let mut ndc = transformation * corner;
-            ndc /= ndc.w;
-            ndc_min = vec3(
-                ndc_min.x.min(ndc.x),
-                ndc_min.y.min(ndc.y),
-                ndc_min.z.min(ndc.z),
-            );
-            ndc_max = vec3(
-                ndc_max.x.max(ndc.x),
-                ndc_max.y.max(ndc.y),
-                ndc_max.z.max(ndc.z),
-            );
-        }
-        overlaps(ndc_min.x, ndc_max.x, -1., 1.)
-            && overlaps(ndc_min.y, ndc_max.y, -1., 1.)
-            && overlaps(ndc_min.z, ndc_max.z, 0., 1.)
-    }
-}
-
-#[inline]
-fn overlaps(min1: f32, max1: f32, min2: f32, max2: f32) -> bool {
-    (min1 <= min2 && max1 >= max2)
-        || (min2 <= min1 && max2 >= max1)
-        || (min1 <= max2 && min1 >= min2)
-        || (min2 <= max1 && min2 >= min1)
+    // Texture animations are timed relative to this
+    start_time: Instant,
 }
 
 /// Which render step we are rendering in this renderer.
@@ -152,8 +127,8 @@ impl PipelineWrapper<CubeGeometryDrawCall, Matrix4<f32>> for CubePipelineWrapper
         };
         let layout = pipeline.layout().clone();
         builder.bind_pipeline_graphics(pipeline);
-        let mut pre_frustum = 0;
-        let mut post_frustum = 0;
+        // Draw calls are only made for chunks that might be visible; see game_state::visibility
+        let mut drawn = 0;
         for call in draw_calls.iter() {
             let pass_data = match pass {
                 BlockRenderPass::Opaque => &call.models.solid_opaque,
@@ -161,26 +136,20 @@ impl PipelineWrapper<CubeGeometryDrawCall, Matrix4<f32>> for CubePipelineWrapper
                 BlockRenderPass::Translucent => &call.models.translucent,
             };
             if let Some(pass_data) = pass_data {
-                pre_frustum += 1;
-                if self.check_frustum(call) {
-                    post_frustum += 1;
-
-                    let push_data: ModelMatrix = call.model_matrix.into();
-                    builder
-                        .push_constants(layout.clone(), 0, push_data)
-                        .bind_vertex_buffers(0, pass_data.vtx.clone())
-                        .bind_index_buffer(pass_data.idx.clone())
-                        .draw_indexed(pass_data.idx.len().try_into()?, 1, 0, 0, 0)?;
-                }
+                drawn += 1;
+                let push_data: ModelMatrix = call.model_matrix.into();
+                builder
+                    .push_constants(layout.clone(), 0, push_data)
+                    .bind_vertex_buffers(0, pass_data.vtx.clone())
+                    .bind_index_buffer(pass_data.idx.clone())
+                    .draw_indexed(pass_data.idx.len().try_into()?, 1, 0, 0, 0)?;
             }
         }
         plot!("total_calls", draw_calls.len() as f64);
-        let draw_rate = pre_frustum as f64 / (draw_calls.len() as f64);
-        let draw_rate_frustum = post_frustum as f64 / pre_frustum as f64;
+        let draw_rate = drawn as f64 / (draw_calls.len() as f64);
         match pass {
             BlockRenderPass::Opaque => {
                 plot!("opaque_rate", draw_rate);
-                plot!("opaque_rate_frustum", draw_rate_frustum);
             }
             BlockRenderPass::Transparent => {
                 plot!("transparent_rate", draw_rate);
@@ -204,7 +173,6 @@ impl PipelineWrapper<CubeGeometryDrawCall, Matrix4<f32>> for CubePipelineWrapper
             BlockRenderPass::Transparent => span!("bind transparent"),
             BlockRenderPass::Translucent => span!("bind translucent"),
         };
-        self.bound_projection = per_frame_config;
         let layout = match pass {
             BlockRenderPass::Opaque => self.solid_pipeline.layout().clone(),
             BlockRenderPass::Transparent => self.sparse_pipeline.layout().clone(),
@@ -228,6 +196,7 @@ impl PipelineWrapper<CubeGeometryDrawCall, Matrix4<f32>> for CubePipelineWrapper
             },
             UniformData {
                 vp_matrix: per_frame_config.into(),
+                time_millis: self.start_time.elapsed().as_millis() as u32,
             },
         )?;
 
@@ -258,6 +227,8 @@ pub(crate) struct CubePipelineProvider {
     vs_cube: Arc<ShaderModule>,
     fs_solid: Arc<ShaderModule>,
     fs_sparse: Arc<ShaderModule>,
+    // Kept across pipeline rebuilds (e.g. on window resize) so animations don't restart
+    start_time: Instant,
 }
 impl CubePipelineProvider {
     pub(crate) fn new(device: Arc<Device>) -> Result<CubePipelineProvider> {
@@ -269,6 +240,7 @@ impl CubePipelineProvider {
             vs_cube,
             fs_solid,
             fs_sparse,
+            start_time: Instant::now(),
         })
     }
 }
@@ -352,7 +324,7 @@ impl PipelineProvider for CubePipelineProvider {
             solid_descriptor,
             sparse_descriptor,
             translucent_descriptor,
-            bound_projection: Matrix4::zero(),
+            start_time: self.start_time,
         })
     }
 
@@ -85,13 +85,22 @@ impl EguiAdapter {
         client_state: &ClientState,
     ) -> Result<()> {
         let mut egui = self.egui_ui.lock();
-        if egui.wants_draw() {
+        let hud_wants_draw = client_state.hud.lock().has_server_elements();
+        if egui.wants_draw() || egui.has_toasts() || hud_wants_draw {
             self.gui_adapter.begin_frame();
-            egui.draw_all_uis(
-                &self.gui_adapter.egui_ctx,
-                self.atlas_texture_id,
-                client_state,
-            );
+            // Server HUD elements are drawn with egui as well, behind any popups
+            client_state
+                .hud
+                .lock()
+                .draw_server_elements(&self.gui_adapter.egui_ctx, self.atlas_texture_id);
+            if egui.wants_draw() {
+                egui.draw_all_uis(
+                    &self.gui_adapter.egui_ctx,
+                    self.atlas_texture_id,
+                    client_state,
+                );
+            }
+            egui.draw_toasts(&self.gui_adapter.egui_ctx);
             let cmdbuf = self
                 .gui_adapter
                 .draw_on_subpass_image([ctx.window_size().0, ctx.window_size().1]);
@@ -36,7 +36,7 @@ use vulkano::{
 };
 
 use crate::vulkan::{
-    shaders::{frag_lighting, vert_2d, PipelineProvider, PipelineWrapper},
+    shaders::{frag_flat_tex, vert_2d, PipelineProvider, PipelineWrapper},
     CommandBufferBuilder, Texture2DHolder, VulkanContext,
 };
 
@@ -204,7 +204,7 @@ pub(crate) struct FlatTexPipelineProvider {
 impl FlatTexPipelineProvider {
     pub(crate) fn new(device: Arc<Device>) -> Result<Self> {
         let vs = vert_2d::load_flat_tex(device.clone())?;
-        let fs = frag_lighting::load(device.clone())?;
+        let fs = frag_flat_tex::load(device.clone())?;
         Ok(FlatTexPipelineProvider { device, vs, fs })
     }
 }
@@ -34,9 +34,16 @@ pub(crate) mod vert_3d {
                 layout(location = 0) in vec3 position;
                 layout(location = 1) in vec2 uv_texcoord;
                 layout(location = 2) in float brightness;
+                layout(location = 3) in vec4 tint;
+                layout(location = 4) in uint anim_frames;
+                layout(location = 5) in uint anim_frame_millis;
+                layout(location = 6) in float anim_frame_stride;
+                layout(location = 7) in vec4 tex_rect;
 
                 layout(set = 1, binding = 0) uniform UniformData { 
                     mat4 vp_matrix;
+                    // Drives texture animations; wraps around after ~49 days
+                    uint time_millis;
                 };
                 // 64 bytes of push constants :(
                 layout(push_constant) uniform ModelMatrix {
@@ -45,11 +52,21 @@ pub(crate) mod vert_3d {
 
                 layout(location = 0) out vec2 uv_texcoord_out;
                 layout(location = 1) out float brightness_out;
+                layout(location = 2) out vec4 tint_out;
+                layout(location = 3) flat out vec4 tex_rect_out;
 
                 void main() {
                     gl_Position = vp_matrix * model_matrix * vec4(position, 1.0);
+                    // Animation frames are stacked vertically in the atlas, so move down
+                    // to the current one
+                    uint frame = 0;
+                    if (anim_frames > 1) {
+                        frame = (time_millis / anim_frame_millis) % anim_frames;
+                    }
+                    tex_rect_out = tex_rect + vec4(0.0, float(frame) * anim_frame_stride, 0.0, 0.0);
                     uv_texcoord_out = uv_texcoord;
                     brightness_out = brightness;
+                    tint_out = tint;
                 }
             "
             },
@@ -74,13 +91,21 @@ pub(crate) mod frag_lighting {
 
     layout(location = 0) in vec2 uv_texcoord;
     layout(location = 1) in float brightness;
+    layout(location = 2) in vec4 tint;
+    layout(location = 3) flat in vec4 tex_rect;
 
     layout(location = 0) out vec4 f_color;
     layout(set = 0, binding = 0) uniform sampler2D tex;
 
+    // uv_texcoord is in tile space, so wrap it into the texture's rectangle in the atlas. The
+    // gradients come from the unwrapped coordinate, so mip selection doesn't jump at the seams.
+    vec4 sample_tiled() {
+        vec2 uv = tex_rect.xy + fract(uv_texcoord) * tex_rect.zw;
+        return textureGrad(tex, uv, dFdx(uv_texcoord) * tex_rect.zw, dFdy(uv_texcoord) * tex_rect.zw);
+    }
+
     void main() {
-        // todo use brightness data
-        f_color = texture(tex, uv_texcoord);
+        f_color = sample_tiled() * tint * vec4(vec3(brightness), 1.0);
     }
     "
     }
@@ -95,13 +120,20 @@ pub(crate) mod frag_lighting_sparse {
 
     layout(location = 0) in vec2 uv_texcoord;
     layout(location = 1) in float brightness;
+    layout(location = 2) in vec4 tint;
+    layout(location = 3) flat in vec4 tex_rect;
 
     layout(location = 0) out vec4 f_color;
     layout(set = 0, binding = 0) uniform sampler2D tex;
 
+    // Same as in frag_lighting
+    vec4 sample_tiled() {
+        vec2 uv = tex_rect.xy + fract(uv_texcoord) * tex_rect.zw;
+        return textureGrad(tex, uv, dFdx(uv_texcoord) * tex_rect.zw, dFdy(uv_texcoord) * tex_rect.zw);
+    }
+
     void main() {
-        // todo brightness
-        f_color = texture(tex, uv_texcoord);
+        f_color = sample_tiled() * tint * vec4(vec3(brightness), 1.0);
         if (f_color.a < 0.5) {
             discard;
         } else {
@@ -143,6 +175,25 @@ pub(crate) mod vert_2d {
     }
 }
 
+// Fragment shader for flat textures drawn over the screen, without any lighting
+pub(crate) mod frag_flat_tex {
+    vulkano_shaders::shader! {
+    ty: "fragment",
+    src: r"
+    #version 460
+
+    layout(location = 0) in vec2 uv_texcoord;
+
+    layout(location = 0) out vec4 f_color;
+    layout(set = 0, binding = 0) uniform sampler2D tex;
+
+    void main() {
+        f_color = texture(tex, uv_texcoord);
+    }
+    "
+    }
+}
+
 // Fragment shader(s) that simply render colors directly
 pub(crate) mod frag_simple {
     vulkano_shaders::shader! {
@@ -44,19 +44,60 @@ pub mod items {
             InteractionRule {
                 block_group: vec![NOT_DIGGABLE.to_string()],
                 dig_behavior: None,
+                tool_wear: 0,
             },
             InteractionRule {
                 block_group: vec![TOOL_REQUIRED.to_string()],
                 dig_behavior: None,
+                tool_wear: 0,
             },
             InteractionRule {
                 block_group: vec![DEFAULT_SOLID.to_string()],
                 dig_behavior: Some(DigBehavior::ConstantTime(1.0)),
+                tool_wear: 0,
             },
         ]
     }
 }
 
+/// Parameters of the player's movement physics. The client simulates movement using these,
+/// and the server uses them to check that the client's reported movement is plausible.
+pub mod player_physics {
+    /// Width of the player's collision box, in blocks
+    pub const PLAYER_WIDTH: f64 = 0.75;
+    /// Distance from the player's eye to the top of the collision box
+    pub const EYE_TO_TOP: f64 = 0.2;
+    /// Distance from the player's eye to the bottom of the collision box
+    pub const EYE_TO_BTM: f64 = 1.5;
+    /// Obstacles up to this height can be walked over without jumping, when on the ground
+    pub const TRAVERSABLE_BUMP_HEIGHT_LANDED: f64 = 0.501;
+    /// Obstacles up to this height can be climbed over when in the air
+    pub const TRAVERSABLE_BUMP_HEIGHT_MIDAIR: f64 = 0.2;
+    /// Horizontal walking speed, blocks/sec, along each of the forward and sideways axes
+    pub const WALK_SPEED: f64 = 3.0;
+    /// Speed when flying, blocks/sec, along each axis
+    pub const FLY_SPEED: f64 = WALK_SPEED * 5.0;
+    /// Initial upward velocity of a jump, blocks/sec
+    pub const JUMP_VELOCITY: f64 = 6.0;
+    /// Downward acceleration, blocks/sec^2. Not quite earth gravity; tuned for a natural feeling
+    pub const GRAVITY_ACCEL: f64 = 16.;
+    /// Maximum falling speed, blocks/sec
+    pub const TERMINAL_VELOCITY: f64 = 90.;
+}
+
+/// Limits on how players interact with blocks in the world
+pub mod player_interaction {
+    /// How far from the player's eye (in blocks) they can point at, dig, and place blocks
+    pub const REACH_DISTANCE: f64 = 6.;
+}
+
+/// Limits on the chunks sent to clients
+pub mod chunks {
+    /// The most out-of-view chunks a client can keep cached. Servers cap the cache size that
+    /// clients ask for to this.
+    pub const MAX_CLIENT_CHUNK_CACHE_SIZE: u32 = 8192;
+}
+
 pub mod textures {
     /// A simple fallback texture.
     pub const FALLBACK_UNKNOWN_TEXTURE: &str = "builtin:unknown";
@@ -40,6 +40,9 @@ pub mod cuberef {
         pub mod ui {
             tonic::include_proto!("cuberef.protocol.ui");
         }
+        pub mod world {
+            tonic::include_proto!("cuberef.protocol.world");
+        }
         pub const DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("cuberef_descriptor");
     }
 }
@@ -140,7 +140,12 @@ impl BlockBuilder {
     /// Create a new block builder that will build a block and a corresponding inventory
     /// item for it.
     pub fn new(name: Block) -> BlockBuilder {
-        let name = name.0;
+        Self::from_name(name.0)
+    }
+
+    /// Same as [new](#method.new), for names that aren't known at compile time (e.g. when loaded
+    /// from content files).
+    pub(crate) fn from_name(name: &str) -> BlockBuilder {
         let item = Item {
             proto: ItemDef {
                 short_name: name.into(),
@@ -355,5 +360,6 @@ impl BlockBuilder {
 fn make_texture_ref(tex_name: String) -> Option<TextureReference> {
     Some(TextureReference {
         texture_name: tex_name,
+        ..Default::default()
     })
 }
@@ -12,15 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::atomic::AtomicU32;
+use std::{sync::atomic::AtomicU32, time::Duration};
 
 use crate::{
     blocks::BlockBuilder,
-    game_builder::{include_texture_bytes, Block, GameBuilder, Tex},
+    game_builder::{include_texture_bytes, Block, GameBuilder, Tex, TexRef},
 };
 use anyhow::Result;
 use cuberef_core::protocol::blocks::{block_type_def::PhysicsInfo, FluidPhysicsInfo};
-use cuberef_server::game_state::blocks::ExtDataHandling;
+use cuberef_server::game_state::{blocks::ExtDataHandling, inventory::BlockViewCallbacks};
 
 use super::block_groups::{BRITTLE, GRANULAR};
 
@@ -44,7 +44,9 @@ const DIRT_GRASS_SIDE_TEXTURE: Tex = Tex("default:dirt_grass_side");
 const GRASS_TOP_TEXTURE: Tex = Tex("default:grass_top");
 const STONE_TEXTURE: Tex = Tex("default:stone");
 const GLASS_TEXTURE: Tex = Tex("default:glass");
+// Vertical strip of WATER_FRAMES frames
 const WATER_TEXTURE: Tex = Tex("default:water");
+const WATER_FRAMES: u32 = 8;
 // TODO real chest texture
 const CHEST_TEXTURE: Tex = Tex("default:chest");
 
@@ -140,7 +142,9 @@ fn register_core_blocks(game_builder: &mut GameBuilder) -> Result<()> {
     let mut water_builder = BlockBuilder::new(WATER)
         .add_block_group(BRITTLE)
         .add_item_group("testonly_wet")
-        .set_texture_all(WATER_TEXTURE)
+        .set_texture_all(
+            TexRef::from(WATER_TEXTURE).animated(WATER_FRAMES, Duration::from_millis(150)),
+        )
         .set_inventory_display_name("Water block")
         .set_needs_translucency();
     water_builder.physics_info = PhysicsInfo::Fluid(FluidPhysicsInfo {
@@ -176,6 +180,7 @@ fn register_core_blocks(game_builder: &mut GameBuilder) -> Result<()> {
                                 true,
                                 true,
                                 false,
+                                BlockViewCallbacks::default(),
                             )?
                             .inventory_view_stored(
                                 "player_inv",
@@ -0,0 +1,763 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Content files are TOML files that define textures, blocks, items and recipes without
+//! writing (or recompiling) Rust code. They're loaded with
+//! [DefaultGameBuilder::load_content_file] or [DefaultGameBuilder::load_content_dir].
+//!
+//! A content file may contain any number of each kind of definition:
+//!
+//! ```toml
+//! # Texture files are looked up relative to the content file.
+//! [[texture]]
+//! name = "example:granite"
+//! file = "textures/granite.png"
+//!
+//! [[block]]
+//! name = "example:granite"
+//! display_name = "Granite"
+//! # Either `texture` for all faces, or a `textures` table with left, right, top,
+//! # bottom, front, back and inventory.
+//! texture = "example:granite"
+//! groups = ["default:brittle"]
+//! item_groups = []
+//! # Defaults to dropping the block's own item; use `no_drops = true` to drop nothing.
+//! drops = { item = "example:granite_rubble", count = 2 }
+//! # One of "opaque" (default), "transparent" or "translucent".
+//! transparency = "opaque"
+//!
+//! [[item]]
+//! name = "example:granite_rubble"
+//! display_name = "Granite rubble"
+//! texture = "example:granite"
+//! groups = ["example:rubble"]
+//! # At most one of `stack` and `wear`. If neither is given, the item stacks up to 256.
+//! stack = 256
+//!
+//! [[item]]
+//! name = "example:granite_pick"
+//! texture = "example:granite"
+//! wear = 100
+//! # If no rules are given, the item digs like a bare hand. If any are given, they
+//! # replace the bare-hand rules entirely.
+//! [[item.interaction_rules]]
+//! block_groups = ["default:brittle"]
+//! # One of "instant", "instant_oneshot", "undiggable", { constant_time = <seconds> }
+//! # or { scaled_time = <multiple of the block's dig time> }
+//! dig = { scaled_time = 0.5 }
+//! tool_wear = 1
+//!
+//! # Slots are "item_name", "" (empty), { item = "name", count = 2 } or
+//! # { group = "name", count = 2 }. Counts default to 1.
+//! # Shaped recipes list the 3x3 grid row by row; missing trailing slots are empty.
+//! [[crafting_recipe]]
+//! slots = ["example:granite", "example:granite", "",
+//!          "example:granite", "example:granite"]
+//! result = "example:granite_rubble"
+//! quantity = 4
+//!
+//! [[crafting_recipe]]
+//! shapeless = true
+//! slots = [{ group = "example:rubble", count = 4 }]
+//! result = "example:granite"
+//!
+//! [[smelting_recipe]]
+//! input = "example:granite_rubble"
+//! result = "default:stone"
+//! ticks = 20
+//!
+//! [[smelting_fuel]]
+//! input = { group = "example:flammable" }
+//! ticks = 40
+//! ```
+
+use std::{
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use cuberef_core::protocol::{self, render::TextureReference};
+use cuberef_server::game_state::items::ItemStack;
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use toml::Spanned;
+
+use crate::{
+    blocks::BlockBuilder,
+    items::{DigSpeed, ItemBuilder},
+};
+
+use super::{
+    recipes::{RecipeImpl, RecipeSlot},
+    DefaultGameBuilder,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContentFile {
+    #[serde(default)]
+    texture: Vec<Spanned<TextureEntry>>,
+    #[serde(default)]
+    block: Vec<Spanned<BlockEntry>>,
+    #[serde(default)]
+    item: Vec<Spanned<ItemEntry>>,
+    #[serde(default)]
+    crafting_recipe: Vec<Spanned<CraftingRecipeEntry>>,
+    #[serde(default)]
+    smelting_recipe: Vec<Spanned<SmeltingRecipeEntry>>,
+    #[serde(default)]
+    smelting_fuel: Vec<Spanned<SmeltingFuelEntry>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureEntry {
+    name: String,
+    file: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockEntry {
+    name: String,
+    display_name: Option<String>,
+    texture: Option<String>,
+    textures: Option<FaceTextures>,
+    #[serde(default)]
+    groups: Vec<String>,
+    #[serde(default)]
+    item_groups: Vec<String>,
+    drops: Option<DropEntry>,
+    #[serde(default)]
+    no_drops: bool,
+    #[serde(default)]
+    transparency: Transparency,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceTextures {
+    left: String,
+    right: String,
+    top: String,
+    bottom: String,
+    front: String,
+    back: String,
+    inventory: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DropEntry {
+    item: String,
+    #[serde(default = "one")]
+    count: u32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum Transparency {
+    #[default]
+    Opaque,
+    Transparent,
+    Translucent,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemEntry {
+    name: String,
+    display_name: Option<String>,
+    texture: Option<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+    stack: Option<u32>,
+    wear: Option<u32>,
+    interaction_rules: Option<Vec<InteractionRuleEntry>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InteractionRuleEntry {
+    #[serde(default)]
+    block_groups: Vec<String>,
+    dig: Option<DigEntry>,
+    #[serde(default)]
+    tool_wear: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DigEntry {
+    Instant,
+    InstantOneshot,
+    Undiggable,
+    ConstantTime(f64),
+    ScaledTime(f64),
+}
+impl From<DigEntry> for DigSpeed {
+    fn from(value: DigEntry) -> Self {
+        match value {
+            DigEntry::Instant => DigSpeed::Instant,
+            DigEntry::InstantOneshot => DigSpeed::InstantOneshot,
+            DigEntry::Undiggable => DigSpeed::Undiggable,
+            DigEntry::ConstantTime(x) => DigSpeed::ConstantTime(x),
+            DigEntry::ScaledTime(x) => DigSpeed::ScaledTime(x),
+        }
+    }
+}
+
+// Deserialized by hand rather than as an untagged enum, so that a malformed slot table
+// reports what's actually wrong with it.
+enum SlotEntry {
+    /// An exact item, or an empty slot if the string is empty
+    Exact(String),
+    Item {
+        item: String,
+        count: u32,
+    },
+    Group {
+        group: String,
+        count: u32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SlotTable {
+    item: Option<String>,
+    group: Option<String>,
+    #[serde(default = "one")]
+    count: u32,
+}
+
+impl<'de> Deserialize<'de> for SlotEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SlotVisitor;
+        impl<'de> Visitor<'de> for SlotVisitor {
+            type Value = SlotEntry;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(
+                    "an item name, or a table with `item` or `group` and an optional `count`",
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<SlotEntry, E> {
+                Ok(SlotEntry::Exact(v.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SlotEntry, A::Error> {
+                let table = SlotTable::deserialize(MapAccessDeserializer::new(map))?;
+                match (table.item, table.group) {
+                    (Some(item), None) => Ok(SlotEntry::Item {
+                        item,
+                        count: table.count,
+                    }),
+                    (None, Some(group)) => Ok(SlotEntry::Group {
+                        group,
+                        count: table.count,
+                    }),
+                    (Some(_), Some(_)) => Err(de::Error::custom(
+                        "a slot may have `item` or `group`, but not both",
+                    )),
+                    (None, None) => Err(de::Error::custom("a slot table needs `item` or `group`")),
+                }
+            }
+        }
+        deserializer.deserialize_any(SlotVisitor)
+    }
+}
+impl SlotEntry {
+    fn into_recipe_slot(self) -> Result<RecipeSlot> {
+        Ok(match self {
+            SlotEntry::Exact(x) if x.is_empty() => RecipeSlot::Empty,
+            SlotEntry::Exact(x) => RecipeSlot::Exact(x),
+            SlotEntry::Item { count: 0, .. } | SlotEntry::Group { count: 0, .. } => {
+                bail!("Slot count must be at least 1")
+            }
+            SlotEntry::Item { item, count: 1 } => RecipeSlot::Exact(item),
+            SlotEntry::Item { item, count } => RecipeSlot::ExactMultiple(item, count),
+            SlotEntry::Group { group, count: 1 } => RecipeSlot::Group(group),
+            SlotEntry::Group { group, count } => RecipeSlot::GroupMultiple(group, count),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CraftingRecipeEntry {
+    slots: Vec<SlotEntry>,
+    result: String,
+    #[serde(default = "one")]
+    quantity: u32,
+    #[serde(default)]
+    shapeless: bool,
+    #[serde(default = "yes")]
+    stackable: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SmeltingRecipeEntry {
+    input: SlotEntry,
+    result: String,
+    #[serde(default = "one")]
+    quantity: u32,
+    ticks: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SmeltingFuelEntry {
+    input: SlotEntry,
+    ticks: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+fn yes() -> bool {
+    true
+}
+
+/// Tracks which file we're loading, to point errors at the definition that caused them.
+struct SourceFile<'a> {
+    path: &'a Path,
+    contents: &'a str,
+}
+impl SourceFile<'_> {
+    fn locate(&self, span: Range<usize>) -> String {
+        let line = self.contents[..span.start.min(self.contents.len())]
+            .matches('\n')
+            .count()
+            + 1;
+        format!("{}:{}", self.path.display(), line)
+    }
+}
+
+pub(crate) fn load_content_dir(game_builder: &mut DefaultGameBuilder, dir: &Path) -> Result<()> {
+    let mut files = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read content directory {}", dir.display()))?
+        .map(|entry| entry.map(|x| x.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read content directory {}", dir.display()))?;
+    files.retain(|x| x.is_file() && x.extension().is_some_and(|ext| ext == "toml"));
+    files.sort();
+    for file in files {
+        load_content_file(game_builder, &file)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn load_content_file(game_builder: &mut DefaultGameBuilder, path: &Path) -> Result<()> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read content file {}", path.display()))?;
+    let content: ContentFile =
+        toml::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    let source = SourceFile {
+        path,
+        contents: &contents,
+    };
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+
+    for entry in content.texture {
+        let location = source.locate(entry.span());
+        let texture = entry.into_inner();
+        game_builder
+            .inner
+            .inner
+            .media_mut()
+            .register_from_file(&texture.name, base_dir.join(&texture.file))
+            .with_context(|| format!("{}: Failed to load texture {}", location, texture.name))?;
+    }
+    for entry in content.block {
+        let location = source.locate(entry.span());
+        let name = entry.get_ref().name.clone();
+        register_block(game_builder, entry.into_inner())
+            .with_context(|| format!("{}: Failed to register block {}", location, name))?;
+    }
+    for entry in content.item {
+        let location = source.locate(entry.span());
+        let name = entry.get_ref().name.clone();
+        register_item(game_builder, entry.into_inner())
+            .with_context(|| format!("{}: Failed to register item {}", location, name))?;
+    }
+    for entry in content.crafting_recipe {
+        let location = source.locate(entry.span());
+        register_crafting_recipe(game_builder, entry.into_inner())
+            .with_context(|| format!("{}: Failed to register crafting recipe", location))?;
+    }
+    for entry in content.smelting_recipe {
+        let location = source.locate(entry.span());
+        register_smelting_recipe(game_builder, entry.into_inner())
+            .with_context(|| format!("{}: Failed to register smelting recipe", location))?;
+    }
+    for entry in content.smelting_fuel {
+        let location = source.locate(entry.span());
+        register_smelting_fuel(game_builder, entry.into_inner())
+            .with_context(|| format!("{}: Failed to register smelting fuel", location))?;
+    }
+    Ok(())
+}
+
+fn texture_ref(name: String) -> TextureReference {
+    TextureReference {
+        texture_name: name,
+        ..Default::default()
+    }
+}
+
+fn register_block(game_builder: &mut DefaultGameBuilder, entry: BlockEntry) -> Result<()> {
+    let mut block = BlockBuilder::from_name(&entry.name);
+    block = match (entry.texture, entry.textures) {
+        (Some(_), Some(_)) => bail!("Only one of texture and textures may be set"),
+        (Some(texture), None) => block.set_texture_all(texture_ref(texture)),
+        (None, Some(textures)) => block.set_individual_textures(
+            texture_ref(textures.left),
+            texture_ref(textures.right),
+            texture_ref(textures.top),
+            texture_ref(textures.bottom),
+            texture_ref(textures.front),
+            texture_ref(textures.back),
+            texture_ref(textures.inventory),
+        ),
+        (None, None) => block,
+    };
+    if let Some(display_name) = &entry.display_name {
+        block = block.set_inventory_display_name(display_name);
+    }
+    for group in &entry.groups {
+        block = block.add_block_group(group);
+    }
+    for group in &entry.item_groups {
+        block = block.add_item_group(group);
+    }
+    block = match (entry.drops, entry.no_drops) {
+        (Some(_), true) => bail!("Only one of drops and no_drops may be set"),
+        (Some(drops), false) => block.set_dropped_item(&drops.item, drops.count),
+        (None, true) => block.set_no_drops(),
+        (None, false) => block,
+    };
+    block = match entry.transparency {
+        Transparency::Opaque => block,
+        Transparency::Transparent => block.set_needs_transparency(),
+        Transparency::Translucent => block.set_needs_translucency(),
+    };
+    game_builder.inner.add_block(block)?;
+    Ok(())
+}
+
+fn register_item(game_builder: &mut DefaultGameBuilder, entry: ItemEntry) -> Result<()> {
+    let mut item = ItemBuilder::from_name(&entry.name);
+    if let Some(display_name) = &entry.display_name {
+        item = item.set_display_name(display_name);
+    }
+    if let Some(texture) = entry.texture {
+        item = item.set_inventory_texture(texture_ref(texture));
+    }
+    for group in &entry.groups {
+        item = item.add_item_group(group);
+    }
+    item = match (entry.stack, entry.wear) {
+        (Some(_), Some(_)) => bail!("Only one of stack and wear may be set"),
+        (Some(stack), None) => item.set_stack_size(stack),
+        (None, Some(wear)) => item.set_wear(wear),
+        (None, None) => item,
+    };
+    if let Some(rules) = entry.interaction_rules {
+        // Rules given in a content file replace the defaults entirely
+        item = item.clear_default_interaction_rules();
+        for rule in rules {
+            let block_groups: Vec<&str> = rule.block_groups.iter().map(String::as_str).collect();
+            item = item.add_interaction_rule(
+                &block_groups,
+                rule.dig.map(DigSpeed::from),
+                rule.tool_wear,
+            );
+        }
+    }
+    game_builder.inner.add_item(item)
+}
+
+fn make_result(item_name: String, quantity: u32, stackable: bool) -> Result<ItemStack> {
+    ensure!(quantity > 0, "Result quantity must be at least 1");
+    Ok(ItemStack {
+        proto: protocol::items::ItemStack {
+            item_name,
+            quantity,
+            max_stack: if stackable { 256 } else { quantity },
+            stackable,
+        },
+    })
+}
+
+fn register_crafting_recipe(
+    game_builder: &mut DefaultGameBuilder,
+    entry: CraftingRecipeEntry,
+) -> Result<()> {
+    ensure!(
+        entry.slots.len() <= 9,
+        "Crafting recipes have at most 9 slots, but {} were given",
+        entry.slots.len()
+    );
+    let mut slots = entry
+        .slots
+        .into_iter()
+        .map(SlotEntry::into_recipe_slot)
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(
+        slots.iter().any(|x| *x != RecipeSlot::Empty),
+        "Crafting recipes need at least one non-empty slot"
+    );
+    slots.resize(9, RecipeSlot::Empty);
+    game_builder.crafting_recipes.register_recipe(RecipeImpl {
+        // unwrap is fine - we just resized to the right length
+        slots: slots.try_into().unwrap(),
+        result: make_result(entry.result, entry.quantity, entry.stackable)?,
+        shapeless: entry.shapeless,
+        metadata: (),
+    });
+    Ok(())
+}
+
+fn smelting_input(input: SlotEntry) -> Result<RecipeSlot> {
+    let slot = input.into_recipe_slot()?;
+    ensure!(slot != RecipeSlot::Empty, "Input must not be empty");
+    Ok(slot)
+}
+
+fn register_smelting_recipe(
+    game_builder: &mut DefaultGameBuilder,
+    entry: SmeltingRecipeEntry,
+) -> Result<()> {
+    game_builder.smelting_recipes.register_recipe(RecipeImpl {
+        slots: [smelting_input(entry.input)?],
+        result: make_result(entry.result, entry.quantity, true)?,
+        shapeless: false,
+        metadata: entry.ticks,
+    });
+    Ok(())
+}
+
+fn register_smelting_fuel(
+    game_builder: &mut DefaultGameBuilder,
+    entry: SmeltingFuelEntry,
+) -> Result<()> {
+    game_builder.smelting_fuels.register_recipe(RecipeImpl {
+        slots: [smelting_input(entry.input)?],
+        result: ItemStack {
+            proto: Default::default(),
+        },
+        shapeless: false,
+        metadata: entry.ticks,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<ContentFile, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    fn slots(entry: CraftingRecipeEntry) -> Vec<RecipeSlot> {
+        entry
+            .slots
+            .into_iter()
+            .map(|x| x.into_recipe_slot().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_content() {
+        let content = parse(
+            r#"
+[[texture]]
+name = "example:granite"
+file = "textures/granite.png"
+
+[[block]]
+name = "example:granite"
+texture = "example:granite"
+drops = { item = "example:rubble", count = 2 }
+transparency = "translucent"
+
+[[item]]
+name = "example:pick"
+wear = 100
+[[item.interaction_rules]]
+block_groups = ["default:brittle"]
+dig = { scaled_time = 0.5 }
+
+[[crafting_recipe]]
+slots = ["example:granite", "", { item = "example:rubble", count = 3 },
+         { group = "example:stone" }, { item = "example:rubble" }]
+result = "example:granite"
+
+[[smelting_fuel]]
+input = { group = "example:flammable", count = 2 }
+ticks = 40
+"#,
+        )
+        .unwrap();
+        assert_eq!(content.texture.len(), 1);
+        assert_eq!(content.block.len(), 1);
+        assert_eq!(content.item.len(), 1);
+        assert!(matches!(
+            content.block[0].get_ref().transparency,
+            Transparency::Translucent
+        ));
+
+        let recipe = content.crafting_recipe.into_iter().next().unwrap();
+        assert_eq!(
+            slots(recipe.into_inner()),
+            vec![
+                RecipeSlot::Exact("example:granite".to_string()),
+                RecipeSlot::Empty,
+                RecipeSlot::ExactMultiple("example:rubble".to_string(), 3),
+                RecipeSlot::Group("example:stone".to_string()),
+                RecipeSlot::Exact("example:rubble".to_string()),
+            ]
+        );
+        let fuel = content
+            .smelting_fuel
+            .into_iter()
+            .next()
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            fuel.input.into_recipe_slot().unwrap(),
+            RecipeSlot::GroupMultiple("example:flammable".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn test_unknown_slot_field() {
+        let error = parse(
+            r#"
+[[crafting_recipe]]
+result = "example:granite"
+slots = ["example:granite", { item = "example:rubble", cout = 3 }]
+"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("unknown field `cout`"), "{error}");
+        assert!(error.contains("line 4"), "{error}");
+    }
+
+    #[test]
+    fn test_ambiguous_slot() {
+        let error = parse(
+            r#"
+[[smelting_fuel]]
+ticks = 10
+input = { item = "example:log", group = "example:wood" }
+"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("not both"), "{error}");
+        assert!(error.contains("line 4"), "{error}");
+
+        let error = parse(
+            r#"
+[[smelting_fuel]]
+ticks = 10
+input = { count = 2 }
+"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("needs `item` or `group`"), "{error}");
+    }
+
+    #[test]
+    fn test_wrong_slot_type() {
+        let error = parse(
+            r#"
+[[smelting_fuel]]
+ticks = 10
+input = 5
+"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("an item name, or a table"), "{error}");
+    }
+
+    #[test]
+    fn test_unknown_top_level_field() {
+        let error = parse(
+            r#"
+[[block]]
+name = "example:granite"
+textur = "example:granite"
+"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("unknown field `textur`"), "{error}");
+        assert!(error.contains("line 4"), "{error}");
+    }
+
+    #[test]
+    fn test_zero_count_rejected() {
+        let content = parse(
+            r#"
+[[crafting_recipe]]
+result = "example:granite"
+slots = [{ item = "example:rubble", count = 0 }]
+"#,
+        )
+        .unwrap();
+        let recipe = content.crafting_recipe.into_iter().next().unwrap();
+        let slot = recipe.into_inner().slots.into_iter().next().unwrap();
+        assert!(slot.into_recipe_slot().is_err());
+    }
+
+    #[test]
+    fn test_locate() {
+        let contents = "[[block]]\nname = \"a\"\n\n[[block]]\nname = \"b\"\n";
+        let source = SourceFile {
+            path: Path::new("content/blocks.toml"),
+            contents,
+        };
+        let content = parse(contents).unwrap();
+        let locations: Vec<_> = content
+            .block
+            .iter()
+            .map(|x| source.locate(x.span()))
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                format!("{}:1", Path::new("content/blocks.toml").display()),
+                format!("{}:4", Path::new("content/blocks.toml").display()),
+            ]
+        );
+    }
+}
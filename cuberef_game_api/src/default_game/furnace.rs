@@ -1,16 +1,19 @@
 use std::{any::Any, collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
-use cuberef_core::constants;
+use cuberef_core::{constants, coordinates::BlockCoordinate};
 use cuberef_server::game_state::{
     blocks::{
         BlockTypeHandle, CustomData, ExtDataHandling, ExtendedData, ExtendedDataHolder,
         InlineContext,
     },
-    client_ui::Popup,
+    client_ui::{Popup, PopupHandle},
+    event::HandlerContext,
     game_map::{TimerCallback, TimerInlineCallback, TimerSettings},
+    inventory::BlockViewCallbacks,
     items::{ItemStack, MaybeStack},
 };
+use parking_lot::Mutex;
 use prost::Message;
 
 use crate::{
@@ -43,7 +46,7 @@ pub struct FurnaceState {
     flame_left: u32,
 
     /// Total amount of flame provided from the last fuel that was burned
-    /// This is used as the denominator for displaying a fuel usage icon (not implemented)
+    /// This is used as the denominator for the fuel bar in the furnace popup
     #[prost(uint32, tag = "2")]
     total_flame: u32,
 
@@ -61,11 +64,33 @@ pub struct FurnaceState {
     smelted_item: String,
 }
 
+impl FurnaceState {
+    fn flame_fraction(&self) -> f32 {
+        if self.total_flame == 0 {
+            0.0
+        } else {
+            self.flame_left as f32 / self.total_flame as f32
+        }
+    }
+    fn smelt_fraction(&self) -> f32 {
+        if self.smelt_ticks == 0 || self.smelted_item.is_empty() {
+            0.0
+        } else {
+            self.smelt_progress as f32 / self.smelt_ticks as f32
+        }
+    }
+}
+
+/// Handles to the furnace popups that players currently have open, so that the timer can
+/// keep their fuel and progress bars up to date.
+type FurnacePopups = Arc<Mutex<HashMap<BlockCoordinate, Vec<PopupHandle>>>>;
+
 struct FurnaceTimerCallback {
     recipes: Arc<RecipeBook<1, u32>>,
     fuels: Arc<RecipeBook<1, u32>>,
     furnace_off_handle: BlockTypeHandle,
     furnace_on_handle: BlockTypeHandle,
+    open_popups: FurnacePopups,
 }
 impl TimerInlineCallback for FurnaceTimerCallback {
     fn inline_callback(
@@ -75,6 +100,21 @@ impl TimerInlineCallback for FurnaceTimerCallback {
         block_type: &mut BlockTypeHandle,
         data: &mut ExtendedDataHolder,
         ctx: &InlineContext,
+    ) -> Result<()> {
+        let result = self.run_tick(coordinate, missed_timers, block_type, data, ctx);
+        self.update_open_popups(coordinate, data);
+        result
+    }
+}
+
+impl FurnaceTimerCallback {
+    fn run_tick(
+        &self,
+        coordinate: BlockCoordinate,
+        missed_timers: u64,
+        block_type: &mut BlockTypeHandle,
+        data: &mut ExtendedDataHolder,
+        ctx: &InlineContext,
     ) -> Result<()> {
         if missed_timers > 0 {
             log::warn!("Unimplemented: FurnaceTimerCallback with missed_timers");
@@ -114,11 +154,9 @@ impl TimerInlineCallback for FurnaceTimerCallback {
             // Try to light the furnace, if we have both a valid fuel and a valid input
             if let Some(fueling_recipe) = self.fuels.find(ctx.items(), &[fuel_stack]) {
                 if self.recipes.find(ctx.items(), &[input_stack]).is_some() {
-                    if fuel_stack
-                        .try_take_all(Some(fueling_recipe.slots[0].quantity()))
-                        .is_some()
-                    {
+                    if fueling_recipe.consume(ctx.items(), std::slice::from_mut(fuel_stack)) {
                         state.flame_left = fueling_recipe.metadata;
+                        state.total_flame = fueling_recipe.metadata;
                         set_dirty = true;
                         self.set_appearance_on(block_type);
                     }
@@ -167,20 +205,21 @@ impl TimerInlineCallback for FurnaceTimerCallback {
             if state.smelt_progress >= state.smelt_ticks {
                 // unwrap should be fine - if we started smelting it, we have a recipe (see above)
                 let recipe = self.recipes.find(ctx.items(), &[input_stack]).unwrap();
-                let can_take_input = input_stack.as_ref().is_some_and(|x| x.proto.quantity >= recipe.slots[0].quantity());
+                let can_take_input = recipe
+                    .input_quantities(ctx.items(), &[input_stack])
+                    .is_some();
                 if !can_take_input {
-                    // The input item got yoinked
-                    // This can only happen once recipes support taking more than one item, but we still want to be ready for that feature
+                    // The input item got yoinked, or there are fewer items left than the recipe takes
                     state.smelted_item = "".to_string();
                     state.smelt_progress = 0;
                     return Ok(());
                 } else if output_stack.try_merge_all(Some(recipe.result.clone())) {
-                    if input_stack.try_take_all(Some(recipe.slots[0].quantity())).is_none() {
+                    if !recipe.consume(ctx.items(), std::slice::from_mut(input_stack)) {
                         log::warn!("Couldn't take items we thought were in the stack. This should not happen.");
                     }
 
                     state.smelt_progress = 0;
-                    // We should recheck the recipe; our input stack may have run out, or in the future recipes might require more than one input
+                    // We should recheck the recipe; our input stack may have run out, or may have fewer items than the recipe takes
                     let recipe = self.recipes.find(ctx.items(), &[input_stack]);
                     match recipe {
                         Some(recipe) => {
@@ -200,9 +239,31 @@ impl TimerInlineCallback for FurnaceTimerCallback {
         }
         Ok(())
     }
-}
 
-impl FurnaceTimerCallback {
+    fn update_open_popups(&self, coordinate: BlockCoordinate, data: &ExtendedDataHolder) {
+        let mut open_popups = self.open_popups.lock();
+        let handles = match open_popups.get_mut(&coordinate) {
+            Some(x) => x,
+            None => return,
+        };
+        let state = data
+            .as_ref()
+            .and_then(|x| x.custom_data.as_ref())
+            .and_then(|x| x.downcast_ref::<FurnaceState>())
+            .cloned()
+            .unwrap_or_default();
+        // Handles stop working once their popup is closed; forget about those
+        handles.retain(|handle| {
+            handle
+                .set_progress(FURNACE_FLAME_BAR, state.flame_fraction())
+                .and_then(|_| handle.set_progress(FURNACE_SMELT_BAR, state.smelt_fraction()))
+                .is_ok()
+        });
+        if handles.is_empty() {
+            open_popups.remove(&coordinate);
+        }
+    }
+
     fn shut_down_furnace(&self, block_type: &mut BlockTypeHandle, state: &mut FurnaceState) -> bool {
 
         let dirty_from_blocktype = if block_type.equals_ignore_variant(self.furnace_on_handle) {
@@ -248,6 +309,17 @@ pub(crate) fn register_furnace(game_builder: &mut DefaultGameBuilder) -> Result<
         "textures/furnace_on_front.png"
     )?;
 
+    let open_popups = FurnacePopups::default();
+    let furnace_off_popup = furnace_popup_handler(
+        game_builder.smelting_recipes.clone(),
+        game_builder.smelting_fuels.clone(),
+        open_popups.clone(),
+    );
+    let furnace_on_popup = furnace_popup_handler(
+        game_builder.smelting_recipes.clone(),
+        game_builder.smelting_fuels.clone(),
+        open_popups.clone(),
+    );
     let furnace_off_handle = game_builder.inner.add_block(
         BlockBuilder::new(FURNACE)
             .add_block_group(BRITTLE)
@@ -261,9 +333,9 @@ pub(crate) fn register_furnace(game_builder: &mut DefaultGameBuilder) -> Result<
                 FURNACE_ON_FRONT_TEXTURE,
             )
             .set_inventory_display_name("Furnace")
-            .set_modifier(Box::new(|bt| {
+            .set_modifier(Box::new(move |bt| {
                 bt.extended_data_handling = ExtDataHandling::ServerSide;
-                bt.interact_key_handler = Some(Box::new(make_furnace_popup));
+                bt.interact_key_handler = Some(furnace_off_popup);
                 bt.dig_handler_inline = Some(Box::new(furnace_dig_handler));
                 bt.deserialize_extended_data_handler = Some(Box::new(furnace_deserialize));
                 bt.serialize_extended_data_handler = Some(Box::new(furnace_serialize));
@@ -283,9 +355,9 @@ pub(crate) fn register_furnace(game_builder: &mut DefaultGameBuilder) -> Result<
             )
             .set_inventory_display_name("Lit furnace (should not see this)")
             .set_dropped_item(FURNACE.0, 1)
-            .set_modifier(Box::new(|bt| {
+            .set_modifier(Box::new(move |bt| {
                 bt.extended_data_handling = ExtDataHandling::ServerSide;
-                bt.interact_key_handler = Some(Box::new(make_furnace_popup));
+                bt.interact_key_handler = Some(furnace_on_popup);
                 bt.dig_handler_inline = Some(Box::new(furnace_dig_handler));
                 bt.deserialize_extended_data_handler = Some(Box::new(furnace_deserialize));
                 bt.serialize_extended_data_handler = Some(Box::new(furnace_serialize));
@@ -297,6 +369,7 @@ pub(crate) fn register_furnace(game_builder: &mut DefaultGameBuilder) -> Result<
         fuels: game_builder.smelting_fuels.clone(),
         furnace_off_handle: furnace_off_handle.0,
         furnace_on_handle: furnace_on_handle.0,
+        open_popups,
     };
     game_builder.inner.inner.add_timer(
         "default:furnace_timer",
@@ -395,11 +468,44 @@ fn furnace_dig_handler(
 const FURNACE_INPUT: &str = "furnace_input";
 const FURNACE_FUEL: &str = "furnace_fuel";
 const FURNACE_OUTPUT: &str = "furnace_output";
+const FURNACE_FLAME_BAR: &str = "furnace_flame";
+const FURNACE_SMELT_BAR: &str = "furnace_smelt";
+
+type PopupHandler = dyn Fn(HandlerContext, BlockCoordinate) -> Result<Option<Popup>> + Send + Sync;
+
+fn furnace_popup_handler(
+    recipes: Arc<RecipeBook<1, u32>>,
+    fuels: Arc<RecipeBook<1, u32>>,
+    open_popups: FurnacePopups,
+) -> Box<PopupHandler> {
+    Box::new(move |ctx, coord| {
+        let popup = make_furnace_popup(ctx, coord, &recipes, &fuels)?;
+        if let Some(popup) = &popup {
+            let mut open_popups = open_popups.lock();
+            let handles = open_popups.entry(coord).or_default();
+            handles.retain(|x| x.is_open());
+            handles.push(popup.handle());
+        }
+        Ok(popup)
+    })
+}
 
 fn make_furnace_popup(
-    ctx: cuberef_server::game_state::event::HandlerContext<'_>,
-    coord: cuberef_core::coordinates::BlockCoordinate,
+    ctx: HandlerContext<'_>,
+    coord: BlockCoordinate,
+    recipes: &RecipeBook<1, u32>,
+    fuels: &RecipeBook<1, u32>,
 ) -> Result<Option<Popup>> {
+    let state = ctx
+        .game_map()
+        .get_block_with_extended_data(coord, |ext| {
+            ext.custom_data
+                .as_ref()
+                .and_then(|x| x.downcast_ref::<FurnaceState>())
+                .cloned()
+        })?
+        .1
+        .unwrap_or_default();
     match ctx.initiator() {
         cuberef_server::game_state::event::EventInitiator::Engine => Ok(None),
         cuberef_server::game_state::event::EventInitiator::Player(p) => Ok(Some(
@@ -414,6 +520,10 @@ fn make_furnace_popup(
                     true,
                     true,
                     false,
+                    BlockViewCallbacks {
+                        slot_filters: vec![recipes.slot_filter(0)],
+                        on_change: None,
+                    },
                 )?
                 .label("Fuel:")
                 .inventory_view_block(
@@ -424,7 +534,13 @@ fn make_furnace_popup(
                     true,
                     true,
                     false,
+                    BlockViewCallbacks {
+                        slot_filters: vec![fuels.slot_filter(0)],
+                        on_change: None,
+                    },
                 )?
+                .progress_bar(FURNACE_FLAME_BAR, state.flame_fraction(), "Fuel")
+                .progress_bar(FURNACE_SMELT_BAR, state.smelt_fraction(), "Smelting")
                 .label("Output:")
                 .inventory_view_block(
                     FURNACE_OUTPUT,
@@ -434,6 +550,7 @@ fn make_furnace_popup(
                     false,
                     true,
                     false,
+                    BlockViewCallbacks::default(),
                 )?
                 .inventory_view_stored("player_inv", p.main_inventory(), true, true)?,
         )),
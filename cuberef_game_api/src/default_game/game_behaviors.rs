@@ -2,60 +2,150 @@ use anyhow::Result;
 use parking_lot::RwLock;
 use std::sync::Arc;
 
+use cuberef_core::protocol::items::item_def::QuantityType;
 use cuberef_server::game_state::{
     client_ui::{Popup, PopupAction, PopupResponse},
-    inventory::{InventoryKey, VirtualOutputCallbacks},
-    items::ItemStack,
+    inventory::{InventoryKey, VirtualInputCallbacks, VirtualOutputCallbacks},
+    items::{Item, ItemManager, ItemStack},
     GameState,
 };
 
-use super::{recipes::RecipeBook, DefaultGameBuilder};
+use super::{item_groups::HIDDEN_FROM_CREATIVE, recipes::RecipeBook, DefaultGameBuilder};
+
+/// Decides, given a player's name, whether they may use the creative inventory.
+pub(crate) type CreativeAccessCheck = Arc<RwLock<Box<dyn Fn(&str) -> bool + Send + Sync>>>;
+
+const CREATIVE_DIMENSIONS: (u32, u32) = (4, 8);
+const CREATIVE_PAGE_SIZE: usize = (CREATIVE_DIMENSIONS.0 * CREATIVE_DIMENSIONS.1) as usize;
 
 pub(crate) fn register_game_behaviors(game_builder: &mut DefaultGameBuilder) -> Result<()> {
     let recipe_book = game_builder.crafting_recipes.clone();
+    let creative_access_check = game_builder.creative_access_check.clone();
     game_builder
         .inner
         .inner
         .game_behaviors_mut()
-        .make_inventory_popup = Box::new(move |game_state, _, inv_key| {
-        make_inventory_popup(game_state, inv_key, recipe_book.clone())
+        .make_inventory_popup = Box::new(move |game_state, player_name, inv_key| {
+        make_inventory_popup(
+            game_state,
+            player_name,
+            inv_key,
+            recipe_book.clone(),
+            creative_access_check.clone(),
+        )
     });
 
     Ok(())
 }
 
+/// What a player's creative inventory is currently showing.
+struct CreativeState {
+    /// Names of the items matching the current search, in the order they're shown
+    items: Vec<String>,
+    page: usize,
+    /// How many of each stackable item to show
+    count: u32,
+}
+impl CreativeState {
+    fn num_pages(&self) -> usize {
+        self.items.len().div_ceil(CREATIVE_PAGE_SIZE).max(1)
+    }
+    fn page_contents(&self) -> &[String] {
+        let start = (self.page * CREATIVE_PAGE_SIZE).min(self.items.len());
+        let end = (start + CREATIVE_PAGE_SIZE).min(self.items.len());
+        &self.items[start..end]
+    }
+}
+
+/// Finds the items to show in the creative inventory, i.e. those whose name, display name or
+/// groups contain the query (case-insensitively), sorted by name.
+fn search_creative_items(items: &ItemManager, query: &str) -> Vec<String> {
+    let query = query.trim().to_lowercase();
+    let mut result: Vec<String> = items
+        .registered_items()
+        .filter(|item| {
+            !item
+                .proto
+                .groups
+                .iter()
+                .any(|group| group == HIDDEN_FROM_CREATIVE)
+        })
+        .filter(|item| {
+            query.is_empty()
+                || item.proto.short_name.to_lowercase().contains(&query)
+                || item.proto.display_name.to_lowercase().contains(&query)
+                || item
+                    .proto
+                    .groups
+                    .iter()
+                    .any(|group| group.to_lowercase().contains(&query))
+        })
+        .map(|item| item.proto.short_name.clone())
+        .collect();
+    result.sort();
+    result
+}
+
+fn make_creative_stack(item: &Item, count: u32) -> ItemStack {
+    match item.proto.quantity_type {
+        // Tools start out with full wear, no matter what count was asked for
+        Some(QuantityType::Wear(wear)) => ItemStack::new(item, wear),
+        _ => ItemStack::new(item, count),
+    }
+}
+
 #[allow(clippy::unnecessary_unwrap)]
 fn make_inventory_popup(
     game_state: Arc<GameState>,
+    player_name: String,
     main_inventory_key: InventoryKey,
     crafting_recipes: Arc<RecipeBook<9, ()>>,
+    creative_access_check: CreativeAccessCheck,
 ) -> Result<Popup> {
-    // Generally all the callbacks would reference a more interesting arc that backs the inventory
-    // For this example, we only need to store one vec
-    let mut all_items = game_state
-        .item_manager()
-        .registered_items()
-        .map(|x| Some(ItemStack::new(x, 256)))
-        .collect::<Vec<Option<ItemStack>>>();
-
-    // TODO pagination
-    all_items.resize_with(32, || None);
-    let all_items_for_peek = Arc::new(RwLock::new(all_items));
-    let all_items_for_take = all_items_for_peek.clone();
-    let all_items_for_update = all_items_for_peek.clone();
-    let creative_inv_callbacks = VirtualOutputCallbacks {
-        peek: Box::new(move |_| {
-            log::info!("Debug only: peeking from creative");
-            all_items_for_peek.read().clone()
-        }),
-        take: Box::new(move |_, slot, count| {
-            log::info!("Debug only: taking from creative");
-            let mut stack = all_items_for_take.read().get(slot).cloned().flatten();
-            if stack.is_some() && count.is_some() {
-                stack.as_mut().unwrap().proto.quantity = count.unwrap();
-            }
-            stack
-        }),
+    let creative_allowed = (creative_access_check.read())(&player_name);
+    let creative_state = Arc::new(RwLock::new(CreativeState {
+        items: search_creative_items(game_state.item_manager(), ""),
+        page: 0,
+        count: 256,
+    }));
+
+    let creative_inv_callbacks = {
+        let state_for_peek = creative_state.clone();
+        let state_for_take = creative_state.clone();
+        let game_state_for_peek = game_state.clone();
+        let game_state_for_take = game_state.clone();
+        VirtualOutputCallbacks {
+            peek: Box::new(move |_| {
+                let state = state_for_peek.read();
+                let mut stacks: Vec<Option<ItemStack>> = state
+                    .page_contents()
+                    .iter()
+                    .map(|name| {
+                        game_state_for_peek
+                            .item_manager()
+                            .get_item(name)
+                            .map(|item| make_creative_stack(item, state.count))
+                    })
+                    .collect();
+                stacks.resize_with(CREATIVE_PAGE_SIZE, || None);
+                stacks
+            }),
+            take: Box::new(move |_, slot, count| {
+                // Check again, in case the player's access was revoked after they joined
+                if !(creative_access_check.read())(&player_name) {
+                    return None;
+                }
+                let state = state_for_take.read();
+                let item = game_state_for_take
+                    .item_manager()
+                    .get_item(state.page_contents().get(slot)?)?;
+                let mut stack = make_creative_stack(item, state.count);
+                if count.is_some() && stack.proto.stackable {
+                    stack.proto.quantity = count.unwrap();
+                }
+                Some(stack)
+            }),
+        }
     };
 
     let peek = {
@@ -86,54 +176,106 @@ fn make_inventory_popup(
                 game_state.item_manager(),
                 input.iter().collect::<Vec<_>>().as_slice(),
             );
-            if result.is_some() {
-                for (i, item) in input.iter().enumerate() {
-                    if item.is_some() {
-                        source_view.take(ctx, i, Some(1)).unwrap();
+            let recipe = result?;
+            let mut remaining = input.clone();
+            if !recipe.consume(game_state.item_manager(), &mut remaining) {
+                return None;
+            }
+            for (i, (before, after)) in input.iter().zip(remaining.iter()).enumerate() {
+                // Slots that were emptied (including non-stackable items such as tools) are
+                // taken whole
+                let count = match (before, after) {
+                    (Some(before), Some(after)) if before.proto.quantity > after.proto.quantity => {
+                        Some(before.proto.quantity - after.proto.quantity)
                     }
-                }
+                    (Some(_), None) => None,
+                    _ => continue,
+                };
+                source_view.take(ctx, i, count).unwrap();
             }
-            result.map(|x| x.result.clone())
+            Some(recipe.result.clone())
         })
     };
 
     let crafting_callbacks = VirtualOutputCallbacks { peek, take };
 
+    // Destroys anything that's put into it
+    let trash_callbacks = VirtualInputCallbacks {
+        peek: Box::new(|_| vec![None]),
+        put: Box::new(|_, _, _| None),
+    };
+
     let button_callback = {
         let game_state = game_state.clone();
+        let creative_state = creative_state.clone();
         move |response: PopupResponse| {
             if let PopupAction::ButtonClicked(x) = &response.user_action {
-                if x == "update_btn" {
-                    if let Some(count) = response
-                        .textfield_values
-                        .get("count")
-                        .and_then(|x| x.parse::<u32>().ok())
-                    {
-                        if count >= 1 {
-                            let mut all_items = game_state
-                                .item_manager()
-                                .registered_items()
-                                .map(|x| Some(ItemStack::new(x, count)))
-                                .collect::<Vec<Option<ItemStack>>>();
-                            all_items.resize_with(32, || None);
-                            *all_items_for_update.write() = all_items;
+                let mut state = creative_state.write();
+                match x.as_str() {
+                    "update_btn" => {
+                        if let Some(count) = response
+                            .textfield_values
+                            .get("count")
+                            .and_then(|x| x.parse::<u32>().ok())
+                        {
+                            if count >= 1 {
+                                state.count = count;
+                            }
                         }
+                        let query = response
+                            .textfield_values
+                            .get("search")
+                            .map(String::as_str)
+                            .unwrap_or("");
+                        state.items = search_creative_items(game_state.item_manager(), query);
+                        state.page = 0;
+                    }
+                    "prev_page" => {
+                        state.page = state.page.saturating_sub(1);
+                    }
+                    "next_page" => {
+                        state.page = (state.page + 1).min(state.num_pages() - 1);
                     }
+                    _ => {}
                 }
             }
         }
     };
 
-    Ok(Popup::new(game_state)
-        .title("Inventory")
-        .text_field("count", "Item count: ", "256", true)
-        .button("update_btn", "Update", true)
-        .label("Creative items:")
-        .inventory_view_virtual_output("creative", (4, 8), creative_inv_callbacks, false)?
+    let mut popup = Popup::new(game_state).title("Inventory");
+    if creative_allowed {
+        popup = popup
+            .text_field("search", "Search: ", "", true)
+            .text_field("count", "Item count: ", "256", true)
+            .button("update_btn", "Update", true)
+            .label("Creative items:")
+            .inventory_view_virtual_output(
+                "creative",
+                CREATIVE_DIMENSIONS,
+                creative_inv_callbacks,
+                false,
+            )?
+            .horizontal(|p| {
+                Ok(p.button("prev_page", "Previous page", true)
+                    .dynamic_label(move |_| {
+                        let state = creative_state.read();
+                        format!(
+                            "Page {} of {} ({} items)",
+                            state.page + 1,
+                            state.num_pages(),
+                            state.items.len()
+                        )
+                    })
+                    .button("next_page", "Next page", true))
+            })?;
+    }
+    Ok(popup
         .label("Crafting input:")
         .inventory_view_transient("craft_in", (3, 3), vec![], true, true)?
         .label("Crafting output:")
         .inventory_view_virtual_output("craft_out", (1, 1), crafting_callbacks, true)?
+        .label("Trash:")
+        .inventory_view_virtual_input("trash", (1, 1), trash_callbacks)?
         .label("Player inventory:")
         .inventory_view_stored("main", main_inventory_key, true, true)?
         .set_button_callback(button_callback))
@@ -0,0 +1,211 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::OnceLock;
+
+use cuberef_core::coordinates::BlockCoordinate;
+use noise::NoiseFn;
+
+use crate::default_game::basic_blocks::{DIRT, DIRT_WITH_GRASS, STONE, WATER};
+
+const CLIMATE_INPUT_SCALE: f64 = 1.0 / 600.0;
+
+/// Describes what fills the space between the terrain and sea level (y = 0)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaterBehavior {
+    /// Fill with the given block, e.g. water
+    Fill(String),
+    /// Fill with `fill`, except at sea level where `surface` is placed instead (e.g. ice on top of water)
+    FillWithSurface {
+        /// The block filling the body of water
+        fill: String,
+        /// The block placed at sea level
+        surface: String,
+    },
+    /// Leave the space empty (air), e.g. for dry basins
+    Dry,
+}
+
+/// A single-block decoration placed on top of the surface of a biome, e.g. a plant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoration {
+    /// The block to place
+    pub block: String,
+    /// The probability, per surface block above sea level, of placing this decoration.
+    /// The sum of all chances in a biome should not exceed 1.
+    pub chance: f64,
+}
+
+/// A biome in the default mapgen.
+///
+/// Biomes are selected by two noise fields, temperature and humidity, each of which
+/// ranges from approximately -1 to 1. At each (x, z) column, the biome whose preferred
+/// temperature and humidity are closest to the local climate is used.
+///
+/// All blocks are given by name and are resolved when the map generator is started;
+/// the game will fail to start if any of them are not registered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    /// The unique name of this biome, e.g. default:grassland
+    pub name: String,
+    /// Preferred temperature, approximately in the range [-1, 1]
+    pub temperature: f64,
+    /// Preferred humidity, approximately in the range [-1, 1]
+    pub humidity: f64,
+    /// The topmost block of the terrain
+    pub surface_block: String,
+    /// The block placed between the surface and the underlying stone
+    pub filler_block: String,
+    /// Minimum depth of filler blocks below the surface
+    pub min_filler_depth: u32,
+    /// Maximum depth of filler blocks below the surface. The actual depth varies smoothly
+    /// between the minimum and maximum.
+    pub max_filler_depth: u32,
+    /// What to do with the space between the terrain and sea level
+    pub water: WaterBehavior,
+    /// Decorations placed on the surface, when the surface is above sea level
+    pub decorations: Vec<Decoration>,
+}
+
+/// Provides lookups of the biome at a given location. An Arc of this can be obtained
+/// from [crate::default_game::DefaultGameBuilder::biome_map] while the game is being set up,
+/// and captured by handlers.
+///
+/// Lookups will return None until the map generator has been started (i.e. before the game
+/// server starts).
+pub struct BiomeMap {
+    state: OnceLock<BiomeMapState>,
+}
+
+struct BiomeMapState {
+    biomes: Vec<Biome>,
+    temperature: noise::SuperSimplex,
+    humidity: noise::SuperSimplex,
+}
+
+impl BiomeMap {
+    pub(crate) fn new() -> BiomeMap {
+        BiomeMap {
+            state: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn initialize(&self, biomes: Vec<Biome>, seed: u32) {
+        assert!(!biomes.is_empty(), "At least one biome must be registered");
+        let state = BiomeMapState {
+            biomes,
+            temperature: noise::SuperSimplex::new(seed.wrapping_add(2)),
+            humidity: noise::SuperSimplex::new(seed.wrapping_add(3)),
+        };
+        if self.state.set(state).is_err() {
+            log::error!("Biome map was initialized twice; ignoring second initialization");
+        }
+    }
+
+    /// Returns the biome at the given coordinate. Biomes currently only vary with
+    /// x and z; the y coordinate is ignored.
+    pub fn biome_at(&self, coord: BlockCoordinate) -> Option<&Biome> {
+        let state = self.state.get()?;
+        Some(&state.biomes[state.biome_index(coord.x, coord.z)])
+    }
+
+    /// Returns the (temperature, humidity) at the given coordinate.
+    pub fn climate_at(&self, coord: BlockCoordinate) -> Option<(f64, f64)> {
+        Some(self.state.get()?.climate(coord.x, coord.z))
+    }
+
+    /// Returns all biomes, in registration order.
+    pub fn biomes(&self) -> &[Biome] {
+        self.state
+            .get()
+            .map(|x| x.biomes.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the index (into [BiomeMap::biomes]) of the biome for the given column.
+    /// Panics if not initialized.
+    pub(crate) fn biome_index(&self, x: i32, z: i32) -> usize {
+        self.state
+            .get()
+            .expect("Biome map not initialized")
+            .biome_index(x, z)
+    }
+}
+
+impl BiomeMapState {
+    fn climate(&self, x: i32, z: i32) -> (f64, f64) {
+        let pos = [
+            x as f64 * CLIMATE_INPUT_SCALE,
+            z as f64 * CLIMATE_INPUT_SCALE,
+        ];
+        (self.temperature.get(pos), self.humidity.get(pos))
+    }
+
+    fn biome_index(&self, x: i32, z: i32) -> usize {
+        let (temperature, humidity) = self.climate(x, z);
+        let mut best = 0;
+        let mut best_distance = f64::INFINITY;
+        for (i, biome) in self.biomes.iter().enumerate() {
+            let distance =
+                (biome.temperature - temperature).powi(2) + (biome.humidity - humidity).powi(2);
+            if distance < best_distance {
+                best = i;
+                best_distance = distance;
+            }
+        }
+        best
+    }
+}
+
+pub(crate) fn default_biomes() -> Vec<Biome> {
+    vec![
+        Biome {
+            name: "default:grassland".to_string(),
+            temperature: 0.0,
+            humidity: 0.0,
+            surface_block: DIRT_WITH_GRASS.0.to_string(),
+            filler_block: DIRT.0.to_string(),
+            min_filler_depth: 2,
+            max_filler_depth: 5,
+            water: WaterBehavior::Fill(WATER.0.to_string()),
+            decorations: vec![],
+        },
+        Biome {
+            name: "default:rocky_highlands".to_string(),
+            temperature: -0.6,
+            humidity: -0.2,
+            surface_block: STONE.0.to_string(),
+            filler_block: STONE.0.to_string(),
+            min_filler_depth: 0,
+            max_filler_depth: 0,
+            water: WaterBehavior::Fill(WATER.0.to_string()),
+            decorations: vec![],
+        },
+        Biome {
+            name: "default:barrens".to_string(),
+            temperature: 0.6,
+            humidity: -0.6,
+            surface_block: DIRT.0.to_string(),
+            filler_block: DIRT.0.to_string(),
+            min_filler_depth: 3,
+            max_filler_depth: 7,
+            water: WaterBehavior::Dry,
+            // Scattered rocks
+            decorations: vec![Decoration {
+                block: STONE.0.to_string(),
+                chance: 0.02,
+            }],
+        },
+    ]
+}
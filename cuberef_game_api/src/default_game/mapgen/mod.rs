@@ -1,17 +1,20 @@
 use std::sync::Arc;
 
-use cuberef_core::{
-    constants::blocks::AIR,
-    coordinates::{ChunkCoordinate, ChunkOffset},
-};
+use anyhow::{Context, Result};
+use cuberef_core::{constants::blocks::AIR, coordinates::ChunkOffset};
 use cuberef_server::game_state::{
     blocks::{BlockTypeHandle, BlockTypeManager},
     game_map::MapChunk,
-    mapgen::MapgenInterface,
+    mapgen::{MapgenContext, MapgenStageHandler},
 };
 use noise::NoiseFn;
 
-use super::basic_blocks::{DIRT, DIRT_WITH_GRASS, STONE, WATER};
+use self::biomes::{Biome, BiomeMap, WaterBehavior};
+
+use super::basic_blocks::STONE;
+
+/// Biomes and their selection
+pub mod biomes;
 
 const ELEVATION_FINE_INPUT_SCALE: f64 = 1.0 / 100.0;
 const ELEVATION_FINE_OUTPUT_SCALE: f64 = 20.0;
@@ -47,17 +50,135 @@ impl ElevationNoise {
     }
 }
 
+const FILLER_DEPTH_INPUT_SCALE: f64 = 1.0 / 40.0;
+
+enum ResolvedWater<B> {
+    Fill(B),
+    FillWithSurface { fill: B, surface: B },
+    Dry,
+}
+
+/// A biome with all of its block names resolved, normally to handles.
+struct ResolvedBiome<B = BlockTypeHandle> {
+    surface: B,
+    filler: B,
+    min_filler_depth: u32,
+    max_filler_depth: u32,
+    water: ResolvedWater<B>,
+    // (cumulative probability, block)
+    decorations: Vec<(f64, B)>,
+}
+impl<B: Copy> ResolvedBiome<B> {
+    fn resolve(biome: &Biome, get: impl Fn(&str) -> Result<B>) -> Result<ResolvedBiome<B>> {
+        let water = match &biome.water {
+            WaterBehavior::Fill(fill) => ResolvedWater::Fill(get(fill)?),
+            WaterBehavior::FillWithSurface { fill, surface } => ResolvedWater::FillWithSurface {
+                fill: get(fill)?,
+                surface: get(surface)?,
+            },
+            WaterBehavior::Dry => ResolvedWater::Dry,
+        };
+        let mut cumulative = 0.0;
+        let decorations = biome
+            .decorations
+            .iter()
+            .map(|x| {
+                cumulative += x.chance;
+                Ok((cumulative, get(&x.block)?))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ResolvedBiome {
+            surface: get(&biome.surface_block)?,
+            filler: get(&biome.filler_block)?,
+            min_filler_depth: biome.min_filler_depth,
+            max_filler_depth: biome.max_filler_depth.max(biome.min_filler_depth),
+            water,
+            decorations,
+        })
+    }
+
+    /// Maps a noise value in [-1, 1] to a filler depth
+    fn filler_depth(&self, noise: f64) -> i32 {
+        let range = (self.max_filler_depth - self.min_filler_depth) as f64;
+        let t = ((noise + 1.0) / 2.0).clamp(0.0, 1.0);
+        self.min_filler_depth as i32 + (t * range).round() as i32
+    }
+
+    fn decoration(&self, random: f64) -> Option<B> {
+        self.decorations
+            .iter()
+            .find(|(cumulative, _)| random < *cumulative)
+            .map(|(_, block)| *block)
+    }
+
+    /// Returns the block at height y, in a column whose surface is at the given elevation.
+    /// `random` is the column's value from [column_random].
+    fn block_at(
+        &self,
+        y: i32,
+        elevation: i32,
+        filler_depth: i32,
+        random: f64,
+        air: B,
+        stone: B,
+    ) -> B {
+        let vert_offset = y - elevation;
+        if vert_offset > 0 {
+            if y > 0 {
+                if vert_offset == 1 {
+                    self.decoration(random).unwrap_or(air)
+                } else {
+                    air
+                }
+            } else {
+                match self.water {
+                    ResolvedWater::Fill(fill) => fill,
+                    ResolvedWater::FillWithSurface { fill, surface } => {
+                        if y == 0 {
+                            surface
+                        } else {
+                            fill
+                        }
+                    }
+                    ResolvedWater::Dry => air,
+                }
+            }
+        } else if vert_offset == 0 {
+            self.surface
+        } else if vert_offset >= -filler_depth {
+            self.filler
+        } else {
+            stone
+        }
+    }
+}
+
+/// Deterministic per-column pseudorandom value in [0, 1)
+fn column_random(seed: u32, x: i32, z: i32) -> f64 {
+    // splitmix64 finalizer
+    let mut v =
+        ((seed as u64) << 32) ^ ((x as u32 as u64) << 16) ^ (z as u32 as u64).rotate_left(40);
+    v = v.wrapping_add(0x9e3779b97f4a7c15);
+    v = (v ^ (v >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    v = (v ^ (v >> 27)).wrapping_mul(0x94d049bb133111eb);
+    v ^= v >> 31;
+    (v >> 11) as f64 / (1u64 << 53) as f64
+}
+
 struct DefaultMapgen {
     air: BlockTypeHandle,
-    dirt: BlockTypeHandle,
-    dirt_grass: BlockTypeHandle,
     stone: BlockTypeHandle,
-    water: BlockTypeHandle,
 
+    seed: u32,
     elevation_noise: Box<ElevationNoise>,
+    filler_depth_noise: noise::SuperSimplex,
+    biome_map: Arc<BiomeMap>,
+    // Same order as biome_map.biomes()
+    biomes: Vec<ResolvedBiome>,
 }
-impl MapgenInterface for DefaultMapgen {
-    fn fill_chunk(&self, coord: ChunkCoordinate, chunk: &mut MapChunk) {
+impl MapgenStageHandler for DefaultMapgen {
+    fn generate(&self, context: &MapgenContext<'_>, chunk: &mut MapChunk) -> Result<()> {
+        let coord = context.coord();
         // todo subdivide by surface vs underground, etc. This is a very minimal MVP
         for x in 0..16 {
             for z in 0..16 {
@@ -65,40 +186,155 @@ impl MapgenInterface for DefaultMapgen {
                 let zg = 16 * coord.z + (z as i32);
 
                 let elevation = self.elevation_noise.get(xg, zg);
+                let biome = &self.biomes[self.biome_map.biome_index(xg, zg)];
+                let filler_depth = biome.filler_depth(self.filler_depth_noise.get([
+                    xg as f64 * FILLER_DEPTH_INPUT_SCALE,
+                    zg as f64 * FILLER_DEPTH_INPUT_SCALE,
+                ]));
+                let random = column_random(self.seed, xg, zg);
                 for y in 0..16 {
                     let offset = ChunkOffset { x, y, z };
                     let coord2 = coord.with_offset(offset);
                     debug_assert!(coord2.chunk() == coord);
 
-                    let vert_offset = coord2.y - elevation;
-                    let block = if vert_offset > 0 {
-                        if coord2.y > 0 {
-                            self.air
-                        } else {
-                            self.water
-                        }
-                    } else if vert_offset == 0 {
-                        self.dirt_grass
-                    } else if vert_offset > -3 {
-                        // todo variable depth of dirt
-                        self.dirt
-                    } else {
-                        self.stone
-                    };
+                    let block = biome.block_at(
+                        coord2.y,
+                        elevation,
+                        filler_depth,
+                        random,
+                        self.air,
+                        self.stone,
+                    );
                     chunk.set_block(offset, block, None);
                 }
             }
         }
+        Ok(())
     }
 }
 
-pub(crate) fn build_mapgen(blocks: Arc<BlockTypeManager>, seed: u32) -> Arc<dyn MapgenInterface> {
-    Arc::new(DefaultMapgen {
-        air: blocks.get_by_name(AIR).expect("air"),
-        dirt: blocks.get_by_name(DIRT.0).expect("dirt"),
-        dirt_grass: blocks.get_by_name(DIRT_WITH_GRASS.0).expect("dirt_grass"),
-        stone: blocks.get_by_name(STONE.0).expect("stone"),
-        water: blocks.get_by_name(WATER.0).expect("water"),
+pub(crate) fn build_mapgen(
+    blocks: Arc<BlockTypeManager>,
+    seed: u32,
+    biome_map: Arc<BiomeMap>,
+    biomes: Vec<Biome>,
+) -> Result<Box<dyn MapgenStageHandler>> {
+    let get = |name: &str| {
+        blocks
+            .get_by_name(name)
+            .with_context(|| format!("Unknown block {name}"))
+    };
+    let resolved_biomes = biomes
+        .iter()
+        .map(|biome| {
+            ResolvedBiome::resolve(biome, get)
+                .with_context(|| format!("Failed to resolve biome {}", biome.name))
+        })
+        .collect::<Result<_>>()?;
+    biome_map.initialize(biomes, seed);
+    Ok(Box::new(DefaultMapgen {
+        air: get(AIR)?,
+        stone: get(STONE.0)?,
+        seed,
         elevation_noise: ElevationNoise::new(seed),
-    })
+        filler_depth_noise: noise::SuperSimplex::new(seed.wrapping_add(4)),
+        biome_map,
+        biomes: resolved_biomes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+
+    use super::*;
+
+    fn barrens() -> ResolvedBiome<&'static str> {
+        let biome = biomes::default_biomes()
+            .into_iter()
+            .find(|biome| biome.name == "default:barrens")
+            .unwrap();
+        ResolvedBiome::resolve(&biome, |name| match name {
+            "default:dirt" => Ok("dirt"),
+            "default:stone" => Ok("stone"),
+            _ => bail!("unexpected block {name}"),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unknown_block() {
+        let biome = biomes::default_biomes()
+            .into_iter()
+            .find(|biome| biome.name == "default:barrens")
+            .unwrap();
+        let resolved = ResolvedBiome::resolve(&biome, |name| match name {
+            "default:dirt" => Ok("dirt"),
+            _ => bail!("unknown block {name}"),
+        });
+        assert_eq!(
+            resolved.err().unwrap().to_string(),
+            "unknown block default:stone"
+        );
+    }
+
+    #[test]
+    fn test_default_biomes_decorated() {
+        let biomes = biomes::default_biomes();
+        assert!(biomes.iter().any(|biome| !biome.decorations.is_empty()));
+        for biome in biomes {
+            let total: f64 = biome.decorations.iter().map(|x| x.chance).sum();
+            assert!(total <= 1.0, "{} decorations are too likely", biome.name);
+        }
+    }
+
+    #[test]
+    fn test_decoration_placement() {
+        let biome = barrens();
+        let column = |elevation, random| {
+            (elevation - 3..=elevation + 3)
+                .map(|y| biome.block_at(y, elevation, 1, random, "air", "stone"))
+                .collect::<Vec<_>>()
+        };
+        // Directly on top of the surface
+        assert_eq!(
+            column(5, 0.0),
+            ["stone", "stone", "dirt", "dirt", "stone", "air", "air"]
+        );
+        assert_eq!(
+            column(5, 0.5),
+            ["stone", "stone", "dirt", "dirt", "air", "air", "air"]
+        );
+        // Not below sea level, even in a dry biome
+        assert_eq!(
+            column(-1, 0.0),
+            ["stone", "stone", "dirt", "dirt", "air", "air", "air"]
+        );
+        assert_eq!(biome.block_at(1, 0, 1, 0.0, "air", "stone"), "stone");
+    }
+
+    #[test]
+    fn test_decoration_frequency() {
+        let biome = barrens();
+        let columns = 200 * 200;
+        let decorated = (0..200)
+            .flat_map(|x| (0..200).map(move |z| (x, z)))
+            .filter(|&(x, z)| biome.decoration(column_random(42, x, z)).is_some())
+            .count();
+        let fraction = decorated as f64 / columns as f64;
+        assert!((0.015..0.025).contains(&fraction), "{fraction}");
+    }
+
+    #[test]
+    fn test_column_random() {
+        for x in -50..50 {
+            for z in -50..50 {
+                let value = column_random(7, x, z);
+                assert!((0.0..1.0).contains(&value));
+                assert_eq!(value, column_random(7, x, z));
+            }
+        }
+        assert_ne!(column_random(7, 1, 2), column_random(8, 1, 2));
+        assert_ne!(column_random(7, 1, 2), column_random(7, 2, 1));
+    }
 }
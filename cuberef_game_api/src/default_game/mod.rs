@@ -12,23 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{sync::Arc, time::Duration};
+use std::{path::Path, sync::Arc, time::Duration};
 
 use crate::game_builder::GameBuilder;
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
+use parking_lot::RwLock;
 
 use cuberef_core::coordinates::BlockCoordinate;
 use cuberef_server::game_state::{
     blocks::{BlockTypeHandle, ExtendedDataHolder},
     game_map::{TimerCallback, TimerInlineCallback, TimerSettings},
     items::ItemStack,
+    mapgen::MapgenStage,
 };
 
-use self::recipes::{RecipeBook, RecipeImpl, RecipeSlot};
+use self::{
+    game_behaviors::CreativeAccessCheck,
+    mapgen::biomes::{Biome, BiomeMap},
+    recipes::{RecipeBook, RecipeImpl, RecipeSlot},
+};
 
 /// Blocks defined in the default game.
 pub mod basic_blocks;
+/// Loading blocks, items and recipes from TOML files
+pub mod content_files;
 /// Basic server behaviors not covered in other modules
 pub mod game_behaviors;
 /// Recipes for crafting, smelting, etc
@@ -71,6 +79,10 @@ pub struct DefaultGameBuilder {
     // Metadata is number of furnace timer ticks (period tbd) that the fuel lasts for
     // Output item is ignored
     smelting_fuels: Arc<RecipeBook<1, u32>>,
+
+    biomes: Vec<Biome>,
+    biome_map: Arc<BiomeMap>,
+    creative_access_check: CreativeAccessCheck,
 }
 impl DefaultGameBuilder {
     /// Provides access to the [GameBuilder] that this DefaultGameBuilder is wrapping,
@@ -98,7 +110,14 @@ impl DefaultGameBuilder {
             crafting_recipes: Arc::new(RecipeBook::new()),
             smelting_recipes: Arc::new(RecipeBook::new()),
             smelting_fuels: Arc::new(RecipeBook::new()),
+            biomes: Vec::new(),
+            biome_map: Arc::new(BiomeMap::new()),
+            creative_access_check: Arc::new(RwLock::new(Box::new(|_| true))),
         };
+        builder.inner.set_game_info(
+            concat!(env!("CARGO_PKG_NAME"), "/default_game"),
+            env!("CARGO_PKG_VERSION"),
+        );
         register_defaults(&mut builder)?;
         Ok(builder)
     }
@@ -111,6 +130,23 @@ impl DefaultGameBuilder {
         unimplemented!()
     }
 
+    /// Registers a biome for the default mapgen.
+    ///
+    /// Fails if a biome with the same name is already registered.
+    pub fn register_biome(&mut self, biome: Biome) -> Result<()> {
+        if self.biomes.iter().any(|x| x.name == biome.name) {
+            bail!("Biome {} already registered", biome.name);
+        }
+        self.biomes.push(biome);
+        Ok(())
+    }
+
+    /// Returns an Arc for the biome map of this game, which can be captured by handlers
+    /// to look up the biome at a given location once the game is running.
+    pub fn biome_map(&self) -> Arc<BiomeMap> {
+        self.biome_map.clone()
+    }
+
     /// Returns an Arc for the crafting recipes in this game.
     pub fn crafting_recipes(&mut self) -> Arc<RecipeBook<9, ()>> {
         self.crafting_recipes.clone()
@@ -127,6 +163,32 @@ impl DefaultGameBuilder {
         result: String,
         quantity: u32,
         stackable: bool,
+    ) {
+        self.register_crafting_recipe_impl(slots, result, quantity, stackable, false)
+    }
+    /// Registers a new shapeless crafting recipe. The non-empty slots may be filled in any
+    /// order and in any position in the crafting grid; empty slots only serve as padding.
+    ///
+    /// Quantity and stackable behave as in [register_crafting_recipe](#method.register_crafting_recipe).
+    ///
+    /// **This API is subject to change.**
+    pub fn register_shapeless_crafting_recipe(
+        &mut self,
+        slots: [RecipeSlot; 9],
+        result: String,
+        quantity: u32,
+        stackable: bool,
+    ) {
+        self.register_crafting_recipe_impl(slots, result, quantity, stackable, true)
+    }
+
+    fn register_crafting_recipe_impl(
+        &mut self,
+        slots: [RecipeSlot; 9],
+        result: String,
+        quantity: u32,
+        stackable: bool,
+        shapeless: bool,
     ) {
         self.crafting_recipes.register_recipe(RecipeImpl {
             slots,
@@ -138,18 +200,53 @@ impl DefaultGameBuilder {
                     stackable,
                 },
             },
-            shapeless: false,
+            shapeless,
             metadata: (),
         })
     }
 
+    /// Sets which players may use the creative inventory, given their name. By default,
+    /// every player may.
+    ///
+    /// This is checked when the player's inventory popup is built, as well as each time they
+    /// take an item from the creative inventory.
+    pub fn set_creative_access_check(
+        &mut self,
+        check: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) {
+        *self.creative_access_check.write() = Box::new(check);
+    }
+
+    /// Loads textures, blocks, items and recipes from a TOML content file. See [content_files]
+    /// for the file format.
+    ///
+    /// Errors name the file and line of the definition that couldn't be loaded.
+    ///
+    /// **This API is subject to change.**
+    pub fn load_content_file(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        content_files::load_content_file(self, path.as_ref())
+    }
+
+    /// Loads every `.toml` file directly inside the given directory as a content file,
+    /// in order of their file names.
+    ///
+    /// **This API is subject to change.**
+    pub fn load_content_dir(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        content_files::load_content_dir(self, path.as_ref())
+    }
+
     /// Starts a game based on this builder.
     pub fn build_and_run(mut self) -> Result<()> {
         self.crafting_recipes.sort();
         self.smelting_recipes.sort();
-        self.game_builder()
-            .inner
-            .set_mapgen(|blocks, seed| mapgen::build_mapgen(blocks, seed));
+        ensure!(!self.biomes.is_empty(), "No biomes registered");
+        let biomes = std::mem::take(&mut self.biomes);
+        let biome_map = self.biome_map.clone();
+        self.game_builder().inner.add_mapgen_stage(
+            MapgenStage::Terrain,
+            "default:terrain",
+            move |blocks, seed| mapgen::build_mapgen(blocks, seed, biome_map, biomes),
+        );
         // let timer_settings = TimerSettings {
         //     interval: Duration::from_secs(5),
         //     shards: 4,
@@ -216,5 +313,8 @@ fn register_defaults(game_builder: &mut DefaultGameBuilder) -> Result<()> {
     game_behaviors::register_game_behaviors(game_builder)?;
     recipes::register_test_recipes(game_builder);
     furnace::register_furnace(game_builder)?;
+    for biome in mapgen::biomes::default_biomes() {
+        game_builder.register_biome(biome)?;
+    }
     Ok(())
 }
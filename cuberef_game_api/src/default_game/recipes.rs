@@ -1,5 +1,6 @@
 use cuberef_server::game_state::{
-    items::{Item, ItemManager, ItemStack},
+    inventory::SlotFilter,
+    items::{Item, ItemManager, ItemStack, MaybeStack},
     GameState,
 };
 use parking_lot::RwLock;
@@ -14,9 +15,12 @@ use super::basic_blocks::DIRT_WITH_GRASS;
 /// Once a recipe is registered, it cannot be removed (in the current impl).
 ///
 /// When the recipe book is sorted, ambiguous matches are tiebroken by most
-/// specific to least specific (sort key is number of slots expecting an item
-/// group rather than specific items). Beyond this sort key, there is no guarantee
-/// the sort is stable.
+/// specific to least specific. The sort key is, in order:
+/// * the number of slots expecting an item group rather than specific items (fewer first)
+/// * whether the recipe is shapeless (shaped recipes first)
+/// * the total number of items consumed (more first)
+///
+/// Beyond this sort key, there is no guarantee the sort is stable.
 ///
 /// When a recipe is added, the recipe book is no longer considered sorted until [`sort()`](#method.sort)
 /// is called. When the book is not sorted, ambiguous matches are broken arbitrarily.
@@ -41,30 +45,51 @@ impl<const N: usize, T: Clone> RecipeBook<N, T> {
         items: &ItemManager,
         stacks: &[&Option<ItemStack>],
     ) -> Option<RecipeImpl<N, T>> {
-        if stacks.len() != N {
-            log::error!("Invalid stacks length passed to Recipes::find()");
-            return None;
-        }
-        let stacks: Vec<Option<&Item>> = stacks
-            .iter()
-            .map(|x| x.as_ref().and_then(|y| items.get_item(&y.proto.item_name)))
-            .collect();
-
-        let stacks = match stacks.try_into() {
-            Ok(x) => x,
-            Err(_) => {
-                log::error!("Conversion from Vec<Option<&Item>> to [Option<&Item>; N] failed; this should not happen");
-                return None;
-            }
-        };
+        let stacks = resolve_stacks(items, stacks)?;
+        self.find_resolved(&stacks)
+    }
 
+    fn find_resolved(&self, stacks: &[Option<(&Item, u32)>; N]) -> Option<RecipeImpl<N, T>> {
         self.recipes
             .read()
             .iter()
-            .find(|x| x.matches(&stacks))
+            .find(|x| x.matches(stacks))
             .map(|x| (*x).clone())
     }
 
+    /// Returns a filter accepting the items that can go into the given slot of at least one
+    /// recipe in this book, e.g. to only allow fuel into a furnace's fuel slot. Any slot of a
+    /// shapeless recipe counts.
+    ///
+    /// This reflects the recipes registered at the time it's called.
+    pub fn slot_filter(&self, slot: usize) -> SlotFilter {
+        let mut groups = vec![];
+        let mut items = vec![];
+        for recipe in self.recipes.read().iter() {
+            let slots = if recipe.shapeless {
+                &recipe.slots[..]
+            } else {
+                recipe.slots.get(slot..=slot).unwrap_or(&[])
+            };
+            for recipe_slot in slots {
+                match recipe_slot {
+                    RecipeSlot::Empty => {}
+                    RecipeSlot::Group(group) | RecipeSlot::GroupMultiple(group, _) => {
+                        groups.push(group.clone())
+                    }
+                    RecipeSlot::Exact(item) | RecipeSlot::ExactMultiple(item, _) => {
+                        items.push(item.clone())
+                    }
+                }
+            }
+        }
+        groups.sort();
+        groups.dedup();
+        items.sort();
+        items.dedup();
+        SlotFilter::OneOf { groups, items }
+    }
+
     maybe_export!(
         /// Adds a recipe to this recipe book
         fn register_recipe(&self, recipe: Recipe<N, T>) {
@@ -73,9 +98,40 @@ impl<const N: usize, T: Clone> RecipeBook<N, T> {
     );
 }
 
+/// Looks up the item for each stack, along with how many items the stack holds.
+/// Items without a stack count (e.g. tools with wear) always count as a single item.
+fn resolve_stacks<'a, const N: usize>(
+    items: &'a ItemManager,
+    stacks: &[&Option<ItemStack>],
+) -> Option<[Option<(&'a Item, u32)>; N]> {
+    if stacks.len() != N {
+        log::error!("Invalid stacks length passed to Recipes::find()");
+        return None;
+    }
+    let stacks: Vec<Option<(&Item, u32)>> = stacks
+        .iter()
+        .map(|x| {
+            x.as_ref().and_then(|y| {
+                let count = if y.proto.stackable {
+                    y.proto.quantity
+                } else {
+                    1
+                };
+                items.get_item(&y.proto.item_name).map(|item| (item, count))
+            })
+        })
+        .collect();
+
+    match stacks.try_into() {
+        Ok(x) => Some(x),
+        Err(_) => {
+            log::error!("Conversion from Vec<Option<(&Item, u32)>> to [Option<(&Item, u32)>; N] failed; this should not happen");
+            None
+        }
+    }
+}
+
 /// Defines what items match a slot in a recipe.
-///
-/// TODO(future) add a variant that consumes multiple items from a stack
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum RecipeSlot {
     /// The slot should be empty
@@ -84,12 +140,45 @@ pub enum RecipeSlot {
     Group(String),
     /// The item should be exactly the indicated item
     Exact(String),
+    /// At least the given number of items, all belonging to the given group, should be present.
+    /// That many items are consumed.
+    GroupMultiple(String, u32),
+    /// At least the given number of the indicated item should be present. That many items are consumed.
+    ExactMultiple(String, u32),
 }
 impl RecipeSlot {
-    /// How many items to take from the input. At the moment, this is always 1, since there is not yet a variant
-    /// that consumes multiple items from a stack
+    /// How many items to take from the input. This is 0 for empty slots.
     pub fn quantity(&self) -> u32 {
-        1
+        match self {
+            RecipeSlot::Empty => 0,
+            RecipeSlot::Group(_) | RecipeSlot::Exact(_) => 1,
+            RecipeSlot::GroupMultiple(_, count) | RecipeSlot::ExactMultiple(_, count) => *count,
+        }
+    }
+
+    fn is_group(&self) -> bool {
+        matches!(self, RecipeSlot::Group(_) | RecipeSlot::GroupMultiple(_, _))
+    }
+
+    /// Whether the given stack (item and count) satisfies this slot.
+    fn accepts(&self, stack: Option<(&Item, u32)>) -> bool {
+        match (self, stack) {
+            (RecipeSlot::Empty, stack) => stack.is_none(),
+            (_, None) => false,
+            (slot, Some((item, count))) => count >= slot.quantity() && slot.accepts_item(item),
+        }
+    }
+
+    fn accepts_item(&self, item: &Item) -> bool {
+        match self {
+            RecipeSlot::Empty => false,
+            RecipeSlot::Group(group) | RecipeSlot::GroupMultiple(group, _) => {
+                item.proto.groups.contains(group)
+            }
+            RecipeSlot::Exact(name) | RecipeSlot::ExactMultiple(name, _) => {
+                &item.proto.short_name == name
+            }
+        }
     }
 }
 
@@ -101,46 +190,141 @@ pub struct RecipeImpl<const N: usize, T> {
     pub slots: [RecipeSlot; N],
     /// The result obtained from this crafting recipe
     pub result: ItemStack,
-    /// If true, the inputs may be given in any order
+    /// If true, the inputs may be given in any order. Empty slots are ignored,
+    /// but the number of non-empty inputs must equal the number of non-empty slots.
     pub shapeless: bool,
     /// Any metadata for this recipe (e.g. time, non-inventory resources, etc)
     pub metadata: T,
 }
 impl<const N: usize, T> RecipeImpl<N, T> {
-    fn sort_key(&self) -> usize {
-        self.slots
-            .iter()
-            .filter(|&x| matches!(x, RecipeSlot::Group(_)))
-            .count()
+    fn sort_key(&self) -> (usize, bool, std::cmp::Reverse<u32>) {
+        (
+            self.slots.iter().filter(|x| x.is_group()).count(),
+            self.shapeless,
+            std::cmp::Reverse(self.slots.iter().map(RecipeSlot::quantity).sum()),
+        )
+    }
+    pub(crate) fn matches(&self, stacks: &[Option<(&Item, u32)>; N]) -> bool {
+        self.solve(stacks).is_some()
     }
-    pub(crate) fn matches(&self, stacks: &[Option<&Item>; N]) -> bool {
+
+    /// Returns how many items this recipe takes from each of the given stacks, or None
+    /// if the recipe doesn't match them.
+    pub fn input_quantities(
+        &self,
+        items: &ItemManager,
+        stacks: &[&Option<ItemStack>],
+    ) -> Option<[u32; N]> {
+        self.solve(&resolve_stacks(items, stacks)?)
+    }
+
+    /// Removes the items used by this recipe from the given stacks, e.g. the contents of a
+    /// crafting grid. Returns false (leaving the stacks untouched) if the recipe doesn't
+    /// match them.
+    pub fn consume(&self, items: &ItemManager, stacks: &mut [Option<ItemStack>]) -> bool {
+        let quantities = match self.input_quantities(items, &stacks.iter().collect::<Vec<_>>()) {
+            Some(x) => x,
+            None => return false,
+        };
+        take_quantities(stacks, &quantities)
+    }
+
+    fn solve(&self, stacks: &[Option<(&Item, u32)>; N]) -> Option<[u32; N]> {
+        let mut quantities = [0; N];
         if self.shapeless {
-            todo!("shapeless solver not written");
+            let wanted: Vec<&RecipeSlot> = self
+                .slots
+                .iter()
+                .filter(|x| !matches!(x, RecipeSlot::Empty))
+                .collect();
+            let present: Vec<usize> = (0..N).filter(|&i| stacks[i].is_some()).collect();
+            if wanted.len() != present.len() {
+                return None;
+            }
+            let compatible: Vec<Vec<bool>> = wanted
+                .iter()
+                .map(|slot| present.iter().map(|&i| slot.accepts(stacks[i])).collect())
+                .collect();
+            let assignment = find_assignment(&compatible)?;
+            for (p, w) in assignment.into_iter().enumerate() {
+                quantities[present[p]] = wanted[w].quantity();
+            }
         } else {
             for (j, stack) in stacks.iter().enumerate() {
-                match &self.slots[j] {
-                    RecipeSlot::Empty => {
-                        if stack.is_some() {
-                            return false;
-                        }
-                    }
-                    RecipeSlot::Group(group) => {
-                        if !stack.is_some_and(|x| x.proto.groups.contains(group)) {
-                            return false;
-                        }
-                    }
-                    RecipeSlot::Exact(item) => {
-                        if !stack.is_some_and(|x| &x.proto.short_name == item) {
-                            return false;
-                        }
-                    }
+                if !self.slots[j].accepts(*stack) {
+                    return None;
                 }
+                quantities[j] = self.slots[j].quantity();
             }
         }
-        true
+        Some(quantities)
     }
 }
 
+/// Finds a perfect matching between recipe slots (rows) and input stacks (columns), where
+/// `compatible[slot][stack]` indicates whether a stack may fill a slot. The matrix must be square.
+///
+/// Returns, for each stack, the slot it fills. N is small, so a simple augmenting-path
+/// search suffices.
+fn find_assignment(compatible: &[Vec<bool>]) -> Option<Vec<usize>> {
+    fn augment(
+        slot: usize,
+        compatible: &[Vec<bool>],
+        owner: &mut [Option<usize>],
+        visited: &mut [bool],
+    ) -> bool {
+        for (stack, &ok) in compatible[slot].iter().enumerate() {
+            if visited[stack] || !ok {
+                continue;
+            }
+            visited[stack] = true;
+            if owner[stack].map_or(true, |other| augment(other, compatible, owner, visited)) {
+                owner[stack] = Some(slot);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owner = vec![None; compatible.len()];
+    for slot in 0..compatible.len() {
+        let mut visited = vec![false; compatible.len()];
+        if !augment(slot, compatible, &mut owner, &mut visited) {
+            return None;
+        }
+    }
+    owner.into_iter().collect()
+}
+
+/// Takes the given number of items out of each stack. Stacks of non-stackable items
+/// are taken entirely. Returns false (leaving the stacks untouched) if any stack
+/// doesn't have enough items.
+fn take_quantities(stacks: &mut [Option<ItemStack>], quantities: &[u32]) -> bool {
+    let sufficient = stacks
+        .iter()
+        .zip(quantities.iter())
+        .all(|(stack, &quantity)| match stack {
+            _ if quantity == 0 => true,
+            Some(stack) => !stack.proto.stackable || stack.proto.quantity >= quantity,
+            None => false,
+        });
+    if !sufficient {
+        return false;
+    }
+    for (stack, &quantity) in stacks.iter_mut().zip(quantities.iter()) {
+        if quantity == 0 {
+            continue;
+        }
+        let count = if stack.as_ref().is_some_and(|x| x.proto.stackable) {
+            Some(quantity)
+        } else {
+            None
+        };
+        stack.try_take_all(count);
+    }
+    true
+}
+
 pub(crate) fn register_test_recipes(game_builder: &mut super::DefaultGameBuilder) {
     use RecipeSlot::*;
     // testonly
@@ -176,4 +360,210 @@ pub(crate) fn register_test_recipes(game_builder: &mut super::DefaultGameBuilder
         2,
         true,
     );
+    // testonly, shapeless and multi-item
+    game_builder.register_shapeless_crafting_recipe(
+        [
+            ExactMultiple("default:dirt".to_string(), 4),
+            Group("testonly_wet".to_string()),
+            Empty,
+            Empty,
+            Empty,
+            Empty,
+            Empty,
+            Empty,
+            Empty,
+        ],
+        DIRT_WITH_GRASS.0.to_string(),
+        4,
+        true,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use cuberef_core::protocol::items::{item_def::QuantityType, ItemDef};
+
+    use super::*;
+    use RecipeSlot::*;
+
+    fn item(name: &str, groups: &[&str]) -> Item {
+        Item {
+            proto: ItemDef {
+                short_name: name.to_string(),
+                groups: groups.iter().map(|x| x.to_string()).collect(),
+                quantity_type: Some(QuantityType::Stack(256)),
+                ..Default::default()
+            },
+            dig_handler: None,
+            tap_handler: None,
+            place_handler: None,
+        }
+    }
+
+    fn stack(name: &str, quantity: u32) -> Option<ItemStack> {
+        Some(ItemStack {
+            proto: cuberef_core::protocol::items::ItemStack {
+                item_name: name.to_string(),
+                quantity,
+                max_stack: 256,
+                stackable: true,
+            },
+        })
+    }
+
+    fn recipe(slots: [RecipeSlot; 3], result: &str, shapeless: bool) -> RecipeImpl<3, ()> {
+        RecipeImpl {
+            slots,
+            result: stack(result, 1).unwrap(),
+            shapeless,
+            metadata: (),
+        }
+    }
+
+    fn group(name: &str) -> RecipeSlot {
+        Group(name.to_string())
+    }
+
+    fn exact(name: &str) -> RecipeSlot {
+        Exact(name.to_string())
+    }
+
+    #[test]
+    fn group_and_exact_slots() {
+        let sand = item("sand", &["wet"]);
+        let dirt = item("dirt", &[]);
+        let shaped = recipe([group("wet"), exact("dirt"), Empty], "mud", false);
+
+        assert!(shaped.matches(&[Some((&sand, 1)), Some((&dirt, 1)), None]));
+        // The group doesn't accept an item outside of it, even if it's otherwise valid
+        assert!(!shaped.matches(&[Some((&dirt, 1)), Some((&dirt, 1)), None]));
+        // The exact slot doesn't accept other items, even if they're in a group
+        assert!(!shaped.matches(&[Some((&sand, 1)), Some((&sand, 1)), None]));
+        // Empty slots must be empty
+        assert!(!shaped.matches(&[Some((&sand, 1)), Some((&dirt, 1)), Some((&dirt, 1))]));
+        // Shaped recipes care about position
+        assert!(!shaped.matches(&[Some((&dirt, 1)), Some((&sand, 1)), None]));
+    }
+
+    #[test]
+    fn shapeless_any_order() {
+        let sand = item("sand", &["wet"]);
+        let dirt = item("dirt", &[]);
+        let shapeless = recipe([group("wet"), exact("dirt"), Empty], "mud", true);
+
+        assert!(shapeless.matches(&[Some((&sand, 1)), Some((&dirt, 1)), None]));
+        assert!(shapeless.matches(&[None, Some((&dirt, 1)), Some((&sand, 1))]));
+        assert!(shapeless.matches(&[Some((&dirt, 1)), None, Some((&sand, 1))]));
+        // Extra or missing inputs don't match
+        assert!(!shapeless.matches(&[Some((&dirt, 1)), Some((&sand, 1)), Some((&sand, 1))]));
+        assert!(!shapeless.matches(&[Some((&dirt, 1)), None, None]));
+        assert!(!shapeless.matches(&[Some((&dirt, 1)), Some((&dirt, 1)), None]));
+    }
+
+    #[test]
+    fn shapeless_reassigns_group_slots() {
+        // Wet dirt fits both slots; the solver must put it in the exact slot even though
+        // the group slot comes first, so that the sand can fill the group slot.
+        let wet_dirt = item("dirt", &["wet"]);
+        let sand = item("sand", &["wet"]);
+        let shapeless = recipe([group("wet"), exact("dirt"), Empty], "mud", true);
+
+        assert!(shapeless.matches(&[Some((&wet_dirt, 1)), Some((&sand, 1)), None]));
+        assert!(!shapeless.matches(&[Some((&sand, 1)), Some((&sand, 1)), None]));
+    }
+
+    #[test]
+    fn quantities() {
+        let sand = item("sand", &["wet"]);
+        let dirt = item("dirt", &[]);
+        let shaped = recipe(
+            [GroupMultiple("wet".to_string(), 3), exact("dirt"), Empty],
+            "mud",
+            false,
+        );
+        assert!(!shaped.matches(&[Some((&sand, 2)), Some((&dirt, 1)), None]));
+        assert_eq!(
+            shaped.solve(&[Some((&sand, 5)), Some((&dirt, 4)), None]),
+            Some([3, 1, 0])
+        );
+
+        let shapeless = recipe(
+            [ExactMultiple("dirt".to_string(), 2), group("wet"), Empty],
+            "mud",
+            true,
+        );
+        assert_eq!(
+            shapeless.solve(&[None, Some((&sand, 5)), Some((&dirt, 2))]),
+            Some([0, 1, 2])
+        );
+        assert!(!shapeless.matches(&[None, Some((&sand, 5)), Some((&dirt, 1))]));
+    }
+
+    #[test]
+    fn consume_takes_exact_quantities() {
+        let mut stacks = [stack("sand", 5), stack("dirt", 2), None];
+        assert!(take_quantities(&mut stacks, &[3, 2, 0]));
+        assert_eq!(stacks[0].as_ref().unwrap().proto.quantity, 2);
+        assert!(stacks[1].is_none());
+        assert!(stacks[2].is_none());
+
+        // Not enough items: nothing is taken
+        assert!(!take_quantities(&mut stacks, &[1, 1, 0]));
+        assert_eq!(stacks[0].as_ref().unwrap().proto.quantity, 2);
+        assert!(!take_quantities(&mut stacks, &[3, 0, 0]));
+        assert_eq!(stacks[0].as_ref().unwrap().proto.quantity, 2);
+    }
+
+    #[test]
+    fn sort_precedence() {
+        let sand = item("sand", &["wet"]);
+        let dirt = item("dirt", &["wet"]);
+        let book = RecipeBook::<3, ()>::new();
+        book.register_recipe(recipe(
+            [group("wet"), group("wet"), Empty],
+            "two_groups",
+            false,
+        ));
+        book.register_recipe(recipe(
+            [exact("sand"), group("wet"), Empty],
+            "one_group",
+            true,
+        ));
+        book.register_recipe(recipe(
+            [exact("sand"), group("wet"), Empty],
+            "shaped",
+            false,
+        ));
+        book.register_recipe(recipe(
+            [ExactMultiple("sand".to_string(), 2), group("wet"), Empty],
+            "more_items",
+            false,
+        ));
+        book.sort();
+
+        let find = |stacks: [Option<(&Item, u32)>; 3]| {
+            book.find_resolved(&stacks)
+                .map(|x| x.result.proto.item_name)
+        };
+        // Consuming more items beats consuming fewer
+        assert_eq!(
+            find([Some((&sand, 2)), Some((&dirt, 1)), None]).as_deref(),
+            Some("more_items")
+        );
+        // Shaped beats shapeless, and fewer group slots beat more
+        assert_eq!(
+            find([Some((&sand, 1)), Some((&dirt, 1)), None]).as_deref(),
+            Some("shaped")
+        );
+        // Fewer group slots beat more, even if the recipe is shapeless
+        assert_eq!(
+            find([Some((&dirt, 1)), Some((&sand, 1)), None]).as_deref(),
+            Some("one_group")
+        );
+        // Only the least specific recipe accepts this
+        assert_eq!(
+            find([Some((&dirt, 1)), Some((&dirt, 1)), None]).as_deref(),
+            Some("two_groups")
+        );
+    }
 }
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::path::Path;
+use std::{path::Path, time::Duration};
 
 
 use cuberef_core::{
@@ -22,7 +22,7 @@ use cuberef_core::{
             block_type_def::{PhysicsInfo, RenderInfo},
             BlockTypeDef, Empty,
         },
-        render::TextureReference,
+        render::{TextureAnimation, TextureCrop, TextureReference, TextureRotation},
     },
 };
 use cuberef_server::{
@@ -39,19 +39,87 @@ impl From<Tex> for TextureReference {
     fn from(value: Tex) -> Self {
         TextureReference {
             texture_name: value.0.to_string(),
+            ..Default::default()
         }
     }
 }
 
+/// A texture along with how it should be drawn, e.g.
+/// `TexRef::from(WATER_TEXTURE).animated(8, Duration::from_millis(150))`.
+/// Can be used anywhere a [Tex] can.
+///
+/// Inventory icons only honor cropping and animation (by showing the first frame).
+#[derive(Clone, Debug)]
+pub struct TexRef(TextureReference);
+impl TexRef {
+    /// Treats the texture as a vertical strip of `frame_count` equally sized frames, shown
+    /// from top to bottom and then starting over.
+    pub fn animated(mut self, frame_count: u32, frame_duration: Duration) -> Self {
+        self.0.animation = Some(TextureAnimation {
+            frame_count,
+            frame_duration_millis: frame_duration.as_millis().try_into().unwrap_or(u32::MAX),
+        });
+        self
+    }
+    /// Only draws the given part of the texture (or of each animation frame), in pixels.
+    pub fn crop(mut self, left: u32, top: u32, width: u32, height: u32) -> Self {
+        self.0.crop = Some(TextureCrop {
+            left,
+            top,
+            width,
+            height,
+        });
+        self
+    }
+    /// Rotates the texture clockwise by the given number of quarter turns.
+    pub fn rotate_cw(mut self, quarter_turns: u32) -> Self {
+        self.0.set_rotation(match quarter_turns % 4 {
+            0 => TextureRotation::Zero,
+            1 => TextureRotation::Cw90,
+            2 => TextureRotation::Cw180,
+            _ => TextureRotation::Cw270,
+        });
+        self
+    }
+    /// Mirrors the texture left-to-right. This is done before rotating it.
+    pub fn flip_horizontal(mut self) -> Self {
+        self.0.flip_horizontal = !self.0.flip_horizontal;
+        self
+    }
+    /// Multiplies the texture's colors with the given color.
+    pub fn tint(mut self, color: [u8; 3]) -> Self {
+        let [r, g, b] = color;
+        self.0.tint = u32::from_be_bytes([0, r, g, b]);
+        self.0.has_tint = true;
+        self
+    }
+}
+impl From<Tex> for TexRef {
+    fn from(value: Tex) -> Self {
+        TexRef(value.into())
+    }
+}
+impl From<TexRef> for TextureReference {
+    fn from(value: TexRef) -> Self {
+        value.0
+    }
+}
+
 /// Type-safe newtype wrapper for a block name
 pub struct Block(pub &'static str);
 
+/// Type-safe newtype wrapper for an item name
+pub struct ItemName(pub &'static str);
+
 #[cfg(feature = "unstable_api")]
 /// Unstable re-export of the raw gameserver API. This API is subject to
 /// breaking changes that do not follow semver, before 1.0
 use cuberef_server::server as server_api;
 
-use crate::blocks::{BlockBuilder, BlockTypeHandleWrapper};
+use crate::{
+    blocks::{BlockBuilder, BlockTypeHandleWrapper},
+    items::ItemBuilder,
+};
 
 /// Stable API for building and configuring a game.
 ///
@@ -89,6 +157,40 @@ impl GameBuilder {
         self.inner
     }
 
+    /// Adds a handler to the map generation pipeline, e.g. to carve caves or place
+    /// ores on top of the terrain generated by another crate.
+    /// See [cuberef_server::server::ServerBuilder::add_mapgen_stage].
+    #[cfg(feature = "unstable_api")]
+    pub fn add_mapgen_stage<F>(
+        &mut self,
+        stage: cuberef_server::game_state::mapgen::MapgenStage,
+        name: impl Into<String>,
+        provider: F,
+    ) where
+        F: (FnOnce(
+                std::sync::Arc<cuberef_server::game_state::blocks::BlockTypeManager>,
+                u32,
+            )
+                -> Result<Box<dyn cuberef_server::game_state::mapgen::MapgenStageHandler>>)
+            + 'static,
+    {
+        self.inner.add_mapgen_stage(stage, name, provider)
+    }
+
+    /// Sets the name under which the map generator is recorded when a world is created.
+    /// See [cuberef_server::server::ServerBuilder::set_mapgen_name].
+    #[cfg(feature = "unstable_api")]
+    pub fn set_mapgen_name(&mut self, name: impl Into<String>) {
+        self.inner.set_mapgen_name(name)
+    }
+
+    /// Sets the name and version of the game content, which are recorded in the world's
+    /// metadata when a world is created. Content crates would typically pass
+    /// `env!("CARGO_PKG_NAME")` and `env!("CARGO_PKG_VERSION")`.
+    pub fn set_game_info(&mut self, name: &str, version: &str) {
+        self.inner.set_game_info(name, version)
+    }
+
     /// Run the game server
     pub fn run_game_server(self) -> Result<()> {
         self.inner.build()?.serve()
@@ -111,6 +213,7 @@ impl GameBuilder {
             groups: vec![],
         };
         let air_block = inner.blocks_mut().register_block(air_block)?;
+        inner.set_game_info(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         Ok(GameBuilder { inner, air_block })
     }
     /// Registers a block and its corresponding item in the game.
@@ -118,6 +221,11 @@ impl GameBuilder {
         block_builder.build_and_deploy_into(self)
     }
 
+    /// Registers an item in the game.
+    pub fn add_item(&mut self, item_builder: ItemBuilder) -> Result<()> {
+        item_builder.build_and_deploy_into(self)
+    }
+
     pub fn get_block(&self, block_name: Block) -> Option<BlockTypeHandle> {
         self.inner.blocks().get_by_name(&block_name.0)
     }
@@ -1,3 +1,285 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use cuberef_core::{
+    constants::{
+        block_groups::NOT_DIGGABLE, items::default_item_interaction_rules,
+        textures::FALLBACK_UNKNOWN_TEXTURE,
+    },
+    protocol::{
+        blocks::Empty,
+        items::{interaction_rule::DigBehavior, item_def::QuantityType, InteractionRule, ItemDef},
+        render::TextureReference,
+    },
+};
+/// Unstable re-export of the raw items API. This API is subject to
+/// breaking changes that do not follow semver, before 1.0
+#[cfg(feature = "unstable_api")]
+pub use cuberef_server::game_state::items as server_api;
+
+use cuberef_server::game_state::items::{BlockInteractionHandler, Item, PlaceHandler};
+
+use crate::{
+    game_builder::{GameBuilder, ItemName},
+    maybe_export,
+};
+
+/// How an item digs the blocks that an interaction rule applies to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigSpeed {
+    /// The block is dug in a single frame, and digging continues as long as the
+    /// dig button is held down
+    Instant,
+    /// The block is dug in a single frame, but the dig button must be released and
+    /// pressed again to dig the next block
+    InstantOneshot,
+    /// Digging takes the given number of seconds, regardless of the block
+    ConstantTime(f64),
+    /// Digging takes the block's base dig time, multiplied by the given factor
+    ScaledTime(f64),
+    /// The block can be selected (e.g. to tap it), but not dug
+    Undiggable,
+}
+impl From<DigSpeed> for DigBehavior {
+    fn from(value: DigSpeed) -> Self {
+        match value {
+            DigSpeed::Instant => DigBehavior::InstantDig(Empty {}),
+            DigSpeed::InstantOneshot => DigBehavior::InstantDigOneshot(Empty {}),
+            DigSpeed::ConstantTime(x) => DigBehavior::ConstantTime(x),
+            DigSpeed::ScaledTime(x) => DigBehavior::ScaledTime(x),
+            DigSpeed::Undiggable => DigBehavior::Undiggable(Empty {}),
+        }
+    }
+}
+
+/// Builder for items that aren't simply a block in inventory, e.g. tools and ingots.
+///
+/// By default, the item stacks up to 256, has no groups, and digs like a bare hand.
 pub struct ItemBuilder {
-    // TODO
+    item: Item,
+    interaction_rules: Vec<InteractionRule>,
+    use_default_rules: bool,
+}
+impl ItemBuilder {
+    /// Create a new item builder for an item with the given name.
+    pub fn new(name: ItemName) -> ItemBuilder {
+        Self::from_name(name.0)
+    }
+
+    /// Same as [new](#method.new), for names that aren't known at compile time (e.g. when loaded
+    /// from content files).
+    pub(crate) fn from_name(name: &str) -> ItemBuilder {
+        ItemBuilder {
+            item: Item {
+                proto: ItemDef {
+                    short_name: name.into(),
+                    display_name: name.into(),
+                    inventory_texture: Some(TextureReference {
+                        texture_name: FALLBACK_UNKNOWN_TEXTURE.to_string(),
+                        ..Default::default()
+                    }),
+                    groups: vec![],
+                    interaction_rules: vec![],
+                    quantity_type: Some(QuantityType::Stack(256)),
+                },
+                dig_handler: None,
+                tap_handler: None,
+                place_handler: None,
+            },
+            interaction_rules: vec![],
+            use_default_rules: true,
+        }
+    }
+    /// Set the display name visible when hovering in the inventory.
+    pub fn set_display_name(mut self, display_name: &str) -> Self {
+        self.item.proto.display_name = display_name.into();
+        self
+    }
+    /// Sets the texture shown for this item in the inventory.
+    pub fn set_inventory_texture<T>(mut self, texture: T) -> Self
+    where
+        T: Into<TextureReference>,
+    {
+        self.item.proto.inventory_texture = Some(texture.into());
+        self
+    }
+    /// Adds a group to the list of groups for this item. These can be used in
+    /// recipes, and by other game content.
+    pub fn add_item_group(mut self, group: &str) -> Self {
+        self.item.proto.groups.push(group.into());
+        self
+    }
+    /// Sets that this item stacks, with up to the given number of items in a stack.
+    pub fn set_stack_size(mut self, max_stack: u32) -> Self {
+        self.item.proto.quantity_type = Some(QuantityType::Stack(max_stack));
+        self
+    }
+    /// Sets that this item doesn't stack, and instead wears out (e.g. a tool).
+    /// New stacks of the item start with the given amount of wear left, and the item breaks
+    /// when it runs out (see [add_interaction_rule](#method.add_interaction_rule)).
+    pub fn set_wear(mut self, max_wear: u32) -> Self {
+        self.item.proto.quantity_type = Some(QuantityType::Wear(max_wear));
+        self
+    }
+    /// Sets how this item digs blocks that have all of the given groups. If `dig_speed` is None,
+    /// those blocks can't even be selected while holding this item.
+    ///
+    /// `tool_wear` is how much wear the item loses each time it digs such a block, if the item
+    /// has wear (see [set_wear](#method.set_wear)).
+    ///
+    /// Rules are checked in the order they're added, and the first rule matching a block applies.
+    /// Unless [clear_default_interaction_rules](#method.clear_default_interaction_rules) is called,
+    /// blocks in [NOT_DIGGABLE](cuberef_core::constants::block_groups::NOT_DIGGABLE) can never be
+    /// dug, and blocks not matching any rule added here are dug as if by a bare hand.
+    pub fn add_interaction_rule(
+        mut self,
+        block_groups: &[&str],
+        dig_speed: Option<DigSpeed>,
+        tool_wear: u32,
+    ) -> Self {
+        self.interaction_rules.push(InteractionRule {
+            block_group: block_groups.iter().map(|x| x.to_string()).collect(),
+            dig_behavior: dig_speed.map(DigBehavior::from),
+            tool_wear,
+        });
+        self
+    }
+    /// Removes the default interaction rules, so that this item can only interact with
+    /// blocks matching the rules given to [add_interaction_rule](#method.add_interaction_rule).
+    pub fn clear_default_interaction_rules(mut self) -> Self {
+        self.use_default_rules = false;
+        self
+    }
+
+    maybe_export!(
+        /// Sets a handler that's called when this item is used to dig a block, replacing
+        /// the default dig behavior. See [cuberef_server::game_state::items::Item::dig_handler].
+        fn set_dig_handler(mut self, handler: Box<BlockInteractionHandler>) -> Self {
+            self.item.dig_handler = Some(handler);
+            self
+        }
+    );
+    maybe_export!(
+        /// Sets a handler that's called when this item is used to tap a block.
+        /// See [cuberef_server::game_state::items::Item::tap_handler].
+        fn set_tap_handler(mut self, handler: Box<BlockInteractionHandler>) -> Self {
+            self.item.tap_handler = Some(handler);
+            self
+        }
+    );
+    maybe_export!(
+        /// Sets a handler that's called when this item is placed (typically with rightclick).
+        /// See [cuberef_server::game_state::items::Item::place_handler].
+        fn set_place_handler(mut self, handler: Box<PlaceHandler>) -> Self {
+            self.item.place_handler = Some(handler);
+            self
+        }
+    );
+
+    pub(crate) fn build_and_deploy_into(self, game_builder: &mut GameBuilder) -> Result<()> {
+        game_builder.inner.items_mut().register_item(self.build())
+    }
+
+    fn build(self) -> Item {
+        let mut item = self.item;
+        item.proto.interaction_rules = if self.use_default_rules {
+            // Keep undiggable blocks undiggable no matter what the custom rules say, but let the
+            // custom rules take precedence over the rest of the defaults (e.g. so that a tool can
+            // dig blocks that require a tool).
+            let (not_diggable, other_defaults): (Vec<_>, Vec<_>) = default_item_interaction_rules()
+                .into_iter()
+                .partition(|x| x.block_group.iter().any(|g| g == NOT_DIGGABLE));
+            not_diggable
+                .into_iter()
+                .chain(self.interaction_rules)
+                .chain(other_defaults)
+                .collect()
+        } else {
+            self.interaction_rules
+        };
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cuberef_core::constants::block_groups::{DEFAULT_SOLID, TOOL_REQUIRED};
+
+    use super::*;
+
+    fn groups(rules: &[InteractionRule]) -> Vec<&str> {
+        rules.iter().map(|x| x.block_group[0].as_str()).collect()
+    }
+
+    #[test]
+    fn test_interaction_rule_precedence() {
+        let rules = ItemBuilder::from_name("test:pickaxe")
+            .add_interaction_rule(&[TOOL_REQUIRED], Some(DigSpeed::ScaledTime(0.5)), 1)
+            .add_interaction_rule(&[NOT_DIGGABLE], Some(DigSpeed::Instant), 0)
+            .build()
+            .proto
+            .interaction_rules;
+        // Undiggable blocks come first, then the custom rules, then the other defaults
+        assert_eq!(
+            groups(&rules),
+            [
+                NOT_DIGGABLE,
+                TOOL_REQUIRED,
+                NOT_DIGGABLE,
+                TOOL_REQUIRED,
+                DEFAULT_SOLID
+            ]
+        );
+        // So custom rules can't make undiggable blocks diggable...
+        assert_eq!(rules[0].dig_behavior, None);
+        // ...but they do override the default for blocks that need a tool
+        assert_eq!(rules[1].dig_behavior, Some(DigBehavior::ScaledTime(0.5)));
+        assert_eq!(rules[1].tool_wear, 1);
+        assert_eq!(rules[3].dig_behavior, None);
+    }
+
+    #[test]
+    fn test_default_interaction_rules() {
+        let rules = ItemBuilder::from_name("test:stick")
+            .build()
+            .proto
+            .interaction_rules;
+        assert_eq!(rules, default_item_interaction_rules());
+    }
+
+    #[test]
+    fn test_clear_default_interaction_rules() {
+        let rules = ItemBuilder::from_name("test:wand")
+            .add_interaction_rule(&["test:magic"], Some(DigSpeed::Instant), 0)
+            .clear_default_interaction_rules()
+            .build()
+            .proto
+            .interaction_rules;
+        assert_eq!(groups(&rules), ["test:magic"]);
+        assert_eq!(
+            rules[0].dig_behavior,
+            Some(DigBehavior::InstantDig(Empty {}))
+        );
+
+        let rules = ItemBuilder::from_name("test:nothing")
+            .clear_default_interaction_rules()
+            .build()
+            .proto
+            .interaction_rules;
+        assert!(rules.is_empty());
+    }
 }
@@ -0,0 +1,121 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    io::BufRead,
+    sync::{Arc, Weak},
+};
+
+use anyhow::{bail, Context, Result};
+use cuberef_core::coordinates::BlockCoordinate;
+
+use crate::game_state::{pregen::PregenRequest, GameState};
+
+const HELP: &str = "Available commands:
+  help                                      Show this message
+  pregen start <x> <y> <z> <radius> [vradius]
+                                            Generate all chunks within <radius> chunks (horizontally)
+                                            and [vradius] chunks (vertically, default 4) of a block.
+                                            The radii are limited to 128 and 16 chunks.
+  pregen status                             Show the progress of world pregeneration
+  pregen cancel                             Stop world pregeneration and forget its progress";
+
+/// Starts a thread that reads admin commands from the server's standard input. Command output
+/// is reported through the log.
+pub(crate) fn spawn_admin_console(game_state: &Arc<GameState>) -> Result<()> {
+    let game_state = Arc::downgrade(game_state);
+    // This is a plain thread rather than a tokio task, since reading stdin blocks and
+    // would otherwise hold up runtime shutdown.
+    std::thread::Builder::new()
+        .name("admin_console".to_string())
+        .spawn(move || run_console(game_state))?;
+    Ok(())
+}
+
+fn run_console(game_state: Weak<GameState>) {
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Admin console stopped reading input: {e:?}");
+                return;
+            }
+        };
+        let game_state = match game_state.upgrade() {
+            Some(x) => x,
+            None => return,
+        };
+        if let Err(e) = handle_command(&game_state, line.trim()) {
+            log::warn!("Admin command {:?} failed: {e:#}", line.trim());
+        }
+    }
+}
+
+fn handle_command(game_state: &GameState, line: &str) -> Result<()> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    match args.as_slice() {
+        [] => {}
+        ["help"] => log::info!("{HELP}"),
+        ["pregen", "start", rest @ ..] => {
+            let numbers = rest
+                .iter()
+                .map(|x| {
+                    x.parse::<i64>()
+                        .with_context(|| format!("Invalid number: {x}"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let (center, radius, vertical_radius) = match numbers.as_slice() {
+                [x, y, z, radius] => ((*x, *y, *z), *radius, 4),
+                [x, y, z, radius, vertical_radius] => ((*x, *y, *z), *radius, *vertical_radius),
+                _ => bail!("Usage: pregen start <x> <y> <z> <radius> [vradius]"),
+            };
+            let request = PregenRequest {
+                center: BlockCoordinate::new(
+                    center.0.try_into()?,
+                    center.1.try_into()?,
+                    center.2.try_into()?,
+                ),
+                radius: radius.try_into().with_context(|| "Invalid radius")?,
+                vertical_radius: vertical_radius
+                    .try_into()
+                    .with_context(|| "Invalid vertical radius")?,
+            };
+            game_state.pregen().start(request)?;
+            log::info!("Pregeneration started");
+        }
+        ["pregen", "status"] => match game_state.pregen().status() {
+            Some(status) => log::info!(
+                "Pregeneration around {:?} ({}): {}/{} chunks ({:.1}%), {} generated",
+                status.request.center,
+                if status.finished {
+                    "finished"
+                } else if status.running {
+                    "running"
+                } else {
+                    "stopped"
+                },
+                status.processed,
+                status.total,
+                100.0 * status.processed as f64 / status.total.max(1) as f64,
+                status.generated
+            ),
+            None => log::info!("No pregeneration job has run since the server started"),
+        },
+        ["pregen", "cancel"] => game_state.pregen().cancel()?,
+        _ => bail!("Unknown command. Type \"help\" for a list of commands."),
+    }
+    Ok(())
+}
@@ -14,11 +14,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::sync::Arc;
-
 use clap::Parser;
 use cuberef_server::{
-    game_state::testutils::{register_test_blocks_and_items, FakeMapgen},
+    game_state::{
+        mapgen::MapgenStage,
+        testutils::{register_test_blocks_and_items, FakeMapgen},
+    },
     server::{ServerArgs, ServerBuilder},
 };
 
@@ -29,10 +30,10 @@ fn main() {
     let args = ServerArgs::parse();
     let mut builder = ServerBuilder::from_args(&args).unwrap();
     register_test_blocks_and_items(&mut builder);
-    builder.set_mapgen(|blocks, _| {
-        Arc::new(FakeMapgen {
+    builder.add_mapgen_stage(MapgenStage::Terrain, "test:terrain", |blocks, _| {
+        Ok(Box::new(FakeMapgen {
             block_type_manager: blocks,
-        })
+        }))
     });
     let server = builder.build().unwrap();
 
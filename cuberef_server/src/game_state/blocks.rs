@@ -176,8 +176,8 @@ pub struct BlockType {
     /// See notes in the header for [`BlockType`] for details about this vs [`BlockType::dig_handler_inline`]. Note that if both
     /// _inline and _full return item stacks, they will be merged.
     ///
-    /// If this returns Err, a message will be logged and the user that dug the block will get an error message in some
-    /// TBD way (for now, just a log message on the client, but eventually a popup or similar)
+    /// If this returns Err, a message will be logged and the user that dug the block will be shown the error as a
+    /// notification
     ///
     /// Note that if the handler performs changes, they will not be rolled back if the handler subsequently returns Err.
     ///
@@ -188,8 +188,8 @@ pub struct BlockType {
     /// Called when the block is dug. To update the block on the map, assign a new value to the BlockTypeRef.
     /// To update extended data, use the DerefMut trait on ExtendedDataHolder
     ///
-    /// If this returns Err, a message will be logged and the user that dug the block will get an error message in some
-    /// TBD way (for now, just a log message on the client). dig_handler_full will not be called in that case
+    /// If this returns Err, a message will be logged and the user that dug the block will be shown the error as a
+    /// notification. dig_handler_full will not be called in that case
     ///
     /// Note that if the handler performs changes, they will not be rolled back if the handler subsequently returns Err.
     pub dig_handler_inline: Option<Box<InlineHandler>>,
@@ -206,8 +206,8 @@ pub struct BlockType {
     /// Note that this is invoked when the place key/button is placed while pointing to this block; it is not
     /// necessarily true that the placement is vertically above this block.
     ///
-    /// If this returns Err, a message will be logged and the user that dug the block will get an error message in some
-    /// TBD way (for now, just a log message on the client, but eventually a popup or similar)
+    /// If this returns Err, a message will be logged and the user that placed the item will be shown the error as a
+    /// notification
     pub place_upon_handler: Option<
         Box<dyn Fn(HandlerContext, BlockCoordinate, Option<Item>) -> Result<()> + Send + Sync>,
     >,
@@ -515,6 +515,10 @@ impl BlockTypeManager {
         blocks_proto::ServerBlockTypeAssignments { block_type: result }
     }
 
+    pub(crate) fn block_types(&self) -> &[BlockType] {
+        &self.block_types
+    }
+
     pub(crate) fn to_client_protos(&self) -> Vec<blocks_proto::BlockTypeDef> {
         self.block_types
             .iter()
@@ -1,27 +1,46 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Weak,
+    },
 };
 
 use super::{
+    event::EventInitiator,
     inventory::{
-        BorrowedStack, InventoryKey, InventoryView, InventoryViewId, VirtualOutputCallbacks,
+        BlockViewCallbacks, BorrowedStack, InventoryKey, InventoryView, InventoryViewId,
+        VirtualInputCallbacks, VirtualOutputCallbacks,
     },
     GameState,
 };
-use anyhow::{bail, Result};
-use cuberef_core::{protocol::ui as proto, coordinates::BlockCoordinate};
+use anyhow::{bail, Context, Result};
+use cuberef_core::{coordinates::BlockCoordinate, protocol::ui as proto};
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::mpsc;
+use tracy_client::span;
+
+use crate::run_handler;
 
 pub type TextField = proto::TextField;
 pub type Button = proto::Button;
+pub type Checkbox = proto::Checkbox;
+pub type Dropdown = proto::Dropdown;
+pub type Slider = proto::Slider;
+pub type ProgressBar = proto::ProgressBar;
+pub type Image = proto::Image;
 /// Callbacks for inventory views will receive this hashmap,
 /// mapping all inventory views using the form_key passed when calling
 /// inventory_view_stored or inventory_view_transient
 
 /// Choice of a widget. This API is subject to change.
+#[derive(Clone)]
 pub enum UiElement {
     /// A static label
     Label(String),
+    /// A label whose text is computed whenever the popup is sent to the client. It may use
+    /// this popup's [PopupHandle]s.
+    DynamicLabel(Arc<dyn Fn(&Popup) -> String + Send + Sync>),
     /// A textfield with the given parameters
     TextField(TextField),
     /// A button that the user can click.
@@ -29,6 +48,26 @@ pub enum UiElement {
     Button(Button),
     /// An inventory view: key (within this popup), the inventory view id
     InventoryView(String, InventoryViewId),
+    /// A checkbox with the given parameters
+    Checkbox(Checkbox),
+    /// A dropdown with the given parameters
+    Dropdown(Dropdown),
+    /// A slider with the given parameters
+    Slider(Slider),
+    /// A progress bar. If its key is non-empty, a [PopupHandle] can change its progress
+    /// and label.
+    ProgressBar(ProgressBar),
+    /// An image of an item or a texture
+    Image(Image),
+    /// A group of elements, either side by side (if horizontal is true) or stacked vertically.
+    /// If key is non-empty, a [PopupHandle] can append elements to it.
+    Layout {
+        key: String,
+        horizontal: bool,
+        elements: Vec<UiElement>,
+    },
+    /// A label whose text can be changed through a [PopupHandle]: key, text
+    KeyedLabel(String, String),
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -46,6 +85,12 @@ pub struct PopupResponse {
     pub user_action: PopupAction,
     /// The values of all textfields
     pub textfield_values: HashMap<String, String>,
+    /// Whether each checkbox is checked
+    pub checkbox_values: HashMap<String, bool>,
+    /// The index of the selected option in each dropdown
+    pub dropdown_values: HashMap<String, u32>,
+    /// The value of each slider
+    pub slider_values: HashMap<String, f64>,
 }
 
 /// A server-side representation of a custom UI drawn on the client's screen.
@@ -55,12 +100,14 @@ pub struct Popup {
     id: u64,
     title: String,
     game_state: Arc<GameState>,
-    widgets: Vec<UiElement>,
+    widgets: Arc<RwLock<Vec<UiElement>>>,
+    // Where changes made through handles are sent, once the popup is shown to a player
+    update_sender: Arc<Mutex<Option<PopupUpdateSender>>>,
     inventory_views: HashMap<String, InventoryView<Popup>>,
     interested_stored_inventories: HashSet<InventoryKey>,
     interested_coordinates: HashSet<BlockCoordinate>,
     inventory_update_callback: Option<Box<dyn Fn(&Popup) + Send + Sync>>,
-    button_callback: Option<Box<dyn Fn(PopupResponse) + Send + Sync>>,
+    button_callback: Option<Box<dyn Fn(PopupResponse) -> Result<()> + Send + Sync>>,
 }
 impl Popup {
     /// Creates a new popup. Until it's sent to a player, it is inert and has no effects
@@ -69,7 +116,8 @@ impl Popup {
             id: NEXT_POPUP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
             title: String::new(),
             game_state,
-            widgets: vec![],
+            widgets: Arc::new(RwLock::new(vec![])),
+            update_sender: Arc::new(Mutex::new(None)),
             inventory_views: HashMap::new(),
             interested_stored_inventories: HashSet::new(),
             interested_coordinates: HashSet::new(),
@@ -83,34 +131,213 @@ impl Popup {
         self
     }
     /// Adds a new label to this popup. At the moment, the layout is still TBD.
-    pub fn label(mut self, label: impl Into<String>) -> Self {
-        self.widgets.push(UiElement::Label(label.into()));
+    pub fn label(self, label: impl Into<String>) -> Self {
+        self.widgets.write().push(UiElement::Label(label.into()));
+        self
+    }
+    /// Adds a new label to this popup, whose text can be changed while the popup is open using
+    /// [PopupHandle::set_text].
+    pub fn keyed_label(self, key: impl Into<String>, text: impl Into<String>) -> Self {
+        self.widgets
+            .write()
+            .push(UiElement::KeyedLabel(key.into(), text.into()));
+        self
+    }
+    /// Adds a new label to this popup, whose text is computed each time the popup is sent to the
+    /// client, e.g. after any button in it is clicked. At the moment, the layout is still TBD.
+    pub fn dynamic_label<F>(self, label: F) -> Self
+    where
+        F: Fn(&Popup) -> String + Send + Sync + 'static,
+    {
+        self.widgets
+            .write()
+            .push(UiElement::DynamicLabel(Arc::new(label)));
         self
     }
     /// Adds a new text field to this popup. At the moment, the layout is still TBD.
     pub fn text_field(
-        mut self,
+        self,
         key: impl Into<String>,
         label: impl Into<String>,
         initial: impl Into<String>,
         enabled: bool,
     ) -> Self {
-        self.widgets.push(UiElement::TextField(TextField {
+        self.widgets.write().push(UiElement::TextField(TextField {
             key: key.into(),
             label: label.into(),
             initial: initial.into(),
             enabled,
+            multiline: false,
         }));
         self
     }
-    /// Adds a new button to this popup.
-    pub fn button(
-        mut self,
+    /// Adds a new multiline text area to this popup. Its value is returned along with the
+    /// text fields' values. At the moment, the layout is still TBD.
+    pub fn text_area(
+        self,
+        key: impl Into<String>,
+        label: impl Into<String>,
+        initial: impl Into<String>,
+        enabled: bool,
+    ) -> Self {
+        self.widgets.write().push(UiElement::TextField(TextField {
+            key: key.into(),
+            label: label.into(),
+            initial: initial.into(),
+            enabled,
+            multiline: true,
+        }));
+        self
+    }
+    /// Adds a new checkbox to this popup.
+    pub fn checkbox(
+        self,
         key: impl Into<String>,
         label: impl Into<String>,
+        initial: bool,
         enabled: bool,
     ) -> Self {
-        self.widgets.push(UiElement::Button(Button {
+        self.widgets.write().push(UiElement::Checkbox(Checkbox {
+            key: key.into(),
+            label: label.into(),
+            initial,
+            enabled,
+        }));
+        self
+    }
+    /// Adds a new dropdown to this popup, with the option at index `initial` selected.
+    /// The index of the selected option is returned in [PopupResponse::dropdown_values].
+    pub fn dropdown(
+        self,
+        key: impl Into<String>,
+        label: impl Into<String>,
+        options: Vec<String>,
+        initial: u32,
+        enabled: bool,
+    ) -> Self {
+        self.widgets.write().push(UiElement::Dropdown(Dropdown {
+            key: key.into(),
+            label: label.into(),
+            options,
+            initial,
+            enabled,
+        }));
+        self
+    }
+    /// Adds a new slider to this popup, ranging from min to max. If step is nonzero, the value
+    /// snaps to multiples of it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn slider(
+        self,
+        key: impl Into<String>,
+        label: impl Into<String>,
+        min: f64,
+        max: f64,
+        initial: f64,
+        step: f64,
+        enabled: bool,
+    ) -> Self {
+        self.widgets.write().push(UiElement::Slider(Slider {
+            key: key.into(),
+            label: label.into(),
+            min,
+            max,
+            initial,
+            step,
+            enabled,
+        }));
+        self
+    }
+    /// Adds a new progress bar to this popup. progress ranges from 0 to 1.
+    ///
+    /// If key is non-empty, the progress and label can be changed while the popup is open using
+    /// [PopupHandle::set_progress] and [PopupHandle::set_text].
+    pub fn progress_bar(
+        self,
+        key: impl Into<String>,
+        progress: f32,
+        label: impl Into<String>,
+    ) -> Self {
+        self.widgets
+            .write()
+            .push(UiElement::ProgressBar(ProgressBar {
+                progress,
+                label: label.into(),
+                key: key.into(),
+            }));
+        self
+    }
+    /// Adds an image of the given item's inventory texture. If width or height are zero, a
+    /// default size is used.
+    pub fn item_image(self, item_name: impl Into<String>, width: u32, height: u32) -> Self {
+        self.widgets.write().push(UiElement::Image(Image {
+            source: Some(proto::image::Source::ItemName(item_name.into())),
+            width,
+            height,
+        }));
+        self
+    }
+    /// Adds an image of the given texture. If width or height are zero, a default size is used.
+    pub fn texture_image(self, texture_name: impl Into<String>, width: u32, height: u32) -> Self {
+        self.widgets.write().push(UiElement::Image(Image {
+            source: Some(proto::image::Source::TextureName(texture_name.into())),
+            width,
+            height,
+        }));
+        self
+    }
+    /// Adds the elements added by `contents` side by side, e.g.
+    /// `popup.horizontal(|p| Ok(p.label("Count:").text_field("count", "", "1", true)))?`
+    pub fn horizontal<F>(self, contents: F) -> Result<Self>
+    where
+        F: FnOnce(Self) -> Result<Self>,
+    {
+        self.layout(String::new(), true, contents)
+    }
+    /// Adds the elements added by `contents` stacked vertically. This is mostly useful inside
+    /// of [horizontal](#method.horizontal), e.g. to make columns.
+    pub fn vertical<F>(self, contents: F) -> Result<Self>
+    where
+        F: FnOnce(Self) -> Result<Self>,
+    {
+        self.layout(String::new(), false, contents)
+    }
+    /// Like [horizontal](#method.horizontal) or [vertical](#method.vertical), but the layout
+    /// has a key, so that elements can be appended to it with [PopupHandle::append] while the
+    /// popup is open.
+    pub fn keyed_layout<F>(
+        self,
+        key: impl Into<String>,
+        horizontal: bool,
+        contents: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce(Self) -> Result<Self>,
+    {
+        self.layout(key.into(), horizontal, contents)
+    }
+    fn layout<F>(self, key: String, horizontal: bool, contents: F) -> Result<Self>
+    where
+        F: FnOnce(Self) -> Result<Self>,
+    {
+        // Let contents add widgets to an empty list (so that inventory views etc. are still
+        // registered with this popup), then move them into the layout.
+        let outer = std::mem::take(&mut *self.widgets.write());
+        let result = contents(self)?;
+        {
+            let mut widgets = result.widgets.write();
+            let elements = std::mem::replace(&mut *widgets, outer);
+            widgets.push(UiElement::Layout {
+                key,
+                horizontal,
+                elements,
+            });
+        }
+        Ok(result)
+    }
+    /// Adds a new button to this popup.
+    pub fn button(self, key: impl Into<String>, label: impl Into<String>, enabled: bool) -> Self {
+        self.widgets.write().push(UiElement::Button(Button {
             key: key.into(),
             label: label.into(),
             enabled,
@@ -131,6 +358,7 @@ impl Popup {
         let view =
             InventoryView::new_stored(inventory_key, self.game_state.clone(), can_place, can_take)?;
         self.widgets
+            .write()
             .push(UiElement::InventoryView(form_key.clone(), view.id));
         self.interested_stored_inventories.insert(inventory_key);
         match self.inventory_views.insert(form_key.clone(), view) {
@@ -166,6 +394,7 @@ impl Popup {
             false,
         )?;
         self.widgets
+            .write()
             .push(UiElement::InventoryView(form_key.clone(), view.id));
         match self.inventory_views.insert(form_key.clone(), view) {
             Some(view) => {
@@ -194,6 +423,33 @@ impl Popup {
             take_exact,
         )?;
         self.widgets
+            .write()
+            .push(UiElement::InventoryView(form_key.clone(), view.id));
+        match self.inventory_views.insert(form_key.clone(), view) {
+            Some(view) => {
+                bail!(
+                    "form_key {} already registered for view {:?}",
+                    form_key,
+                    view.id
+                );
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// Adds an inventory view that accepts items put into it (e.g. a trash slot or a shop's
+    /// sell slot), but never holds them. See [VirtualInputCallbacks].
+    pub fn inventory_view_virtual_input(
+        mut self,
+        form_key: impl Into<String>,
+        dimensions: (u32, u32),
+        callbacks: VirtualInputCallbacks<Popup>,
+    ) -> Result<Self> {
+        let form_key = form_key.into();
+        let view =
+            InventoryView::new_virtual_input(self.game_state.clone(), dimensions, callbacks)?;
+        self.widgets
+            .write()
             .push(UiElement::InventoryView(form_key.clone(), view.id));
         match self.inventory_views.insert(form_key.clone(), view) {
             Some(view) => {
@@ -207,6 +463,12 @@ impl Popup {
         }
     }
 
+    /// Adds an inventory view showing the inventory stored in the given block under inv_key.
+    /// If the block doesn't have that inventory yet, it's created with default_dimensions.
+    ///
+    /// callbacks can restrict which items may be put into each slot (e.g. only fuel into a
+    /// furnace's fuel slot), and be notified when the contents change.
+    #[allow(clippy::too_many_arguments)]
     pub fn inventory_view_block(
         mut self,
         form_key: impl Into<String>,
@@ -215,7 +477,8 @@ impl Popup {
         inv_key: String,
         can_place: bool,
         can_take: bool,
-        take_exact: bool
+        take_exact: bool,
+        callbacks: BlockViewCallbacks<Popup>,
     ) -> Result<Self> {
         let form_key = form_key.into();
         let view = InventoryView::new_block(
@@ -226,8 +489,10 @@ impl Popup {
             can_place,
             can_take,
             take_exact,
+            callbacks,
         )?;
         self.widgets
+            .write()
             .push(UiElement::InventoryView(form_key.clone(), view.id));
         match self.inventory_views.insert(form_key.clone(), view) {
             Some(view) => {
@@ -256,12 +521,29 @@ impl Popup {
 
     /// Sets a function that will be called when a button is clicked
     ///
+    /// Once the callback returns, the popup (including any dynamic labels) and its inventory
+    /// views are sent to the client again.
+    ///
     /// Exact parameters are still tbd
     ///
     /// This replaces any previously set function
-    pub fn set_button_callback<F>(mut self, callback: F) -> Self
+    pub fn set_button_callback<F>(self, callback: F) -> Self
     where
         F: Fn(PopupResponse) + Send + Sync + 'static,
+    {
+        self.set_fallible_button_callback(move |response| {
+            callback(response);
+            Ok(())
+        })
+    }
+
+    /// Like [set_button_callback](#method.set_button_callback), but the callback may fail.
+    /// If it returns Err, the error is shown to the player.
+    ///
+    /// This replaces any previously set function
+    pub fn set_fallible_button_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(PopupResponse) -> Result<()> + Send + Sync + 'static,
     {
         self.button_callback = Some(Box::new(callback));
         self
@@ -275,63 +557,670 @@ impl Popup {
 
     pub(crate) fn handle_response(
         &mut self,
-        response: PopupResponse,
+        mut response: PopupResponse,
         player_main_inv: InventoryKey,
+        initiator: EventInitiator,
     ) -> Result<()> {
         if let PopupAction::PopupClosed = response.user_action {
             for view in self.inventory_views.values_mut() {
                 view.clear_if_transient(Some(player_main_inv))?;
             }
         }
+        validate_response(&self.widgets.read(), &mut response);
         if let Some(callback) = &self.button_callback {
-            callback(response)
+            run_handler!(|| callback(response), "popup_button", initiator)?;
         }
         Ok(())
     }
 
     pub(crate) fn to_proto(&self) -> proto::PopupDescription {
-        let elements = self
-            .widgets
-            .iter()
-            .map(|x| {
-                let element = match x {
-                    UiElement::Label(label) => proto::ui_element::Element::Label(label.clone()),
-                    UiElement::TextField(field) => {
-                        proto::ui_element::Element::TextField(field.clone())
-                    }
-                    UiElement::Button(button) => proto::ui_element::Element::Button(button.clone()),
-                    UiElement::InventoryView(key, view_id) => {
-                        // we can expect() here since presence in the map is an invariant
-                        let view = self
-                            .inventory_views
-                            .get(key)
-                            .expect("No inventory view found for provided key");
-                        proto::ui_element::Element::Inventory(proto::InventoryView {
-                            inventory_key: view_id.0,
-                            can_place: view.can_place,
-                            can_take: view.can_take,
-                        })
-                    }
-                };
-                proto::UiElement {
-                    element: Some(element),
-                }
-            })
-            .collect();
+        // Dynamic labels may change this popup through a handle, so they're computed on a copy
+        // of the widgets rather than under the lock.
+        let widgets = self.widgets.read().clone();
         proto::PopupDescription {
             popup_id: self.id,
             title: self.title.clone(),
-            element: elements,
+            element: widgets
+                .iter()
+                .map(|x| {
+                    element_to_proto(x, Some(self))
+                        .expect("Popup should be able to draw any element")
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns a handle that can change this popup's elements while it's open, e.g. from a timer
+    /// or another popup's callback. The handle can be cloned and kept after the popup is sent
+    /// to a player.
+    pub fn handle(&self) -> PopupHandle {
+        PopupHandle {
+            id: self.id,
+            widgets: Arc::downgrade(&self.widgets),
+            update_sender: self.update_sender.clone(),
         }
     }
 
+    /// Called when the popup is shown to a player, so that changes made through its handles are
+    /// sent to that player's client.
+    pub(crate) fn show_to(&self, sender: PopupUpdateSender) {
+        *self.update_sender.lock() = Some(sender);
+    }
+
     pub fn inventory_views(&self) -> &HashMap<String, InventoryView<Popup>> {
         &self.inventory_views
     }
 
+    /// The inventory views in this popup, in the order they're shown
+    pub(crate) fn inventory_views_in_order(&self) -> Vec<&InventoryView<Popup>> {
+        let mut keys = vec![];
+        collect_inventory_view_keys(&self.widgets.read(), &mut keys);
+        keys.iter()
+            .filter_map(|key| self.inventory_views.get(key))
+            .collect()
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// Converts an element to its proto. Elements that depend on the popup they're in (dynamic labels
+/// and inventory views) can only be converted if the popup is given; otherwise, None is returned.
+fn element_to_proto(x: &UiElement, popup: Option<&Popup>) -> Option<proto::UiElement> {
+    let element = match x {
+        UiElement::Label(label) => proto::ui_element::Element::Label(label.clone()),
+        UiElement::DynamicLabel(label) => proto::ui_element::Element::Label(label(popup?)),
+        UiElement::TextField(field) => proto::ui_element::Element::TextField(field.clone()),
+        UiElement::Button(button) => proto::ui_element::Element::Button(button.clone()),
+        UiElement::InventoryView(key, view_id) => {
+            // we can expect() here since presence in the map is an invariant
+            let view = popup?
+                .inventory_views
+                .get(key)
+                .expect("No inventory view found for provided key");
+            proto::ui_element::Element::Inventory(proto::InventoryView {
+                inventory_key: view_id.0,
+                can_place: view.can_place,
+                can_take: view.can_take,
+            })
+        }
+        UiElement::Checkbox(checkbox) => proto::ui_element::Element::Checkbox(checkbox.clone()),
+        UiElement::Dropdown(dropdown) => proto::ui_element::Element::Dropdown(dropdown.clone()),
+        UiElement::Slider(slider) => proto::ui_element::Element::Slider(slider.clone()),
+        UiElement::ProgressBar(progress_bar) => {
+            proto::ui_element::Element::ProgressBar(progress_bar.clone())
+        }
+        UiElement::Image(image) => proto::ui_element::Element::Image(image.clone()),
+        UiElement::Layout {
+            key,
+            horizontal,
+            elements,
+        } => proto::ui_element::Element::Layout(proto::Layout {
+            horizontal: *horizontal,
+            element: elements
+                .iter()
+                .map(|x| element_to_proto(x, popup))
+                .collect::<Option<_>>()?,
+            key: key.clone(),
+        }),
+        UiElement::KeyedLabel(key, text) => {
+            proto::ui_element::Element::KeyedLabel(proto::KeyedLabel {
+                key: key.clone(),
+                text: text.clone(),
+            })
+        }
+    };
+    Some(proto::UiElement {
+        element: Some(element),
+    })
+}
+
+fn collect_inventory_view_keys(widgets: &[UiElement], result: &mut Vec<String>) {
+    for widget in widgets {
+        match widget {
+            UiElement::InventoryView(key, _) => result.push(key.clone()),
+            UiElement::Layout { elements, .. } => collect_inventory_view_keys(elements, result),
+            _ => {}
+        }
+    }
+}
+
+/// Drops dropdown and slider values sent by the client that don't belong to a dropdown or slider
+/// in the popup, or that pick an option that doesn't exist. Slider values are clamped to the
+/// slider's range.
+fn validate_response(widgets: &[UiElement], response: &mut PopupResponse) {
+    response
+        .dropdown_values
+        .retain(|key, index| match find_keyed_element(widgets, key) {
+            Some(UiElement::Dropdown(dropdown)) if (*index as usize) < dropdown.options.len() => {
+                true
+            }
+            _ => {
+                log::warn!("Ignoring invalid selection {index} for dropdown {key}");
+                false
+            }
+        });
+    response
+        .slider_values
+        .retain(|key, value| match find_keyed_element(widgets, key) {
+            Some(UiElement::Slider(slider)) if !value.is_nan() => {
+                let (min, max) = (slider.min.min(slider.max), slider.max.max(slider.min));
+                *value = value.max(min).min(max);
+                true
+            }
+            _ => {
+                log::warn!("Ignoring invalid value {value} for slider {key}");
+                false
+            }
+        });
+}
+
+/// Finds the element with the given key, searching inside layouts. Empty keys never match.
+fn find_keyed_element<'a>(widgets: &'a [UiElement], key: &str) -> Option<&'a UiElement> {
+    if key.is_empty() {
+        return None;
+    }
+    for widget in widgets {
+        if element_key(widget) == Some(key) {
+            return Some(widget);
+        }
+        if let UiElement::Layout { elements, .. } = widget {
+            if let Some(found) = find_keyed_element(elements, key) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+/// Like [find_keyed_element], but returns a mutable reference.
+fn find_keyed_element_mut<'a>(
+    widgets: &'a mut [UiElement],
+    key: &str,
+) -> Option<&'a mut UiElement> {
+    if key.is_empty() {
+        return None;
+    }
+    for widget in widgets {
+        if element_key(widget) == Some(key) {
+            return Some(widget);
+        }
+        if let UiElement::Layout { elements, .. } = widget {
+            if let Some(found) = find_keyed_element_mut(elements, key) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+fn element_key(element: &UiElement) -> Option<&str> {
+    match element {
+        UiElement::KeyedLabel(key, _)
+        | UiElement::TextField(TextField { key, .. })
+        | UiElement::Button(Button { key, .. })
+        | UiElement::Checkbox(Checkbox { key, .. })
+        | UiElement::Dropdown(Dropdown { key, .. })
+        | UiElement::Slider(Slider { key, .. })
+        | UiElement::ProgressBar(ProgressBar { key, .. })
+        | UiElement::Layout { key, .. } => Some(key),
+        _ => None,
+    }
+}
+
+/// A handle to a popup that can be kept while the popup is open, to change its elements (e.g. to
+/// show the progress of a furnace). Changes are sent to the player who has the popup open;
+/// setting an element to the value it already has sends nothing.
+///
+/// Elements are found by their key; empty keys never match. Once the popup is closed (or was
+/// never shown and has been dropped), all methods return an error.
+#[derive(Clone)]
+pub struct PopupHandle {
+    id: u64,
+    widgets: Weak<RwLock<Vec<UiElement>>>,
+    update_sender: Arc<Mutex<Option<PopupUpdateSender>>>,
+}
+impl PopupHandle {
+    /// The ID of the popup this handle refers to.
     pub fn id(&self) -> u64 {
         self.id
     }
+
+    /// Whether the popup still exists (i.e. hasn't been closed).
+    pub fn is_open(&self) -> bool {
+        self.widgets.strong_count() > 0
+    }
+
+    /// Changes the text of a keyed label, or the label of a button or progress bar.
+    pub fn set_text(&self, key: &str, text: impl Into<String>) -> Result<()> {
+        let text = text.into();
+        let changed = self.modify(key, |element| match element {
+            UiElement::KeyedLabel(_, x)
+            | UiElement::Button(Button { label: x, .. })
+            | UiElement::ProgressBar(ProgressBar { label: x, .. }) => {
+                Ok(replace_if_changed(x, text.clone()))
+            }
+            _ => bail!("Element {key} doesn't have text that can be set"),
+        })?;
+        if changed {
+            self.send(key, proto::element_update::Update::Text(text));
+        }
+        Ok(())
+    }
+
+    /// Changes the progress (from 0 to 1) of a progress bar.
+    pub fn set_progress(&self, key: &str, progress: f32) -> Result<()> {
+        let changed = self.modify(key, |element| match element {
+            UiElement::ProgressBar(bar) => Ok(replace_if_changed(&mut bar.progress, progress)),
+            _ => bail!("Element {key} isn't a progress bar"),
+        })?;
+        if changed {
+            self.send(key, proto::element_update::Update::Progress(progress));
+        }
+        Ok(())
+    }
+
+    /// Enables or disables a text field, button, checkbox, dropdown, or slider.
+    pub fn set_enabled(&self, key: &str, enabled: bool) -> Result<()> {
+        let changed = self.modify(key, |element| match element {
+            UiElement::TextField(TextField { enabled: x, .. })
+            | UiElement::Button(Button { enabled: x, .. })
+            | UiElement::Checkbox(Checkbox { enabled: x, .. })
+            | UiElement::Dropdown(Dropdown { enabled: x, .. })
+            | UiElement::Slider(Slider { enabled: x, .. }) => Ok(replace_if_changed(x, enabled)),
+            _ => bail!("Element {key} can't be enabled or disabled"),
+        })?;
+        if changed {
+            self.send(key, proto::element_update::Update::Enabled(enabled));
+        }
+        Ok(())
+    }
+
+    /// Appends an element to the end of the keyed layout with the given key, or to the end of
+    /// the popup itself if layout_key is empty.
+    ///
+    /// Dynamic labels and inventory views can't be appended, since they need to be registered
+    /// with the popup when it's built.
+    pub fn append(&self, layout_key: &str, element: UiElement) -> Result<()> {
+        let element_proto = element_to_proto(&element, None)
+            .context("Dynamic labels and inventory views can't be appended to an open popup")?;
+        let widgets = self.widgets.upgrade().context("Popup is closed")?;
+        {
+            let mut widgets = widgets.write();
+            if layout_key.is_empty() {
+                widgets.push(element);
+            } else {
+                match find_keyed_element_mut(&mut widgets, layout_key) {
+                    Some(UiElement::Layout { elements, .. }) => elements.push(element),
+                    Some(_) => bail!("Element {layout_key} isn't a layout"),
+                    None => bail!("No element with key {layout_key}"),
+                }
+            }
+        }
+        self.send(
+            layout_key,
+            proto::element_update::Update::Append(element_proto),
+        );
+        Ok(())
+    }
+
+    // Runs f on the element with the given key, returning what f returns
+    fn modify<F, T>(&self, key: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut UiElement) -> Result<T>,
+    {
+        if key.is_empty() {
+            bail!("Elements can only be found by a non-empty key");
+        }
+        let widgets = self.widgets.upgrade().context("Popup is closed")?;
+        let mut widgets = widgets.write();
+        match find_keyed_element_mut(&mut widgets, key) {
+            Some(element) => f(element),
+            None => bail!("No element with key {key}"),
+        }
+    }
+
+    fn send(&self, key: &str, update: proto::element_update::Update) {
+        // If the popup hasn't been shown yet, the change will be sent along with the rest of it
+        if let Some(sender) = &*self.update_sender.lock() {
+            sender.send(proto::PopupUpdate {
+                popup_id: self.id,
+                update: vec![proto::ElementUpdate {
+                    key: key.to_string(),
+                    update: Some(update),
+                }],
+            });
+        }
+    }
+}
+
+fn replace_if_changed<T: PartialEq>(target: &mut T, value: T) -> bool {
+    if *target == value {
+        false
+    } else {
+        *target = value;
+        true
+    }
+}
+
+const POPUP_UPDATE_CHANNEL_SIZE: usize = 256;
+
+/// Creates the channel that carries changes to one player's open popups to their client context.
+pub(crate) fn popup_update_channel() -> (PopupUpdateSender, PopupUpdateReceiver) {
+    let (sender, receiver) = mpsc::channel(POPUP_UPDATE_CHANNEL_SIZE);
+    let lagged = Arc::new(AtomicBool::new(false));
+    (
+        PopupUpdateSender {
+            sender,
+            lagged: lagged.clone(),
+        },
+        PopupUpdateReceiver { receiver, lagged },
+    )
+}
+
+#[derive(Clone)]
+pub(crate) struct PopupUpdateSender {
+    sender: mpsc::Sender<proto::PopupUpdate>,
+    // Set when an update was dropped because the channel was full
+    lagged: Arc<AtomicBool>,
+}
+impl PopupUpdateSender {
+    fn send(&self, update: proto::PopupUpdate) {
+        match self.sender.try_send(update) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => self.lagged.store(true, Ordering::Release),
+            // The player disconnected, so nobody needs the update
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+pub(crate) enum PopupUpdateEvent {
+    /// An update to forward to the client
+    Update(proto::PopupUpdate),
+    /// Some updates were dropped, so the player's popups need to be sent again in full
+    Lagged,
+}
+
+pub(crate) struct PopupUpdateReceiver {
+    receiver: mpsc::Receiver<proto::PopupUpdate>,
+    lagged: Arc<AtomicBool>,
+}
+impl PopupUpdateReceiver {
+    /// Waits for the next update. Returns None once all senders are gone.
+    pub(crate) async fn recv(&mut self) -> Option<PopupUpdateEvent> {
+        let update = self.receiver.recv().await?;
+        if self.lagged.swap(false, Ordering::AcqRel) {
+            // Anything still queued is older than the popups that are about to be resent
+            while self.receiver.try_recv().is_ok() {}
+            return Some(PopupUpdateEvent::Lagged);
+        }
+        Some(PopupUpdateEvent::Update(update))
+    }
 }
 
 static NEXT_POPUP_ID: AtomicU64 = AtomicU64::new(1);
+
+#[cfg(test)]
+mod tests {
+    use crate::game_state::tests::make_empty_game_state;
+
+    use super::*;
+
+    fn nested_widgets() -> Vec<UiElement> {
+        vec![
+            UiElement::Label("Status".to_string()),
+            UiElement::Layout {
+                key: "outer".to_string(),
+                horizontal: true,
+                elements: vec![
+                    UiElement::KeyedLabel("count".to_string(), "0".to_string()),
+                    UiElement::Layout {
+                        key: String::new(),
+                        horizontal: false,
+                        elements: vec![UiElement::ProgressBar(ProgressBar {
+                            progress: 0.0,
+                            label: "Progress".to_string(),
+                            key: "progress".to_string(),
+                        })],
+                    },
+                ],
+            },
+        ]
+    }
+
+    fn make_handle(widgets: &Arc<RwLock<Vec<UiElement>>>) -> (PopupHandle, PopupUpdateReceiver) {
+        let (sender, receiver) = popup_update_channel();
+        let handle = PopupHandle {
+            id: 1,
+            widgets: Arc::downgrade(widgets),
+            update_sender: Arc::new(Mutex::new(Some(sender))),
+        };
+        (handle, receiver)
+    }
+
+    #[test]
+    fn test_find_nested_keyed_element() {
+        let widgets = nested_widgets();
+        assert!(matches!(
+            find_keyed_element(&widgets, "outer"),
+            Some(UiElement::Layout { .. })
+        ));
+        assert!(matches!(
+            find_keyed_element(&widgets, "count"),
+            Some(UiElement::KeyedLabel(_, text)) if text == "0"
+        ));
+        assert!(matches!(
+            find_keyed_element(&widgets, "progress"),
+            Some(UiElement::ProgressBar(_))
+        ));
+        assert!(find_keyed_element(&widgets, "missing").is_none());
+        // The inner layout has an empty key, which doesn't match
+        assert!(find_keyed_element(&widgets, "").is_none());
+    }
+
+    #[test]
+    fn test_handle_updates_nested_element() {
+        let widgets = Arc::new(RwLock::new(nested_widgets()));
+        let (handle, mut receiver) = make_handle(&widgets);
+
+        handle.set_progress("progress", 0.5).unwrap();
+        match find_keyed_element(&widgets.read(), "progress") {
+            Some(UiElement::ProgressBar(bar)) => assert_eq!(bar.progress, 0.5),
+            _ => panic!("progress bar not found"),
+        }
+        let update = receiver.receiver.try_recv().unwrap();
+        assert_eq!(update.popup_id, 1);
+        assert_eq!(
+            update.update,
+            vec![proto::ElementUpdate {
+                key: "progress".to_string(),
+                update: Some(proto::element_update::Update::Progress(0.5)),
+            }]
+        );
+
+        // Nothing is sent if the value doesn't change
+        handle.set_progress("progress", 0.5).unwrap();
+        assert!(receiver.receiver.try_recv().is_err());
+
+        handle.set_text("count", "1").unwrap();
+        match find_keyed_element(&widgets.read(), "count") {
+            Some(UiElement::KeyedLabel(_, text)) => assert_eq!(text, "1"),
+            _ => panic!("label not found"),
+        }
+        assert!(receiver.receiver.try_recv().is_ok());
+
+        assert!(handle.set_progress("count", 0.5).is_err());
+        assert!(handle.set_text("missing", "1").is_err());
+        assert!(handle.set_enabled("", false).is_err());
+        assert!(receiver.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_handle_appends_to_keyed_layout() {
+        let widgets = Arc::new(RwLock::new(nested_widgets()));
+        let (handle, mut receiver) = make_handle(&widgets);
+
+        handle
+            .append("outer", UiElement::Label("Appended".to_string()))
+            .unwrap();
+        match find_keyed_element(&widgets.read(), "outer") {
+            Some(UiElement::Layout { elements, .. }) => {
+                assert_eq!(elements.len(), 3);
+                assert!(matches!(&elements[2], UiElement::Label(x) if x == "Appended"));
+            }
+            _ => panic!("layout not found"),
+        }
+        let update = receiver.receiver.try_recv().unwrap();
+        assert_eq!(update.update[0].key, "outer");
+        assert!(matches!(
+            update.update[0].update,
+            Some(proto::element_update::Update::Append(_))
+        ));
+
+        handle
+            .append("", UiElement::Label("At the end".to_string()))
+            .unwrap();
+        assert_eq!(widgets.read().len(), 3);
+
+        assert!(handle
+            .append("count", UiElement::Label("x".to_string()))
+            .is_err());
+        assert!(handle
+            .append(
+                "outer",
+                UiElement::DynamicLabel(Arc::new(|_| String::new()))
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_handle_of_closed_popup() {
+        let widgets = Arc::new(RwLock::new(nested_widgets()));
+        let (handle, _receiver) = make_handle(&widgets);
+        assert!(handle.is_open());
+        drop(widgets);
+        assert!(!handle.is_open());
+        assert!(handle.set_text("count", "1").is_err());
+    }
+
+    #[test]
+    fn test_full_channel_resends_popups() {
+        let widgets = Arc::new(RwLock::new(nested_widgets()));
+        let (handle, mut receiver) = make_handle(&widgets);
+        for i in 0..=POPUP_UPDATE_CHANNEL_SIZE {
+            handle.set_text("count", i.to_string()).unwrap();
+        }
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        assert!(matches!(
+            rt.block_on(receiver.recv()),
+            Some(PopupUpdateEvent::Lagged)
+        ));
+        // The queued updates are dropped, since the popups are sent again in full
+        assert!(receiver.receiver.try_recv().is_err());
+
+        handle.set_text("count", "new").unwrap();
+        assert!(matches!(
+            rt.block_on(receiver.recv()),
+            Some(PopupUpdateEvent::Update(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_response() {
+        let widgets = vec![
+            UiElement::Dropdown(Dropdown {
+                key: "mode".to_string(),
+                label: "Mode".to_string(),
+                options: vec!["a".to_string(), "b".to_string()],
+                initial: 0,
+                enabled: true,
+            }),
+            UiElement::Layout {
+                key: String::new(),
+                horizontal: false,
+                elements: vec![UiElement::Slider(Slider {
+                    key: "speed".to_string(),
+                    label: "Speed".to_string(),
+                    min: 1.0,
+                    max: 10.0,
+                    initial: 5.0,
+                    step: 0.0,
+                    enabled: true,
+                })],
+            },
+        ];
+        let response = |dropdown: u32, slider: f64| PopupResponse {
+            user_action: PopupAction::PopupClosed,
+            textfield_values: HashMap::new(),
+            checkbox_values: HashMap::new(),
+            dropdown_values: HashMap::from([
+                ("mode".to_string(), dropdown),
+                ("speed".to_string(), dropdown),
+            ]),
+            slider_values: HashMap::from([
+                ("speed".to_string(), slider),
+                ("mode".to_string(), slider),
+                ("missing".to_string(), slider),
+            ]),
+        };
+
+        // Values for other kinds of elements, or for missing ones, are dropped
+        let mut valid = response(1, 2.5);
+        validate_response(&widgets, &mut valid);
+        assert_eq!(
+            valid.dropdown_values,
+            HashMap::from([("mode".to_string(), 1)])
+        );
+        assert_eq!(
+            valid.slider_values,
+            HashMap::from([("speed".to_string(), 2.5)])
+        );
+
+        let mut invalid = response(2, 100.0);
+        validate_response(&widgets, &mut invalid);
+        assert!(invalid.dropdown_values.is_empty());
+        assert_eq!(
+            invalid.slider_values,
+            HashMap::from([("speed".to_string(), 10.0)])
+        );
+
+        let mut below = response(0, -100.0);
+        validate_response(&widgets, &mut below);
+        assert_eq!(
+            below.slider_values,
+            HashMap::from([("speed".to_string(), 1.0)])
+        );
+
+        let mut nan = response(0, f64::NAN);
+        validate_response(&widgets, &mut nan);
+        assert!(nan.slider_values.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dynamic_label_uses_handle() {
+        let game_state = make_empty_game_state();
+
+        let popup = Popup::new(game_state.clone()).keyed_label("count", "0");
+        let handle = popup.handle();
+        let popup = popup.dynamic_label(move |_| {
+            handle.set_text("count", "1").unwrap();
+            "Dynamic".to_string()
+        });
+
+        // This would deadlock if the label were computed under the widgets lock
+        let description = popup.to_proto();
+        assert!(matches!(
+            &description.element[1].element,
+            Some(proto::ui_element::Element::Label(x)) if x == "Dynamic"
+        ));
+        assert!(matches!(
+            find_keyed_element(&popup.widgets.read(), "count"),
+            Some(UiElement::KeyedLabel(_, text)) if text == "1"
+        ));
+
+        drop(popup);
+        game_state.finish_shutdown().await;
+    }
+}
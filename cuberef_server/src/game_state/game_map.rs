@@ -66,7 +66,7 @@ use tokio::{
     task::JoinHandle,
 };
 
-trait AsDbKey
+pub(crate) trait AsDbKey
 where
     Self: Sized,
 {
@@ -177,6 +177,26 @@ impl MapChunk {
         }
     }
 
+    // Creates a chunk that isn't attached to a game state, e.g. for partially-generated
+    // chunks in the mapgen pipeline. It must not be serialized or stored in the map.
+    pub(crate) fn new_detached(own_coord: ChunkCoordinate) -> Self {
+        Self {
+            own_coord,
+            block_ids: vec![0; 4096],
+            extended_data: FxHashMap::default(),
+            game_state: Weak::new(),
+            dirty: false,
+        }
+    }
+
+    // Replaces the blocks in this chunk with those from another chunk, and moves its extended data
+    // into this chunk.
+    pub(crate) fn take_contents_from(&mut self, other: &mut MapChunk) {
+        self.block_ids.copy_from_slice(&other.block_ids);
+        self.extended_data = std::mem::take(&mut other.extended_data);
+        self.dirty = true;
+    }
+
     fn serialize(&self, usage: ChunkUsage) -> Result<mapchunk_proto::StoredChunk> {
         let _span = span!("serialize chunk");
         let mut extended_data = Vec::new();
@@ -289,6 +309,15 @@ impl MapChunk {
             None => bail!("Missing chunk_data or unrecognized format"),
         }
     }
+    /// Gets the block at the given coordinate within the chunk.
+    pub fn get_block(
+        &self,
+        coordinate: ChunkOffset,
+        block_types: &BlockTypeManager,
+    ) -> Result<BlockTypeHandle> {
+        block_types.make_blockref(self.block_ids[coordinate.as_index()].into())
+    }
+
     /// Sets the block at the given coordinate within the chunk.
     /// This function is intended to be used from map generators, and may
     /// lead to data loss or inconsistency when used elsewhere
@@ -516,6 +545,14 @@ impl MapChunkHolder {
             HolderState::Ok(_) => Ok(Some(MapChunkInnerGuard { guard })),
         }
     }
+    /// Get the chunk if it's loaded and nobody else is using it right now, without blocking
+    fn try_get_uncontended(&self) -> Option<MapChunkInnerGuard<'_>> {
+        let guard = self.chunk.try_lock()?;
+        match &*guard {
+            HolderState::Ok(_) => Some(MapChunkInnerGuard { guard }),
+            _ => None,
+        }
+    }
     /// Set the chunk, and notify any threads waiting in wait_and_get
     fn fill(&self, chunk: MapChunk) {
         let _span = span!("game_map waiting to fill chunk");
@@ -676,6 +713,26 @@ impl ServerGameMap {
         self.block_type_manager().make_blockref(id.into())
     }
 
+    /// Gets a block + variant, but only if its chunk is already loaded in memory.
+    /// Returns None (without loading or generating anything) if the chunk isn't loaded.
+    pub fn try_get_block(&self, coord: BlockCoordinate) -> Option<BlockTypeHandle> {
+        let _span = span!("game_map read lock");
+        let read_guard = self.live_chunks.read();
+        let holder = read_guard.get(&coord.chunk())?;
+        let chunk = holder.try_get().ok()??;
+        let id = chunk.block_ids[coord.offset().as_index()];
+        self.block_type_manager().make_blockref(id.into()).ok()
+    }
+
+    // Copies the block IDs of a chunk if it's loaded in memory, without loading it or blocking.
+    // Used by mapgen to read already-generated neighbors; since mapgen may run while the caller
+    // holds locks on the map or on other chunks, this gives up rather than waiting for them.
+    pub(crate) fn try_get_loaded_block_ids(&self, coord: ChunkCoordinate) -> Option<Vec<u32>> {
+        let read_guard = self.live_chunks.try_read_recursive()?;
+        let chunk = read_guard.get(&coord)?.try_get_uncontended()?;
+        Some(chunk.block_ids.clone())
+    }
+
     /// Sets a block on the map. No handlers are run, and the block is updated unconditionally.
     /// The old block is returned along with its extended data, if any.
     pub fn set_block<T: TryAsHandle>(
@@ -996,7 +1053,7 @@ impl ServerGameMap {
         chunk.dirty = true;
         {
             let _span = span!("mapgen running");
-            run_handler!(|| Ok(self.game_state().mapgen().fill_chunk(coord, &mut chunk)), "mapgen", EventInitiator::Engine)?;
+            run_handler!(|| self.game_state().mapgen().fill_chunk(coord, &mut chunk), "mapgen", EventInitiator::Engine)?;
         }
         // This will only execute after the mutex is released.
         self.enqueue_writeback(coord)?;
@@ -1007,6 +1064,35 @@ impl ServerGameMap {
         Weak::upgrade(&self.game_state).unwrap()
     }
 
+    // Generates a chunk and stores it directly into the database, without loading it into memory.
+    // Does nothing if the chunk is already in memory or in the database.
+    // Returns true if the chunk was generated.
+    pub(crate) fn pregenerate_chunk(&self, coord: ChunkCoordinate) -> Result<bool> {
+        let _span = span!("pregenerate_chunk");
+        let db_key = KeySpace::MapchunkData.make_key(&coord.as_bytes());
+        if self.live_chunks.read().contains_key(&coord) || self.database.get(&db_key)?.is_some() {
+            return Ok(false);
+        }
+
+        // Generation is slow, so do it without holding any locks
+        let mut chunk = MapChunk::new(coord, self.game_state());
+        {
+            let _span = span!("mapgen running");
+            run_handler!(|| self.game_state().mapgen().fill_chunk(coord, &mut chunk), "mapgen", EventInitiator::Engine)?;
+        }
+        let serialized = chunk.serialize(ChunkUsage::Server)?.encode_to_vec();
+
+        // Loading a chunk into memory requires the write lock, so holding the read lock ensures that nobody
+        // loads this chunk (and possibly modifies it and writes it back) while we check and store it.
+        let lock = self.live_chunks.read();
+        if lock.contains_key(&coord) || self.database.get(&db_key)?.is_some() {
+            // Someone else loaded or generated the chunk in the meantime; their copy wins.
+            return Ok(false);
+        }
+        self.database.put(&db_key, &serialized)?;
+        Ok(true)
+    }
+
     fn unload_chunk_locked(
         &self,
         lock: &mut RwLockWriteGuard<FxHashMap<ChunkCoordinate, MapChunkHolder>>,
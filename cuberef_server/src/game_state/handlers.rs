@@ -14,6 +14,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::fmt::Display;
 use std::panic::{UnwindSafe, AssertUnwindSafe};
 
 use anyhow::Error;
@@ -21,19 +22,94 @@ use tracy_client::{span, span_location};
 
 use super::event::EventInitiator;
 
-/// Wrapper for handlers, eventually used for accounting, error handling, etc.
-/// Currently a no-op
+/// Wrapper for handlers, eventually used for accounting, etc.
+///
+/// Errors (and panics) are tagged with a [HandlerError], so that they can be shown to the
+/// player whose action ran the handler.
 #[inline]
-pub(crate) fn run_handler_impl<T, F>(closure: F, name: &'static str, _initiator: EventInitiator) -> anyhow::Result<T>
+pub(crate) fn run_handler_impl<T, F>(closure: F, name: &'static str, initiator: EventInitiator) -> anyhow::Result<T>
 where
     F: FnOnce() -> anyhow::Result<T>,
 {
+    let player = match initiator {
+        EventInitiator::Engine => None,
+        EventInitiator::Player(player) => Some(player.name().to_string()),
+    };
     // todo clean up AssertUnwindSafe if possible
     match std::panic::catch_unwind(AssertUnwindSafe(closure)) {
-        Ok(x) => x,
-        Err(_e) => Err(Error::msg(format!("Handler {} panicked", name))),
+        Ok(Ok(x)) => Ok(x),
+        Ok(Err(e)) => Err(Error::new(HandlerError {
+            handler: name,
+            player,
+            source: Some(e),
+        })),
+        Err(_e) => Err(Error::new(HandlerError {
+            handler: name,
+            player,
+            source: None,
+        })),
+    }
+}
+
+/// Marks an error as coming from a game content handler, rather than from the engine itself.
+#[derive(Debug)]
+pub(crate) struct HandlerError {
+    handler: &'static str,
+    // The player whose action ran the handler, or None if the engine ran it (e.g. mapgen)
+    player: Option<String>,
+    // The handler's own error, or None if it panicked. This is the error's source (rather than
+    // the HandlerError being context on it), so that it shows up in error chains.
+    source: Option<Error>,
+}
+impl HandlerError {
+    /// Finds the error from a handler that ran because of the given player's own action, if
+    /// there is one in the error's chain. Errors from handlers that the engine ran along the
+    /// way (e.g. mapgen for a chunk the player was digging in) are skipped.
+    pub(crate) fn find_for_player<'a>(error: &'a Error, player: &str) -> Option<&'a HandlerError> {
+        error
+            .chain()
+            .filter_map(|x| x.downcast_ref::<HandlerError>())
+            .find(|x| x.player.as_deref() == Some(player))
+    }
+
+    /// A version of the error that's suitable to show to players: only the first line of the
+    /// handler's message (without the errors that caused it), without control characters, and
+    /// limited in length. Panics aren't described at all.
+    pub(crate) fn user_message(&self) -> String {
+        let message = match &self.source {
+            Some(x) => x.to_string(),
+            None => return "Something went wrong on the server".to_string(),
+        };
+        let message = message.lines().next().unwrap_or_default();
+        let mut result: String = message
+            .chars()
+            .filter(|x| !x.is_control())
+            .take(MAX_USER_MESSAGE_CHARS)
+            .collect();
+        if message.chars().count() > MAX_USER_MESSAGE_CHARS {
+            result.push('…');
+        }
+        result
     }
 }
+impl Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            // The handler's error itself follows in the error chain
+            Some(_) => write!(f, "Handler {} failed", self.handler),
+            None => write!(f, "Handler {} panicked", self.handler),
+        }
+    }
+}
+impl std::error::Error for HandlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|x| &**x as &(dyn std::error::Error + 'static))
+    }
+}
+
+const MAX_USER_MESSAGE_CHARS: usize = 200;
 
 #[macro_export]
 macro_rules! run_handler {
@@ -44,3 +120,81 @@ macro_rules! run_handler {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn handler_error(player: Option<&str>, source: Option<Error>) -> Error {
+        Error::new(HandlerError {
+            handler: "test",
+            player: player.map(str::to_string),
+            source,
+        })
+    }
+
+    fn user_message(source: Option<Error>) -> String {
+        handler_error(None, source)
+            .downcast_ref::<HandlerError>()
+            .unwrap()
+            .user_message()
+    }
+
+    #[test]
+    fn test_user_message() {
+        assert_eq!(
+            user_message(Some(anyhow!("Not enough items"))),
+            "Not enough items"
+        );
+        // Only the first line, without control characters
+        assert_eq!(
+            user_message(Some(anyhow!("Bad\tstack\nat line 5"))),
+            "Badstack"
+        );
+        // Only the handler's outermost error, not what caused it
+        assert_eq!(
+            user_message(Some(anyhow!("disk full").context("Couldn't save"))),
+            "Couldn't save"
+        );
+        // Panics aren't described
+        assert_eq!(user_message(None), "Something went wrong on the server");
+    }
+
+    #[test]
+    fn test_user_message_truncated() {
+        let long = "x".repeat(MAX_USER_MESSAGE_CHARS + 10);
+        let message = user_message(Some(anyhow!(long)));
+        assert_eq!(message.chars().count(), MAX_USER_MESSAGE_CHARS + 1);
+        assert!(message.ends_with('…'));
+
+        let exact = "x".repeat(MAX_USER_MESSAGE_CHARS);
+        assert_eq!(user_message(Some(anyhow!(exact.clone()))), exact);
+    }
+
+    #[test]
+    fn test_find_for_player() {
+        let engine_error = || handler_error(None, Some(anyhow!("mapgen failed")));
+        assert!(HandlerError::find_for_player(&engine_error(), "alice").is_none());
+
+        let other_error = handler_error(Some("bob"), Some(anyhow!("no")));
+        assert!(HandlerError::find_for_player(&other_error, "alice").is_none());
+
+        // The player's handler failed because of an engine handler; the player's one is found,
+        // even under further context
+        let own_error = handler_error(Some("alice"), Some(engine_error().context("dig failed")))
+            .context("Handling dig");
+        assert_eq!(
+            HandlerError::find_for_player(&own_error, "alice")
+                .unwrap()
+                .user_message(),
+            "dig failed"
+        );
+        // An engine error under further context still isn't shown
+        let wrapped = engine_error().context("Handling dig");
+        assert!(HandlerError::find_for_player(&wrapped, "alice").is_none());
+
+        assert!(HandlerError::find_for_player(&anyhow!("plain error"), "alice").is_none());
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Elements drawn on a player's HUD (e.g. health, coordinates, or objectives), shown and
+//! changed through [super::player::Player::add_hud_element] and related methods.
+
+use std::sync::atomic::AtomicU64;
+
+use cuberef_core::protocol::ui as proto;
+
+/// Identifies a HUD element shown to a player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HudElementId(pub(crate) u64);
+impl HudElementId {
+    pub(crate) fn next() -> HudElementId {
+        HudElementId(NEXT_HUD_ELEMENT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+}
+
+/// Where a HUD element is placed on the screen. The same point of the element is placed at
+/// this point of the screen, e.g. a [HudAnchor::BottomRight] element's bottom right corner is at
+/// the bottom right corner of the screen, before the element's offset is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum HudAnchor {
+    #[default]
+    TopLeft,
+    TopCenter,
+    TopRight,
+    CenterLeft,
+    Center,
+    CenterRight,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+impl From<HudAnchor> for proto::HudAnchor {
+    fn from(value: HudAnchor) -> Self {
+        match value {
+            HudAnchor::TopLeft => proto::HudAnchor::TopLeft,
+            HudAnchor::TopCenter => proto::HudAnchor::TopCenter,
+            HudAnchor::TopRight => proto::HudAnchor::TopRight,
+            HudAnchor::CenterLeft => proto::HudAnchor::CenterLeft,
+            HudAnchor::Center => proto::HudAnchor::Center,
+            HudAnchor::CenterRight => proto::HudAnchor::CenterRight,
+            HudAnchor::BottomLeft => proto::HudAnchor::BottomLeft,
+            HudAnchor::BottomCenter => proto::HudAnchor::BottomCenter,
+            HudAnchor::BottomRight => proto::HudAnchor::BottomRight,
+        }
+    }
+}
+
+/// What a HUD element shows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HudContent {
+    /// A line of text
+    Text {
+        text: String,
+        /// Font size in pixels, or 0 for the default size
+        size: f32,
+    },
+    /// A texture, which must be available to the client's HUD (e.g. as an item's
+    /// inventory texture)
+    Image {
+        texture_name: String,
+        /// Size the texture is drawn at, in pixels
+        width: u32,
+        height: u32,
+    },
+    /// A bar filled from the left
+    Bar {
+        /// How much of the bar is filled, from 0 to 1
+        progress: f32,
+        /// Size of the whole bar, in pixels
+        width: u32,
+        height: u32,
+    },
+    /// An item's inventory texture, with the quantity shown if it's more than 1
+    ItemIcon {
+        item_name: String,
+        quantity: u32,
+        /// Width and height of the icon in pixels, or 0 for the default size
+        size: u32,
+    },
+}
+
+/// A HUD element, e.g. `HudElement::text("Day 3").anchor(HudAnchor::TopRight).offset(-8, 8)`
+///
+/// Text and bars are white unless [color](#method.color) is called.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HudElement {
+    anchor: HudAnchor,
+    offset: (i32, i32),
+    color: [u8; 3],
+    content: HudContent,
+}
+impl HudElement {
+    fn new(content: HudContent) -> HudElement {
+        HudElement {
+            anchor: HudAnchor::default(),
+            offset: (0, 0),
+            color: [255, 255, 255],
+            content,
+        }
+    }
+    /// Creates a text element, drawn in the default font size.
+    pub fn text(text: impl Into<String>) -> HudElement {
+        Self::new(HudContent::Text {
+            text: text.into(),
+            size: 0.0,
+        })
+    }
+    /// Creates an image element of the given size in pixels.
+    pub fn image(texture_name: impl Into<String>, width: u32, height: u32) -> HudElement {
+        Self::new(HudContent::Image {
+            texture_name: texture_name.into(),
+            width,
+            height,
+        })
+    }
+    /// Creates a bar of the given size in pixels, filled according to progress (from 0 to 1).
+    pub fn bar(progress: f32, width: u32, height: u32) -> HudElement {
+        Self::new(HudContent::Bar {
+            progress,
+            width,
+            height,
+        })
+    }
+    /// Creates an icon of the given item, drawn at the default size.
+    pub fn item_icon(item_name: impl Into<String>, quantity: u32) -> HudElement {
+        Self::new(HudContent::ItemIcon {
+            item_name: item_name.into(),
+            quantity,
+            size: 0,
+        })
+    }
+    /// Sets where the element is placed on the screen.
+    pub fn anchor(mut self, anchor: HudAnchor) -> Self {
+        self.anchor = anchor;
+        self
+    }
+    /// Moves the element by the given number of pixels from its anchor. Positive values move
+    /// right and down.
+    pub fn offset(mut self, x: i32, y: i32) -> Self {
+        self.offset = (x, y);
+        self
+    }
+    /// Sets the color of text, or of the filled part of a bar.
+    pub fn color(mut self, color: [u8; 3]) -> Self {
+        self.color = color;
+        self
+    }
+    /// Sets the font size of text, or the size of an item icon, in pixels. Has no effect on
+    /// other elements.
+    pub fn size(mut self, new_size: f32) -> Self {
+        match &mut self.content {
+            HudContent::Text { size, .. } => *size = new_size,
+            HudContent::ItemIcon { size, .. } => *size = new_size as u32,
+            _ => {}
+        }
+        self
+    }
+    /// Returns what the element shows, e.g. to change it based on its current value before
+    /// passing it to [super::player::Player::update_hud_element].
+    pub fn content(&self) -> &HudContent {
+        &self.content
+    }
+
+    pub(crate) fn to_proto(&self) -> proto::HudElement {
+        let [r, g, b] = self.color;
+        let color = u32::from_be_bytes([0, r, g, b]);
+        let content = match &self.content {
+            HudContent::Text { text, size } => proto::hud_element::Content::Text(proto::HudText {
+                text: text.clone(),
+                color,
+                size: *size,
+            }),
+            HudContent::Image {
+                texture_name,
+                width,
+                height,
+            } => proto::hud_element::Content::Image(proto::HudImage {
+                texture_name: texture_name.clone(),
+                width: *width,
+                height: *height,
+            }),
+            HudContent::Bar {
+                progress,
+                width,
+                height,
+            } => proto::hud_element::Content::Bar(proto::HudBar {
+                progress: *progress,
+                width: *width,
+                height: *height,
+                color,
+            }),
+            HudContent::ItemIcon {
+                item_name,
+                quantity,
+                size,
+            } => proto::hud_element::Content::ItemIcon(proto::HudItemIcon {
+                item_name: item_name.clone(),
+                quantity: *quantity,
+                size: *size,
+            }),
+        };
+        proto::HudElement {
+            anchor: proto::HudAnchor::from(self.anchor).into(),
+            offset_x: self.offset.0,
+            offset_y: self.offset.1,
+            content: Some(content),
+        }
+    }
+}
+
+static NEXT_HUD_ELEMENT_ID: AtomicU64 = AtomicU64::new(1);
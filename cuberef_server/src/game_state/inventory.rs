@@ -23,7 +23,7 @@ use std::{
 
 use crate::{
     database::database_engine::{GameDatabase, KeySpace},
-    game_state::items::{ItemStack, MaybeStack},
+    game_state::items::{ItemManager, ItemStack, MaybeStack},
 };
 use anyhow::{bail, ensure, Context, Result};
 use cuberef_core::{coordinates::BlockCoordinate, protocol::items as items_proto};
@@ -326,6 +326,8 @@ pub struct BorrowedStack {
     pub borrowed_stack: ItemStack,
 }
 
+/// Callbacks backing a [ViewBacking::VirtualOutput] view, whose items are generated on the fly
+/// (e.g. the output of a crafting grid).
 pub struct VirtualOutputCallbacks<T> {
     /// Callback that shows what items are visible in the view. This should be idempotent
     pub peek: Box<dyn Fn(&T) -> Vec<Option<ItemStack>> + Sync + Send>,
@@ -334,6 +336,104 @@ pub struct VirtualOutputCallbacks<T> {
     pub take: Box<dyn FnMut(&T, usize, Option<u32>) -> Option<ItemStack> + Sync + Send>,
 }
 
+/// Callbacks backing a [ViewBacking::VirtualInput] view, which hands anything put into it to the
+/// game rather than storing it (e.g. a trash slot).
+pub struct VirtualInputCallbacks<T> {
+    /// Callback that shows what items are visible in the view (e.g. nothing for a trash slot).
+    /// This should be idempotent
+    pub peek: Box<dyn Fn(&T) -> Vec<Option<ItemStack>> + Sync + Send>,
+    /// Callback of (context, slot#, stack), invoked when the user puts a stack into the view.
+    /// The callback consumes whatever part of the stack it accepts, and returns the rest
+    /// (None if it accepted everything, or the entire stack to reject it).
+    ///
+    /// The returned leftover must be the same item, with no more items than the stack that was put.
+    pub put: Box<dyn FnMut(&T, usize, ItemStack) -> Option<ItemStack> + Sync + Send>,
+}
+
+/// Which items may be put into a slot of an inventory view.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SlotFilter {
+    /// Any item may be put into the slot
+    #[default]
+    Any,
+    /// Nothing may be put into the slot (e.g. an output slot). Items can still be taken out.
+    Nothing,
+    /// Only items belonging to at least one of the groups, or having one of the names, may be
+    /// put into the slot
+    OneOf {
+        groups: Vec<String>,
+        items: Vec<String>,
+    },
+}
+impl SlotFilter {
+    /// A filter accepting only items in the given group
+    pub fn group(group: impl Into<String>) -> SlotFilter {
+        SlotFilter::OneOf {
+            groups: vec![group.into()],
+            items: vec![],
+        }
+    }
+    /// A filter accepting only the given item
+    pub fn item(item_name: impl Into<String>) -> SlotFilter {
+        SlotFilter::OneOf {
+            groups: vec![],
+            items: vec![item_name.into()],
+        }
+    }
+
+    /// Whether the stack may be put into a slot with this filter. Items that aren't registered
+    /// are only accepted by [SlotFilter::Any].
+    pub fn accepts(&self, items: &ItemManager, stack: &ItemStack) -> bool {
+        match self {
+            SlotFilter::Any => true,
+            SlotFilter::Nothing => false,
+            SlotFilter::OneOf {
+                groups,
+                items: names,
+            } => {
+                names.contains(&stack.proto.item_name)
+                    || items.get_item(&stack.proto.item_name).is_some_and(|item| {
+                        item.proto.groups.iter().any(|group| groups.contains(group))
+                    })
+            }
+        }
+    }
+
+    pub(crate) fn to_proto(&self) -> items_proto::SlotFilter {
+        match self {
+            SlotFilter::Any => items_proto::SlotFilter::default(),
+            SlotFilter::Nothing => items_proto::SlotFilter {
+                reject_all: true,
+                ..Default::default()
+            },
+            SlotFilter::OneOf { groups, items } => items_proto::SlotFilter {
+                // With both lists empty, the client would otherwise accept anything
+                reject_all: groups.is_empty() && items.is_empty(),
+                accepted_groups: groups.clone(),
+                accepted_items: items.clone(),
+            },
+        }
+    }
+}
+
+/// Per-slot restrictions and hooks for a view into a block's inventory.
+pub struct BlockViewCallbacks<T> {
+    /// Which items may be put into each slot, in row-major order.
+    /// Slots past the end of this vec accept any item.
+    pub slot_filters: Vec<SlotFilter>,
+    /// Callback of (context, slot#), invoked after items are put into or taken out of a slot
+    /// through this view (e.g. to start a machine once its input is filled)
+    pub on_change: Option<Box<dyn Fn(&T, usize) + Sync + Send>>,
+}
+impl<T> Default for BlockViewCallbacks<T> {
+    fn default() -> Self {
+        Self {
+            slot_filters: vec![],
+            on_change: None,
+        }
+    }
+}
+
 /// Where the items in the inventory view actually come from.
 ///
 /// The type parameter T represents the type passed to the callbacks as context.
@@ -364,14 +464,15 @@ pub enum ViewBacking<T> {
     /// Note that VirtualInput and VirtualOutput may be refactored into the same enum variant with the callbacks combined;
     /// however the edge cases involving both input and output are not yet resolved in the current MVP.
     VirtualOutput(parking_lot::RwLock<VirtualOutputCallbacks<T>>),
-    /// This inventory view doesn't hold anything. When a stack is placed into it, it is consumed from
-    /// its source and a callback will be invoked.
+    /// This inventory view doesn't hold anything. When a stack is placed into it, a callback decides how
+    /// much of it to accept; the accepted items are consumed from their source, and the rest are returned.
+    /// (e.g. a trash slot, selling items to a shop, a machine that consumes its input immediately)
     ///
     /// Nothing interesting happens when this view is deleted, because this view doesn't "hold" any items.
     ///
     /// Note that VirtualInput and VirtualOutput may be refactored into the same enum variant with the callbacks combined;
     /// however the edge cases involving both input and output are not yet resolved in the current MVP.
-    VirtualInput(Box<dyn Fn(&T) + Send + Sync>),
+    VirtualInput(parking_lot::RwLock<VirtualInputCallbacks<T>>),
 
     /// This inventory view is stored in the database. Nothing interesting happens when the view
     /// is deleted, because any actions on the view have been written back to the database by then.
@@ -399,6 +500,10 @@ pub struct InventoryView<T> {
     /// Whether the user can take things out of this view
     pub(crate) can_take: bool,
     take_exact: bool,
+    /// Which items may be put into each slot; slots past the end accept anything
+    slot_filters: Vec<SlotFilter>,
+    /// Called with the slot# after items are put into or taken out of it
+    on_change: Option<Box<dyn Fn(&T, usize) + Sync + Send>>,
     /// The kind of inventory this view is showing    
     pub(crate) backing: ViewBacking<T>,
     pub(crate) id: InventoryViewId,
@@ -420,6 +525,8 @@ impl<T> InventoryView<T> {
             can_place,
             can_take,
             take_exact: false,
+            slot_filters: vec![],
+            on_change: None,
             backing: ViewBacking::Stored(inventory_key),
             game_state,
             id: next_id(),
@@ -445,6 +552,8 @@ impl<T> InventoryView<T> {
             can_place,
             can_take,
             take_exact,
+            slot_filters: vec![],
+            on_change: None,
             backing: ViewBacking::Transient(initial_contents.into()),
             id: next_id(),
             game_state,
@@ -462,12 +571,35 @@ impl<T> InventoryView<T> {
             can_place: false,
             can_take: true,
             take_exact,
+            slot_filters: vec![],
+            on_change: None,
             backing: ViewBacking::VirtualOutput(RwLock::new(callbacks)),
             id: next_id(),
             game_state,
         })
     }
 
+    pub(crate) fn new_virtual_input(
+        game_state: Arc<GameState>,
+        dimensions: (u32, u32),
+        callbacks: VirtualInputCallbacks<T>,
+    ) -> Result<InventoryView<T>> {
+        Ok(InventoryView {
+            dimensions,
+            can_place: true,
+            can_take: false,
+            take_exact: false,
+            slot_filters: vec![],
+            on_change: None,
+            backing: ViewBacking::VirtualInput(RwLock::new(callbacks)),
+            id: next_id(),
+            game_state,
+        })
+    }
+
+    /// Creates a new view into the inventory stored in the given block under the given key,
+    /// creating that inventory with the given dimensions if it doesn't exist yet.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new_block(
         game_state: Arc<GameState>,
         dimensions: (u32, u32),
@@ -476,6 +608,7 @@ impl<T> InventoryView<T> {
         can_place: bool,
         can_take: bool,
         take_exact: bool,
+        callbacks: BlockViewCallbacks<T>,
     ) -> Result<InventoryView<T>> {
         let actual_dimensions =
             game_state
@@ -511,6 +644,8 @@ impl<T> InventoryView<T> {
             can_place,
             can_take,
             take_exact,
+            slot_filters: callbacks.slot_filters,
+            on_change: callbacks.on_change,
             backing: ViewBacking::StoredInBlock(coord, key),
             id: next_id(),
             game_state,
@@ -629,6 +764,8 @@ pub trait TypeErasedInventoryView {
     // if true, user must take exactly the amount shown in the stack they're taking. Probably only useful for VirtualOutput
     // stacks (or maybe transient stacks) used as crafting output or similar.
     fn take_exact(&self) -> bool;
+    /// Whether the slot filters allow the given stack to be put into the given slot
+    fn accepts(&self, slot: usize, stack: &ItemStack) -> bool;
 
     fn to_client_proto(&self) -> Result<cuberef_core::protocol::game_rpc::InventoryUpdate>;
 }
@@ -666,6 +803,10 @@ impl<'a, T> TypeErasedInventoryView for InventoryViewWithContext<'a, T> {
         self.view.take_exact
     }
 
+    fn accepts(&self, slot: usize, stack: &ItemStack) -> bool {
+        self.view.accepts(slot, stack)
+    }
+
     fn to_client_proto(&self) -> Result<cuberef_core::protocol::game_rpc::InventoryUpdate> {
         self.view.to_client_proto(self.context)
     }
@@ -704,6 +845,10 @@ impl TypeErasedInventoryView for &InventoryView<()> {
         self.take_exact
     }
 
+    fn accepts(&self, slot: usize, stack: &ItemStack) -> bool {
+        (*self).accepts(slot, stack)
+    }
+
     fn to_client_proto(&self) -> Result<cuberef_core::protocol::game_rpc::InventoryUpdate> {
         (*self).to_client_proto(&())
     }
@@ -725,7 +870,11 @@ impl<T> InventoryView<T> {
                 ensure!(peeked.len() == self.dimensions.0 as usize * self.dimensions.1 as usize);
                 Ok(peeked)
             }
-            ViewBacking::VirtualInput(_) => todo!(),
+            ViewBacking::VirtualInput(virt_in) => {
+                let peeked = (virt_in.read().peek)(context);
+                ensure!(peeked.len() == self.dimensions.0 as usize * self.dimensions.1 as usize);
+                Ok(peeked)
+            }
             ViewBacking::Stored(key) => Ok(self
                 .game_state
                 .inventory_manager()
@@ -760,7 +909,7 @@ impl<T> InventoryView<T> {
         count: Option<u32>,
     ) -> Result<Option<BorrowedStack>> {
         ensure!(slot < (self.dimensions.0 as usize * self.dimensions.1 as usize));
-        match &self.backing {
+        let taken = match &self.backing {
             ViewBacking::Transient(contents) => {
                 let mut guard = contents.try_write().context("already borrowed")?;
                 // unwrap is ok - we checked the length
@@ -790,7 +939,10 @@ impl<T> InventoryView<T> {
                 borrows_from: BorrowLocation::NotBorrowed,
                 borrowed_stack: x,
             })),
-            ViewBacking::VirtualInput(_) => todo!(),
+            ViewBacking::VirtualInput(_) => {
+                // can't take from a virtualinput
+                Ok(None)
+            }
             ViewBacking::Stored(key) => Ok(self
                 .game_state
                 .inventory_manager()
@@ -820,7 +972,11 @@ impl<T> InventoryView<T> {
                                 })
                         }))
                 }),
+        }?;
+        if taken.is_some() {
+            self.invoke_on_change(context, slot);
         }
+        Ok(taken)
     }
 
     /// Attempts to place a stack into the given slot in the view.
@@ -829,12 +985,13 @@ impl<T> InventoryView<T> {
     /// peek should be called immediate after to see what the view should display.
     pub fn put(
         &self,
-        _context: &T,
+        context: &T,
         slot: usize,
         stack: BorrowedStack,
     ) -> Result<Option<BorrowedStack>> {
         ensure!(slot < (self.dimensions.0 as usize * self.dimensions.1 as usize));
-        match &self.backing {
+        let original_quantity = stack.borrowed_stack.proto.quantity;
+        let leftover = match &self.backing {
             ViewBacking::Transient(contents) => {
                 let mut guard = contents.try_write().context("already borrowed")?;
                 let slot_contents = guard.get_mut(slot).unwrap();
@@ -863,7 +1020,17 @@ impl<T> InventoryView<T> {
                 // can't put into a virtualoutput
                 Ok(Some(stack))
             }
-            ViewBacking::VirtualInput(_) => todo!(),
+            ViewBacking::VirtualInput(virt_in) => {
+                let original = stack.borrowed_stack.clone();
+                let leftover = (virt_in.write().put)(context, slot, stack.borrowed_stack);
+                // The callback has already run, so bailing here would lose the items it
+                // didn't accept
+                let leftover = checked_virtual_leftover(self.id, &original, leftover);
+                Ok(leftover.map(|leftover| BorrowedStack {
+                    borrows_from: stack.borrows_from,
+                    borrowed_stack: leftover,
+                }))
+            }
             ViewBacking::Stored(key) => Ok(self
                 .game_state
                 .inventory_manager()
@@ -898,6 +1065,27 @@ impl<T> InventoryView<T> {
                         None => Ok(Some(stack)),
                     }
                 })?),
+        }?;
+        let leftover_quantity = leftover.as_ref().map(|x| x.borrowed_stack.proto.quantity);
+        if leftover_quantity.map_or(true, |x| x < original_quantity) {
+            self.invoke_on_change(context, slot);
+        }
+        Ok(leftover)
+    }
+
+    /// Whether the given stack may be put into the given slot, according to this view's
+    /// slot filters. This doesn't check [can_place](#method.can_place) or whether there's
+    /// space for the stack.
+    pub fn accepts(&self, slot: usize, stack: &ItemStack) -> bool {
+        match self.slot_filters.get(slot) {
+            Some(filter) => filter.accepts(self.game_state.item_manager(), stack),
+            None => true,
+        }
+    }
+
+    fn invoke_on_change(&self, context: &T, slot: usize) {
+        if let Some(on_change) = &self.on_change {
+            on_change(context, slot);
         }
     }
 
@@ -952,6 +1140,7 @@ impl<T> InventoryView<T> {
             can_place: self.can_place(),
             can_take: self.can_take(),
             take_exact: self.take_exact(),
+            slot_filters: self.slot_filters.iter().map(SlotFilter::to_proto).collect(),
         })
     }
 }
@@ -966,6 +1155,36 @@ impl<T> Drop for InventoryView<T> {
 
 static INVENTORY_VIEW_COUNTER: AtomicU64 = AtomicU64::new(1);
 
+/// Checks the leftover returned by a virtual input's put callback against the stack that was put.
+///
+/// A leftover of a different item is logged and returned as-is, since the callback has already
+/// acted on the stack. A leftover with more items than were put is logged and capped, so that a
+/// buggy callback can't duplicate items.
+fn checked_virtual_leftover(
+    id: InventoryViewId,
+    original: &ItemStack,
+    leftover: Option<ItemStack>,
+) -> Option<ItemStack> {
+    let mut leftover = leftover?;
+    if leftover.proto.item_name != original.proto.item_name {
+        log::error!(
+            "Virtual input view {:?} returned {:?} as leftover for {:?}",
+            id,
+            leftover,
+            original
+        );
+    } else if leftover.proto.quantity > original.proto.quantity {
+        log::error!(
+            "Virtual input view {:?} returned {:?} as leftover for {:?}; capping its quantity",
+            id,
+            leftover,
+            original
+        );
+        leftover.proto.quantity = original.proto.quantity;
+    }
+    Some(leftover)
+}
+
 fn next_id() -> InventoryViewId {
     InventoryViewId(INVENTORY_VIEW_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
 }
@@ -977,3 +1196,56 @@ pub(crate) enum UpdatedInventory {
 }
 
 const BROADCAST_CHANNEL_SIZE: usize = 32;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(name: &str, quantity: u32) -> ItemStack {
+        ItemStack {
+            proto: items_proto::ItemStack {
+                item_name: name.to_string(),
+                quantity,
+                max_stack: 256,
+                stackable: true,
+            },
+        }
+    }
+
+    fn check(original: &ItemStack, leftover: Option<ItemStack>) -> Option<items_proto::ItemStack> {
+        checked_virtual_leftover(next_id(), original, leftover).map(|x| x.proto)
+    }
+
+    #[test]
+    fn test_virtual_leftover_valid() {
+        let original = stack("default:dirt", 10);
+        assert_eq!(check(&original, None), None);
+        assert_eq!(
+            check(&original, Some(stack("default:dirt", 3))),
+            Some(stack("default:dirt", 3).proto)
+        );
+        assert_eq!(
+            check(&original, Some(original.clone())),
+            Some(original.proto)
+        );
+    }
+
+    #[test]
+    fn test_virtual_leftover_too_many() {
+        let original = stack("default:dirt", 10);
+        assert_eq!(
+            check(&original, Some(stack("default:dirt", 50))),
+            Some(stack("default:dirt", 10).proto)
+        );
+    }
+
+    #[test]
+    fn test_virtual_leftover_different_item() {
+        // Whatever the callback returned is kept, rather than dropped
+        let original = stack("default:dirt", 10);
+        assert_eq!(
+            check(&original, Some(stack("default:stone", 1))),
+            Some(stack("default:stone", 1).proto)
+        );
+    }
+}
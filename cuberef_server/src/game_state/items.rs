@@ -23,6 +23,7 @@ use super::blocks::BlockTypeHandle;
 use super::event::HandlerContext;
 
 use cuberef_core::coordinates::BlockCoordinate;
+use cuberef_core::protocol::blocks::BlockTypeDef;
 use cuberef_core::protocol::items as proto;
 
 /// Result of the dig_handler of an Item.
@@ -69,6 +70,23 @@ pub struct Item {
     /// The anchor block is the existing block that the player was pointing to when they clicked the place button.
     pub place_handler: Option<Box<PlaceHandler>>,
 }
+impl Item {
+    /// Returns the first of this item's interaction rules that applies to the given block.
+    pub fn interaction_rule_for(&self, block: &BlockTypeDef) -> Option<&proto::InteractionRule> {
+        find_interaction_rule(&self.proto.interaction_rules, block)
+    }
+}
+
+/// Returns the first rule that applies to the given block, i.e. the first rule whose
+/// block groups are all present on the block.
+pub fn find_interaction_rule<'a>(
+    rules: &'a [proto::InteractionRule],
+    block: &BlockTypeDef,
+) -> Option<&'a proto::InteractionRule> {
+    rules
+        .iter()
+        .find(|rule| rule.block_group.iter().all(|x| block.groups.contains(x)))
+}
 
 #[derive(Debug, Clone)]
 pub struct ItemStack {
@@ -161,6 +179,25 @@ impl ItemStack {
         true
     }
 
+    /// Takes the given amount of wear off of a stack of an item that has wear. Returns None
+    /// if the item breaks (i.e. its wear reaches zero).
+    ///
+    /// Stacks of items without wear are returned unchanged.
+    pub fn apply_wear(&self, item: &Item, wear: u32) -> Option<ItemStack> {
+        if wear == 0 || !matches!(item.proto.quantity_type, Some(QuantityType::Wear(_))) {
+            return Some(self.clone());
+        }
+        match self.proto.quantity.saturating_sub(wear) {
+            0 => None,
+            x => Some(ItemStack {
+                proto: proto::ItemStack {
+                    quantity: x,
+                    ..self.proto.clone()
+                },
+            }),
+        }
+    }
+
     pub fn decrement(&self) -> Option<ItemStack> {
         match self.proto.quantity {
             0 | 1 => None,
@@ -307,3 +344,68 @@ impl ItemManager {
         self.items.values()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(quantity_type: QuantityType) -> Item {
+        Item {
+            proto: proto::ItemDef {
+                short_name: "test:pick".to_string(),
+                quantity_type: Some(quantity_type),
+                ..Default::default()
+            },
+            dig_handler: None,
+            tap_handler: None,
+            place_handler: None,
+        }
+    }
+
+    fn stack(quantity: u32) -> ItemStack {
+        ItemStack {
+            proto: proto::ItemStack {
+                item_name: "test:pick".to_string(),
+                quantity,
+                max_stack: 1,
+                stackable: false,
+            },
+        }
+    }
+
+    fn quantity_after_wear(item: &Item, quantity: u32, wear: u32) -> Option<u32> {
+        stack(quantity)
+            .apply_wear(item, wear)
+            .map(|x| x.proto.quantity)
+    }
+
+    #[test]
+    fn test_apply_wear() {
+        let pick = item(QuantityType::Wear(100));
+        assert_eq!(quantity_after_wear(&pick, 100, 1), Some(99));
+        assert_eq!(quantity_after_wear(&pick, 50, 20), Some(30));
+        // No wear at all leaves the stack as it was
+        assert_eq!(quantity_after_wear(&pick, 50, 0), Some(50));
+    }
+
+    #[test]
+    fn test_apply_wear_breaks_at_limit() {
+        let pick = item(QuantityType::Wear(100));
+        assert_eq!(quantity_after_wear(&pick, 1, 1), None);
+        assert_eq!(quantity_after_wear(&pick, 5, 5), None);
+        // More wear than what's left doesn't underflow
+        assert_eq!(quantity_after_wear(&pick, 5, 1000), None);
+        assert_eq!(quantity_after_wear(&pick, 6, 5), Some(1));
+    }
+
+    #[test]
+    fn test_apply_wear_unlimited() {
+        // Items without wear are never used up by digging
+        let dirt = item(QuantityType::Stack(256));
+        assert_eq!(quantity_after_wear(&dirt, 1, 1), Some(1));
+        assert_eq!(quantity_after_wear(&dirt, 64, 1000), Some(64));
+        let mut no_quantity = item(QuantityType::Wear(1));
+        no_quantity.proto.quantity_type = None;
+        assert_eq!(quantity_after_wear(&no_quantity, 1, 1), Some(1));
+    }
+}
@@ -14,11 +14,597 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use cuberef_core::coordinates::ChunkCoordinate;
+use std::sync::{Arc, Weak};
 
-use super::game_map::MapChunk;
+use anyhow::{bail, ensure, Context, Result};
+use cuberef_core::coordinates::{BlockCoordinate, ChunkCoordinate};
+use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
+use tracy_client::span;
 
+use super::{
+    blocks::{BlockTypeHandle, BlockTypeManager},
+    game_map::MapChunk,
+    GameState,
+};
+
+/// Generates the full contents of a single chunk, from scratch.
+///
+/// This is the interface between the game map and the map generator. Game content should
+/// not implement this directly; instead, it should register [MapgenStageHandler]s, which
+/// are assembled into a [MapgenPipeline] that implements this trait.
 pub trait MapgenInterface: Send + Sync {
-    // todo figure this interface out and document it
-    fn fill_chunk(&self, coord: ChunkCoordinate, chunk: &mut MapChunk);
+    /// Fills the given chunk, which is initially filled with block ID 0. The result
+    /// must only depend on the coordinate and on the world seed.
+    fn fill_chunk(&self, coord: ChunkCoordinate, chunk: &mut MapChunk) -> Result<()>;
+}
+
+/// The stages of map generation, in the order in which they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MapgenStage {
+    /// The base shape of the terrain, e.g. stone, surface blocks, and bodies of water
+    Terrain,
+    /// Removal of terrain, e.g. caves and ravines
+    Carving,
+    /// Placement of ores and other underground features
+    Ores,
+    /// Surface decorations, e.g. plants, trees, and structures
+    Decorations,
+    /// Final fixups that need to see the results of all of the other stages
+    PostProcessing,
+}
+
+/// A requirement that nearby chunks have progressed through mapgen before a
+/// handler runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NeighborRequirement {
+    /// Maximum distance (in chunks, L-infinity norm) of the neighbors that are needed.
+    pub radius: u32,
+    /// The stage that those neighbors must have completed. Must be strictly earlier than
+    /// the stage of the handler making the request.
+    pub stage: MapgenStage,
+}
+
+/// A single step of the mapgen pipeline.
+///
+/// Handlers only write to the chunk they are given, but may read blocks from neighboring chunks
+/// through [MapgenContext::get_block], if they request those neighbors with
+/// [MapgenStageHandler::neighbor_requirement]. Neighbors are guaranteed to have completed at
+/// least the requested stage, but may have progressed further. A neighbor that is already
+/// fully generated and loaded is read from the map, including any changes made to it since.
+///
+/// Like [MapgenInterface], the output should only depend on the chunk's coordinate, the seed,
+/// and the neighbors: partially-generated chunks are discarded and regenerated as needed.
+pub trait MapgenStageHandler: Send + Sync {
+    /// Which neighbors this handler needs to read from, if any.
+    fn neighbor_requirement(&self) -> Option<NeighborRequirement> {
+        None
+    }
+    /// Runs this handler on the given chunk.
+    fn generate(&self, context: &MapgenContext<'_>, chunk: &mut MapChunk) -> Result<()>;
+}
+
+/// Provides information about the chunk being generated, and read access to
+/// its neighborhood.
+pub struct MapgenContext<'a> {
+    coord: ChunkCoordinate,
+    seed: u32,
+    block_types: &'a BlockTypeManager,
+    // Snapshots of neighbors' block IDs, taken just before the handler was invoked
+    neighbors: FxHashMap<ChunkCoordinate, Vec<u32>>,
+}
+impl<'a> MapgenContext<'a> {
+    /// The coordinate of the chunk being generated
+    pub fn coord(&self) -> ChunkCoordinate {
+        self.coord
+    }
+    /// The seed for the world
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+    pub fn block_types(&self) -> &BlockTypeManager {
+        self.block_types
+    }
+    /// Gets a block from a neighboring chunk. Returns an error if the chunk containing the
+    /// block was not requested via [MapgenStageHandler::neighbor_requirement], or if it is
+    /// the chunk being generated (use the chunk passed to the handler instead).
+    pub fn get_block(&self, coord: BlockCoordinate) -> Result<BlockTypeHandle> {
+        let chunk = coord.chunk();
+        ensure!(
+            chunk != self.coord,
+            "get_block called for the chunk being generated"
+        );
+        let ids = self
+            .neighbors
+            .get(&chunk)
+            .with_context(|| format!("Chunk {chunk:?} is outside the requested neighborhood"))?;
+        self.block_types
+            .make_blockref(ids[coord.offset().as_index()].into())
+    }
+}
+
+// Runs a mapgen that fills whole chunks by itself as a single stage
+pub(crate) struct WholeChunkMapgen(pub(crate) Arc<dyn MapgenInterface>);
+impl MapgenStageHandler for WholeChunkMapgen {
+    fn generate(&self, context: &MapgenContext<'_>, chunk: &mut MapChunk) -> Result<()> {
+        self.0.fill_chunk(context.coord(), chunk)
+    }
+}
+
+/// Creates a stage handler, given the block types and world seed.
+pub(crate) type MapgenStageProvider =
+    Box<dyn FnOnce(Arc<BlockTypeManager>, u32) -> Result<Box<dyn MapgenStageHandler>>>;
+
+struct RegisteredHandler {
+    stage: MapgenStage,
+    name: String,
+    handler: Box<dyn MapgenStageHandler>,
+}
+
+struct ProtoChunk {
+    // Number of handlers that have already run on this chunk
+    progress: usize,
+    chunk: MapChunk,
+    // Set once the finished chunk was handed to the game map. Its blocks are kept so that
+    // neighbors can still read them, but its extended data was moved out.
+    handed_over: bool,
+}
+
+// Partially-generated chunks that are not referenced by any in-flight generation
+// are discarded once the cache grows beyond this size.
+const MAX_IDLE_PROTO_CHUNKS: usize = 4096;
+
+/// An ordered sequence of [MapgenStageHandler]s, run one after another to generate each chunk.
+///
+/// Chunks that are generated only to satisfy a neighbor requirement are kept in memory
+/// as partially-generated chunks until they are needed, or until the cache is full. Finished
+/// chunks stay in the same cache, so that their neighbors can read them without generating
+/// them again.
+pub struct MapgenPipeline {
+    seed: u32,
+    block_types: Arc<BlockTypeManager>,
+    // Sorted by stage; registration order is preserved within a stage
+    handlers: Vec<RegisteredHandler>,
+    proto_chunks: Mutex<FxHashMap<ChunkCoordinate, Arc<Mutex<ProtoChunk>>>>,
+    // Used to read neighbors that are already generated and loaded. Not set in tests.
+    game_state: Weak<GameState>,
+}
+impl MapgenPipeline {
+    pub(crate) fn new(
+        block_types: Arc<BlockTypeManager>,
+        seed: u32,
+        handlers: Vec<(MapgenStage, String, Box<dyn MapgenStageHandler>)>,
+    ) -> Result<MapgenPipeline> {
+        ensure!(!handlers.is_empty(), "No mapgen stages registered");
+        let mut handlers: Vec<_> = handlers
+            .into_iter()
+            .map(|(stage, name, handler)| RegisteredHandler {
+                stage,
+                name,
+                handler,
+            })
+            .collect();
+        handlers.sort_by_key(|x| x.stage);
+        for handler in handlers.iter() {
+            if let Some(requirement) = handler.handler.neighbor_requirement() {
+                if requirement.stage >= handler.stage {
+                    bail!(
+                        "Mapgen handler {} in stage {:?} requires neighbors to reach {:?}, which is not an earlier stage",
+                        handler.name,
+                        handler.stage,
+                        requirement.stage
+                    );
+                }
+            }
+        }
+        log::info!(
+            "Mapgen pipeline: {}",
+            handlers
+                .iter()
+                .map(|x| format!("{} ({:?})", x.name, x.stage))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(MapgenPipeline {
+            seed,
+            block_types,
+            handlers,
+            proto_chunks: Mutex::new(FxHashMap::default()),
+            game_state: Weak::new(),
+        })
+    }
+
+    /// Lets the pipeline read already-generated neighbors from the game's map.
+    pub(crate) fn with_game_state(self, game_state: Weak<GameState>) -> MapgenPipeline {
+        MapgenPipeline { game_state, ..self }
+    }
+
+    // The progress value at which all handlers in the given stage (and earlier stages) have run
+    fn end_of_stage(&self, stage: MapgenStage) -> usize {
+        self.handlers.partition_point(|x| x.stage <= stage)
+    }
+
+    fn get_or_create_proto(&self, coord: ChunkCoordinate) -> Arc<Mutex<ProtoChunk>> {
+        let mut protos = self.proto_chunks.lock();
+        if protos.len() > MAX_IDLE_PROTO_CHUNKS {
+            // Idle entries are only referenced by the map itself.
+            protos.retain(|_, v| Arc::strong_count(v) > 1);
+        }
+        protos
+            .entry(coord)
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(ProtoChunk {
+                    progress: 0,
+                    chunk: MapChunk::new_detached(coord),
+                    handed_over: false,
+                }))
+            })
+            .clone()
+    }
+
+    // Runs handlers on the chunk at coord until at least `target` handlers have run.
+    fn advance(&self, coord: ChunkCoordinate, target: usize) -> Result<Arc<Mutex<ProtoChunk>>> {
+        let proto = self.get_or_create_proto(coord);
+        loop {
+            let next = proto.lock().progress;
+            if next >= target {
+                return Ok(proto);
+            }
+            let registered = &self.handlers[next];
+
+            // Neighbors are advanced and snapshotted *without* holding our own lock, since
+            // neighbors may be waiting on us in the same way.
+            let mut neighbors = FxHashMap::default();
+            if let Some(requirement) = registered.handler.neighbor_requirement() {
+                let needed = self.end_of_stage(requirement.stage);
+                let radius = requirement.radius as i32;
+                for dx in -radius..=radius {
+                    for dy in -radius..=radius {
+                        for dz in -radius..=radius {
+                            if dx == 0 && dy == 0 && dz == 0 {
+                                continue;
+                            }
+                            let neighbor_coord = match coord.try_delta(dx, dy, dz) {
+                                Some(x) => x,
+                                None => continue,
+                            };
+                            let snapshot = self.neighbor_snapshot(neighbor_coord, needed)?;
+                            neighbors.insert(neighbor_coord, snapshot);
+                        }
+                    }
+                }
+            }
+
+            let mut guard = proto.lock();
+            if guard.progress != next {
+                // Someone else ran this handler while we were gathering neighbors
+                continue;
+            }
+            let context = MapgenContext {
+                coord,
+                seed: self.seed,
+                block_types: &self.block_types,
+                neighbors,
+            };
+            let result = {
+                let _span = span!("mapgen stage");
+                registered.handler.generate(&context, &mut guard.chunk)
+            };
+            if let Err(e) = result {
+                // The chunk may be partially modified; discard it so it's regenerated from scratch
+                drop(guard);
+                self.proto_chunks.lock().remove(&coord);
+                return Err(e.context(format!(
+                    "Mapgen handler {} failed for {:?}",
+                    registered.name, coord
+                )));
+            }
+            guard.progress = next + 1;
+        }
+    }
+
+    // Gets the block IDs of a neighbor that has run at least `target` handlers. The neighbor is
+    // taken from the map or from the cache if possible, and only generated if it's in neither.
+    fn neighbor_snapshot(&self, coord: ChunkCoordinate, target: usize) -> Result<Vec<u32>> {
+        // The map comes first: a chunk that was handed over to it is still cached, but may
+        // have been changed on the map since.
+        if let Some(game_state) = self.game_state.upgrade() {
+            if let Some(block_ids) = game_state.map().try_get_loaded_block_ids(coord) {
+                return Ok(block_ids);
+            }
+        }
+        let cached = self.proto_chunks.lock().get(&coord).cloned();
+        if let Some(proto) = cached {
+            let guard = proto.lock();
+            if guard.progress >= target {
+                return Ok(guard.chunk.block_ids.clone());
+            }
+        }
+        let neighbor = self.advance(coord, target)?;
+        let block_ids = neighbor.lock().chunk.block_ids.clone();
+        Ok(block_ids)
+    }
+}
+impl MapgenInterface for MapgenPipeline {
+    fn fill_chunk(&self, coord: ChunkCoordinate, chunk: &mut MapChunk) -> Result<()> {
+        loop {
+            let proto = self.advance(coord, self.handlers.len())?;
+            let mut guard = proto.lock();
+            if !guard.handed_over {
+                chunk.take_contents_from(&mut guard.chunk);
+                guard.handed_over = true;
+                return Ok(());
+            }
+            // This chunk was generated before, and its extended data went to the map then.
+            // Start over from scratch.
+            drop(guard);
+            let mut protos = self.proto_chunks.lock();
+            if protos.get(&coord).map_or(false, |x| Arc::ptr_eq(x, &proto)) {
+                protos.remove(&coord);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cuberef_core::{coordinates::ChunkOffset, protocol::blocks::BlockTypeDef};
+
+    use crate::game_state::{blocks::BlockType, tests::make_game_state};
+
+    use super::*;
+
+    // Appends its ID to the first block of the chunk, and counts how often it ran for each chunk
+    struct RecordingStage {
+        id: u32,
+        runs: Arc<Mutex<FxHashMap<ChunkCoordinate, usize>>>,
+    }
+    impl MapgenStageHandler for RecordingStage {
+        fn generate(&self, context: &MapgenContext<'_>, chunk: &mut MapChunk) -> Result<()> {
+            *self.runs.lock().entry(context.coord()).or_default() += 1;
+            chunk.block_ids[0] = chunk.block_ids[0] * 10 + self.id;
+            Ok(())
+        }
+    }
+
+    // Records the first block of each neighbor it was given
+    struct NeighborStage {
+        requirement: NeighborRequirement,
+        seen: Arc<Mutex<Vec<(ChunkCoordinate, u32)>>>,
+    }
+    impl MapgenStageHandler for NeighborStage {
+        fn neighbor_requirement(&self) -> Option<NeighborRequirement> {
+            Some(self.requirement)
+        }
+        fn generate(&self, context: &MapgenContext<'_>, _chunk: &mut MapChunk) -> Result<()> {
+            let mut seen = self.seen.lock();
+            for (coord, block_ids) in context.neighbors.iter() {
+                seen.push((*coord, block_ids[0]));
+            }
+            Ok(())
+        }
+    }
+
+    fn recording(
+        id: u32,
+        runs: &Arc<Mutex<FxHashMap<ChunkCoordinate, usize>>>,
+    ) -> Box<dyn MapgenStageHandler> {
+        Box::new(RecordingStage {
+            id,
+            runs: runs.clone(),
+        })
+    }
+
+    fn make_pipeline(
+        handlers: Vec<(MapgenStage, &str, Box<dyn MapgenStageHandler>)>,
+    ) -> Result<MapgenPipeline> {
+        MapgenPipeline::new(
+            Arc::new(BlockTypeManager::new()),
+            1234,
+            handlers
+                .into_iter()
+                .map(|(stage, name, handler)| (stage, name.to_string(), handler))
+                .collect(),
+        )
+    }
+
+    fn generate(pipeline: &MapgenPipeline, coord: ChunkCoordinate) -> MapChunk {
+        let mut chunk = MapChunk::new_detached(coord);
+        pipeline.fill_chunk(coord, &mut chunk).unwrap();
+        chunk
+    }
+
+    #[test]
+    fn test_stage_ordering() {
+        let runs = Arc::new(Mutex::new(FxHashMap::default()));
+        // Registered out of order; stages run in order, and handlers within a stage run in
+        // registration order.
+        let pipeline = make_pipeline(vec![
+            (MapgenStage::Decorations, "d", recording(4, &runs)),
+            (MapgenStage::Terrain, "t1", recording(1, &runs)),
+            (MapgenStage::Ores, "o", recording(3, &runs)),
+            (MapgenStage::Terrain, "t2", recording(2, &runs)),
+        ])
+        .unwrap();
+        let chunk = generate(&pipeline, ChunkCoordinate::new(0, 0, 0));
+        assert_eq!(chunk.block_ids[0], 1234);
+    }
+
+    #[test]
+    fn test_neighbor_requirement() {
+        let runs = Arc::new(Mutex::new(FxHashMap::default()));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let pipeline = make_pipeline(vec![
+            (MapgenStage::Terrain, "terrain", recording(1, &runs)),
+            (
+                MapgenStage::Decorations,
+                "decorations",
+                Box::new(NeighborStage {
+                    requirement: NeighborRequirement {
+                        radius: 1,
+                        stage: MapgenStage::Terrain,
+                    },
+                    seen: seen.clone(),
+                }),
+            ),
+            (MapgenStage::PostProcessing, "post", recording(2, &runs)),
+        ])
+        .unwrap();
+        let center = ChunkCoordinate::new(5, 5, 5);
+        let chunk = generate(&pipeline, center);
+        assert_eq!(chunk.block_ids[0], 12);
+
+        let seen = std::mem::take(&mut *seen.lock());
+        assert_eq!(seen.len(), 26);
+        for (coord, first_block) in seen {
+            assert_ne!(coord, center);
+            assert!(center.x.abs_diff(coord.x) <= 1);
+            assert!(center.y.abs_diff(coord.y) <= 1);
+            assert!(center.z.abs_diff(coord.z) <= 1);
+            // Neighbors ran the terrain stage, but nothing after the requested stage
+            assert_eq!(first_block, 1);
+        }
+    }
+
+    #[test]
+    fn test_neighbors_not_regenerated() {
+        let runs = Arc::new(Mutex::new(FxHashMap::default()));
+        let pipeline = make_pipeline(vec![
+            (MapgenStage::Terrain, "terrain", recording(1, &runs)),
+            (
+                MapgenStage::Decorations,
+                "decorations",
+                Box::new(NeighborStage {
+                    requirement: NeighborRequirement {
+                        radius: 1,
+                        stage: MapgenStage::Terrain,
+                    },
+                    seen: Arc::new(Mutex::new(Vec::new())),
+                }),
+            ),
+        ])
+        .unwrap();
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    let chunk = generate(&pipeline, ChunkCoordinate::new(x, y, z));
+                    assert_eq!(chunk.block_ids[0], 1);
+                }
+            }
+        }
+        // Each chunk's terrain was generated once, whether it was needed as a neighbor
+        // before or after it was finished itself.
+        let runs = runs.lock();
+        assert_eq!(runs.len(), 5 * 5 * 5);
+        assert!(runs.values().all(|&x| x == 1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_neighbors_read_from_map() {
+        let mut blocks = BlockTypeManager::new();
+        let mut register = |name: &str| {
+            blocks
+                .register_block(BlockType {
+                    client_info: BlockTypeDef {
+                        short_name: name.to_string(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .unwrap()
+        };
+        register("test:air");
+        let marker = register("test:marker");
+        let runs = Arc::new(Mutex::new(FxHashMap::default()));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let terrain = recording(1, &runs);
+        let decorations: Box<dyn MapgenStageHandler> = Box::new(NeighborStage {
+            requirement: NeighborRequirement {
+                radius: 1,
+                stage: MapgenStage::Terrain,
+            },
+            seen: seen.clone(),
+        });
+        let terrain: MapgenStageProvider = Box::new(move |_, _| Ok(terrain));
+        let decorations: MapgenStageProvider = Box::new(move |_, _| Ok(decorations));
+        let game_state = make_game_state(
+            blocks,
+            vec![
+                (MapgenStage::Terrain, "terrain", terrain),
+                (MapgenStage::Decorations, "decorations", decorations),
+            ],
+        );
+
+        // Generates the first chunk and hands it over to the map, then changes it there
+        let origin = ChunkOffset { x: 0, y: 0, z: 0 };
+        let first = ChunkCoordinate::new(0, 0, 0);
+        game_state
+            .map()
+            .set_block(first.with_offset(origin), marker, None)
+            .unwrap();
+        seen.lock().clear();
+
+        // Its neighbor sees the change, rather than the chunk as it was generated
+        let second = ChunkCoordinate::new(1, 0, 0);
+        game_state
+            .map()
+            .get_block(second.with_offset(origin))
+            .unwrap();
+        let neighbors = std::mem::take(&mut *seen.lock());
+        assert_eq!(neighbors.len(), 26);
+        for (coord, first_block) in neighbors {
+            if coord == first {
+                assert_eq!(first_block, u32::from(marker.id()));
+            } else {
+                assert_eq!(first_block, 1);
+            }
+        }
+        game_state.finish_shutdown().await;
+    }
+
+    #[test]
+    fn test_regenerates_handed_over_chunk() {
+        let runs = Arc::new(Mutex::new(FxHashMap::default()));
+        let pipeline =
+            make_pipeline(vec![(MapgenStage::Terrain, "terrain", recording(1, &runs))]).unwrap();
+        let coord = ChunkCoordinate::new(0, 0, 0);
+        assert_eq!(generate(&pipeline, coord).block_ids[0], 1);
+        // Generating the same chunk again produces the same result
+        assert_eq!(generate(&pipeline, coord).block_ids[0], 1);
+        assert_eq!(runs.lock()[&coord], 2);
+    }
+
+    #[test]
+    fn test_rejects_invalid_neighbor_stage() {
+        let make_stage = |stage| -> Box<dyn MapgenStageHandler> {
+            Box::new(NeighborStage {
+                requirement: NeighborRequirement { radius: 1, stage },
+                seen: Arc::new(Mutex::new(Vec::new())),
+            })
+        };
+        // Neighbors must have completed an earlier stage, not the same or a later one
+        assert!(make_pipeline(vec![(
+            MapgenStage::Ores,
+            "ores",
+            make_stage(MapgenStage::Ores)
+        )])
+        .is_err());
+        assert!(make_pipeline(vec![(
+            MapgenStage::Ores,
+            "ores",
+            make_stage(MapgenStage::Decorations)
+        )])
+        .is_err());
+        assert!(make_pipeline(vec![(
+            MapgenStage::Ores,
+            "ores",
+            make_stage(MapgenStage::Carving)
+        )])
+        .is_ok());
+    }
+
+    #[test]
+    fn test_rejects_empty_pipeline() {
+        assert!(make_pipeline(vec![]).is_err());
+    }
 }
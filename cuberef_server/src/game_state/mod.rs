@@ -20,36 +20,40 @@ pub mod event;
 pub mod game_behaviors;
 pub mod game_map;
 pub mod handlers;
+pub mod hud;
 pub mod inventory;
 pub mod items;
 pub mod mapgen;
 pub mod player;
+pub mod pregen;
+pub mod world_metadata;
 
 #[cfg(test)]
 pub mod tests;
 
 pub mod testutils;
 
-use anyhow::{bail, Result};
-use integer_encoding::VarInt;
-use log::{info, warn};
-use rand::distributions::Standard;
-use rand::prelude::Distribution;
-use std::fmt::Debug;
+use anyhow::{Context, Result};
 use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
-use crate::database::database_engine::{GameDatabase, KeySpace};
+use crate::database::database_engine::GameDatabase;
 
-use crate::game_state::{game_map::ServerGameMap, mapgen::MapgenInterface};
+use crate::game_state::{
+    game_map::ServerGameMap,
+    mapgen::{MapgenInterface, MapgenPipeline, MapgenStage, MapgenStageProvider},
+};
 use crate::media::MediaManager;
 use crate::network_server::auth::AuthService;
+use crate::network_server::movement_validation::MovementValidationSettings;
 
 use self::blocks::BlockTypeManager;
 use self::game_behaviors::GameBehaviors;
 use self::inventory::InventoryManager;
 use self::items::ItemManager;
 use self::player::PlayerManager;
+use self::pregen::PregenManager;
+use self::world_metadata::{WorldMetadata, WorldOptions};
 
 pub struct GameState {
     map: Arc<ServerGameMap>,
@@ -60,9 +64,12 @@ pub struct GameState {
     player_manager: Arc<PlayerManager>,
     media_resources: Arc<MediaManager>,
     early_shutdown: CancellationToken,
-    mapgen_seed: u32,
+    world_metadata: WorldMetadata,
     game_behaviors: GameBehaviors,
-    auth: AuthService
+    auth: AuthService,
+    pregen: PregenManager,
+    movement_validation: MovementValidationSettings,
+    max_view_distance: u32,
 }
 
 impl GameState {
@@ -71,23 +78,38 @@ impl GameState {
         blocks: Arc<BlockTypeManager>,
         items: ItemManager,
         media: MediaManager,
-        mapgen_provider: Box<dyn FnOnce(Arc<BlockTypeManager>, u32) -> Arc<dyn MapgenInterface>>,
+        mapgen_stages: Vec<(MapgenStage, String, MapgenStageProvider)>,
         game_behaviors: GameBehaviors,
+        world_options: WorldOptions,
+        movement_validation: MovementValidationSettings,
+        max_view_distance: u32,
     ) -> Result<Arc<Self>> {
         // TODO figure out a way to replace unwrap with error propagation
-        let mapgen_seed = get_or_create_seed(db.as_ref(), b"mapgen_seed")?;
-        let mapgen = mapgen_provider(blocks.clone(), mapgen_seed);
+        let world_metadata = world_metadata::load_or_create(db.as_ref(), &world_options)?;
+        let mapgen_seed = world_metadata.seed;
+        let mapgen_stages = mapgen_stages
+            .into_iter()
+            .map(|(stage, name, provider)| {
+                let handler = provider(blocks.clone(), mapgen_seed)
+                    .with_context(|| format!("Failed to create mapgen handler {name}"))?;
+                Ok((stage, name, handler))
+            })
+            .collect::<Result<_>>()?;
+        let mapgen = MapgenPipeline::new(blocks.clone(), mapgen_seed, mapgen_stages)?;
         Ok(Arc::new_cyclic(|weak| Self {
             map: ServerGameMap::new(weak.clone(), db.clone(), blocks).unwrap(),
-            mapgen,
+            mapgen: Arc::new(mapgen.with_game_state(weak.clone())),
             database: db.clone(),
             inventory_manager: Arc::new(InventoryManager::new(db.clone())),
             item_manager: Arc::new(items),
             player_manager: PlayerManager::new(weak.clone(), db.clone()),
             media_resources: Arc::new(media),
             early_shutdown: CancellationToken::new(),
-            mapgen_seed,
+            world_metadata,
             game_behaviors,
+            pregen: PregenManager::new(weak.clone(), db.clone()),
+            movement_validation,
+            max_view_distance,
             auth: AuthService::create(db).unwrap()
         }))
     }
@@ -123,6 +145,8 @@ impl GameState {
     // Shut down things that handle events (e.g. map, database)
     // and wait for them to safely flush data.
     pub(crate) async fn finish_shutdown(&self) {
+        // Pregeneration writes to the map and database, so stop it first
+        tokio::task::block_in_place(|| self.pregen.shutdown());
         self.map.request_shutdown();
         self.player_manager.request_shutdown();
         self.map.await_shutdown().await.unwrap();
@@ -150,53 +174,26 @@ impl GameState {
         &self.game_behaviors
     }
 
-    pub(crate) fn auth(&self) -> &AuthService {
-        &self.auth
+    /// Gets information about how this world was created.
+    pub fn world_metadata(&self) -> &WorldMetadata {
+        &self.world_metadata
     }
-}
 
-fn get_or_create_seed<T: VarInt + Debug + Copy>(db: &dyn GameDatabase, name: &[u8]) -> Result<T>
-where
-    Standard: Distribution<T>,
-{
-    let mut key = b"seed_".to_vec();
-    key.append(&mut name.to_vec());
-    let key = KeySpace::Metadata.make_key(&key);
-    match db.get(&key)? {
-        Some(x) => match T::decode_var(&x) {
-            Some((val, read)) => {
-                if read != x.len() {
-                    warn!(
-                        "Saved seed for {} was {} bytes but only {} bytes were decoded (to {:?})",
-                        String::from_utf8_lossy(name),
-                        x.len(),
-                        read,
-                        val
-                    )
-                }
-                info!(
-                    "Loaded seed {:?} for {}",
-                    val,
-                    String::from_utf8_lossy(name)
-                );
-                Ok(val)
-            }
-            None => {
-                bail!(
-                    "Decoding varint for {} seed failed",
-                    String::from_utf8_lossy(name)
-                );
-            }
-        },
-        None => {
-            let seed: T = rand::random();
-            db.put(&key, &seed.encode_var_vec())?;
-            info!(
-                "Generated seed {:?} for {}",
-                seed,
-                String::from_utf8_lossy(name)
-            );
-            Ok(seed)
-        }
+    /// Gets the manager for world pregeneration jobs.
+    pub fn pregen(&self) -> &PregenManager {
+        &self.pregen
+    }
+
+    pub(crate) fn movement_validation(&self) -> &MovementValidationSettings {
+        &self.movement_validation
+    }
+
+    /// The largest view distance (in chunks) that players may use.
+    pub(crate) fn max_view_distance(&self) -> u32 {
+        self.max_view_distance
+    }
+
+    pub(crate) fn auth(&self) -> &AuthService {
+        &self.auth
     }
 }
@@ -31,7 +31,7 @@ use cuberef_core::{
 use log::warn;
 use parking_lot::{Mutex};
 use prost::Message;
-use tokio::{select, task::JoinHandle};
+use tokio::{select, sync::watch, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
@@ -41,9 +41,11 @@ use crate::{
 
 use super::{
     client_ui::{Popup},
+    hud::{HudElement, HudElementId},
     inventory::{
-        InventoryKey, InventoryView, InventoryViewId, TypeErasedInventoryView,
+        InventoryKey, InventoryView, InventoryViewId, TypeErasedInventoryView, ViewBacking,
     },
+    items::ItemStack,
     GameState,
 };
 
@@ -55,6 +57,8 @@ pub struct Player {
     pub(crate) main_inventory_key: InventoryKey,
     // Mutable state of the player
     pub(crate) state: Mutex<PlayerState>,
+    // Notifies the player's client context that hud_elements in the state changed
+    pub(crate) hud_changed: watch::Sender<()>,
 }
 
 impl Player {
@@ -70,6 +74,32 @@ impl Player {
     pub fn last_position(&self) -> PlayerPositionUpdate {
         self.state.lock().last_position
     }
+    /// Shows a new element on this player's HUD, returning an ID that can be used to update or
+    /// remove it. HUD elements aren't saved; they disappear when the player disconnects.
+    pub fn add_hud_element(&self, element: HudElement) -> HudElementId {
+        let id = HudElementId::next();
+        self.state.lock().hud_elements.insert(id, element);
+        self.hud_changed.send_replace(());
+        id
+    }
+    /// Replaces a HUD element previously added to this player's HUD. Returns an error if the
+    /// element was removed.
+    pub fn update_hud_element(&self, id: HudElementId, element: HudElement) -> Result<()> {
+        match self.state.lock().hud_elements.get_mut(&id) {
+            Some(existing) => *existing = element,
+            None => bail!("HUD element {id:?} not found"),
+        }
+        self.hud_changed.send_replace(());
+        Ok(())
+    }
+    /// Removes an element from this player's HUD. Returns false if it was already removed.
+    pub fn remove_hud_element(&self, id: HudElementId) -> bool {
+        let removed = self.state.lock().hud_elements.remove(&id).is_some();
+        if removed {
+            self.hud_changed.send_replace(());
+        }
+        removed
+    }
     fn to_server_proto(&self) -> StoredPlayer {
         StoredPlayer {
             name: self.name.clone(),
@@ -95,6 +125,7 @@ impl Player {
                     face_direction: (0., 0.),
                 },
                 active_popups: vec![],
+                hud_elements: HashMap::new(),
                 inventory_popup: (game_state.game_behaviors().make_inventory_popup)(
                     game_state.clone(),
                     proto.name.clone(),
@@ -115,6 +146,7 @@ impl Player {
                     false,
                 )?,
             }),
+            hud_changed: watch::channel(()).0,
         })
     }
 
@@ -133,6 +165,7 @@ impl Player {
                     face_direction: (0., 0.),
                 },
                 active_popups: vec![],
+                hud_elements: HashMap::new(),
                 inventory_popup: (game_state.game_behaviors().make_inventory_popup)(
                     game_state.clone(),
                     name.to_string(),
@@ -154,6 +187,7 @@ impl Player {
                 )?,
             }
             .into(),
+            hud_changed: watch::channel(()).0,
         };
 
         Ok(player)
@@ -166,6 +200,8 @@ pub(crate) struct PlayerState {
     pub(crate) inventory_popup: Popup,
     // Other active popups for the player. These get deleted when closed.
     pub(crate) active_popups: Vec<Popup>,
+    // Elements shown on the player's HUD
+    pub(crate) hud_elements: HashMap<HudElementId, HudElement>,
     // The player's main inventory, which is shown in the user hotbar
     pub(crate) hotbar_inventory_view: InventoryView<()>,
     // A 1x1 transient view used to carry items with the mouse
@@ -173,10 +209,34 @@ pub(crate) struct PlayerState {
 }
 impl PlayerState {
     pub(crate) fn handle_inventory_action(&mut self, action: &InventoryAction) -> Result<()> {
+        if action.quick_move {
+            self.handle_quick_move(action)?;
+            return self.invoke_inventory_action_callbacks(action);
+        }
         let source_view = self.find_inv_view(InventoryViewId(action.source_view))?;
         let destination_view = self.find_inv_view(InventoryViewId(action.destination_view))?;
 
         if source_view.can_take() && destination_view.can_place() {
+            // The slot filters must allow the stacks to move into their new slots (in both
+            // directions for a swap); otherwise, nothing is moved.
+            let source_slot = action.source_slot as usize;
+            let destination_slot = action.destnation_slot as usize;
+            let destination_accepts = source_view
+                .peek()?
+                .get(source_slot)
+                .and_then(|x| x.as_ref())
+                .map_or(true, |x| destination_view.accepts(destination_slot, x));
+            let source_accepts = !action.swap
+                || destination_view
+                    .peek()?
+                    .get(destination_slot)
+                    .and_then(|x| x.as_ref())
+                    .map_or(true, |x| source_view.accepts(source_slot, x));
+            if !(destination_accepts && source_accepts) {
+                warn!("Slot filters rejected {action:?}");
+                return self.invoke_inventory_action_callbacks(action);
+            }
+
             if action.swap {
                 let taken_stack = source_view.take(action.source_slot as usize, None)?;
                 let other_taken_stack =
@@ -213,6 +273,99 @@ impl PlayerState {
         } else {
             warn!("Inventory view(s) not found for {action:?}");
         }
+        self.invoke_inventory_action_callbacks(action)
+    }
+
+    /// Moves the stack in the source slot into the first other view of the same popup, in the
+    /// order they're shown, that has room for the whole stack. Items are merged into existing
+    /// stacks before any empty slots are used. If no view has room, nothing is moved.
+    fn handle_quick_move(&self, action: &InventoryAction) -> Result<()> {
+        let source_id = InventoryViewId(action.source_view);
+        let source_slot = action.source_slot as usize;
+        let popup = match self
+            .active_popups
+            .iter()
+            .chain(std::iter::once(&self.inventory_popup))
+            .find(|x| x.inventory_views().values().any(|x| x.id == source_id))
+        {
+            Some(x) => x,
+            None => {
+                warn!("Quick move from a view that isn't in a popup: {action:?}");
+                return Ok(());
+            }
+        };
+        let source_view = self.find_inv_view(source_id)?;
+        if !source_view.can_take() {
+            warn!("Quick move from a view that can't be taken from: {action:?}");
+            return Ok(());
+        }
+        let stack = match source_view.peek()?.get(source_slot).cloned().flatten() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        // Virtual inputs are skipped, since they would consume (e.g. destroy) the items.
+        // Check that everything fits before taking anything, since some views (e.g. crafting
+        // outputs) can't take back what was taken from them.
+        let mut target = None;
+        for view in popup.inventory_views_in_order() {
+            if view.id == source_id
+                || !view.can_place
+                || matches!(view.backing, ViewBacking::VirtualInput(_))
+            {
+                continue;
+            }
+            let destination = InventoryViewWithContext {
+                view,
+                context: popup,
+            };
+            if quick_move_space(&destination, &stack)? >= stack.proto.quantity {
+                target = Some(destination);
+                break;
+            }
+        }
+        let destination = match target {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let mut taken = match source_view.take(source_slot, None)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        for merge_only in [true, false] {
+            for (slot, existing) in destination.peek()?.iter().enumerate() {
+                let candidate = match existing {
+                    Some(existing) => {
+                        merge_only
+                            && taken.borrowed_stack.proto.stackable
+                            && existing.proto.item_name == taken.borrowed_stack.proto.item_name
+                    }
+                    None => !merge_only,
+                };
+                if !candidate || !destination.accepts(slot, &taken.borrowed_stack) {
+                    continue;
+                }
+                match destination.put(slot, taken)? {
+                    Some(leftover) => taken = leftover,
+                    None => return Ok(()),
+                }
+            }
+        }
+        // Only reached if the destination changed after its space was checked (e.g. from an
+        // on_change callback). The items go back to the source slot, or to the player's cursor
+        // if the source can't take them back.
+        let leftover = match source_view.put(source_slot, taken)? {
+            Some(leftover) => self.inventory_manipulation_view.put(&(), 0, leftover)?,
+            None => None,
+        };
+        if let Some(leftover) = leftover {
+            log::error!("Quick move couldn't return leftover items: {:?}", leftover);
+        }
+        Ok(())
+    }
+
+    fn invoke_inventory_action_callbacks(&self, action: &InventoryAction) -> Result<()> {
         for popup in self
             .active_popups
             .iter()
@@ -257,6 +410,29 @@ impl PlayerState {
     }
 }
 
+// How many of the stack's items the destination has room for, across all of its slots
+fn quick_move_space(destination: &dyn TypeErasedInventoryView, stack: &ItemStack) -> Result<u32> {
+    let mut space = 0u32;
+    for (slot, existing) in destination.peek()?.iter().enumerate() {
+        if !destination.accepts(slot, stack) {
+            continue;
+        }
+        space = space.saturating_add(match existing {
+            None => stack.proto.quantity,
+            Some(existing)
+                if stack.proto.stackable && existing.proto.item_name == stack.proto.item_name =>
+            {
+                existing
+                    .proto
+                    .max_stack
+                    .saturating_sub(existing.proto.quantity)
+            }
+            Some(_) => 0,
+        });
+    }
+    Ok(space)
+}
+
 // Struct held by the client contexts for this player. When dropped, the
 // PlayerManager is notified of this via the Drop impl
 // TODO make this cleaner and more sensible, this is fine for an initial impl
@@ -322,6 +498,9 @@ impl PlayerManager {
         };
         let player = Arc::new(player);
         lock.insert(name.to_string(), player.clone());
+        // Still under the lock, so that notifications for concurrent connects/disconnects
+        // can't arrive out of order
+        self.game_state().pregen().set_players_online(true);
 
         Ok(PlayerContext {
             player,
@@ -329,7 +508,8 @@ impl PlayerManager {
         })
     }
     fn drop_disconnect(&self, name: &str) {
-        match self.active_players.lock().entry(name.to_string()) {
+        let mut lock = self.active_players.lock();
+        match lock.entry(name.to_string()) {
             Entry::Occupied(entry) => {
                 let count = Arc::strong_count(entry.get());
                 // We expect 2 - one in the map, and one that we're holding while calling drop_disconnect
@@ -345,6 +525,9 @@ impl PlayerManager {
                     }
                 }
                 entry.remove();
+                if let Some(game_state) = self.game_state.upgrade() {
+                    game_state.pregen().set_players_online(!lock.is_empty());
+                }
             }
             Entry::Vacant(_) => {
                 log::error!("Trying to disconnect player {name} but they're not present. This is a sign of a serious inconsistency.");
@@ -354,6 +537,21 @@ impl PlayerManager {
             }
         }
     }
+    /// Returns the number of players that are currently connected.
+    pub fn connected_player_count(&self) -> usize {
+        self.active_players.lock().len()
+    }
+    /// Calls the given function for each connected player, e.g. to update their HUDs.
+    ///
+    /// Players can't connect or disconnect while this is running, so f should return quickly.
+    pub fn for_each_connected_player<F>(&self, mut f: F)
+    where
+        F: FnMut(&Player),
+    {
+        for player in self.active_players.lock().values() {
+            f(player);
+        }
+    }
     fn write_back(&self, player: &Player) -> Result<()> {
         self.db.put(
             &Self::db_key(&player.name),
@@ -416,3 +614,208 @@ impl PlayerManager {
 }
 
 const PLAYER_WRITEBACK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[cfg(test)]
+mod tests {
+    use cuberef_core::{coordinates::BlockCoordinate, protocol::items as items_proto};
+
+    use crate::game_state::{
+        inventory::{BlockViewCallbacks, BorrowLocation, BorrowedStack, SlotFilter},
+        tests::make_empty_game_state,
+    };
+
+    use super::*;
+
+    fn stack(name: &str, quantity: u32) -> Option<BorrowedStack> {
+        Some(BorrowedStack {
+            borrows_from: BorrowLocation::NotBorrowed,
+            borrowed_stack: ItemStack {
+                proto: items_proto::ItemStack {
+                    item_name: name.to_string(),
+                    quantity,
+                    max_stack: 256,
+                    stackable: true,
+                },
+            },
+        })
+    }
+
+    fn player_state(game_state: Arc<GameState>, popup: Popup) -> PlayerState {
+        PlayerState {
+            last_position: PlayerPositionUpdate {
+                tick: 0,
+                position: Vector3::zero(),
+                velocity: Vector3::zero(),
+                face_direction: (0., 0.),
+            },
+            inventory_popup: popup,
+            active_popups: vec![],
+            hud_elements: HashMap::new(),
+            hotbar_inventory_view: InventoryView::new_transient(
+                game_state.clone(),
+                (1, 1),
+                vec![None],
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+            inventory_manipulation_view: InventoryView::new_transient(
+                game_state,
+                (1, 1),
+                vec![None],
+                true,
+                true,
+                false,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn view_id(state: &PlayerState, form_key: &str) -> InventoryViewId {
+        state.inventory_popup.inventory_views()[form_key].id
+    }
+
+    // The (item, quantity) in each slot of the view with the given form key
+    fn contents(state: &PlayerState, form_key: &str) -> Vec<Option<(String, u32)>> {
+        state
+            .find_inv_view(view_id(state, form_key))
+            .unwrap()
+            .peek()
+            .unwrap()
+            .into_iter()
+            .map(|x| x.map(|x| (x.proto.item_name, x.proto.quantity)))
+            .collect()
+    }
+
+    fn quick_move(state: &mut PlayerState, form_key: &str, slot: u32) {
+        let action = InventoryAction {
+            source_view: view_id(state, form_key).0,
+            source_slot: slot,
+            quick_move: true,
+            ..Default::default()
+        };
+        state.handle_inventory_action(&action).unwrap();
+    }
+
+    fn some(name: &str, quantity: u32) -> Option<(String, u32)> {
+        Some((name.to_string(), quantity))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_quick_move_merges_whole_stack() {
+        let game_state = make_empty_game_state();
+        let popup = Popup::new(game_state.clone())
+            .inventory_view_transient("source", (1, 1), vec![stack("test:a", 10)], true, true)
+            .unwrap()
+            // Only has room for 6 more
+            .inventory_view_transient("full", (1, 1), vec![stack("test:a", 250)], true, true)
+            .unwrap()
+            .inventory_view_transient(
+                "target",
+                (3, 1),
+                vec![None, stack("test:b", 1), stack("test:a", 5)],
+                true,
+                true,
+            )
+            .unwrap();
+        let mut state = player_state(game_state.clone(), popup);
+
+        quick_move(&mut state, "source", 0);
+        assert_eq!(contents(&state, "source"), vec![None]);
+        assert_eq!(contents(&state, "full"), vec![some("test:a", 250)]);
+        // Merged into the existing stack rather than the empty slot before it
+        assert_eq!(
+            contents(&state, "target"),
+            vec![None, some("test:b", 1), some("test:a", 15)]
+        );
+
+        // Neither of the other views has room for the whole stack, so nothing moves
+        quick_move(&mut state, "full", 0);
+        assert_eq!(contents(&state, "full"), vec![some("test:a", 250)]);
+        assert_eq!(
+            contents(&state, "target"),
+            vec![None, some("test:b", 1), some("test:a", 15)]
+        );
+
+        drop(state);
+        game_state.finish_shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_quick_move_slot_filters() {
+        let game_state = make_empty_game_state();
+        let coord = BlockCoordinate::new(0, 0, 0);
+        let popup = Popup::new(game_state.clone())
+            .inventory_view_transient("source", (1, 1), vec![stack("test:a", 10)], true, true)
+            .unwrap()
+            .inventory_view_block(
+                "rejects",
+                (1, 1),
+                coord,
+                "rejects".to_string(),
+                true,
+                true,
+                false,
+                BlockViewCallbacks {
+                    slot_filters: vec![SlotFilter::item("test:b")],
+                    on_change: None,
+                },
+            )
+            .unwrap()
+            .inventory_view_block(
+                "accepts",
+                (2, 1),
+                coord,
+                "accepts".to_string(),
+                true,
+                true,
+                false,
+                BlockViewCallbacks {
+                    slot_filters: vec![SlotFilter::Nothing, SlotFilter::item("test:a")],
+                    on_change: None,
+                },
+            )
+            .unwrap();
+        let mut state = player_state(game_state.clone(), popup);
+
+        quick_move(&mut state, "source", 0);
+        assert_eq!(contents(&state, "source"), vec![None]);
+        assert_eq!(contents(&state, "rejects"), vec![None]);
+        assert_eq!(contents(&state, "accepts"), vec![None, some("test:a", 10)]);
+
+        drop(state);
+        game_state.finish_shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_quick_move_can_take_and_can_place() {
+        let game_state = make_empty_game_state();
+        let popup = Popup::new(game_state.clone())
+            .inventory_view_transient("display", (1, 1), vec![], false, true)
+            .unwrap()
+            .inventory_view_transient("locked", (1, 1), vec![stack("test:a", 10)], true, false)
+            .unwrap()
+            .inventory_view_transient("source", (1, 1), vec![stack("test:a", 10)], true, true)
+            .unwrap()
+            .inventory_view_transient("target", (1, 1), vec![], true, true)
+            .unwrap();
+        let mut state = player_state(game_state.clone(), popup);
+
+        // Nothing can be taken from a view with can_take unset
+        quick_move(&mut state, "locked", 0);
+        assert_eq!(contents(&state, "locked"), vec![some("test:a", 10)]);
+        assert_eq!(contents(&state, "target"), vec![None]);
+
+        // The display view comes first, but can_place is unset, so the items go into the
+        // locked view, which has room for them
+        quick_move(&mut state, "source", 0);
+        assert_eq!(contents(&state, "source"), vec![None]);
+        assert_eq!(contents(&state, "locked"), vec![some("test:a", 20)]);
+        assert_eq!(contents(&state, "display"), vec![None]);
+        assert_eq!(contents(&state, "target"), vec![None]);
+
+        drop(state);
+        game_state.finish_shutdown().await;
+    }
+}
@@ -0,0 +1,763 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use cuberef_core::{
+    coordinates::{BlockCoordinate, ChunkCoordinate},
+    protocol::map::PregenJob,
+};
+use log::{error, info, warn};
+use parking_lot::{Condvar, Mutex};
+use prost::Message;
+
+use crate::database::database_engine::{GameDatabase, KeySpace};
+
+use super::GameState;
+
+const PREGEN_JOB_KEY: &[u8] = b"pregen_job";
+// Progress is saved after every batch of this many chunks
+const CHECKPOINT_BATCH_SIZE: usize = 256;
+// While players are connected, only one worker runs, and it starts at most one chunk per this interval
+const THROTTLE_DELAY: Duration = Duration::from_millis(25);
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The largest horizontal radius a job may have, in chunks. A running job keeps the coordinates
+/// of all of its chunks in memory.
+pub const MAX_PREGEN_RADIUS: u32 = 128;
+/// The largest vertical radius a job may have, in chunks.
+pub const MAX_PREGEN_VERTICAL_RADIUS: u32 = 16;
+
+/// Describes a region of the world to generate ahead of time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PregenRequest {
+    /// The block around which chunks are generated
+    pub center: BlockCoordinate,
+    /// Horizontal radius, in chunks. Chunks are generated in a circle of this radius.
+    /// At most [MAX_PREGEN_RADIUS].
+    pub radius: u32,
+    /// Vertical radius, in chunks. At most [MAX_PREGEN_VERTICAL_RADIUS].
+    pub vertical_radius: u32,
+}
+impl PregenRequest {
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.radius <= MAX_PREGEN_RADIUS,
+            "Pregeneration radius {} is too large (at most {MAX_PREGEN_RADIUS} chunks)",
+            self.radius
+        );
+        ensure!(
+            self.vertical_radius <= MAX_PREGEN_VERTICAL_RADIUS,
+            "Pregeneration vertical radius {} is too large (at most {MAX_PREGEN_VERTICAL_RADIUS} chunks)",
+            self.vertical_radius
+        );
+        Ok(())
+    }
+
+    /// Returns all chunks covered by this request, nearest first. The order is
+    /// deterministic, so that a job can be resumed by index.
+    fn chunks(&self) -> Vec<ChunkCoordinate> {
+        let center = self.center.chunk();
+        let radius = self.radius as i64;
+        let vertical_radius = self.vertical_radius as i64;
+        let mut chunks = Vec::new();
+        for dx in -radius..=radius {
+            for dz in -radius..=radius {
+                if dx * dx + dz * dz > radius * radius {
+                    continue;
+                }
+                for dy in -vertical_radius..=vertical_radius {
+                    if let Some(coord) = center.try_delta(dx as i32, dy as i32, dz as i32) {
+                        chunks.push(coord);
+                    }
+                }
+            }
+        }
+        // The sort key is recomputed rather than stored, to keep large jobs small in memory
+        chunks.sort_unstable_by_key(|coord| {
+            let dx = coord.x as i64 - center.x as i64;
+            let dy = coord.y as i64 - center.y as i64;
+            let dz = coord.z as i64 - center.z as i64;
+            (dx * dx + dz * dz, dy.abs(), dx, dy, dz)
+        });
+        chunks
+    }
+
+    fn to_proto(self, next_index: u64, total: u64) -> PregenJob {
+        PregenJob {
+            center: Some(self.center.into()),
+            radius: self.radius,
+            vertical_radius: self.vertical_radius,
+            next_index,
+            total,
+        }
+    }
+
+    fn from_proto(proto: &PregenJob) -> Result<PregenRequest> {
+        Ok(PregenRequest {
+            center: proto
+                .center
+                .as_ref()
+                .with_context(|| "Missing center in stored pregen job")?
+                .into(),
+            radius: proto.radius,
+            vertical_radius: proto.vertical_radius,
+        })
+    }
+}
+
+/// A snapshot of the progress of a pregeneration job.
+#[derive(Clone, Copy, Debug)]
+pub struct PregenStatus {
+    pub request: PregenRequest,
+    /// Chunks that have been checked, whether or not they needed to be generated
+    pub processed: u64,
+    /// Chunks that were generated by this job (since the server last started)
+    pub generated: u64,
+    pub total: u64,
+    /// True if the job ran to completion
+    pub finished: bool,
+    /// True if the job is still running (i.e. it has neither finished nor been stopped)
+    pub running: bool,
+}
+
+struct JobProgress {
+    processed: AtomicU64,
+    generated: AtomicU64,
+    total: u64,
+    finished: AtomicBool,
+}
+
+struct RunningJob {
+    request: PregenRequest,
+    progress: Arc<JobProgress>,
+    queue: Arc<WorkQueue>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Hands out the indices of chunks to generate to a job's workers, one checkpoint batch at a time.
+///
+/// Workers block on a condvar until there's work for them, so that they wake up as soon as a
+/// batch is opened, the job is stopped, or throttling changes.
+struct WorkQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+struct QueueState {
+    // The next index to hand out
+    next: usize,
+    // Indices up to (but not including) this one may be handed out
+    batch_end: usize,
+    // Indices that were handed out, but whose chunks aren't done yet
+    outstanding: usize,
+    // Whether players are connected, in which case only worker 0 gets any work
+    throttled: bool,
+    // When worker 0 may get its next index while throttled
+    next_throttled: Option<Instant>,
+    stopped: bool,
+}
+impl WorkQueue {
+    fn new(start_index: usize, throttled: bool) -> WorkQueue {
+        WorkQueue {
+            state: Mutex::new(QueueState {
+                next: start_index,
+                batch_end: start_index,
+                outstanding: 0,
+                throttled,
+                next_throttled: None,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Allows indices up to (but not including) batch_end to be handed out.
+    fn open_batch(&self, batch_end: usize) {
+        self.state.lock().batch_end = batch_end;
+        self.changed.notify_all();
+    }
+
+    /// Blocks until there's an index for the given worker to work on, and returns it.
+    /// Returns None once the queue is stopped.
+    fn next(&self, worker_index: usize) -> Option<usize> {
+        let mut state = self.state.lock();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            if state.next < state.batch_end {
+                if !state.throttled {
+                    break;
+                }
+                if worker_index == 0 {
+                    // Players are online; leave the CPU and the map mostly to them
+                    match state.next_throttled {
+                        Some(deadline) if Instant::now() < deadline => {
+                            self.changed.wait_until(&mut state, deadline);
+                            continue;
+                        }
+                        _ => {
+                            state.next_throttled = Some(Instant::now() + THROTTLE_DELAY);
+                            break;
+                        }
+                    }
+                }
+            }
+            self.changed.wait(&mut state);
+        }
+        let index = state.next;
+        state.next += 1;
+        state.outstanding += 1;
+        Some(index)
+    }
+
+    /// Marks one of the handed out indices as done.
+    fn complete(&self) {
+        let mut state = self.state.lock();
+        state.outstanding -= 1;
+        if state.outstanding == 0 && state.next >= state.batch_end {
+            self.changed.notify_all();
+        }
+    }
+
+    /// Blocks until every index of the current batch is done. Returns false if the queue
+    /// was stopped instead.
+    fn wait_for_batch(&self) -> bool {
+        let mut state = self.state.lock();
+        while !state.stopped && (state.next < state.batch_end || state.outstanding > 0) {
+            self.changed.wait(&mut state);
+        }
+        !state.stopped
+    }
+
+    fn set_throttled(&self, throttled: bool) {
+        self.state.lock().throttled = throttled;
+        self.changed.notify_all();
+    }
+
+    /// Stops handing out work, and wakes up anything waiting on the queue.
+    fn stop(&self) {
+        self.state.lock().stopped = true;
+        self.changed.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.state.lock().stopped
+    }
+}
+
+/// Generates and stores all chunks in a region of the world ahead of time, so that players
+/// don't need to wait for the map generator when exploring it.
+///
+/// Only one job runs at a time. Its progress is saved to the database, and an unfinished job
+/// is resumed when the server restarts.
+pub struct PregenManager {
+    game_state: Weak<GameState>,
+    db: Arc<dyn GameDatabase>,
+    job: Mutex<Option<RunningJob>>,
+    players_online: AtomicBool,
+}
+impl PregenManager {
+    pub(crate) fn new(game_state: Weak<GameState>, db: Arc<dyn GameDatabase>) -> PregenManager {
+        PregenManager {
+            game_state,
+            db,
+            job: Mutex::new(None),
+            players_online: AtomicBool::new(false),
+        }
+    }
+
+    /// Called when players connect or disconnect. While any players are online, pregeneration
+    /// is throttled.
+    pub(crate) fn set_players_online(&self, online: bool) {
+        let lock = self.job.lock();
+        self.players_online.store(online, Ordering::Relaxed);
+        if let Some(job) = lock.as_ref() {
+            job.queue.set_throttled(online);
+        }
+    }
+
+    fn load_stored_job(&self) -> Result<Option<PregenJob>> {
+        match self.db.get(&KeySpace::Metadata.make_key(PREGEN_JOB_KEY))? {
+            Some(bytes) => Ok(Some(PregenJob::decode(bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Starts a pregeneration job. If an unfinished job for the same region was stored,
+    /// it picks up where that job left off.
+    ///
+    /// Fails if a job is already running.
+    pub fn start(&self, request: PregenRequest) -> Result<()> {
+        let start_index = start_index_for(self.load_stored_job()?.as_ref(), request)?;
+        self.start_at(request, start_index)
+    }
+
+    /// Resumes an unfinished job from a previous run of the server, if there is one.
+    /// Returns true if a job was resumed.
+    pub fn resume_pending(&self) -> Result<bool> {
+        match self.load_stored_job()? {
+            Some(stored) if stored.next_index < stored.total => {
+                info!(
+                    "Resuming world pregeneration at chunk {} of {}",
+                    stored.next_index, stored.total
+                );
+                self.start_at(PregenRequest::from_proto(&stored)?, stored.next_index)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn start_at(&self, request: PregenRequest, start_index: u64) -> Result<()> {
+        request.validate()?;
+        // Listing the chunks takes a while for large jobs, so don't hold the lock for it
+        let chunks = request.chunks();
+        let mut lock = self.job.lock();
+        if let Some(job) = lock.as_ref() {
+            if !job.progress.finished.load(Ordering::Relaxed) && !job.queue.is_stopped() {
+                bail!("A pregeneration job is already running");
+            }
+        }
+        if let Some(mut old_job) = lock.take() {
+            if let Some(handle) = old_job.handle.take() {
+                // Either finished or cancelled, so this shouldn't block for long
+                let _ = handle.join();
+            }
+        }
+        let game_state = self
+            .game_state
+            .upgrade()
+            .with_context(|| "GameState is gone")?;
+
+        let total = chunks.len() as u64;
+        let start_index = start_index.min(total);
+        self.db.put(
+            &KeySpace::Metadata.make_key(PREGEN_JOB_KEY),
+            &request.to_proto(start_index, total).encode_to_vec(),
+        )?;
+        info!(
+            "Starting world pregeneration around {:?}: {} chunks, starting at {}",
+            request.center, total, start_index
+        );
+
+        let progress = Arc::new(JobProgress {
+            processed: AtomicU64::new(start_index),
+            generated: AtomicU64::new(0),
+            total,
+            finished: AtomicBool::new(false),
+        });
+        let queue = Arc::new(WorkQueue::new(
+            start_index as usize,
+            self.players_online.load(Ordering::Relaxed),
+        ));
+        let worker = JobWorker {
+            game_state,
+            db: self.db.clone(),
+            request,
+            chunks,
+            progress: progress.clone(),
+            queue: queue.clone(),
+        };
+        let handle = std::thread::Builder::new()
+            .name("pregen".to_string())
+            .spawn(move || worker.run(start_index as usize))?;
+        *lock = Some(RunningJob {
+            request,
+            progress,
+            queue,
+            handle: Some(handle),
+        });
+        Ok(())
+    }
+
+    /// Returns the status of the current or most recent job since the server started, if any.
+    pub fn status(&self) -> Option<PregenStatus> {
+        self.job.lock().as_ref().map(|job| PregenStatus {
+            request: job.request,
+            processed: job.progress.processed.load(Ordering::Relaxed),
+            generated: job.progress.generated.load(Ordering::Relaxed),
+            total: job.progress.total,
+            finished: job.progress.finished.load(Ordering::Relaxed),
+            running: !job.progress.finished.load(Ordering::Relaxed) && !job.queue.is_stopped(),
+        })
+    }
+
+    /// Stops the current job, and discards its saved progress so that it is not resumed.
+    /// Chunks that were already generated are kept.
+    pub fn cancel(&self) -> Result<()> {
+        let handle = {
+            let mut lock = self.job.lock();
+            match lock.as_mut() {
+                Some(job) => {
+                    job.queue.stop();
+                    job.handle.take()
+                }
+                None => bail!("No pregeneration job is running"),
+            }
+        };
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+        if let Some(stored) = self.load_stored_job()? {
+            self.db.put(
+                &KeySpace::Metadata.make_key(PREGEN_JOB_KEY),
+                &PregenJob {
+                    next_index: stored.total,
+                    ..stored
+                }
+                .encode_to_vec(),
+            )?;
+        }
+        info!("World pregeneration cancelled");
+        Ok(())
+    }
+
+    // Stops the current job, keeping its saved progress so that it resumes on the next startup.
+    pub(crate) fn shutdown(&self) {
+        let handle = {
+            let mut lock = self.job.lock();
+            match lock.as_mut() {
+                Some(job) => {
+                    job.queue.stop();
+                    job.handle.take()
+                }
+                None => None,
+            }
+        };
+        if let Some(handle) = handle {
+            if handle.join().is_err() {
+                error!("Pregeneration thread panicked");
+            }
+        }
+    }
+}
+
+/// Returns the index at which a job for the given request should start, given the job stored in
+/// the database: the stored job's progress if it's an unfinished job for the same region, or 0.
+fn start_index_for(stored: Option<&PregenJob>, request: PregenRequest) -> Result<u64> {
+    match stored {
+        Some(stored)
+            if PregenRequest::from_proto(stored)? == request
+                && stored.next_index < stored.total =>
+        {
+            Ok(stored.next_index)
+        }
+        _ => Ok(0),
+    }
+}
+
+struct JobWorker {
+    game_state: Arc<GameState>,
+    db: Arc<dyn GameDatabase>,
+    request: PregenRequest,
+    chunks: Vec<ChunkCoordinate>,
+    progress: Arc<JobProgress>,
+    queue: Arc<WorkQueue>,
+}
+impl JobWorker {
+    fn run(self, start_index: usize) {
+        let workers = std::thread::available_parallelism()
+            .map(|x| x.get())
+            .unwrap_or(1);
+        // The workers live for the whole job, and pick up each batch as it's opened
+        let finished = std::thread::scope(|s| {
+            for worker_index in 0..workers {
+                let this = &self;
+                s.spawn(move || this.run_worker(worker_index));
+            }
+            let finished = self.run_batches(start_index);
+            // Lets the workers exit
+            self.queue.stop();
+            finished
+        });
+        if finished {
+            self.progress.finished.store(true, Ordering::Relaxed);
+            info!(
+                "World pregeneration finished: {} chunks, {} generated",
+                self.chunks.len(),
+                self.progress.generated.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    // Opens one batch at a time, and saves progress once all of its chunks are done.
+    // Returns true if the job ran to completion.
+    fn run_batches(&self, start_index: usize) -> bool {
+        let mut batch_start = start_index;
+        let mut last_log = Instant::now();
+        while batch_start < self.chunks.len() {
+            let batch_end = (batch_start + CHECKPOINT_BATCH_SIZE).min(self.chunks.len());
+            self.queue.open_batch(batch_end);
+            if !self.queue.wait_for_batch() {
+                // The batch may be incomplete; it'll be redone (quickly, since most of it is stored already)
+                // when the job is resumed.
+                info!(
+                    "World pregeneration stopped at chunk {} of {}",
+                    batch_start,
+                    self.chunks.len()
+                );
+                return false;
+            }
+            batch_start = batch_end;
+            if let Err(e) = self.db.put(
+                &KeySpace::Metadata.make_key(PREGEN_JOB_KEY),
+                &self
+                    .request
+                    .to_proto(batch_start as u64, self.chunks.len() as u64)
+                    .encode_to_vec(),
+            ) {
+                warn!("Failed to save pregeneration progress: {e:?}");
+            }
+            if last_log.elapsed() >= PROGRESS_LOG_INTERVAL {
+                last_log = Instant::now();
+                info!(
+                    "World pregeneration: {}/{} chunks ({:.1}%), {} generated",
+                    batch_start,
+                    self.chunks.len(),
+                    100.0 * batch_start as f64 / self.chunks.len() as f64,
+                    self.progress.generated.load(Ordering::Relaxed)
+                );
+            }
+        }
+        true
+    }
+
+    fn run_worker(&self, worker_index: usize) {
+        let _span = tracy_client::span!("pregen worker");
+        while let Some(index) = self.queue.next(worker_index) {
+            match self.game_state.map().pregenerate_chunk(self.chunks[index]) {
+                Ok(true) => {
+                    self.progress.generated.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to pregenerate {:?}: {e:?}", self.chunks[index]);
+                }
+            }
+            self.progress.processed.fetch_add(1, Ordering::Relaxed);
+            self.queue.complete();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cuberef_core::protocol::map::{stored_chunk::ChunkData, StoredChunk};
+
+    use crate::game_state::{
+        blocks::BlockTypeManager,
+        game_map::{AsDbKey, MapChunk},
+        mapgen::{MapgenContext, MapgenStage, MapgenStageHandler, MapgenStageProvider},
+        tests::make_game_state,
+    };
+
+    use super::*;
+
+    fn request(radius: u32) -> PregenRequest {
+        PregenRequest {
+            center: BlockCoordinate::new(0, 0, 0),
+            radius,
+            vertical_radius: 1,
+        }
+    }
+
+    #[test]
+    fn test_chunks_nearest_first() {
+        let chunks = request(2).chunks();
+        // 13 columns within a radius of 2, each 3 chunks tall
+        assert_eq!(chunks.len(), 13 * 3);
+        assert_eq!(chunks[0], ChunkCoordinate::new(0, 0, 0));
+        let distance = |c: &ChunkCoordinate| c.x * c.x + c.z * c.z;
+        assert!(chunks
+            .windows(2)
+            .all(|w| distance(&w[0]) <= distance(&w[1])));
+        assert_eq!(chunks, request(2).chunks());
+    }
+
+    #[test]
+    fn test_start_index() {
+        let stored = request(2).to_proto(100, 200);
+        // Resumes an unfinished job for the same region
+        assert_eq!(start_index_for(Some(&stored), request(2)).unwrap(), 100);
+        // Starts over for a different region, a finished job, or no job at all
+        assert_eq!(start_index_for(Some(&stored), request(3)).unwrap(), 0);
+        let finished = request(2).to_proto(200, 200);
+        assert_eq!(start_index_for(Some(&finished), request(2)).unwrap(), 0);
+        assert_eq!(start_index_for(None, request(2)).unwrap(), 0);
+        // A stored job without a center is corrupt
+        let corrupt = PregenJob {
+            center: None,
+            ..stored
+        };
+        assert!(start_index_for(Some(&corrupt), request(2)).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(request(MAX_PREGEN_RADIUS).validate().is_ok());
+        assert!(request(MAX_PREGEN_RADIUS + 1).validate().is_err());
+        assert!(request(u32::MAX).validate().is_err());
+        let too_tall = PregenRequest {
+            vertical_radius: MAX_PREGEN_VERTICAL_RADIUS + 1,
+            ..request(2)
+        };
+        assert!(too_tall.validate().is_err());
+    }
+
+    // Marks the first block of each chunk, so that generated chunks can be told apart
+    struct MarkerMapgen;
+    impl MapgenStageHandler for MarkerMapgen {
+        fn generate(&self, _context: &MapgenContext<'_>, chunk: &mut MapChunk) -> Result<()> {
+            chunk.block_ids[0] = 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pregenerate_small_job() {
+        let mapgen: MapgenStageProvider = Box::new(|_, _| Ok(Box::new(MarkerMapgen)));
+        let game_state = make_game_state(
+            BlockTypeManager::new(),
+            vec![(MapgenStage::Terrain, "test:marker", mapgen)],
+        );
+        assert!(game_state
+            .pregen()
+            .start(request(MAX_PREGEN_RADIUS + 1))
+            .is_err());
+        assert!(game_state.pregen().status().is_none());
+
+        let request = PregenRequest {
+            center: BlockCoordinate::new(100, -20, 40),
+            radius: 1,
+            vertical_radius: 1,
+        };
+        game_state.pregen().start(request).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let status = loop {
+            let status = game_state.pregen().status().unwrap();
+            if status.finished {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "Pregeneration didn't finish");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        // 5 columns within a radius of 1, each 3 chunks tall
+        assert_eq!(status.total, 5 * 3);
+        assert_eq!(status.processed, 5 * 3);
+        assert_eq!(status.generated, 5 * 3);
+
+        let chunks = request.chunks();
+        assert_eq!(chunks[0], ChunkCoordinate::new(6, -2, 2));
+        for coord in chunks.iter() {
+            let data = game_state
+                .db()
+                .get(&KeySpace::MapchunkData.make_key(&coord.as_bytes()))
+                .unwrap()
+                .unwrap_or_else(|| panic!("{coord:?} wasn't stored"));
+            match StoredChunk::decode(data.as_slice()).unwrap().chunk_data {
+                Some(ChunkData::V1(v1)) => assert_eq!(v1.block_ids[0], 1),
+                None => panic!("{coord:?} has no data"),
+            }
+        }
+        // The finished job isn't resumed, and stored chunks aren't generated again
+        let stored = game_state.pregen().load_stored_job().unwrap().unwrap();
+        assert_eq!(stored.next_index, stored.total);
+        assert!(!game_state.pregen().resume_pending().unwrap());
+        assert!(!game_state.map().pregenerate_chunk(chunks[0]).unwrap());
+
+        game_state.finish_shutdown().await;
+    }
+
+    #[test]
+    fn test_queue_batches() {
+        let queue = WorkQueue::new(5, false);
+        queue.open_batch(8);
+        assert_eq!(queue.next(1), Some(5));
+        assert_eq!(queue.next(2), Some(6));
+        assert_eq!(queue.next(1), Some(7));
+        queue.complete();
+        queue.complete();
+        queue.complete();
+        // Every index was handed out and completed
+        assert!(queue.wait_for_batch());
+        queue.open_batch(9);
+        assert_eq!(queue.next(0), Some(8));
+        queue.complete();
+        assert!(queue.wait_for_batch());
+    }
+
+    #[test]
+    fn test_queue_waits_for_outstanding() {
+        let queue = Arc::new(WorkQueue::new(0, false));
+        queue.open_batch(1);
+        assert_eq!(queue.next(0), Some(0));
+        let waiter = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.wait_for_batch())
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        queue.complete();
+        assert!(waiter.join().unwrap());
+    }
+
+    #[test]
+    fn test_queue_stop_wakes_workers() {
+        let queue = Arc::new(WorkQueue::new(0, false));
+        // No batch is open yet, so the worker blocks
+        let worker = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.next(0))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!worker.is_finished());
+        queue.stop();
+        assert_eq!(worker.join().unwrap(), None);
+        assert!(queue.is_stopped());
+        assert!(!queue.wait_for_batch());
+    }
+
+    #[test]
+    fn test_queue_throttled() {
+        let queue = Arc::new(WorkQueue::new(0, true));
+        queue.open_batch(10);
+        // Only the first worker gets work while throttled, and it's paced
+        let worker = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.next(1))
+        };
+        let start = Instant::now();
+        assert_eq!(queue.next(0), Some(0));
+        assert_eq!(queue.next(0), Some(1));
+        assert!(start.elapsed() >= THROTTLE_DELAY);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!worker.is_finished());
+
+        // Unthrottling wakes up the other workers
+        queue.set_throttled(false);
+        assert_eq!(worker.join().unwrap(), Some(2));
+        assert_eq!(queue.next(1), Some(3));
+    }
+}
@@ -15,3 +15,73 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //mod map_tests;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::{
+    database::database_engine::InMemGameDabase,
+    game_state::{
+        blocks::BlockTypeManager,
+        game_behaviors::GameBehaviors,
+        game_map::MapChunk,
+        items::ItemManager,
+        mapgen::{MapgenContext, MapgenStage, MapgenStageHandler, MapgenStageProvider},
+        world_metadata::WorldOptions,
+        GameState,
+    },
+    media::MediaManager,
+    network_server::movement_validation::{MovementValidationMode, MovementValidationSettings},
+};
+
+/// Creates a game with the given blocks and mapgen stages, stored in an in-memory database.
+/// Must be called from within a tokio runtime.
+pub(crate) fn make_game_state(
+    blocks: BlockTypeManager,
+    mapgen_stages: Vec<(MapgenStage, &str, MapgenStageProvider)>,
+) -> Arc<GameState> {
+    GameState::new(
+        Arc::new(InMemGameDabase::new()),
+        Arc::new(blocks),
+        ItemManager::new(),
+        MediaManager::new(),
+        mapgen_stages
+            .into_iter()
+            .map(|(stage, name, provider)| (stage, name.to_string(), provider))
+            .collect(),
+        GameBehaviors::default(),
+        WorldOptions {
+            seed: Some(1234),
+            game_name: "test_game".to_string(),
+            game_version: "1.0.0".to_string(),
+            mapgen_name: "test:mapgen".to_string(),
+            allow_mapgen_mismatch: false,
+        },
+        MovementValidationSettings {
+            mode: MovementValidationMode::Off,
+            allow_flight: true,
+            log_interval: 1,
+        },
+        8,
+    )
+    .unwrap()
+}
+
+/// A mapgen stage that leaves every chunk as it is, i.e. filled with block 0.
+pub(crate) struct EmptyMapgen;
+impl MapgenStageHandler for EmptyMapgen {
+    fn generate(&self, _context: &MapgenContext<'_>, _chunk: &mut MapChunk) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates a game with no blocks, whose map is generated by [EmptyMapgen].
+/// Must be called from within a tokio runtime.
+pub(crate) fn make_empty_game_state() -> Arc<GameState> {
+    let mapgen: MapgenStageProvider = Box::new(|_, _| Ok(Box::new(EmptyMapgen)));
+    make_game_state(
+        BlockTypeManager::new(),
+        vec![(MapgenStage::Terrain, "test:empty", mapgen)],
+    )
+}
@@ -19,7 +19,7 @@ use std::{sync::Arc};
 use anyhow::Context;
 use cuberef_core::{
     constants::{block_groups::DEFAULT_SOLID, items::default_item_interaction_rules},
-    coordinates::{BlockCoordinate, ChunkCoordinate, ChunkOffset},
+    coordinates::{BlockCoordinate, ChunkOffset},
     protocol::{blocks::CubeRenderMode, render::TextureReference},
     protocol::{
         blocks::{
@@ -36,7 +36,7 @@ use crate::{
         blocks::{BlockType, BlockTypeManager},
         game_map::{CasOutcome, MapChunk},
         items::{DigResult, ItemStack},
-        mapgen::MapgenInterface,
+        mapgen::{MapgenContext, MapgenStageHandler},
         GameState,
     },
     server::ServerBuilder,
@@ -48,8 +48,9 @@ pub struct FakeMapgen {
     pub block_type_manager: Arc<BlockTypeManager>,
 }
 
-impl MapgenInterface for FakeMapgen {
-    fn fill_chunk(&self, coord: ChunkCoordinate, chunk: &mut MapChunk) {
+impl MapgenStageHandler for FakeMapgen {
+    fn generate(&self, context: &MapgenContext<'_>, chunk: &mut MapChunk) -> anyhow::Result<()> {
+        let coord = context.coord();
         let grass = self
             .block_type_manager
             .make_block_name("test:grass".to_string());
@@ -85,12 +86,14 @@ impl MapgenInterface for FakeMapgen {
                 }
             }
         }
+        Ok(())
     }
 }
 
 fn testonly_make_tex(name: &str) -> Option<TextureReference> {
     Some(TextureReference {
         texture_name: name.to_string(),
+        ..Default::default()
     })
 }
 
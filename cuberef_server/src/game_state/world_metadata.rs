@@ -0,0 +1,310 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use cuberef_core::protocol::world as world_proto;
+use integer_encoding::VarInt;
+use log::{info, warn};
+use prost::Message;
+
+use crate::database::database_engine::{GameDatabase, KeySpace};
+
+use super::mapgen::MapgenStage;
+
+const WORLD_METADATA_KEY: &[u8] = b"world_metadata";
+// Worlds created before the metadata record existed only stored their seed, under this key
+const LEGACY_SEED_KEY: &[u8] = b"seed_mapgen_seed";
+
+/// Information about how a world was created.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorldMetadata {
+    /// The seed used by the map generator
+    pub seed: u32,
+    /// When the world was created, if known
+    pub creation_time: Option<SystemTime>,
+    /// The name of the game content crate that created the world
+    pub game_name: String,
+    /// The version of the game content crate that created the world
+    pub game_version: String,
+    /// The name of the map generator used for the world
+    pub mapgen_name: String,
+}
+impl WorldMetadata {
+    fn to_proto(&self) -> world_proto::WorldMetadata {
+        world_proto::WorldMetadata {
+            seed: self.seed,
+            creation_time: self
+                .creation_time
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+            game_name: self.game_name.clone(),
+            game_version: self.game_version.clone(),
+            mapgen_name: self.mapgen_name.clone(),
+        }
+    }
+
+    fn from_proto(proto: world_proto::WorldMetadata) -> WorldMetadata {
+        WorldMetadata {
+            seed: proto.seed,
+            creation_time: match proto.creation_time {
+                0 => None,
+                x => Some(UNIX_EPOCH + Duration::from_secs(x)),
+            },
+            game_name: proto.game_name,
+            game_version: proto.game_version,
+            mapgen_name: proto.mapgen_name,
+        }
+    }
+}
+
+/// Settings from the game and the command line that affect world creation
+/// and the checks done when loading an existing world.
+pub(crate) struct WorldOptions {
+    pub(crate) seed: Option<u32>,
+    pub(crate) game_name: String,
+    pub(crate) game_version: String,
+    pub(crate) mapgen_name: String,
+    pub(crate) allow_mapgen_mismatch: bool,
+}
+
+/// Returns the mapgen name to record for a game that didn't set one: the name of the stage that
+/// runs first, i.e. the first terrain stage that was added. Later stages don't change the name.
+pub(crate) fn base_mapgen_name<'a>(
+    stages: impl IntoIterator<Item = (MapgenStage, &'a str)>,
+) -> String {
+    let mut base: Option<(MapgenStage, &str)> = None;
+    for (stage, name) in stages {
+        if base.map_or(true, |(base_stage, _)| stage < base_stage) {
+            base = Some((stage, name));
+        }
+    }
+    base.map(|(_, name)| name.to_string()).unwrap_or_default()
+}
+
+/// Loads the metadata for the world in the given database, creating it if this is a new world.
+pub(crate) fn load_or_create(
+    db: &dyn GameDatabase,
+    options: &WorldOptions,
+) -> Result<WorldMetadata> {
+    let key = KeySpace::Metadata.make_key(WORLD_METADATA_KEY);
+    if let Some(bytes) = db.get(&key)? {
+        let metadata = WorldMetadata::from_proto(
+            world_proto::WorldMetadata::decode(bytes.as_slice())
+                .with_context(|| "Failed to decode world metadata")?,
+        );
+        check_existing(&metadata, options)?;
+        info!(
+            "Loaded world created by {} {} with mapgen {}, seed {}",
+            metadata.game_name, metadata.game_version, metadata.mapgen_name, metadata.seed
+        );
+        return Ok(metadata);
+    }
+
+    let metadata = match load_legacy_seed(db)? {
+        Some(seed) => {
+            // The world exists, but predates the metadata record. Assume it was created by
+            // the current game and mapgen, since there's no way to tell otherwise.
+            let metadata = WorldMetadata {
+                seed,
+                creation_time: None,
+                game_name: options.game_name.clone(),
+                game_version: options.game_version.clone(),
+                mapgen_name: options.mapgen_name.clone(),
+            };
+            check_existing(&metadata, options)?;
+            info!("Adding metadata record to existing world with seed {seed}");
+            metadata
+        }
+        None => {
+            let seed = options.seed.unwrap_or_else(rand::random);
+            info!(
+                "Creating new world with mapgen {}, seed {}",
+                options.mapgen_name, seed
+            );
+            WorldMetadata {
+                seed,
+                creation_time: Some(SystemTime::now()),
+                game_name: options.game_name.clone(),
+                game_version: options.game_version.clone(),
+                mapgen_name: options.mapgen_name.clone(),
+            }
+        }
+    };
+    db.put(&key, &metadata.to_proto().encode_to_vec())?;
+    Ok(metadata)
+}
+
+fn check_existing(metadata: &WorldMetadata, options: &WorldOptions) -> Result<()> {
+    if let Some(seed) = options.seed {
+        if seed != metadata.seed {
+            bail!(
+                "--seed {} was given, but this world was already created with seed {}. The seed can only be set when a world is created.",
+                seed,
+                metadata.seed
+            );
+        }
+    }
+    if metadata.mapgen_name != options.mapgen_name {
+        if options.allow_mapgen_mismatch {
+            warn!(
+                "World was created with mapgen {}, but the game is using mapgen {}. Continuing anyway since --allow-mapgen-mismatch was given.",
+                metadata.mapgen_name, options.mapgen_name
+            );
+        } else {
+            bail!(
+                "World was created with mapgen {}, but the game is using mapgen {}. This will lead to inconsistent terrain. Pass --allow-mapgen-mismatch to start anyway.",
+                metadata.mapgen_name,
+                options.mapgen_name
+            );
+        }
+    }
+    if metadata.game_name != options.game_name || metadata.game_version != options.game_version {
+        info!(
+            "World was created by {} {}; now running {} {}",
+            metadata.game_name, metadata.game_version, options.game_name, options.game_version
+        );
+    }
+    Ok(())
+}
+
+fn load_legacy_seed(db: &dyn GameDatabase) -> Result<Option<u32>> {
+    match db.get(&KeySpace::Metadata.make_key(LEGACY_SEED_KEY))? {
+        Some(x) => match u32::decode_var(&x) {
+            Some((seed, _)) => Ok(Some(seed)),
+            None => bail!("Decoding varint for legacy mapgen seed failed"),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::database_engine::InMemGameDabase;
+
+    use super::*;
+
+    fn options(seed: Option<u32>, mapgen_name: &str) -> WorldOptions {
+        WorldOptions {
+            seed,
+            game_name: "test_game".to_string(),
+            game_version: "1.0.0".to_string(),
+            mapgen_name: mapgen_name.to_string(),
+            allow_mapgen_mismatch: false,
+        }
+    }
+
+    #[test]
+    fn test_base_mapgen_name() {
+        assert_eq!(
+            base_mapgen_name([
+                (MapgenStage::Decorations, "test:trees"),
+                (MapgenStage::Terrain, "test:terrain"),
+                (MapgenStage::Terrain, "test:more_terrain"),
+                (MapgenStage::Carving, "test:caves"),
+            ]),
+            "test:terrain"
+        );
+        assert_eq!(
+            base_mapgen_name([(MapgenStage::Ores, "test:ores")]),
+            "test:ores"
+        );
+        assert_eq!(base_mapgen_name(Vec::<(MapgenStage, &str)>::new()), "");
+    }
+
+    #[test]
+    fn test_create_and_reload() {
+        let db = InMemGameDabase::new();
+        let created = load_or_create(&db, &options(Some(1234), "test:terrain")).unwrap();
+        assert_eq!(created.seed, 1234);
+        assert_eq!(created.mapgen_name, "test:terrain");
+        assert_eq!(created.game_name, "test_game");
+        assert!(created.creation_time.is_some());
+
+        // The seed may be omitted once the world exists
+        let loaded = load_or_create(&db, &options(None, "test:terrain")).unwrap();
+        assert_eq!(loaded.seed, 1234);
+        // Creation time is stored with a resolution of seconds
+        let secs = |x: &WorldMetadata| x.creation_time.unwrap().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(secs(&loaded).as_secs(), secs(&created).as_secs());
+        assert!(load_or_create(&db, &options(Some(1234), "test:terrain")).is_ok());
+    }
+
+    #[test]
+    fn test_seed_mismatch() {
+        let db = InMemGameDabase::new();
+        load_or_create(&db, &options(Some(1234), "test:terrain")).unwrap();
+        let err = load_or_create(&db, &options(Some(5678), "test:terrain")).unwrap_err();
+        assert!(err.to_string().contains("seed 1234"), "{err}");
+    }
+
+    #[test]
+    fn test_mapgen_mismatch() {
+        let db = InMemGameDabase::new();
+        load_or_create(&db, &options(Some(1234), "test:terrain")).unwrap();
+        let err = load_or_create(&db, &options(None, "test:other")).unwrap_err();
+        assert!(err.to_string().contains("--allow-mapgen-mismatch"), "{err}");
+
+        let allowed = WorldOptions {
+            allow_mapgen_mismatch: true,
+            ..options(None, "test:other")
+        };
+        // The world keeps the mapgen it was created with
+        assert_eq!(
+            load_or_create(&db, &allowed).unwrap().mapgen_name,
+            "test:terrain"
+        );
+    }
+
+    #[test]
+    fn test_game_version_change() {
+        let db = InMemGameDabase::new();
+        load_or_create(&db, &options(Some(1234), "test:terrain")).unwrap();
+        let upgraded = WorldOptions {
+            game_version: "2.0.0".to_string(),
+            ..options(None, "test:terrain")
+        };
+        // The game that created the world is kept, and a different version isn't an error
+        assert_eq!(
+            load_or_create(&db, &upgraded).unwrap().game_version,
+            "1.0.0"
+        );
+    }
+
+    #[test]
+    fn test_legacy_seed() {
+        let db = InMemGameDabase::new();
+        db.put(
+            &KeySpace::Metadata.make_key(LEGACY_SEED_KEY),
+            &4321u32.encode_var_vec(),
+        )
+        .unwrap();
+        let metadata = load_or_create(&db, &options(None, "test:terrain")).unwrap();
+        assert_eq!(metadata.seed, 4321);
+        assert_eq!(metadata.creation_time, None);
+        assert_eq!(metadata.mapgen_name, "test:terrain");
+        // A legacy world can't be given a different seed either
+        let db = InMemGameDabase::new();
+        db.put(
+            &KeySpace::Metadata.make_key(LEGACY_SEED_KEY),
+            &4321u32.encode_var_vec(),
+        )
+        .unwrap();
+        assert!(load_or_create(&db, &options(Some(1), "test:terrain")).is_err());
+    }
+}
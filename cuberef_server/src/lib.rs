@@ -15,6 +15,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+mod admin_console;
 mod database;
 pub mod game_state;
 pub mod media;
@@ -14,6 +14,8 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::iter::once;
 use std::sync::atomic::AtomicUsize;
@@ -22,13 +24,19 @@ use std::time::Instant;
 
 use crate::game_state::blocks;
 use crate::game_state::blocks::BlockType;
+use crate::game_state::client_ui::popup_update_channel;
+use crate::game_state::client_ui::Popup;
 use crate::game_state::client_ui::PopupAction;
 use crate::game_state::client_ui::PopupResponse;
+use crate::game_state::client_ui::PopupUpdateEvent;
+use crate::game_state::client_ui::PopupUpdateReceiver;
+use crate::game_state::client_ui::PopupUpdateSender;
 use crate::game_state::event::EventInitiator;
 use crate::game_state::event::HandlerContext;
 
 use crate::game_state::game_map::BlockUpdate;
 use crate::game_state::handlers;
+use crate::game_state::handlers::HandlerError;
 use crate::game_state::inventory::InventoryKey;
 use crate::game_state::inventory::UpdatedInventory;
 use crate::game_state::inventory::InventoryViewWithContext;
@@ -38,6 +46,11 @@ use crate::game_state::items::DigResult;
 use crate::game_state::items::Item;
 use crate::game_state::player::PlayerContext;
 use crate::game_state::GameState;
+use crate::network_server::interaction_validation;
+use crate::network_server::interaction_validation::DigTracker;
+use crate::network_server::interaction_validation::InteractionViolation;
+use crate::network_server::movement_validation::MovementCheck;
+use crate::network_server::movement_validation::MovementValidator;
 use crate::run_handler;
 
 use anyhow::bail;
@@ -45,6 +58,7 @@ use anyhow::Context;
 use anyhow::Result;
 use cgmath::Vector3;
 use cgmath::Zero;
+use cuberef_core::constants::chunks::MAX_CLIENT_CHUNK_CACHE_SIZE;
 use cuberef_core::coordinates::{BlockCoordinate, ChunkCoordinate, PlayerPositionUpdate};
 
 use cuberef_core::protocol::coordinates::Angles;
@@ -53,6 +67,10 @@ use cuberef_core::protocol::game_rpc::stream_to_client::ServerMessage;
 use cuberef_core::protocol::game_rpc::MapDeltaUpdateBatch;
 use cuberef_core::protocol::game_rpc::PositionUpdate;
 use cuberef_core::protocol::game_rpc::StreamToClient;
+use cuberef_core::protocol::ui::HudElement;
+use cuberef_core::protocol::ui::Notification;
+use cuberef_core::protocol::ui::NotificationSeverity;
+use cuberef_core::protocol::ui::SetHudElement;
 use itertools::iproduct;
 use log::error;
 use log::info;
@@ -79,9 +97,11 @@ pub(crate) async fn make_client_contexts(
         velocity: cgmath::Vector3::zero(),
         face_direction: (0., 0.),
     };
+    let initial_view = ViewSettings::new(game_state.max_view_distance(), 0, 0);
     let (pos_send, pos_recv) = watch::channel(PositionAndPacing {
         position: initial_position,
         chunks_to_send: 16,
+        view: initial_view,
     });
 
     let cancellation = CancellationToken::new();
@@ -89,7 +109,19 @@ pub(crate) async fn make_client_contexts(
     let mut interested_inventories = HashSet::new();
     interested_inventories.insert(player_context.main_inventory());
 
+    let movement_validator = MovementValidator::new(
+        &game_state,
+        game_state.movement_validation().clone(),
+        player_context.name().to_string(),
+        initial_position.position,
+    );
     let player_context = Arc::new(player_context);
+    let (popup_update_sender, popup_updates) = popup_update_channel();
+    player_context
+        .state
+        .lock()
+        .inventory_popup
+        .show_to(popup_update_sender.clone());
 
     let inbound = ClientInboundContext {
         context_id: id,
@@ -100,6 +132,11 @@ pub(crate) async fn make_client_contexts(
         own_positions: pos_send,
         outbound_tx: outbound_tx.clone(),
         next_pos_writeback: Instant::now(),
+        movement_validator,
+        dig_tracker: DigTracker::default(),
+        notification_limiter: NotificationLimiter::new(),
+        popup_update_sender,
+        view: initial_view,
         chunk_pacing: Aimd {
             val: INITIAL_CHUNKS_PER_UPDATE as f64,
             floor: 0.,
@@ -110,6 +147,7 @@ pub(crate) async fn make_client_contexts(
     };
     let block_events = game_state.map().subscribe();
     let inventory_events = game_state.inventory_manager().subscribe();
+    let hud_changes = player_context.hud_changed.subscribe();
 
     let outbound = ClientOutboundContext {
         context_id: id,
@@ -119,10 +157,16 @@ pub(crate) async fn make_client_contexts(
         cancellation,
         block_events,
         inventory_events,
+        popup_updates,
+        hud_changes,
         own_positions: pos_recv,
         interested_chunks: HashSet::new(),
         interested_inventories,
         chunks_known_to_client: HashSet::new(),
+        chunks_cached_by_client: ChunkLru::new(initial_view.chunk_cache_size),
+        load_coords: Arc::new(chunk_offsets_within(initial_view.view_distance)),
+        load_coords_distance: initial_view.view_distance,
+        hud_elements_known_to_client: HashMap::new(),
     };
     Ok((inbound, outbound))
 }
@@ -145,6 +189,10 @@ pub(crate) struct ClientOutboundContext {
     block_events: broadcast::Receiver<BlockUpdate>,
     // TODO consider delta updates for this
     inventory_events: broadcast::Receiver<UpdatedInventory>,
+    // Changes to elements of this player's open popups
+    popup_updates: PopupUpdateReceiver,
+    // Notified when the player's HUD elements change
+    hud_changes: watch::Receiver<()>,
     // This character's own movement, coming from their client (forwarded by the ClientInboundContext to here
     // and to elsewhere)
     // In the future, anticheat might check for shenanigans involving these, probably not as part of ClientOutboundContext
@@ -160,8 +208,17 @@ pub(crate) struct ClientOutboundContext {
     // The client should have these chunks cached already. If a chunk is missing from this set
     // we'll need to send the full chunk to the client first.
     chunks_known_to_client: HashSet<ChunkCoordinate>,
+    // Chunks that went out of view, but that the client keeps cached until we evict them. Their
+    // block updates are still sent, and they're moved back into chunks_known_to_client (without
+    // being sent again) if they come back into view.
+    chunks_cached_by_client: ChunkLru,
+    // Offsets of the chunks within view of the player, nearest first, for the given distance
+    load_coords: Arc<Vec<(i32, i32, i32)>>,
+    load_coords_distance: i32,
 
     interested_inventories: HashSet<InventoryKey>,
+    // The HUD elements last sent to the client, by ID
+    hud_elements_known_to_client: HashMap<u64, HudElement>,
 }
 impl ClientOutboundContext {
     // Poll for world events and send relevant messages to the client through outbound_tx
@@ -176,6 +233,13 @@ impl ClientOutboundContext {
                 inv_key = self.inventory_events.recv() => {
                     self.handle_inventory_update(inv_key).await?;
                 }
+                popup_update = self.popup_updates.recv() => {
+                    self.handle_popup_element_update(popup_update).await?;
+                }
+                _ = self.hud_changes.changed() => {
+                    self.hud_changes.borrow_and_update();
+                    self.send_hud_changes().await?;
+                }
                 _ = self.own_positions.changed() => {
                     let update = *self.own_positions.borrow_and_update();
                     self.handle_position_update(update).await?;
@@ -282,6 +346,74 @@ impl ClientOutboundContext {
         Ok(())
     }
 
+    async fn handle_popup_element_update(
+        &mut self,
+        update: Option<PopupUpdateEvent>,
+    ) -> Result<()> {
+        let message = match update {
+            Some(PopupUpdateEvent::Update(x)) => x,
+            Some(PopupUpdateEvent::Lagged) => {
+                // Element updates can be replaced by just sending the popups again
+                warn!(
+                    "Client {} missed popup updates, resending popups",
+                    self.context_id
+                );
+                let updates = {
+                    let player_state = self.player_context.state.lock();
+                    player_state
+                        .active_popups
+                        .iter()
+                        .chain(once(&player_state.inventory_popup))
+                        .map(|popup| make_popup_update(&self.game_state, popup))
+                        .collect::<Vec<_>>()
+                };
+                for update in updates {
+                    self.outbound_tx
+                        .send(Ok(update))
+                        .await
+                        .with_context(|| "Could not send outbound message (popup update)")?;
+                }
+                return Ok(());
+            }
+            None => return self.shut_down_connected_client(),
+        };
+        // The popup may have been closed since; the client ignores updates for popups it isn't
+        // showing.
+        self.outbound_tx
+            .send(Ok(StreamToClient {
+                tick: self.game_state.tick(),
+                server_message: Some(ServerMessage::UpdatePopupElements(message)),
+            }))
+            .await
+            .with_context(|| "Could not send outbound message (popup element update)")?;
+        Ok(())
+    }
+
+    // Sends the HUD elements that were added, changed, or removed since the last call
+    async fn send_hud_changes(&mut self) -> Result<()> {
+        let current: HashMap<u64, HudElement> = self
+            .player_context
+            .state
+            .lock()
+            .hud_elements
+            .iter()
+            .map(|(id, element)| (id.0, element.to_proto()))
+            .collect();
+        let messages = hud_changes(&self.hud_elements_known_to_client, &current);
+        self.hud_elements_known_to_client = current;
+
+        for message in messages {
+            self.outbound_tx
+                .send(Ok(StreamToClient {
+                    tick: self.game_state.tick(),
+                    server_message: Some(message),
+                }))
+                .await
+                .with_context(|| "Could not send outbound message (HUD update)")?;
+        }
+        Ok(())
+    }
+
     async fn handle_block_update(
         &mut self,
         update: Result<BlockUpdate, broadcast::error::RecvError>,
@@ -316,16 +448,15 @@ impl ClientOutboundContext {
 
         let mut update_protos = Vec::new();
         for update in updates {
-            if !self
-                .chunks_known_to_client
-                .contains(&update.location.chunk())
+            let chunk = update.location.chunk();
+            if !self.chunks_known_to_client.contains(&chunk)
+                && !self.chunks_cached_by_client.contains(chunk)
             {
                 // The client doesn't know this chunk, but we think it should be interested in it
                 // It was probably not in server cache when it became interesting, and wasn't
                 // interesting enough to load into memory then. However, it's being updated, so we
                 // may as well get it to the client
-                self.maybe_send_full_chunk(update.location.chunk(), true)
-                    .await?;
+                self.maybe_send_full_chunk(chunk, true).await?;
             }
             update_protos.push(proto::MapDeltaUpdate {
                 block_coord: Some(update.location.into()),
@@ -352,6 +483,7 @@ impl ClientOutboundContext {
 
     fn wants_block_update(&self, coord: BlockCoordinate) -> bool {
         self.interested_chunks.contains(&coord.chunk())
+            || self.chunks_cached_by_client.contains(coord.chunk())
     }
 
     async fn handle_block_update_lagged(&mut self) -> Result<()> {
@@ -361,6 +493,12 @@ impl ClientOutboundContext {
         self.block_events.resubscribe();
         self.chunks_known_to_client.clear();
         self.interested_chunks.clear();
+        // Cached chunks missed updates too, and since they're out of view they won't be resent,
+        // so the client needs to drop them.
+        let cached_chunks = self.chunks_cached_by_client.clear();
+        if !cached_chunks.is_empty() {
+            self.send_unsubscribe(&[], &cached_chunks).await?;
+        }
         // TODO back off with the number of chunks to subscribe to
         Ok(())
     }
@@ -409,27 +547,7 @@ impl ClientOutboundContext {
     }
 
     async fn teleport_player(&mut self, location: Vector3<f64>) -> Result<()> {
-        let message = {
-            let player_state = self.player_context.state.lock();
-            StreamToClient {
-                tick: self.game_state.tick(),
-                server_message: Some(proto::stream_to_client::ServerMessage::ClientState(
-                    proto::SetClientState {
-                        position: Some(PositionUpdate {
-                            position: Some(location.try_into()?),
-                            velocity: Some(Vector3::zero().try_into()?),
-                            face_direction: Some(Angles {
-                                deg_azimuth: 0.,
-                                deg_elevation: 0.,
-                            }),
-                        }),
-                        hotbar_inventory_view: player_state.hotbar_inventory_view.id.0,
-                        inventory_popup: Some(player_state.inventory_popup.to_proto()),
-                        inventory_manipulation_view: player_state.inventory_manipulation_view.id.0,
-                    },
-                )),
-            }
-        };
+        let message = make_client_state_update(&self.game_state, &self.player_context, location)?;
         self.outbound_tx
             .send(Ok(message))
             .await
@@ -438,7 +556,7 @@ impl ClientOutboundContext {
 
     async fn handle_position_update(&mut self, update: PositionAndPacing) -> Result<()> {
         let position = update.position;
-        // TODO anticheat/safety checks
+        // Positions were already validated by the inbound context
         // TODO consider caching more in the player's movement direction as a form of prefetch???
         let player_block_coord: BlockCoordinate = match position.position.try_into() {
             Ok(x) => x,
@@ -453,42 +571,52 @@ impl ClientOutboundContext {
             }
         };
         let player_chunk = player_block_coord.chunk();
+        let view = update.view;
+        if view.view_distance != self.load_coords_distance {
+            self.load_coords = Arc::new(chunk_offsets_within(view.view_distance));
+            self.load_coords_distance = view.view_distance;
+        }
+        let unload_distance = view.unload_distance() as u32;
 
         // These chunks are far enough away to unsubscribe, but the client doesn't even know about them yet
         let chunks_to_silently_unsubscribe = self
             .interested_chunks
             .iter()
             .copied()
-            .filter(|&chunk| player_chunk.manhattan_distance(chunk) > UNLOAD_DISTANCE as u32)
+            .filter(|&chunk| player_chunk.l_infinity_norm_distance(chunk) > unload_distance)
             .collect::<Vec<_>>();
 
         for chunk in chunks_to_silently_unsubscribe.iter() {
             self.interested_chunks.remove(chunk);
         }
 
-        // These chunks are far enough away to unsubscribe, and the client knows about them
+        // These chunks are far enough away to unsubscribe, and the client knows about them.
+        // The client moves them into its cache, from which we evict the oldest ones once it's
+        // full.
         let chunks_to_unsubscribe = self
             .chunks_known_to_client
             .iter()
             .copied()
-            .filter(|&chunk| player_chunk.manhattan_distance(chunk) > UNLOAD_DISTANCE as u32)
+            .filter(|&chunk| player_chunk.l_infinity_norm_distance(chunk) > unload_distance)
             .collect::<Vec<_>>();
 
+        let mut chunks_to_evict = self
+            .chunks_cached_by_client
+            .set_capacity(view.chunk_cache_size);
         for chunk in chunks_to_unsubscribe.iter() {
             self.chunks_known_to_client.remove(chunk);
+            chunks_to_evict.extend(self.chunks_cached_by_client.insert(*chunk));
+        }
+        // Sent first, so that evicted chunks that are sent again below aren't dropped
+        if !chunks_to_unsubscribe.is_empty() || !chunks_to_evict.is_empty() {
+            self.send_unsubscribe(&chunks_to_unsubscribe, &chunks_to_evict)
+                .await?;
         }
-
-        let message = proto::StreamToClient {
-            tick: self.game_state.tick(),
-            server_message: Some(proto::stream_to_client::ServerMessage::MapChunkUnsubscribe(
-                proto::MapChunkUnsubscribe {
-                    chunk_coord: chunks_to_unsubscribe.iter().map(|&x| x.into()).collect(),
-                },
-            )),
-        };
 
         let mut sent_chunks = 0;
-        for &(dx, dy, dz) in LOAD_LAZY_SORTED_COORDS.iter() {
+        let mut resubscribed_chunks = vec![];
+        let load_coords = self.load_coords.clone();
+        for &(dx, dy, dz) in load_coords.iter() {
             let coord = ChunkCoordinate {
                 x: player_chunk.x.saturating_add(dx),
                 y: player_chunk.y.saturating_add(dy),
@@ -498,10 +626,16 @@ impl ClientOutboundContext {
             if !coord.is_in_bounds() || self.chunks_known_to_client.contains(&coord) {
                 continue;
             }
+            if self.chunks_cached_by_client.remove(coord) {
+                // Still up to date on the client, which only needs to render it again
+                self.chunks_known_to_client.insert(coord);
+                resubscribed_chunks.push(coord);
+                continue;
+            }
             let chunk_data = tokio::task::block_in_place(|| {
                 self.game_state
                     .map()
-                    .get_chunk_client_proto(coord, distance <= LOAD_EAGER_DISTANCE)
+                    .get_chunk_client_proto(coord, distance <= view.eager_distance())
             })?;
             if let Some(chunk_data) = chunk_data {
                 let message = proto::StreamToClient {
@@ -525,15 +659,45 @@ impl ClientOutboundContext {
             }
         }
 
-        if !chunks_to_unsubscribe.is_empty() {
+        if !resubscribed_chunks.is_empty() {
+            let message = proto::StreamToClient {
+                tick: self.game_state.tick(),
+                server_message: Some(proto::stream_to_client::ServerMessage::MapChunkResubscribe(
+                    proto::MapChunkResubscribe {
+                        chunk_coord: resubscribed_chunks.iter().map(|&x| x.into()).collect(),
+                    },
+                )),
+            };
             self.outbound_tx
                 .send(Ok(message))
                 .await
-                .with_context(|| "Could not send outbound message (chunk unsubscribe)")?;
+                .with_context(|| "Could not send outbound message (chunk resubscribe)")?;
         }
         Ok(())
     }
 
+    // Tells the client to move chunks that went out of view into its cache, and to drop evicted
+    // chunks from its cache
+    async fn send_unsubscribe(
+        &mut self,
+        chunks: &[ChunkCoordinate],
+        evicted_chunks: &[ChunkCoordinate],
+    ) -> Result<()> {
+        let message = proto::StreamToClient {
+            tick: self.game_state.tick(),
+            server_message: Some(proto::stream_to_client::ServerMessage::MapChunkUnsubscribe(
+                proto::MapChunkUnsubscribe {
+                    chunk_coord: chunks.iter().map(|&x| x.into()).collect(),
+                    evicted_chunk_coord: evicted_chunks.iter().map(|&x| x.into()).collect(),
+                },
+            )),
+        };
+        self.outbound_tx
+            .send(Ok(message))
+            .await
+            .with_context(|| "Could not send outbound message (chunk unsubscribe)")
+    }
+
     async fn initialize_outbound_loop(&mut self) -> Result<()> {
         let initial_position = PlayerPositionUpdate {
             tick: self.game_state.tick(),
@@ -557,32 +721,18 @@ impl ClientOutboundContext {
         self.outbound_tx.send(Ok(inv_manipulation_update)).await?;
         self.send_popup_updates().await?;
 
-        let message = {
-            let player_state = self.player_context.state.lock();
-            StreamToClient {
-                tick: self.game_state.tick(),
-                server_message: Some(proto::stream_to_client::ServerMessage::ClientState(
-                    proto::SetClientState {
-                        position: Some(PositionUpdate {
-                            position: Some(initial_position.position.try_into()?),
-                            velocity: Some(Vector3::zero().try_into()?),
-                            face_direction: Some(Angles {
-                                deg_azimuth: 0.,
-                                deg_elevation: 0.,
-                            }),
-                        }),
-                        // TODO pull these out (or better yet, the whole proto) into a player_state method
-                        hotbar_inventory_view: player_state.hotbar_inventory_view.id.0,
-                        inventory_popup: Some(player_state.inventory_popup.to_proto()),
-                        inventory_manipulation_view: player_state.inventory_manipulation_view.id.0,
-                    },
-                )),
-            }
-        };
+        let message = make_client_state_update(
+            &self.game_state,
+            &self.player_context,
+            initial_position.position,
+        )?;
         self.outbound_tx
             .send(Ok(message))
             .await
             .with_context(|| "Could not send outbound message (initial state)")?;
+
+        self.hud_changes.borrow_and_update();
+        self.send_hud_changes().await?;
         Ok(())
     }
 }
@@ -611,6 +761,16 @@ pub(crate) struct ClientInboundContext {
     // The client's self-reported position
     own_positions: watch::Sender<PositionAndPacing>,
     next_pos_writeback: Instant,
+    // Checks the client's self-reported positions before they're used
+    movement_validator: MovementValidator,
+    // The block the player is digging, and when they started
+    dig_tracker: DigTracker,
+    // Limits how many handler errors are shown to the player
+    notification_limiter: NotificationLimiter,
+    // Given to popups shown to the player, so that changes to them reach the outbound context
+    popup_update_sender: PopupUpdateSender,
+    // The view distance and chunk cache size the client last asked for, capped by the server
+    view: ViewSettings,
 
     chunk_pacing: Aimd,
 }
@@ -634,7 +794,7 @@ impl ClientInboundContext {
                                 Ok(_) => {},
                                 Err(e) => {
                                     warn!("Client {} failed to handle message: {:?}, error: {:?}", self.context_id, message, e);
-                                    // TODO notify the client once there's a chat or server->client error handling message
+                                    self.report_handler_error(&e).await;
                                 },
                             }
                         }
@@ -650,6 +810,31 @@ impl ClientInboundContext {
         Ok(())
     }
 
+    // If the error came from a game content handler that ran for the player's own action, shows
+    // it to the player (unless they've already been shown too many recently).
+    async fn report_handler_error(&mut self, error: &anyhow::Error) {
+        let handler_error = match HandlerError::find_for_player(error, self.player_context.name()) {
+            Some(x) => x,
+            None => return,
+        };
+        if !self.notification_limiter.try_acquire() {
+            return;
+        }
+        let message = StreamToClient {
+            tick: self.game_state.tick(),
+            server_message: Some(ServerMessage::Notification(Notification {
+                severity: NotificationSeverity::Error.into(),
+                message: handler_error.user_message(),
+            })),
+        };
+        if let Err(e) = self.outbound_tx.send(Ok(message)).await {
+            warn!(
+                "Client {} couldn't be sent an error notification: {:?}",
+                self.context_id, e
+            );
+        }
+    }
+
     async fn handle_message(&mut self, message: &proto::StreamToServer) -> Result<()> {
         // todo do something with the client tick once we define ticks
         match &message.client_message {
@@ -660,21 +845,25 @@ impl ClientInboundContext {
                 );
             }
             Some(proto::stream_to_server::ClientMessage::Dig(dig_message)) => {
-                // TODO check whether the current item can dig this block, and whether
-                // it's been long enough since the last dig
                 let coord: BlockCoordinate = dig_message
                     .block_coord
                     .as_ref()
                     .map(|x| x.into())
                     .with_context(|| "Missing block_coord")?;
-                self.run_map_handlers(
-                    coord,
-                    dig_message.item_slot,
-                    |item| item.dig_handler.as_deref(),
-                    |block| block.dig_handler_inline.as_deref(),
-                    |block| block.dig_handler_full.as_deref(),
-                )
-                .await?;
+                let check = block_in_place(|| self.check_dig(coord, dig_message.item_slot))?;
+                if let Err(violation) = check {
+                    self.reject_interaction("dig", violation, &[coord]).await?;
+                } else {
+                    self.run_map_handlers(
+                        coord,
+                        dig_message.item_slot,
+                        true,
+                        |item| item.dig_handler.as_deref(),
+                        |block| block.dig_handler_inline.as_deref(),
+                        |block| block.dig_handler_full.as_deref(),
+                    )
+                    .await?;
+                }
             }
             Some(proto::stream_to_server::ClientMessage::Tap(tap_message)) => {
                 let coord: BlockCoordinate = tap_message
@@ -682,17 +871,43 @@ impl ClientInboundContext {
                     .as_ref()
                     .map(|x| x.into())
                     .with_context(|| "Missing block_coord")?;
-                self.run_map_handlers(
+                let check = interaction_validation::check_reach(
+                    self.player_context.last_position().position,
+                    coord,
+                );
+                if let Err(violation) = check {
+                    self.reject_interaction("tap", violation, &[coord]).await?;
+                } else {
+                    self.run_map_handlers(
+                        coord,
+                        tap_message.item_slot,
+                        false,
+                        |item| item.tap_handler.as_deref(),
+                        |block| block.tap_handler_inline.as_deref(),
+                        |block| block.tap_handler_full.as_deref(),
+                    )
+                    .await?;
+                }
+            }
+            Some(proto::stream_to_server::ClientMessage::DigStart(dig_start_message)) => {
+                let coord: BlockCoordinate = dig_start_message
+                    .block_coord
+                    .as_ref()
+                    .map(|x| x.into())
+                    .with_context(|| "Missing block_coord")?;
+                // Out-of-reach digs are refused when they finish; there's nothing to undo here.
+                if interaction_validation::check_reach(
+                    self.player_context.last_position().position,
                     coord,
-                    tap_message.item_slot,
-                    |item| item.tap_handler.as_deref(),
-                    |block| block.tap_handler_inline.as_deref(),
-                    |block| block.tap_handler_full.as_deref(),
                 )
-                .await?;
+                .is_ok()
+                {
+                    self.dig_tracker.start_dig(coord);
+                }
             }
             Some(proto::stream_to_server::ClientMessage::PositionUpdate(pos_update)) => {
-                self.handle_pos_update(message.client_tick, pos_update)?;
+                self.handle_pos_update(message.client_tick, pos_update)
+                    .await?;
             }
             Some(proto::stream_to_server::ClientMessage::BugCheck(bug_check)) => {
                 error!("Client bug check: {:?}", bug_check);
@@ -723,10 +938,111 @@ impl ClientInboundContext {
         Ok(())
     }
 
+    // Checks that the item in the given slot can dig the block, that the block is within reach,
+    // and that the player has been digging it for long enough.
+    fn check_dig(
+        &mut self,
+        coord: BlockCoordinate,
+        item_slot: u32,
+    ) -> Result<std::result::Result<(), InteractionViolation>> {
+        if let Err(violation) =
+            interaction_validation::check_reach(self.player_context.last_position().position, coord)
+        {
+            return Ok(Err(violation));
+        }
+        let block = self.game_state.map().get_block(coord)?;
+        let (block_type, _) = self
+            .game_state
+            .map()
+            .block_type_manager()
+            .get_block(&block)?;
+        let inventory = self
+            .game_state
+            .inventory_manager()
+            .get(&self.player_context.main_inventory())?
+            .with_context(|| "Player's main inventory is missing")?;
+        let item = inventory
+            .contents()
+            .get(item_slot as usize)
+            .with_context(|| "Item slot was out of bounds")?
+            .as_ref()
+            .and_then(|x| self.game_state.item_manager().from_stack(x));
+        let behavior = interaction_validation::dig_behavior(
+            item.map(|x| x.proto.interaction_rules.as_slice()),
+            &block_type.client_info,
+        );
+        let required =
+            match interaction_validation::dig_time(behavior.as_ref(), &block_type.client_info) {
+                Some(x) => x,
+                None => return Ok(Err(InteractionViolation::NotDiggable)),
+            };
+        Ok(self.dig_tracker.finish_dig(coord, required))
+    }
+
+    // Logs a refused dig/tap/place, and sends the client the actual state of the affected
+    // blocks and its hotbar, in case it already assumed the action succeeded.
+    async fn reject_interaction(
+        &mut self,
+        action: &str,
+        violation: InteractionViolation,
+        coords: &[BlockCoordinate],
+    ) -> Result<()> {
+        warn!(
+            "Player {} attempted an invalid {} at {:?}: {:?}",
+            self.player_context.name(),
+            action,
+            coords,
+            violation
+        );
+        let messages = block_in_place(|| -> Result<_> {
+            let player_chunk =
+                BlockCoordinate::try_from(self.player_context.last_position().position)
+                    .ok()
+                    .map(|x| x.chunk());
+            let mut updates = vec![];
+            for &coord in coords {
+                // The client only has chunks close to it; it would complain about updates
+                // to any others.
+                let nearby = player_chunk.map_or(false, |x| {
+                    x.manhattan_distance(coord.chunk()) <= self.view.eager_distance() as u32
+                });
+                if !nearby || !coord.chunk().is_in_bounds() {
+                    continue;
+                }
+                updates.push(proto::MapDeltaUpdate {
+                    block_coord: Some(coord.into()),
+                    new_id: self.game_state.map().get_block(coord)?.id().into(),
+                });
+            }
+            let mut messages = vec![];
+            if !updates.is_empty() {
+                messages.push(StreamToClient {
+                    tick: self.game_state.tick(),
+                    server_message: Some(ServerMessage::MapDeltaUpdate(MapDeltaUpdateBatch {
+                        updates,
+                    })),
+                });
+            }
+            messages.push(make_inventory_update(
+                &self.game_state,
+                &&self.player_context.state.lock().hotbar_inventory_view,
+            )?);
+            Ok(messages)
+        })?;
+        for message in messages {
+            self.outbound_tx
+                .send(Ok(message))
+                .await
+                .with_context(|| "Could not send outbound message (interaction rollback)")?;
+        }
+        Ok(())
+    }
+
     async fn run_map_handlers<F, G, H>(
         &mut self,
         coord: BlockCoordinate,
         selected_inv_slot: u32,
+        apply_tool_wear: bool,
         get_item_handler: F,
         get_block_inline_handler: G,
         get_block_full_handler: H,
@@ -739,6 +1055,7 @@ impl ClientInboundContext {
         tokio::task::block_in_place(|| {
             self.map_handler_sync(
                 selected_inv_slot,
+                apply_tool_wear,
                 get_item_handler,
                 coord,
                 get_block_inline_handler,
@@ -750,6 +1067,7 @@ impl ClientInboundContext {
     fn map_handler_sync<F, G, H>(
         &mut self,
         selected_inv_slot: u32,
+        apply_tool_wear: bool,
         get_item_handler: F,
         coord: BlockCoordinate,
         get_block_inline_handler: G,
@@ -770,10 +1088,10 @@ impl ClientInboundContext {
 
                 let initiator = EventInitiator::Player(&self.player_context);
 
-                let item_dig_handler = stack
+                let item = stack
                     .as_ref()
-                    .and_then(|x| self.game_state.item_manager().from_stack(x))
-                    .and_then(get_item_handler);
+                    .and_then(|x| self.game_state.item_manager().from_stack(x));
+                let item_dig_handler = item.and_then(get_item_handler);
 
                 let result = if let Some(handler) = item_dig_handler {
                     let ctx = HandlerContext {
@@ -794,6 +1112,20 @@ impl ClientInboundContext {
                         initiator.clone(),
                     )?
                 } else {
+                    // The wear depends on the block, so look it up before the block is dug
+                    let wear_and_block = match item {
+                        Some(item) if apply_tool_wear => {
+                            let block = self.game_state.map().get_block(coord)?;
+                            let (block_type, _) = self
+                                .game_state
+                                .map()
+                                .block_type_manager()
+                                .get_block(&block)?;
+                            item.interaction_rule_for(&block_type.client_info)
+                                .map(|rule| (rule.tool_wear, block))
+                        }
+                        _ => None,
+                    };
                     // This is blocking code, not async code (because of the mutex ops)
                     let obtained_items = self.game_state.map().run_block_interaction(
                         coord,
@@ -802,8 +1134,19 @@ impl ClientInboundContext {
                         get_block_inline_handler,
                         get_block_full_handler,
                     )?;
+                    // Only wear the tool if the dig actually changed the block (e.g. the block's
+                    // handler may have refused to remove it)
+                    let wear = match wear_and_block {
+                        Some((wear, block)) if self.game_state.map().get_block(coord)? != block => {
+                            wear
+                        }
+                        _ => 0,
+                    };
                     DigResult {
-                        updated_tool: stack.clone(),
+                        updated_tool: match (stack.as_ref(), item) {
+                            (Some(stack), Some(item)) => stack.apply_wear(item, wear),
+                            _ => stack.clone(),
+                        },
                         obtained_items,
                     }
                 };
@@ -841,7 +1184,7 @@ impl ClientInboundContext {
         Ok(())
     }
 
-    fn handle_pos_update(&mut self, tick: u64, update: &proto::ClientUpdate) -> Result<()> {
+    async fn handle_pos_update(&mut self, tick: u64, update: &proto::ClientUpdate) -> Result<()> {
         match &update.position {
             Some(pos_update) => {
                 let (az, el) = match &pos_update.face_direction {
@@ -866,6 +1209,27 @@ impl ClientInboundContext {
                     face_direction: (az, el),
                 };
 
+                let check = block_in_place(|| {
+                    self.movement_validator
+                        .check(&self.game_state, pos.position)
+                })?;
+                match check {
+                    MovementCheck::Accepted => {}
+                    MovementCheck::Rejected(reset_position) => {
+                        let message = make_client_state_update(
+                            &self.game_state,
+                            &self.player_context,
+                            reset_position,
+                        )?;
+                        self.outbound_tx
+                            .send(Ok(message))
+                            .await
+                            .with_context(|| "Could not send outbound message (movement reset)")?;
+                        return Ok(());
+                    }
+                    MovementCheck::AwaitingReset => return Ok(()),
+                }
+
                 if let Some(pacing) = &update.pacing {
                     if pacing.pending_chunks < 16 {
                         self.chunk_pacing.increase();
@@ -874,9 +1238,15 @@ impl ClientInboundContext {
                     }
                 }
 
+                self.view = ViewSettings::new(
+                    self.game_state.max_view_distance(),
+                    update.view_distance,
+                    update.chunk_cache_size,
+                );
                 self.own_positions.send_replace(PositionAndPacing {
                     position: pos,
                     chunks_to_send: self.chunk_pacing.get(),
+                    view: self.view,
                 });
                 self.player_context.update_position(pos);
             }
@@ -888,6 +1258,26 @@ impl ClientInboundContext {
     }
 
     async fn handle_place(&mut self, place_message: &proto::PlaceAction) -> Result<()> {
+        let coord: BlockCoordinate = place_message
+            .block_coord
+            .as_ref()
+            .map(|x| x.into())
+            .with_context(|| "Missing block_coord in place message")?;
+        let anchor: BlockCoordinate = place_message
+            .anchor
+            .as_ref()
+            .map(|x| x.into())
+            .with_context(|| "Missing anchor in place message")?;
+        let eye_position = self.player_context.last_position().position;
+        let check = interaction_validation::check_reach(eye_position, coord)
+            .and_then(|_| interaction_validation::check_reach(eye_position, anchor))
+            .and_then(|_| interaction_validation::check_adjacent(coord, anchor));
+        if let Err(violation) = check {
+            return self
+                .reject_interaction("place", violation, &[coord, anchor])
+                .await;
+        }
+
         tokio::task::block_in_place(|| {
             let _span = span!("handle_place");
             self.game_state
@@ -911,22 +1301,7 @@ impl ClientInboundContext {
                             game_state: self.game_state.clone(),
                         };
                         let new_stack = run_handler!(
-                            || {
-                                handler(
-                                    ctx,
-                                    place_message
-                                        .block_coord
-                                        .clone()
-                                        .with_context(|| "Missing block_coord in place message")?
-                                        .into(),
-                                    place_message
-                                        .anchor
-                                        .clone()
-                                        .with_context(|| "Missing anchor in place message")?
-                                        .into(),
-                                    stack.as_ref().unwrap(),
-                                )
-                            },
+                            || { handler(ctx, coord, anchor, stack.as_ref().unwrap()) },
                             "item_place",
                             initiator,
                         )?;
@@ -997,10 +1372,12 @@ impl ClientInboundContext {
         } else {
             PopupAction::ButtonClicked(action.clicked_button.clone())
         };
-        let updates = tokio::task::block_in_place(|| -> anyhow::Result<_> {
+        let (updates, handler_result) = tokio::task::block_in_place(|| -> anyhow::Result<_> {
             let _span = span!("handle_popup_response");
             let mut player_state = self.player_context.state.lock();
             let mut updates = vec![];
+            // The popup still needs to be updated (or removed, if closed) if its handler fails
+            let mut handler_result = Ok(());
             if action.closed {
                 player_state
                     .inventory_manipulation_view
@@ -1010,18 +1387,26 @@ impl ClientInboundContext {
                     &&player_state.inventory_manipulation_view,
                 )?);
             }
+            let response = PopupResponse {
+                user_action,
+                textfield_values: action.text_fields.clone(),
+                checkbox_values: action.checkboxes.clone(),
+                dropdown_values: action.dropdowns.clone(),
+                slider_values: action.sliders.clone(),
+            };
             if let Some(popup) = player_state
                 .active_popups
                 .iter_mut()
                 .find(|x| x.id() == action.popup_id)
             {
-                popup.handle_response(
-                    PopupResponse {
-                        user_action,
-                        textfield_values: action.text_fields.clone(),
-                    },
+                handler_result = popup.handle_response(
+                    response,
                     self.player_context.main_inventory(),
-                )?;
+                    EventInitiator::Player(&self.player_context),
+                );
+                if !action.closed {
+                    updates.push(make_popup_update(&self.game_state, popup));
+                }
                 for view in popup.inventory_views().values() {
                     updates.push(make_inventory_update(
                         &self.game_state,
@@ -1032,13 +1417,17 @@ impl ClientInboundContext {
                     )?);
                 }
             } else if player_state.inventory_popup.id() == action.popup_id {
-                player_state.inventory_popup.handle_response(
-                    PopupResponse {
-                        user_action,
-                        textfield_values: action.text_fields.clone(),
-                    },
+                handler_result = player_state.inventory_popup.handle_response(
+                    response,
                     self.player_context.main_inventory(),
-                )?;
+                    EventInitiator::Player(&self.player_context),
+                );
+                if !action.closed {
+                    updates.push(make_popup_update(
+                        &self.game_state,
+                        &player_state.inventory_popup,
+                    ));
+                }
                 for view in player_state.inventory_popup.inventory_views().values() {
                     updates.push(make_inventory_update(
                         &self.game_state,
@@ -1061,7 +1450,7 @@ impl ClientInboundContext {
             }
             // drop before async calls
             drop(player_state);
-            anyhow::Result::Ok(updates)
+            anyhow::Result::Ok((updates, handler_result))
         })?;
         for update in updates {
             self.outbound_tx
@@ -1070,7 +1459,7 @@ impl ClientInboundContext {
                 .with_context(|| "Could not send outbound message (inventory update)")?;
         }
 
-        Ok(())
+        handler_result
     }
 
     async fn handle_interact_key(
@@ -1132,6 +1521,7 @@ impl ClientInboundContext {
                         },
                     )?)
                 }
+                popup.show_to(self.popup_update_sender.clone());
                 self.player_context
                     .player
                     .state
@@ -1149,41 +1539,135 @@ impl Drop for ClientInboundContext {
     }
 }
 
-// TODO tune these and make them adjustable via settings
 // Units of chunks
-const LOAD_EAGER_DISTANCE: i32 = 20;
-const LOAD_LAZY_DISTANCE: i32 = 30;
-const UNLOAD_DISTANCE: i32 = 40;
+// Used if the client doesn't ask for a view distance
+const DEFAULT_VIEW_DISTANCE: u32 = 30;
+const MIN_VIEW_DISTANCE: u32 = 2;
+// Chunks are only unsubscribed this much further away than they're sent, to avoid flapping
+const UNLOAD_HYSTERESIS: i32 = 10;
 const MAX_UPDATE_BATCH_SIZE: usize = 256;
 
 const INITIAL_CHUNKS_PER_UPDATE: usize = 16;
 const MAX_CHUNKS_PER_UPDATE: usize = 512;
+// Up to this many notifications can be sent at once, after which they're limited to the
+// given rate
+const NOTIFICATION_BURST: f64 = 3.;
+const NOTIFICATIONS_PER_SECOND: f64 = 0.5;
+
+// Returns the offsets of all chunks in the cube extending the given distance from the
+// player's chunk in each direction, nearest (by Manhattan distance) first
+fn chunk_offsets_within(distance: i32) -> Vec<(i32, i32, i32)> {
+    let mut zigzag = vec![0];
+    for i in 1..=distance {
+        zigzag.push(i);
+        zigzag.push(-i);
+    }
+    let mut v = vec![];
+    for (&x, &y, &z) in iproduct!(zigzag.iter(), zigzag.iter(), zigzag.iter()) {
+        v.push((x, y, z));
+    }
+    v.sort_by_key(|(x, y, z)| x.abs() + y.abs() + z.abs());
+    v
+}
 
-lazy_static::lazy_static! {
-    static ref LOAD_LAZY_ZIGZAG_VEC: Vec<i32> = {
-        let mut v = vec![0];
-        for i in 1..=LOAD_LAZY_DISTANCE {
-            v.push(i);
-            v.push(-i);
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct ViewSettings {
+    // Chunks are sent to the client within this distance
+    view_distance: i32,
+    // How many out-of-view chunks the client keeps
+    chunk_cache_size: usize,
+}
+impl ViewSettings {
+    // Caps the client's requested settings to what the server allows. A view distance of zero
+    // means the client didn't ask for anything in particular.
+    fn new(max_view_distance: u32, view_distance: u32, chunk_cache_size: u32) -> ViewSettings {
+        let max_view_distance = max_view_distance.max(MIN_VIEW_DISTANCE);
+        let view_distance = if view_distance == 0 {
+            DEFAULT_VIEW_DISTANCE
+        } else {
+            view_distance
+        };
+        ViewSettings {
+            view_distance: view_distance.clamp(MIN_VIEW_DISTANCE, max_view_distance) as i32,
+            chunk_cache_size: chunk_cache_size.min(MAX_CLIENT_CHUNK_CACHE_SIZE) as usize,
         }
-        v
-    };
+    }
+    // Chunks within this distance are loaded (or generated) if needed. Beyond it, chunks are
+    // only sent if they're already loaded.
+    fn eager_distance(&self) -> i32 {
+        self.view_distance * 2 / 3
+    }
+    fn unload_distance(&self) -> i32 {
+        self.view_distance + UNLOAD_HYSTERESIS
+    }
+}
+
+// The chunks a client keeps cached after they go out of view, oldest first. The client doesn't
+// evict chunks from its cache by itself; it waits for us to evict them.
+struct ChunkLru {
+    // Each chunk is tagged with a sequence number, which is its key in `order`
+    members: HashMap<ChunkCoordinate, u64>,
+    order: BTreeMap<u64, ChunkCoordinate>,
+    next_seq: u64,
+    capacity: usize,
 }
-lazy_static::lazy_static! {
-    static ref LOAD_LAZY_SORTED_COORDS: Vec<(i32, i32, i32)> = {
-        let mut v = vec![];
-        for (&x, &y, &z) in iproduct!(LOAD_LAZY_ZIGZAG_VEC.iter(), LOAD_LAZY_ZIGZAG_VEC.iter(), LOAD_LAZY_ZIGZAG_VEC.iter()) {
-            v.push((x, y, z));
+impl ChunkLru {
+    fn new(capacity: usize) -> ChunkLru {
+        ChunkLru {
+            members: HashMap::new(),
+            order: BTreeMap::new(),
+            next_seq: 0,
+            capacity,
         }
-        v.sort_by_key(|(x, y, z)| x.abs() + y.abs() + z.abs());
-        v
-    };
+    }
+    fn contains(&self, coord: ChunkCoordinate) -> bool {
+        self.members.contains_key(&coord)
+    }
+    // Returns the chunks that had to be evicted to make room
+    fn insert(&mut self, coord: ChunkCoordinate) -> Vec<ChunkCoordinate> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some(old_seq) = self.members.insert(coord, seq) {
+            self.order.remove(&old_seq);
+        }
+        self.order.insert(seq, coord);
+        self.evict()
+    }
+    fn remove(&mut self, coord: ChunkCoordinate) -> bool {
+        match self.members.remove(&coord) {
+            Some(seq) => {
+                self.order.remove(&seq);
+                true
+            }
+            None => false,
+        }
+    }
+    // Returns the chunks that had to be evicted to fit the new capacity
+    fn set_capacity(&mut self, capacity: usize) -> Vec<ChunkCoordinate> {
+        self.capacity = capacity;
+        self.evict()
+    }
+    // Returns all the chunks that were cached
+    fn clear(&mut self) -> Vec<ChunkCoordinate> {
+        self.members.clear();
+        std::mem::take(&mut self.order).into_values().collect()
+    }
+    fn evict(&mut self) -> Vec<ChunkCoordinate> {
+        let mut evicted = vec![];
+        while self.members.len() > self.capacity {
+            let (_, coord) = self.order.pop_first().unwrap();
+            self.members.remove(&coord);
+            evicted.push(coord);
+        }
+        evicted
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 struct PositionAndPacing {
     position: PlayerPositionUpdate,
     chunks_to_send: usize,
+    view: ViewSettings,
 }
 
 struct Aimd {
@@ -1206,6 +1690,66 @@ impl Aimd {
     }
 }
 
+// Token bucket that limits how often notifications are sent, so that e.g. a broken handler for a
+// block that's dug over and over doesn't flood the player with them
+struct NotificationLimiter {
+    tokens: f64,
+    last_refill: Instant,
+}
+impl NotificationLimiter {
+    fn new() -> Self {
+        NotificationLimiter {
+            tokens: NOTIFICATION_BURST,
+            last_refill: Instant::now(),
+        }
+    }
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * NOTIFICATIONS_PER_SECOND).min(NOTIFICATION_BURST);
+        self.last_refill = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn make_client_state_update(
+    game_state: &GameState,
+    player_context: &PlayerContext,
+    position: Vector3<f64>,
+) -> Result<StreamToClient> {
+    let player_state = player_context.state.lock();
+    Ok(StreamToClient {
+        tick: game_state.tick(),
+        server_message: Some(ServerMessage::ClientState(proto::SetClientState {
+            position: Some(PositionUpdate {
+                position: Some(position.try_into()?),
+                velocity: Some(Vector3::zero().try_into()?),
+                face_direction: Some(Angles {
+                    deg_azimuth: 0.,
+                    deg_elevation: 0.,
+                }),
+            }),
+            hotbar_inventory_view: player_state.hotbar_inventory_view.id.0,
+            inventory_popup: Some(player_state.inventory_popup.to_proto()),
+            inventory_manipulation_view: player_state.inventory_manipulation_view.id.0,
+            allow_flight: game_state.movement_validation().flight_allowed(),
+            allow_noclip: game_state.movement_validation().noclip_allowed(),
+        })),
+    })
+}
+
+fn make_popup_update(game_state: &GameState, popup: &Popup) -> StreamToClient {
+    StreamToClient {
+        tick: game_state.tick(),
+        server_message: Some(ServerMessage::UpdatePopup(popup.to_proto())),
+    }
+}
+
 fn make_inventory_update(
     game_state: &GameState,
     view: &dyn TypeErasedInventoryView,
@@ -1217,3 +1761,200 @@ fn make_inventory_update(
         })
     })
 }
+
+// Messages that bring a client that knows the `known` HUD elements up to date with `current`
+fn hud_changes(
+    known: &HashMap<u64, HudElement>,
+    current: &HashMap<u64, HudElement>,
+) -> Vec<ServerMessage> {
+    let mut messages = vec![];
+    for (&id, element) in current {
+        if known.get(&id) != Some(element) {
+            messages.push(ServerMessage::SetHudElement(SetHudElement {
+                id,
+                element: Some(element.clone()),
+            }));
+        }
+    }
+    for &id in known.keys() {
+        if !current.contains_key(&id) {
+            messages.push(ServerMessage::RemoveHudElement(id));
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::game_state::hud;
+
+    use super::*;
+
+    #[test]
+    fn test_notification_limiter() {
+        let mut limiter = NotificationLimiter::new();
+        for _ in 0..NOTIFICATION_BURST as usize {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+
+        // Refills over time, but not beyond the burst size
+        limiter.last_refill -= Duration::from_secs_f64(1.0 / NOTIFICATIONS_PER_SECOND);
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        limiter.last_refill -=
+            Duration::from_secs_f64(10. * NOTIFICATION_BURST / NOTIFICATIONS_PER_SECOND);
+        for _ in 0..NOTIFICATION_BURST as usize {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_view_settings_default() {
+        assert_eq!(
+            ViewSettings::new(64, 0, 0).view_distance,
+            DEFAULT_VIEW_DISTANCE as i32
+        );
+        // The default is still capped by the server
+        assert_eq!(ViewSettings::new(10, 0, 0).view_distance, 10);
+    }
+
+    #[test]
+    fn test_view_settings_clamped() {
+        assert_eq!(ViewSettings::new(40, 25, 0).view_distance, 25);
+        assert_eq!(ViewSettings::new(40, 100, 0).view_distance, 40);
+        assert_eq!(
+            ViewSettings::new(40, 1, 0).view_distance,
+            MIN_VIEW_DISTANCE as i32
+        );
+        // A server limit below the minimum is raised to it
+        assert_eq!(
+            ViewSettings::new(0, 30, 0).view_distance,
+            MIN_VIEW_DISTANCE as i32
+        );
+    }
+
+    #[test]
+    fn test_view_settings_chunk_cache_size() {
+        assert_eq!(ViewSettings::new(30, 30, 0).chunk_cache_size, 0);
+        assert_eq!(ViewSettings::new(30, 30, 100).chunk_cache_size, 100);
+        assert_eq!(
+            ViewSettings::new(30, 30, u32::MAX).chunk_cache_size,
+            MAX_CLIENT_CHUNK_CACHE_SIZE as usize
+        );
+    }
+
+    #[test]
+    fn test_view_settings_distances() {
+        let view = ViewSettings::new(30, 30, 0);
+        assert_eq!(view.eager_distance(), 20);
+        assert_eq!(view.unload_distance(), 30 + UNLOAD_HYSTERESIS);
+    }
+
+    #[test]
+    fn test_chunk_offsets_within() {
+        assert_eq!(chunk_offsets_within(0), vec![(0, 0, 0)]);
+
+        let offsets = chunk_offsets_within(3);
+        // A cube, with every offset exactly once
+        assert_eq!(offsets.len(), 7 * 7 * 7);
+        let unique: HashSet<_> = offsets.iter().copied().collect();
+        assert_eq!(unique.len(), offsets.len());
+        assert!(offsets
+            .iter()
+            .all(|&(x, y, z)| x.abs() <= 3 && y.abs() <= 3 && z.abs() <= 3));
+        assert!(unique.contains(&(3, -3, 3)));
+
+        // Nearest first
+        assert_eq!(offsets[0], (0, 0, 0));
+        let distances: Vec<_> = offsets
+            .iter()
+            .map(|(x, y, z)| x.abs() + y.abs() + z.abs())
+            .collect();
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(*distances.last().unwrap(), 9);
+    }
+
+    #[test]
+    fn test_hud_changes() {
+        let text = |text: &str| hud::HudElement::text(text).to_proto();
+        let known = HashMap::from([
+            (1, text("unchanged")),
+            (2, text("old")),
+            (3, text("removed")),
+        ]);
+        let current = HashMap::from([(1, text("unchanged")), (2, text("new")), (4, text("added"))]);
+
+        let mut set = vec![];
+        let mut removed = vec![];
+        for message in hud_changes(&known, &current) {
+            match message {
+                ServerMessage::SetHudElement(SetHudElement { id, element }) => {
+                    assert_eq!(element.as_ref(), current.get(&id));
+                    set.push(id);
+                }
+                ServerMessage::RemoveHudElement(id) => removed.push(id),
+                _ => panic!("unexpected message"),
+            }
+        }
+        set.sort();
+        assert_eq!(set, vec![2, 4]);
+        assert_eq!(removed, vec![3]);
+
+        assert!(hud_changes(&current, &current).is_empty());
+    }
+
+    fn coord(x: i32) -> ChunkCoordinate {
+        ChunkCoordinate::new(x, 0, 0)
+    }
+
+    fn coords(xs: &[i32]) -> Vec<ChunkCoordinate> {
+        xs.iter().copied().map(coord).collect()
+    }
+
+    #[test]
+    fn test_chunk_lru_evicts_oldest() {
+        let mut lru = ChunkLru::new(2);
+        assert!(lru.insert(coord(1)).is_empty());
+        assert!(lru.insert(coord(2)).is_empty());
+        assert_eq!(lru.insert(coord(3)), coords(&[1]));
+        assert!(!lru.contains(coord(1)));
+        assert!(lru.contains(coord(2)) && lru.contains(coord(3)));
+
+        // Reinserting makes a chunk the newest
+        assert!(lru.insert(coord(2)).is_empty());
+        assert_eq!(lru.insert(coord(4)), coords(&[3]));
+    }
+
+    #[test]
+    fn test_chunk_lru_remove() {
+        let mut lru = ChunkLru::new(2);
+        lru.insert(coord(1));
+        lru.insert(coord(2));
+        assert!(lru.remove(coord(1)));
+        assert!(!lru.remove(coord(1)));
+        // The removed chunk doesn't take up space
+        assert!(lru.insert(coord(3)).is_empty());
+        assert_eq!(lru.insert(coord(4)), coords(&[2]));
+    }
+
+    #[test]
+    fn test_chunk_lru_capacity_and_clear() {
+        let mut lru = ChunkLru::new(4);
+        for x in 0..4 {
+            lru.insert(coord(x));
+        }
+        assert_eq!(lru.set_capacity(1), coords(&[0, 1, 2]));
+        assert!(lru.set_capacity(3).is_empty());
+        lru.insert(coord(5));
+        assert_eq!(lru.clear(), coords(&[3, 5]));
+        assert!(!lru.contains(coord(3)));
+
+        // With no capacity, chunks are evicted as soon as they're inserted
+        lru.set_capacity(0);
+        assert_eq!(lru.insert(coord(6)), coords(&[6]));
+    }
+}
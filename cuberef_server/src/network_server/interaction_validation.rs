@@ -0,0 +1,299 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server-side checks for digging and placing, mirroring the rules that the client's
+//! tool controller follows.
+
+use std::time::Instant;
+
+use cgmath::{InnerSpace, Vector3};
+use cuberef_core::{
+    constants::{items::default_item_interaction_rules, player_interaction::REACH_DISTANCE},
+    coordinates::BlockCoordinate,
+    protocol::{
+        blocks::BlockTypeDef,
+        items::{interaction_rule::DigBehavior, InteractionRule},
+    },
+};
+
+use crate::game_state::items::find_interaction_rule;
+
+// The player's position is only known as of their last position update, and the target
+// may be anywhere within a block; allow for both.
+const REACH_TOLERANCE: f64 = 2.0;
+// Digging is timed on the client, and the start and end of a dig may be delayed by
+// different amounts on their way to the server.
+const DIG_TIME_TOLERANCE: f64 = 0.9;
+const DIG_TIME_SLACK_SECONDS: f64 = 0.2;
+
+/// Why a dig or place was refused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum InteractionViolation {
+    /// The target was too far from the player
+    OutOfReach { distance: f64 },
+    /// The item can't dig the block at all
+    NotDiggable,
+    /// The dig finished sooner than the item's dig time allows
+    TooFast { elapsed: f64, required: f64 },
+    /// The block being placed isn't adjacent to the block it was placed against
+    NotAdjacent,
+}
+
+/// Finds the dig behavior of the first rule that applies to the block. If there is no item,
+/// the default rules for bare hands are used.
+pub(crate) fn dig_behavior(
+    rules: Option<&[InteractionRule]>,
+    block: &BlockTypeDef,
+) -> Option<DigBehavior> {
+    let default_rules;
+    let rules = match rules {
+        Some(x) => x,
+        None => {
+            default_rules = default_item_interaction_rules();
+            &default_rules
+        }
+    };
+    find_interaction_rule(rules, block).and_then(|rule| rule.dig_behavior.clone())
+}
+
+/// The minimum time, in seconds, that a dig with the given behavior takes. None if the block
+/// can't be dug.
+pub(crate) fn dig_time(behavior: Option<&DigBehavior>, block: &BlockTypeDef) -> Option<f64> {
+    match behavior? {
+        DigBehavior::InstantDig(_) | DigBehavior::InstantDigOneshot(_) => Some(0.),
+        DigBehavior::ConstantTime(seconds) => Some(*seconds),
+        DigBehavior::ScaledTime(scale) => Some(scale * block.base_dig_time),
+        DigBehavior::Undiggable(_) => None,
+    }
+}
+
+/// Checks that a block is within reach of the player's eye, measured to the center of the block.
+pub(crate) fn check_reach(
+    eye_position: Vector3<f64>,
+    target: BlockCoordinate,
+) -> Result<(), InteractionViolation> {
+    // Blocks extend half a block to each side of their coordinates (positions are rounded to
+    // the nearest block, and cubes are drawn from -0.5 to 0.5), so the coordinates themselves
+    // are the center.
+    let center = Vector3::new(target.x as f64, target.y as f64, target.z as f64);
+    let distance = (center - eye_position).magnitude();
+    if distance.is_finite() && distance <= REACH_DISTANCE + REACH_TOLERANCE {
+        Ok(())
+    } else {
+        Err(InteractionViolation::OutOfReach { distance })
+    }
+}
+
+pub(crate) fn check_adjacent(
+    target: BlockCoordinate,
+    anchor: BlockCoordinate,
+) -> Result<(), InteractionViolation> {
+    let distance = (target.x as i64 - anchor.x as i64).abs()
+        + (target.y as i64 - anchor.y as i64).abs()
+        + (target.z as i64 - anchor.z as i64).abs();
+    if distance == 1 {
+        Ok(())
+    } else {
+        Err(InteractionViolation::NotAdjacent)
+    }
+}
+
+/// Tracks the block a player is currently digging, so that the server can tell
+/// how long they've been digging it.
+#[derive(Default)]
+pub(crate) struct DigTracker {
+    current: Option<(BlockCoordinate, Instant)>,
+}
+impl DigTracker {
+    /// Records that the client started digging the given block. Repeated starts on the same
+    /// block don't restart the timer, since the client keeps its progress when the dig
+    /// button is released and pressed again.
+    pub(crate) fn start_dig(&mut self, coord: BlockCoordinate) {
+        if self.current.map_or(true, |(current, _)| current != coord) {
+            self.current = Some((coord, Instant::now()));
+        }
+    }
+
+    /// Checks that a dig of the given block, which takes `required` seconds, has been in
+    /// progress long enough. The tracked dig is finished either way.
+    pub(crate) fn finish_dig(
+        &mut self,
+        coord: BlockCoordinate,
+        required: f64,
+    ) -> Result<(), InteractionViolation> {
+        let started = match self.current.take() {
+            Some((current, started)) if current == coord => Some(started),
+            _ => None,
+        };
+        if required <= 0. {
+            return Ok(());
+        }
+        let elapsed = started.map_or(0., |x| x.elapsed().as_secs_f64());
+        if elapsed + DIG_TIME_SLACK_SECONDS >= required * DIG_TIME_TOLERANCE {
+            Ok(())
+        } else {
+            Err(InteractionViolation::TooFast { elapsed, required })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cuberef_core::protocol::items::Empty;
+
+    use super::*;
+
+    fn block_with_dig_time(base_dig_time: f64) -> BlockTypeDef {
+        BlockTypeDef {
+            base_dig_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dig_time() {
+        let block = block_with_dig_time(2.);
+        assert_eq!(dig_time(None, &block), None);
+        assert_eq!(
+            dig_time(Some(&DigBehavior::InstantDig(Empty {})), &block),
+            Some(0.)
+        );
+        assert_eq!(
+            dig_time(Some(&DigBehavior::InstantDigOneshot(Empty {})), &block),
+            Some(0.)
+        );
+        assert_eq!(
+            dig_time(Some(&DigBehavior::ConstantTime(1.5)), &block),
+            Some(1.5)
+        );
+        assert_eq!(
+            dig_time(Some(&DigBehavior::ScaledTime(0.5)), &block),
+            Some(1.)
+        );
+        assert_eq!(
+            dig_time(Some(&DigBehavior::Undiggable(Empty {})), &block),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_reach() {
+        let target = BlockCoordinate::new(10, -5, 3);
+        let center = Vector3::new(10., -5., 3.);
+        // Points within half a block of the coordinates are in the block
+        assert_eq!(
+            BlockCoordinate::try_from(center + Vector3::new(0.49, -0.49, 0.49)).unwrap(),
+            target
+        );
+        // Since that's the center, the reach is the same in every direction
+        let limit = REACH_DISTANCE + REACH_TOLERANCE;
+        for direction in [
+            Vector3::unit_x(),
+            -Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_z(),
+        ] {
+            assert_eq!(
+                check_reach(center + direction * (limit - 0.01), target),
+                Ok(())
+            );
+            assert!(check_reach(center + direction * (limit + 0.01), target).is_err());
+        }
+        assert!(check_reach(Vector3::new(f64::NAN, 0., 0.), target).is_err());
+    }
+
+    #[test]
+    fn test_check_adjacent() {
+        let anchor = BlockCoordinate::new(10, -5, 3);
+        for (dx, dy, dz) in [
+            (1, 0, 0),
+            (-1, 0, 0),
+            (0, 1, 0),
+            (0, -1, 0),
+            (0, 0, 1),
+            (0, 0, -1),
+        ] {
+            let target = BlockCoordinate::new(anchor.x + dx, anchor.y + dy, anchor.z + dz);
+            assert_eq!(check_adjacent(target, anchor), Ok(()));
+        }
+        for (dx, dy, dz) in [(0, 0, 0), (1, 1, 0), (2, 0, 0), (1, -1, 1)] {
+            let target = BlockCoordinate::new(anchor.x + dx, anchor.y + dy, anchor.z + dz);
+            assert_eq!(
+                check_adjacent(target, anchor),
+                Err(InteractionViolation::NotAdjacent)
+            );
+        }
+    }
+
+    #[test]
+    fn test_dig_tracker_instant_dig() {
+        let mut tracker = DigTracker::default();
+        // Instant digs don't need to be started first
+        assert_eq!(
+            tracker.finish_dig(BlockCoordinate::new(1, 2, 3), 0.),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_dig_tracker_too_fast() {
+        let coord = BlockCoordinate::new(1, 2, 3);
+        let mut tracker = DigTracker::default();
+        tracker.start_dig(coord);
+        assert!(matches!(
+            tracker.finish_dig(coord, 10.),
+            Err(InteractionViolation::TooFast { required, .. }) if required == 10.
+        ));
+    }
+
+    #[test]
+    fn test_dig_tracker_within_slack() {
+        let coord = BlockCoordinate::new(1, 2, 3);
+        let mut tracker = DigTracker::default();
+        tracker.start_dig(coord);
+        assert_eq!(tracker.finish_dig(coord, DIG_TIME_SLACK_SECONDS), Ok(()));
+    }
+
+    #[test]
+    fn test_dig_tracker_not_started() {
+        let coord = BlockCoordinate::new(1, 2, 3);
+        let mut tracker = DigTracker::default();
+        assert!(tracker.finish_dig(coord, 1.).is_err());
+        // A dig of another block doesn't count
+        tracker.start_dig(BlockCoordinate::new(1, 2, 4));
+        assert!(tracker.finish_dig(coord, 1.).is_err());
+    }
+
+    #[test]
+    fn test_dig_tracker_restart() {
+        let coord = BlockCoordinate::new(1, 2, 3);
+        let mut tracker = DigTracker::default();
+        tracker.start_dig(coord);
+        let (_, started) = tracker.current.unwrap();
+        // Starting again on the same block keeps the original start time
+        tracker.start_dig(coord);
+        assert_eq!(tracker.current, Some((coord, started)));
+        // Switching blocks restarts it
+        let other = BlockCoordinate::new(0, 0, 0);
+        tracker.start_dig(other);
+        assert_eq!(tracker.current.map(|x| x.0), Some(other));
+        // Finishing a dig clears it
+        assert_eq!(tracker.finish_dig(other, 0.), Ok(()));
+        assert_eq!(tracker.current, None);
+    }
+}
@@ -16,4 +16,6 @@
 
 pub(crate) mod auth;
 mod client_context;
+pub(crate) mod interaction_validation;
+pub(crate) mod movement_validation;
 pub mod grpc_service;
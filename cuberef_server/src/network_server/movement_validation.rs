@@ -0,0 +1,730 @@
+// Copyright 2023 drey7925
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server-side plausibility checks for the positions reported by clients.
+//!
+//! The client runs its own physics and reports the result, so the server can't know exactly
+//! where the player should be. Instead, it checks that each reported movement could have been
+//! produced by the client's physics: that it isn't faster than the player can move, and that
+//! it doesn't pass through solid blocks.
+
+use std::{collections::HashSet, time::Instant};
+
+use anyhow::Result;
+use cgmath::{InnerSpace, Vector3};
+use cuberef_core::{
+    constants::player_physics::{
+        EYE_TO_BTM, EYE_TO_TOP, FLY_SPEED, JUMP_VELOCITY, TERMINAL_VELOCITY,
+        TRAVERSABLE_BUMP_HEIGHT_LANDED, WALK_SPEED,
+    },
+    coordinates::BlockCoordinate,
+    protocol::blocks::{block_type_def::PhysicsInfo, FluidPhysicsInfo},
+};
+use log::warn;
+
+use crate::game_state::GameState;
+
+/// How the server reacts to implausible movement from clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum MovementValidationMode {
+    /// Accept all positions reported by clients
+    Off,
+    /// Check movement and log violations, but accept the positions anyway
+    LogOnly,
+    /// Check movement, log violations, and move the player back to their last valid position
+    Enforce,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MovementValidationSettings {
+    pub(crate) mode: MovementValidationMode,
+    /// If true, players may move vertically at flying speed.
+    pub(crate) allow_flight: bool,
+    /// A violation is logged the first time it happens for a player, and then every
+    /// this many violations.
+    pub(crate) log_interval: u64,
+}
+impl MovementValidationSettings {
+    /// Whether players may fly without it being a violation. Clients are told this, so that
+    /// they don't let players fly when the server would reject it.
+    pub(crate) fn flight_allowed(&self) -> bool {
+        self.mode == MovementValidationMode::Off || self.allow_flight
+    }
+    /// Whether players may move through solid blocks without it being a violation.
+    pub(crate) fn noclip_allowed(&self) -> bool {
+        self.mode == MovementValidationMode::Off
+    }
+}
+
+// Allowance for rounding, timer inaccuracy, and frame time variations on the client
+const SPEED_TOLERANCE: f64 = 1.25;
+// Clients send positions at irregular intervals, and network jitter can bunch them up.
+// Unused movement allowance accumulates for up to this long, so that a late update followed
+// by an early one isn't mistaken for a burst of speed.
+const MAX_BURST_SECONDS: f64 = 1.0;
+// Extra distance that can always be covered, in blocks, on top of the speed-based allowance
+const DISTANCE_SLACK: f64 = 0.5;
+// After the server moves a player back, updates that the client sent before it processed the
+// move are ignored. An update within this distance of the reset position means the client
+// has caught up.
+const RESET_ACCEPT_DISTANCE: f64 = 2.0;
+// If the client hasn't caught up after this long, the reset is sent again.
+const RESET_RESEND_SECONDS: f64 = 1.0;
+// Spacing of the points checked for solid blocks along a movement
+const COLLISION_SAMPLE_SPACING: f64 = 0.25;
+// Solid blocks are checked at these heights relative to the player's eye. The bottom and top
+// are pulled in slightly so that standing on a floor or touching a ceiling isn't flagged.
+const COLLISION_SAMPLE_HEIGHTS: [f64; 3] = [EYE_TO_TOP - 0.05, -EYE_TO_BTM / 2., -EYE_TO_BTM + 0.1];
+
+/// The fastest the client's physics can move a player, in blocks/sec.
+#[derive(Clone, Copy, Debug)]
+struct SpeedLimits {
+    horizontal: f64,
+    up: f64,
+    down: f64,
+}
+impl SpeedLimits {
+    fn new(game_state: &GameState, allow_flight: bool) -> SpeedLimits {
+        let block_types = game_state.map().block_type_manager().block_types();
+        let fluids = block_types
+            .iter()
+            .filter_map(|block| match &block.client_info.physics_info {
+                Some(PhysicsInfo::Fluid(fluid)) => Some(fluid),
+                _ => None,
+            });
+        SpeedLimits::from_fluids(fluids, allow_flight)
+    }
+
+    fn from_fluids<'a>(
+        fluids: impl IntoIterator<Item = &'a FluidPhysicsInfo>,
+        allow_flight: bool,
+    ) -> SpeedLimits {
+        let mut horizontal = WALK_SPEED;
+        let mut up = JUMP_VELOCITY;
+        let mut down = TERMINAL_VELOCITY;
+        for fluid in fluids {
+            horizontal = horizontal
+                .max(fluid.horizontal_speed.abs())
+                .max(fluid.surf_horizontal_speed.abs());
+            for vertical in [
+                fluid.vertical_speed,
+                fluid.jump_speed,
+                fluid.sink_speed,
+                fluid.surf_vertical_speed,
+                fluid.surf_jump_speed,
+                fluid.surf_sink_speed,
+            ] {
+                up = up.max(vertical);
+                down = down.max(-vertical);
+            }
+        }
+        if allow_flight {
+            horizontal = horizontal.max(FLY_SPEED);
+            up = up.max(FLY_SPEED);
+            down = down.max(FLY_SPEED);
+        }
+        // The forward and sideways movement keys each move the player at full speed, so
+        // moving diagonally is faster by a factor of sqrt(2).
+        horizontal *= std::f64::consts::SQRT_2;
+        SpeedLimits {
+            horizontal: horizontal * SPEED_TOLERANCE,
+            up: up * SPEED_TOLERANCE,
+            down: down * SPEED_TOLERANCE,
+        }
+    }
+}
+
+// A token bucket of distance that the player may move.
+struct MotionBudget {
+    available: f64,
+    rate: f64,
+    capacity: f64,
+}
+impl MotionBudget {
+    fn new(rate: f64, extra_capacity: f64) -> MotionBudget {
+        let capacity = rate * MAX_BURST_SECONDS + DISTANCE_SLACK + extra_capacity;
+        MotionBudget {
+            available: capacity,
+            rate,
+            capacity,
+        }
+    }
+    fn refill(&mut self, elapsed_secs: f64) {
+        self.available = (self.available + self.rate * elapsed_secs).min(self.capacity);
+    }
+    fn reset(&mut self) {
+        self.available = self.capacity;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ViolationKind {
+    InvalidPosition,
+    HorizontalSpeed,
+    VerticalSpeed,
+    SolidBlock,
+}
+
+#[derive(Default, Debug)]
+struct ViolationCounters {
+    invalid_position: u64,
+    horizontal_speed: u64,
+    vertical_speed: u64,
+    solid_block: u64,
+}
+impl ViolationCounters {
+    fn record(&mut self, kind: ViolationKind) {
+        match kind {
+            ViolationKind::InvalidPosition => self.invalid_position += 1,
+            ViolationKind::HorizontalSpeed => self.horizontal_speed += 1,
+            ViolationKind::VerticalSpeed => self.vertical_speed += 1,
+            ViolationKind::SolidBlock => self.solid_block += 1,
+        }
+    }
+    fn total(&self) -> u64 {
+        self.invalid_position + self.horizontal_speed + self.vertical_speed + self.solid_block
+    }
+}
+
+/// The outcome of checking a position update from the client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MovementCheck {
+    /// The position is plausible, and should be used.
+    Accepted,
+    /// The position is not plausible. The client should be moved back to the given position.
+    Rejected(Vector3<f64>),
+    /// The client was recently moved back, and this update predates the move. It should be ignored.
+    AwaitingReset,
+}
+
+struct PendingReset {
+    position: Vector3<f64>,
+    sent_at: Instant,
+}
+
+/// Tracks a single player's movement and checks each new position they report.
+pub(crate) struct MovementValidator {
+    settings: MovementValidationSettings,
+    player_name: String,
+    last_valid_position: Vector3<f64>,
+    last_update: Instant,
+    horizontal: MotionBudget,
+    up: MotionBudget,
+    down: MotionBudget,
+    pending_reset: Option<PendingReset>,
+    counters: ViolationCounters,
+}
+impl MovementValidator {
+    /// Creates a validator for a player who was just sent to the given position.
+    pub(crate) fn new(
+        game_state: &GameState,
+        settings: MovementValidationSettings,
+        player_name: String,
+        initial_position: Vector3<f64>,
+    ) -> MovementValidator {
+        let limits = SpeedLimits::new(game_state, settings.allow_flight);
+        let now = Instant::now();
+        MovementValidator {
+            settings,
+            player_name,
+            last_valid_position: initial_position,
+            last_update: now,
+            horizontal: MotionBudget::new(limits.horizontal, 0.),
+            up: MotionBudget::new(limits.up, TRAVERSABLE_BUMP_HEIGHT_LANDED),
+            down: MotionBudget::new(limits.down, 0.),
+            // The client is sent its initial position when it connects, and may report its own
+            // default position until it processes that.
+            pending_reset: Some(PendingReset {
+                position: initial_position,
+                sent_at: now,
+            }),
+            counters: ViolationCounters::default(),
+        }
+    }
+
+    /// Checks a position reported by the client. This may access the map, and hence block.
+    pub(crate) fn check(
+        &mut self,
+        game_state: &GameState,
+        position: Vector3<f64>,
+    ) -> Result<MovementCheck> {
+        if self.settings.mode == MovementValidationMode::Off {
+            return Ok(MovementCheck::Accepted);
+        }
+        let now = Instant::now();
+
+        if let Some(pending) = &self.pending_reset {
+            if (position - pending.position).magnitude() <= RESET_ACCEPT_DISTANCE {
+                self.last_valid_position = pending.position;
+                self.pending_reset = None;
+                self.horizontal.reset();
+                self.up.reset();
+                self.down.reset();
+            } else if self.settings.mode == MovementValidationMode::Enforce {
+                if (now - pending.sent_at).as_secs_f64() < RESET_RESEND_SECONDS {
+                    return Ok(MovementCheck::AwaitingReset);
+                }
+                let position = pending.position;
+                self.pending_reset = Some(PendingReset {
+                    position,
+                    sent_at: now,
+                });
+                return Ok(MovementCheck::Rejected(position));
+            } else {
+                // Nothing is enforced, so there's nothing to wait for
+                self.pending_reset = None;
+            }
+        }
+
+        let elapsed = (now - self.last_update).as_secs_f64();
+        self.last_update = now;
+        self.horizontal.refill(elapsed);
+        self.up.refill(elapsed);
+        self.down.refill(elapsed);
+
+        let violation = self.find_violation(game_state, position)?;
+        match violation {
+            None => {
+                self.last_valid_position = position;
+                Ok(MovementCheck::Accepted)
+            }
+            Some(kind) => {
+                self.record_violation(kind, position);
+                match self.settings.mode {
+                    MovementValidationMode::Enforce => {
+                        self.pending_reset = Some(PendingReset {
+                            position: self.last_valid_position,
+                            sent_at: now,
+                        });
+                        Ok(MovementCheck::Rejected(self.last_valid_position))
+                    }
+                    _ => {
+                        // Start over from wherever the client says it is, so one violation
+                        // doesn't cause a cascade of them
+                        self.last_valid_position = position;
+                        self.horizontal.reset();
+                        self.up.reset();
+                        self.down.reset();
+                        Ok(MovementCheck::Accepted)
+                    }
+                }
+            }
+        }
+    }
+
+    fn find_violation(
+        &mut self,
+        game_state: &GameState,
+        position: Vector3<f64>,
+    ) -> Result<Option<ViolationKind>> {
+        if !(position.x.is_finite() && position.y.is_finite() && position.z.is_finite())
+            || BlockCoordinate::try_from(position).is_err()
+        {
+            return Ok(Some(ViolationKind::InvalidPosition));
+        }
+        let delta = position - self.last_valid_position;
+        let horizontal_distance = (delta.x * delta.x + delta.z * delta.z).sqrt();
+        if horizontal_distance > self.horizontal.available {
+            return Ok(Some(ViolationKind::HorizontalSpeed));
+        }
+        if delta.y > self.up.available || -delta.y > self.down.available {
+            return Ok(Some(ViolationKind::VerticalSpeed));
+        }
+        if self.passes_through_solid(game_state, position)? {
+            return Ok(Some(ViolationKind::SolidBlock));
+        }
+        self.horizontal.available -= horizontal_distance;
+        if delta.y > 0. {
+            self.up.available -= delta.y;
+        } else {
+            self.down.available += delta.y;
+        }
+        Ok(None)
+    }
+
+    // Checks whether the straight path from the last valid position to the given position
+    // goes through any solid blocks. Blocks that the player was already inside at the start
+    // are ignored, since the player is allowed to move out of blocks placed on top of them.
+    fn passes_through_solid(&self, game_state: &GameState, position: Vector3<f64>) -> Result<bool> {
+        let start = self.last_valid_position;
+        let occupied_blocks = |pos: Vector3<f64>| {
+            COLLISION_SAMPLE_HEIGHTS.iter().filter_map(move |&height| {
+                BlockCoordinate::try_from(pos + Vector3::new(0., height, 0.)).ok()
+            })
+        };
+        let initial: HashSet<BlockCoordinate> = occupied_blocks(start).collect();
+
+        let distance = (position - start).magnitude();
+        let steps = (distance / COLLISION_SAMPLE_SPACING).ceil().max(1.) as usize;
+        let mut checked = HashSet::new();
+        for i in 1..=steps {
+            let sample = start + (position - start) * (i as f64 / steps as f64);
+            for coord in occupied_blocks(sample) {
+                if initial.contains(&coord) || !checked.insert(coord) {
+                    continue;
+                }
+                if self.is_solid(game_state, coord)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn is_solid(&self, game_state: &GameState, coord: BlockCoordinate) -> Result<bool> {
+        if !coord.chunk().is_in_bounds() {
+            return Ok(false);
+        }
+        // Don't load or generate chunks just to validate a movement; unloaded chunks are treated
+        // as passable.
+        let block = match game_state.map().try_get_block(coord) {
+            Some(block) => block,
+            None => return Ok(false),
+        };
+        let (block_type, _) = game_state.map().block_type_manager().get_block(&block)?;
+        Ok(matches!(
+            block_type.client_info.physics_info,
+            Some(PhysicsInfo::Solid(_))
+        ))
+    }
+
+    fn record_violation(&mut self, kind: ViolationKind, position: Vector3<f64>) {
+        self.counters.record(kind);
+        let total = self.counters.total();
+        if total == 1 || total % self.settings.log_interval.max(1) == 0 {
+            warn!(
+                "Player {} made an invalid movement ({:?}) from {:?} to {:?}. Violations so far: {:?}",
+                self.player_name, kind, self.last_valid_position, position, self.counters
+            );
+        }
+    }
+}
+impl Drop for MovementValidator {
+    fn drop(&mut self) {
+        if self.counters.total() > 0 {
+            warn!(
+                "Player {} disconnected after {} movement violations: {:?}",
+                self.player_name,
+                self.counters.total(),
+                self.counters
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use cuberef_core::{
+        coordinates::ChunkOffset,
+        protocol::blocks::{BlockTypeDef, Empty},
+    };
+
+    use crate::game_state::{
+        blocks::{BlockType, BlockTypeManager},
+        game_map::MapChunk,
+        mapgen::{MapgenContext, MapgenStage, MapgenStageHandler, MapgenStageProvider},
+        tests::make_game_state,
+    };
+
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_speed_limits_walking() {
+        let limits = SpeedLimits::from_fluids([], false);
+        assert_close(
+            limits.horizontal,
+            WALK_SPEED * std::f64::consts::SQRT_2 * SPEED_TOLERANCE,
+        );
+        assert_close(limits.up, JUMP_VELOCITY * SPEED_TOLERANCE);
+        assert_close(limits.down, TERMINAL_VELOCITY * SPEED_TOLERANCE);
+    }
+
+    #[test]
+    fn test_speed_limits_flight() {
+        let limits = SpeedLimits::from_fluids([], true);
+        assert_close(
+            limits.horizontal,
+            FLY_SPEED * std::f64::consts::SQRT_2 * SPEED_TOLERANCE,
+        );
+        assert_close(limits.up, FLY_SPEED * SPEED_TOLERANCE);
+        // Falling is still faster than flying down
+        assert_close(limits.down, TERMINAL_VELOCITY * SPEED_TOLERANCE);
+    }
+
+    #[test]
+    fn test_speed_limits_fluids() {
+        let fast_fluid = FluidPhysicsInfo {
+            horizontal_speed: -20.,
+            surf_horizontal_speed: 4.,
+            jump_speed: 10.,
+            sink_speed: -100.,
+            ..Default::default()
+        };
+        let slow_fluid = FluidPhysicsInfo {
+            horizontal_speed: 1.,
+            ..Default::default()
+        };
+        let limits = SpeedLimits::from_fluids([&fast_fluid, &slow_fluid], false);
+        assert_close(
+            limits.horizontal,
+            20. * std::f64::consts::SQRT_2 * SPEED_TOLERANCE,
+        );
+        assert_close(limits.up, 10. * SPEED_TOLERANCE);
+        assert_close(limits.down, 100. * SPEED_TOLERANCE);
+    }
+
+    #[test]
+    fn test_motion_budget_starts_full() {
+        let budget = MotionBudget::new(2., 0.5);
+        let capacity = 2. * MAX_BURST_SECONDS + DISTANCE_SLACK + 0.5;
+        assert_close(budget.capacity, capacity);
+        assert_close(budget.available, capacity);
+    }
+
+    #[test]
+    fn test_motion_budget_refill() {
+        let mut budget = MotionBudget::new(2., 0.);
+        budget.available = 0.;
+        budget.refill(0.25);
+        assert_close(budget.available, 0.5);
+        budget.refill(0.);
+        assert_close(budget.available, 0.5);
+        // Refilling never exceeds the capacity
+        budget.refill(100.);
+        assert_close(budget.available, budget.capacity);
+    }
+
+    #[test]
+    fn test_motion_budget_reset() {
+        let mut budget = MotionBudget::new(3., 0.);
+        budget.available = -1.;
+        budget.reset();
+        assert_close(budget.available, budget.capacity);
+    }
+
+    #[test]
+    fn test_validation_settings() {
+        let settings = |mode, allow_flight| MovementValidationSettings {
+            mode,
+            allow_flight,
+            log_interval: 1,
+        };
+        assert!(settings(MovementValidationMode::Off, false).flight_allowed());
+        assert!(settings(MovementValidationMode::Off, false).noclip_allowed());
+        assert!(!settings(MovementValidationMode::Enforce, false).flight_allowed());
+        assert!(settings(MovementValidationMode::Enforce, true).flight_allowed());
+        assert!(!settings(MovementValidationMode::Enforce, true).noclip_allowed());
+        assert!(!settings(MovementValidationMode::LogOnly, false).flight_allowed());
+    }
+
+    // Stone at x >= 2, and air everywhere else
+    struct WallMapgen {
+        stone: u32,
+    }
+    impl MapgenStageHandler for WallMapgen {
+        fn generate(&self, context: &MapgenContext<'_>, chunk: &mut MapChunk) -> Result<()> {
+            for index in 0..4096 {
+                let offset = ChunkOffset::from_index(index);
+                if context.coord().with_offset(offset).x >= 2 {
+                    chunk.block_ids[index] = self.stone;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn make_wall_game_state() -> Arc<GameState> {
+        let mut blocks = BlockTypeManager::new();
+        let mut register = |name: &str, physics_info| {
+            blocks
+                .register_block(BlockType {
+                    client_info: BlockTypeDef {
+                        short_name: name.to_string(),
+                        physics_info: Some(physics_info),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .unwrap()
+        };
+        register("test:air", PhysicsInfo::Air(Empty {}));
+        let stone = register("test:stone", PhysicsInfo::Solid(Empty {}))
+            .id()
+            .into();
+        let mapgen: MapgenStageProvider = Box::new(move |_, _| Ok(Box::new(WallMapgen { stone })));
+        let game_state = make_game_state(blocks, vec![(MapgenStage::Terrain, "test:wall", mapgen)]);
+        // Chunks that aren't loaded are treated as passable
+        game_state
+            .map()
+            .get_block(BlockCoordinate::new(0, 0, 0))
+            .unwrap();
+        game_state
+    }
+
+    fn start() -> Vector3<f64> {
+        Vector3::new(0.5, 5., 0.5)
+    }
+
+    // Makes a validator whose client already reported its initial position
+    fn make_validator(game_state: &GameState, mode: MovementValidationMode) -> MovementValidator {
+        let settings = MovementValidationSettings {
+            mode,
+            allow_flight: false,
+            log_interval: 1,
+        };
+        let mut validator =
+            MovementValidator::new(game_state, settings, "test".to_string(), start());
+        assert_eq!(
+            validator.check(game_state, start()).unwrap(),
+            MovementCheck::Accepted
+        );
+        validator
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_accepts_normal_movement() {
+        let game_state = make_wall_game_state();
+        let mut validator = make_validator(&game_state, MovementValidationMode::Enforce);
+        for position in [
+            start() + Vector3::new(1., 0., 0.),
+            start() + Vector3::new(1., 1., -1.),
+            start() + Vector3::new(0., -2., -1.),
+        ] {
+            assert_eq!(
+                validator.check(&game_state, position).unwrap(),
+                MovementCheck::Accepted
+            );
+        }
+        assert_eq!(validator.counters.total(), 0);
+        game_state.finish_shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_rejects_speed_bursts() {
+        let game_state = make_wall_game_state();
+        let mut validator = make_validator(&game_state, MovementValidationMode::Enforce);
+        let moved = start() + Vector3::new(0., 0., -1.);
+        assert_eq!(
+            validator.check(&game_state, moved).unwrap(),
+            MovementCheck::Accepted
+        );
+        assert_eq!(
+            validator
+                .check(&game_state, moved + Vector3::new(0., 0., -20.))
+                .unwrap(),
+            MovementCheck::Rejected(moved)
+        );
+        assert_eq!(validator.counters.horizontal_speed, 1);
+
+        let mut validator = make_validator(&game_state, MovementValidationMode::Enforce);
+        assert_eq!(
+            validator
+                .check(&game_state, start() + Vector3::new(0., 20., 0.))
+                .unwrap(),
+            MovementCheck::Rejected(start())
+        );
+        assert_eq!(validator.counters.vertical_speed, 1);
+        game_state.finish_shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_awaiting_reset() {
+        let game_state = make_wall_game_state();
+        let settings = MovementValidationSettings {
+            mode: MovementValidationMode::Enforce,
+            allow_flight: false,
+            log_interval: 1,
+        };
+        let mut validator =
+            MovementValidator::new(&game_state, settings, "test".to_string(), start());
+        // Positions from before the client learned its initial position are ignored
+        assert_eq!(
+            validator
+                .check(&game_state, Vector3::new(0., 50., 0.))
+                .unwrap(),
+            MovementCheck::AwaitingReset
+        );
+        assert_eq!(
+            validator.check(&game_state, start()).unwrap(),
+            MovementCheck::Accepted
+        );
+
+        let far = start() + Vector3::new(0., 0., -20.);
+        assert_eq!(
+            validator.check(&game_state, far).unwrap(),
+            MovementCheck::Rejected(start())
+        );
+        // The client may still send positions from before it was moved back
+        assert_eq!(
+            validator.check(&game_state, far).unwrap(),
+            MovementCheck::AwaitingReset
+        );
+        // If it doesn't catch up for a while, it's moved back again
+        validator.pending_reset.as_mut().unwrap().sent_at -= Duration::from_secs(2);
+        assert_eq!(
+            validator.check(&game_state, far).unwrap(),
+            MovementCheck::Rejected(start())
+        );
+        // Once it's back, its movement is checked normally again
+        let back = start() + Vector3::new(0., 0., -0.5);
+        assert_eq!(
+            validator.check(&game_state, back).unwrap(),
+            MovementCheck::Accepted
+        );
+        assert!(validator.pending_reset.is_none());
+        assert_eq!(validator.last_valid_position, back);
+        assert_eq!(validator.counters.total(), 1);
+        game_state.finish_shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_solid_blocks() {
+        let game_state = make_wall_game_state();
+        let into_wall = start() + Vector3::new(2., 0., 0.);
+        let mut validator = make_validator(&game_state, MovementValidationMode::Enforce);
+        assert_eq!(
+            validator.check(&game_state, into_wall).unwrap(),
+            MovementCheck::Rejected(start())
+        );
+        assert_eq!(validator.counters.solid_block, 1);
+
+        // Violations are only counted when logging
+        let mut validator = make_validator(&game_state, MovementValidationMode::LogOnly);
+        assert_eq!(
+            validator.check(&game_state, into_wall).unwrap(),
+            MovementCheck::Accepted
+        );
+        assert_eq!(validator.counters.solid_block, 1);
+
+        // Nothing is checked when validation is off
+        let mut validator = make_validator(&game_state, MovementValidationMode::Off);
+        assert_eq!(
+            validator.check(&game_state, into_wall).unwrap(),
+            MovementCheck::Accepted
+        );
+        assert_eq!(validator.counters.total(), 0);
+        game_state.finish_shutdown().await;
+    }
+}
@@ -23,17 +23,26 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 use clap::Parser;
-use cuberef_core::protocol::game_rpc::cuberef_game_server::CuberefGameServer;
+use cuberef_core::{
+    coordinates::BlockCoordinate,
+    protocol::game_rpc::cuberef_game_server::CuberefGameServer,
+};
 use tonic::codegen::CompressionEncoding;
 
 use crate::{
+    admin_console,
     database::{database_engine::GameDatabase, rocksdb::RocksDbBackend},
     game_state::{
         blocks::BlockTypeManager, game_behaviors::GameBehaviors, items::ItemManager,
-        mapgen::MapgenInterface, GameState, game_map::{TimerSettings, TimerCallback},
+        mapgen::{MapgenInterface, MapgenStage, MapgenStageHandler, MapgenStageProvider, WholeChunkMapgen}, GameState, game_map::{TimerSettings, TimerCallback},
+        pregen::PregenRequest,
+        world_metadata::{self, WorldOptions},
     },
     media::MediaManager,
-    network_server::{grpc_service::CuberefGameServerImpl},
+    network_server::{
+        grpc_service::CuberefGameServerImpl,
+        movement_validation::{MovementValidationMode, MovementValidationSettings},
+    },
 };
 
 #[derive(Parser, Debug, Clone)]
@@ -52,23 +61,73 @@ pub struct ServerArgs {
 
     #[arg(short, long, default_value_t = 28273)]
     port: u16,
+
+    /// If set, all chunks within this many chunks (horizontally) of --pregen-center
+    /// are generated in the background after the server starts. At most 128.
+    #[arg(long, value_name = "CHUNKS")]
+    pregen_radius: Option<u32>,
+
+    /// The center of the area to pregenerate, in block coordinates.
+    #[arg(long, value_name = "X,Y,Z", value_delimiter = ',', allow_negative_numbers = true, default_values_t = [0, 0, 0])]
+    pregen_center: Vec<i32>,
+
+    /// How many chunks above and below --pregen-center to pregenerate. At most 16.
+    #[arg(long, value_name = "CHUNKS", default_value_t = 4)]
+    pregen_vertical_radius: u32,
+
+    /// The seed for the map generator. Only allowed when creating a new world;
+    /// if omitted, a random seed is chosen.
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Start even if the world was created with a different map generator than
+    /// the one the game is using. Newly generated terrain may not match existing terrain.
+    #[arg(long)]
+    allow_mapgen_mismatch: bool,
+
+    /// How to handle player movement that the game's physics would not allow,
+    /// e.g. moving too fast or through solid blocks.
+    #[arg(long, value_enum, default_value_t = MovementValidationMode::Enforce)]
+    movement_validation: MovementValidationMode,
+
+    /// Allow players to fly. If false, flying is treated as a movement violation, and clients
+    /// don't let players fly.
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    allow_flight: bool,
+
+    /// Log every Nth movement violation by each player (the first one is always logged).
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    movement_violation_log_interval: u64,
+
+    /// The largest view distance players may choose, in chunks. Larger distances let players
+    /// see further, but need more memory and bandwidth for each player.
+    #[arg(long, value_name = "CHUNKS", default_value_t = 30)]
+    max_view_distance: u32,
+
+    /// Read admin commands (e.g. world pregeneration) from standard input. Type "help" for a
+    /// list of commands.
+    #[arg(long)]
+    admin_console: bool,
 }
 
 pub struct Server {
     runtime: tokio::runtime::Runtime,
     game_state: Arc<GameState>,
     bind_address: SocketAddr,
+    admin_console: bool,
 }
 impl Server {
     fn new(
         runtime: tokio::runtime::Runtime,
         game_state: Arc<GameState>,
         bind_address: SocketAddr,
+        admin_console: bool,
     ) -> Result<Server> {
         Ok(Server {
             runtime,
             game_state,
             bind_address,
+            admin_console,
         })
     }
 
@@ -108,6 +167,10 @@ impl Server {
             });
         }
 
+        if self.admin_console {
+            admin_console::spawn_admin_console(&self.game_state)?;
+        }
+
         let cuberef_service = CuberefGameServer::new(CuberefGameServerImpl::new(
             self.game_state.clone(),
         ))
@@ -145,11 +208,14 @@ pub struct ServerBuilder {
     db: Arc<dyn GameDatabase>,
     blocks: BlockTypeManager,
     items: ItemManager,
-    mapgen: Option<Box<dyn FnOnce(Arc<BlockTypeManager>, u32) -> Arc<dyn MapgenInterface>>>,
+    mapgen_stages: Vec<(MapgenStage, String, MapgenStageProvider)>,
     media: MediaManager,
     map_timers: Vec<(String, TimerSettings, TimerCallback)>,
     args: ServerArgs,
     game_behaviors: GameBehaviors,
+    game_name: String,
+    game_version: String,
+    mapgen_name: Option<String>,
 }
 impl ServerBuilder {
     pub fn from_cmdline() -> Result<ServerBuilder> {
@@ -180,11 +246,14 @@ impl ServerBuilder {
             db,
             blocks,
             items,
-            mapgen: None,
+            mapgen_stages: Vec::new(),
             media,
             map_timers: Vec::new(),
             args: args.clone(),
             game_behaviors: Default::default(),
+            game_name: env!("CARGO_PKG_NAME").to_string(),
+            game_version: env!("CARGO_PKG_VERSION").to_string(),
+            mapgen_name: None,
         })
     }
     pub fn blocks_mut(&mut self) -> &mut BlockTypeManager {
@@ -208,13 +277,49 @@ impl ServerBuilder {
     pub fn add_timer(&mut self, name: impl Into<String>, settings: TimerSettings, callback: TimerCallback) {
         self.map_timers.push((name.into(), settings, callback));
     }
-    /// Sets the mapgen for this game.
+    /// Adds a handler to the mapgen pipeline. Handlers run in the order of their stages,
+    /// and in registration order within a single stage.
+    ///
+    /// The provider is called with the block types and the world seed once the game starts.
+    /// If it fails, e.g. because a block it needs wasn't registered, the game doesn't start.
+    /// Stability note: The mapgen API is a WIP, and has not been stabilized yet.
+    pub fn add_mapgen_stage<F>(&mut self, stage: MapgenStage, name: impl Into<String>, provider: F)
+    where
+        F: (FnOnce(Arc<BlockTypeManager>, u32) -> Result<Box<dyn MapgenStageHandler>>) + 'static,
+    {
+        self.mapgen_stages
+            .push((stage, name.into(), Box::new(provider)))
+    }
+    /// Sets the mapgen for this game, replacing any mapgen stages added so far. The mapgen
+    /// runs as the only handler in the terrain stage.
     /// Stability note: The mapgen API is a WIP, and has not been stabilized yet.
+    #[deprecated(note = "Use add_mapgen_stage, which lets several handlers generate the map")]
     pub fn set_mapgen<F>(&mut self, mapgen: F)
     where
         F: (FnOnce(Arc<BlockTypeManager>, u32) -> Arc<dyn MapgenInterface>) + 'static,
     {
-        self.mapgen = Some(Box::new(mapgen))
+        self.mapgen_stages.clear();
+        self.add_mapgen_stage(MapgenStage::Terrain, "mapgen", move |blocks, seed| {
+            Ok(Box::new(WholeChunkMapgen(mapgen(blocks, seed))))
+        });
+    }
+
+    /// Sets the name and version of the game content, which are recorded when a world is created.
+    pub fn set_game_info(&mut self, name: impl Into<String>, version: impl Into<String>) {
+        self.game_name = name.into();
+        self.game_version = version.into();
+    }
+
+    /// Sets the name under which the map generator is recorded when a world is created. Loading
+    /// a world that was created with a different mapgen name fails, unless the server is started
+    /// with --allow-mapgen-mismatch.
+    ///
+    /// By default, the name of the first terrain stage is used, so that adding later stages
+    /// (e.g. decorations) to a game doesn't stop its existing worlds from loading. Games should
+    /// change the mapgen name when a seed would no longer produce the same terrain.
+    /// Stability note: The mapgen API is a WIP, and has not been stabilized yet.
+    pub fn set_mapgen_name(&mut self, name: impl Into<String>) {
+        self.mapgen_name = Some(name.into());
     }
 
     pub fn build(self) -> Result<Server> {
@@ -226,6 +331,20 @@ impl ServerBuilder {
 
         let blocks = Arc::new(self.blocks);
         blocks.save_to(self.db.as_ref())?;
+        let mapgen_name = self.mapgen_name.unwrap_or_else(|| {
+            world_metadata::base_mapgen_name(
+                self.mapgen_stages
+                    .iter()
+                    .map(|(stage, name, _)| (*stage, name.as_str())),
+            )
+        });
+        let world_options = WorldOptions {
+            seed: self.args.seed,
+            game_name: self.game_name,
+            game_version: self.game_version,
+            mapgen_name,
+            allow_mapgen_mismatch: self.args.allow_mapgen_mismatch,
+        };
         let _rt_guard = self.runtime.enter();
         let game_state = 
         GameState::new(
@@ -233,16 +352,39 @@ impl ServerBuilder {
             blocks,
             self.items,
             self.media,
-            self.mapgen.with_context(|| "Mapgen not specified")?,
+            self.mapgen_stages,
             self.game_behaviors,
+            world_options,
+            MovementValidationSettings {
+                mode: self.args.movement_validation,
+                allow_flight: self.args.allow_flight,
+                log_interval: self.args.movement_violation_log_interval,
+            },
+            self.args.max_view_distance,
         )?;
         for (name, settings, callback) in self.map_timers {
             game_state.map().register_timer(name, settings, callback)?;
         }
+        if let Some(radius) = self.args.pregen_radius {
+            let center: [i32; 3] = self
+                .args
+                .pregen_center
+                .as_slice()
+                .try_into()
+                .with_context(|| "--pregen-center must have exactly three components")?;
+            game_state.pregen().start(PregenRequest {
+                center: BlockCoordinate::new(center[0], center[1], center[2]),
+                radius,
+                vertical_radius: self.args.pregen_vertical_radius,
+            })?;
+        } else {
+            game_state.pregen().resume_pending()?;
+        }
         Server::new(
             self.runtime,
             game_state,
             addr,
+            self.args.admin_console,
         )
     }
 